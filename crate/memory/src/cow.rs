@@ -19,16 +19,24 @@
 //! so we need to maintain the count of write and read reference.
 //! When page fault occurs, if the read reference count is 0 and the write reference count is 1，
 //! The copy process should be skipped and the entry is mark as writable directly.
+//!
+//! The reference counts are global, so the frames can be shared between page tables.
+//! Memory handlers use clone_shared(), release_shared() and handle_write_fault()
+//! directly to share frames between processes on fork.
 
+use super::memory_set::MemoryAttr;
 use super::paging::*;
 use super::*;
 use alloc::collections::BTreeMap;
 use core::ops::{Deref, DerefMut};
+use spin::Mutex;
+
+/// Reference counts of the frames shared between page tables
+static RC_MAP: Mutex<FrameRcMap> = Mutex::new(FrameRcMap(None));
 
 /// Wrapper for page table, supporting shared map & copy-on-write
 pub struct CowExt<T: PageTable> {
     page_table: T,
-    rc_map: FrameRcMap,
 }
 
 impl<T: PageTable> CowExt<T> {
@@ -38,10 +46,7 @@ impl<T: PageTable> CowExt<T> {
      **  @retval CowExt               the COW extension created
      */
     pub fn new(page_table: T) -> Self {
-        CowExt {
-            page_table,
-            rc_map: FrameRcMap::default(),
-        }
+        CowExt { page_table }
     }
     /*
      **  @brief  map the virtual address to a target physics address as shared
@@ -53,14 +58,7 @@ impl<T: PageTable> CowExt<T> {
      */
     pub fn map_to_shared(&mut self, addr: VirtAddr, target: PhysAddr, writable: bool) {
         let entry = self.page_table.map(addr, target);
-        entry.set_writable(false);
-        entry.set_shared(writable);
-        entry.update();
-        let frame = target / PAGE_SIZE;
-        match writable {
-            true => self.rc_map.write_increase(&frame),
-            false => self.rc_map.read_increase(&frame),
-        }
+        share_entry(entry, writable);
     }
     /*
      **  @brief  unmap a virual address from physics address
@@ -70,12 +68,7 @@ impl<T: PageTable> CowExt<T> {
      */
     pub fn unmap_shared(&mut self, addr: VirtAddr) {
         let entry = self.page_table.get_entry(addr).expect("entry not exist");
-        let frame = entry.target() / PAGE_SIZE;
-        if entry.readonly_shared() {
-            self.rc_map.read_decrease(&frame);
-        } else if entry.writable_shared() {
-            self.rc_map.write_decrease(&frame);
-        }
+        release_shared(entry);
         self.page_table.unmap(addr);
     }
     /*
@@ -93,33 +86,132 @@ impl<T: PageTable> CowExt<T> {
         addr: VirtAddr,
         alloc_frame: impl FnOnce() -> PhysAddr,
     ) -> bool {
-        let entry = self.page_table.get_entry(addr);
-        if entry.is_none() {
-            return false;
-        }
-        let entry = entry.unwrap();
+        handle_write_fault(&mut self.page_table, addr, alloc_frame)
+    }
+}
+
+/// Mark `entry` as sharing its frame, and count the reference
+fn share_entry(entry: &mut dyn Entry, writable: bool) {
+    entry.set_writable(false);
+    entry.set_shared(writable);
+    entry.update();
+    let frame = entry.target() / PAGE_SIZE;
+    match writable {
+        true => RC_MAP.lock().write_increase(&frame),
+        false => RC_MAP.lock().read_increase(&frame),
+    }
+}
+
+/// Map `addr` in `pt` to the frame it has in `src_pt`, shared by both
+/// until one writes to it if the area is writable.
+/// Return false if the page is not present, or the page table entries can't
+/// mark a frame as shared, then the caller should copy the page.
+///
+/// Only the TLB of this CPU is flushed. Other CPUs running `src_pt` may still write
+/// to the frame until theirs are flushed too, which is left to the caller.
+pub fn clone_shared(
+    pt: &mut dyn PageTable,
+    src_pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    attr: &MemoryAttr,
+) -> bool {
+    let entry = src_pt.get_entry(addr).expect("failed to get entry");
+    // the sharing bits of entries not present may mean something else, like swapped out
+    if !entry.present() {
+        return false;
+    }
+    if !entry.readonly_shared() && !entry.writable_shared() {
+        entry.set_shared(!attr.is_readonly());
         if !entry.readonly_shared() && !entry.writable_shared() {
+            // no bits for it on this architecture
             return false;
         }
-        let frame = entry.target() / PAGE_SIZE;
-        if self.rc_map.read_count(&frame) == 0 && self.rc_map.write_count(&frame) == 1 {
-            entry.clear_shared();
-            entry.set_writable(true);
-            entry.update();
-            self.rc_map.write_decrease(&frame);
-            return true;
-        }
-        use core::mem::MaybeUninit;
-        let mut temp_data: [u8; PAGE_SIZE] = unsafe { MaybeUninit::zeroed().assume_init() };
-        temp_data[..].copy_from_slice(self.get_page_slice_mut(addr));
+        share_entry(entry, !attr.is_readonly());
+    }
+    let writable = entry.writable_shared();
+    let target = entry.target();
+    let entry = pt.map(addr, target);
+    attr.apply(entry);
+    share_entry(entry, writable);
+    true
+}
 
-        self.unmap_shared(addr);
-        self.map(addr, alloc_frame());
+/// Drop the reference of `entry` to its frame if it is shared.
+/// Return true if other page tables still map the frame, so it must not be freed.
+pub fn release_shared(entry: &mut dyn Entry) -> bool {
+    if !entry.present() {
+        return false;
+    }
+    let frame = entry.target() / PAGE_SIZE;
+    let mut rc_map = RC_MAP.lock();
+    if entry.readonly_shared() {
+        rc_map.read_decrease(&frame);
+    } else if entry.writable_shared() {
+        rc_map.write_decrease(&frame);
+    } else {
+        return false;
+    }
+    entry.clear_shared();
+    rc_map.read_count(&frame) + rc_map.write_count(&frame) > 0
+}
 
-        self.get_page_slice_mut(addr)
-            .copy_from_slice(&temp_data[..]);
-        true
+/// Make a shared `entry` copy-on-write if `writable`, or read-only otherwise.
+/// Return whether the entry shares its frame.
+pub fn reshare(entry: &mut dyn Entry, writable: bool) -> bool {
+    if !entry.present() {
+        return false;
     }
+    let frame = entry.target() / PAGE_SIZE;
+    let mut rc_map = RC_MAP.lock();
+    if entry.readonly_shared() && writable {
+        rc_map.write_increase(&frame);
+        rc_map.read_decrease(&frame);
+    } else if entry.writable_shared() && !writable {
+        rc_map.read_increase(&frame);
+        rc_map.write_decrease(&frame);
+    } else {
+        return entry.readonly_shared() || entry.writable_shared();
+    }
+    entry.set_shared(writable);
+    true
+}
+
+/// Give `addr` in `pt` a frame of its own on write, if it shares one copy-on-write.
+/// Return false if it does not.
+pub fn handle_write_fault(
+    pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    alloc_frame: impl FnOnce() -> PhysAddr,
+) -> bool {
+    let addr = addr & !(PAGE_SIZE - 1);
+    let entry = match pt.get_entry(addr) {
+        Some(entry) => entry,
+        None => return false,
+    };
+    if !entry.present() || !entry.writable_shared() {
+        return false;
+    }
+    let frame = entry.target() / PAGE_SIZE;
+    // hold the lock until the copy is done, so the last other owner
+    // can't make the frame writable and change it meanwhile
+    let mut rc_map = RC_MAP.lock();
+    rc_map.write_decrease(&frame);
+    if rc_map.read_count(&frame) == 0 && rc_map.write_count(&frame) == 0 {
+        entry.clear_shared();
+        entry.set_writable(true);
+        entry.update();
+        return true;
+    }
+    let execute = entry.execute();
+    let data = pt.get_page_slice_mut(addr);
+    let entry = pt.get_entry(addr).expect("failed to get entry");
+    entry.set_target(alloc_frame());
+    entry.clear_shared();
+    entry.set_writable(true);
+    entry.update();
+    pt.get_page_slice_mut(addr).copy_from_slice(data);
+    pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+    true
 }
 
 impl<T: PageTable> Deref for CowExt<T> {
@@ -177,6 +269,7 @@ impl FrameRcMap {
      */
     fn read_decrease(&mut self, frame: &Frame) {
         self.map().get_mut(frame).unwrap().0 -= 1;
+        self.remove_unused(frame);
     }
     /*
      **  @brief  increase the write reference count of the frame
@@ -194,6 +287,17 @@ impl FrameRcMap {
      */
     fn write_decrease(&mut self, frame: &Frame) {
         self.map().get_mut(frame).unwrap().1 -= 1;
+        self.remove_unused(frame);
+    }
+    /*
+     **  @brief  forget the frame if it has no reference left
+     **  @param  frame: &Frame        the frame to check
+     **  @retval none
+     */
+    fn remove_unused(&mut self, frame: &Frame) {
+        if self.map().get(frame) == Some(&(0, 0)) {
+            self.map().remove(frame);
+        }
     }
     /*
      **  @brief  get the internal btree map, lazily initialize the btree map if it is not present
@@ -234,6 +338,67 @@ pub mod test {
         test_with(&mut pt);
     }
 
+    #[test]
+    fn clone_shared() {
+        use crate::memory_set::handler::*;
+        use alloc::sync::Arc;
+
+        #[derive(Debug, Clone)]
+        struct FrameAlloc(Arc<Mutex<Vec<PhysAddr>>>);
+        impl FrameAllocator for FrameAlloc {
+            fn alloc(&self) -> Option<PhysAddr> {
+                self.0.lock().pop()
+            }
+            fn alloc_contiguous(&self, _size: usize, _align_log2: usize) -> Option<PhysAddr> {
+                unimplemented!()
+            }
+            fn dealloc(&self, target: PhysAddr) {
+                self.0.lock().push(target);
+            }
+        }
+        let alloc = FrameAlloc(Arc::new(Mutex::new(
+            (8..12).map(|i| i * PAGE_SIZE).collect(),
+        )));
+        let handler = ByFrame::new(alloc.clone());
+        let attr = MemoryAttr::default().user();
+
+        let mut parent = MockPageTable::new();
+        let mut child = MockPageTable::new();
+        for pt in [&mut parent, &mut child].iter_mut() {
+            let handler = handler.clone();
+            pt.set_handler(Box::new(move |pt: &mut MockPageTable, addr: VirtAddr| {
+                assert!(handler.handle_page_fault_ext(pt, addr, AccessType::write(true)));
+            }));
+        }
+        handler.map(&mut parent, 0x1000, &attr);
+        handler.map(&mut parent, 0x2000, &attr);
+        parent.write(0x1000, 1);
+        handler.clone_map(&mut child, &mut parent, 0x1000, &attr);
+        handler.clone_map(&mut child, &mut parent, 0x2000, &attr);
+        let target = parent.get_entry(0x1000).unwrap().target();
+        assert_eq!(child.get_entry(0x1000).unwrap().target(), target);
+        assert!(!parent.get_entry(0x1000).unwrap().writable());
+        assert_eq!(alloc.0.lock().len(), 2);
+
+        child.write(0x1000, 2);
+        assert_ne!(child.get_entry(0x1000).unwrap().target(), target);
+        assert_eq!(parent.read(0x1000), 1);
+        assert_eq!(child.read(0x1000), 2);
+        parent.write(0x1000, 3);
+        assert_eq!(
+            parent.get_entry(0x1000).unwrap().target(),
+            target,
+            "The last owner should not allocate new frame."
+        );
+
+        handler.unmap(&mut child, 0x2000);
+        assert_eq!(alloc.0.lock().len(), 1);
+        handler.unmap(&mut parent, 0x2000);
+        handler.unmap(&mut parent, 0x1000);
+        handler.unmap(&mut child, 0x1000);
+        assert_eq!(alloc.0.lock().len(), 4);
+    }
+
     pub fn test_with(pt: &mut CowExt<impl PageTable>) {
         let target = 0x0;
         let frame = 0x0;
@@ -246,28 +411,28 @@ pub mod test {
         pt.map_to_shared(0x1000, target, true);
         pt.map_to_shared(0x2000, target, true);
         pt.map_to_shared(0x3000, target, false);
-        assert_eq!(pt.rc_map.read_count(&frame), 1);
-        assert_eq!(pt.rc_map.write_count(&frame), 2);
+        assert_eq!(RC_MAP.lock().read_count(&frame), 1);
+        assert_eq!(RC_MAP.lock().write_count(&frame), 2);
         assert_eq!(pt.read(0x1000), 1);
         assert_eq!(pt.read(0x2000), 1);
         assert_eq!(pt.read(0x3000), 1);
 
         pt.write(0x1000, 2);
-        assert_eq!(pt.rc_map.read_count(&frame), 1);
-        assert_eq!(pt.rc_map.write_count(&frame), 1);
+        assert_eq!(RC_MAP.lock().read_count(&frame), 1);
+        assert_eq!(RC_MAP.lock().write_count(&frame), 1);
         assert_ne!(pt.get_entry(0x1000).unwrap().target(), target);
         assert_eq!(pt.read(0x1000), 2);
         assert_eq!(pt.read(0x2000), 1);
         assert_eq!(pt.read(0x3000), 1);
 
         pt.unmap_shared(0x3000);
-        assert_eq!(pt.rc_map.read_count(&frame), 0);
-        assert_eq!(pt.rc_map.write_count(&frame), 1);
+        assert_eq!(RC_MAP.lock().read_count(&frame), 0);
+        assert_eq!(RC_MAP.lock().write_count(&frame), 1);
         // assert!(!pt.get_entry(0x3000).present());
 
         pt.write(0x2000, 3);
-        assert_eq!(pt.rc_map.read_count(&frame), 0);
-        assert_eq!(pt.rc_map.write_count(&frame), 0);
+        assert_eq!(RC_MAP.lock().read_count(&frame), 0);
+        assert_eq!(RC_MAP.lock().write_count(&frame), 0);
         assert_eq!(
             pt.get_entry(0x2000).unwrap().target(),
             target,
@@ -11,12 +11,14 @@ pub mod cow;
 pub mod memory_set;
 pub mod no_mmu;
 pub mod paging;
-//pub mod swap;
+pub mod swap;
 
 pub use crate::addr::*;
 
 pub enum VMError {
     InvalidPtr,
+    /// The area can not be given the asked permissions
+    PermissionDenied,
 }
 
 pub type VMResult<T> = Result<T, VMError>;
@@ -17,8 +17,10 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
     }
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
-        let target = pt.get_entry(addr).expect("fail to get entry").target();
-        self.allocator.dealloc(target);
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        if !cow::release_shared(entry) {
+            self.allocator.dealloc(entry.target());
+        }
         pt.unmap(addr);
     }
 
@@ -29,13 +31,24 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         addr: VirtAddr,
         attr: &MemoryAttr,
     ) {
+        if cow::clone_shared(pt, src_pt, addr, attr) {
+            return;
+        }
         self.map(pt, addr, attr);
         let data = src_pt.get_page_slice_mut(addr);
         pt.get_page_slice_mut(addr).copy_from_slice(data);
     }
 
-    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
-        false
+    fn handle_page_fault_ext(
+        &self,
+        pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        access: super::AccessType,
+    ) -> bool {
+        access.write
+            && cow::handle_write_fault(pt, addr, || {
+                self.allocator.alloc().expect("failed to alloc frame")
+            })
     }
 }
 
@@ -18,7 +18,7 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
         let entry = pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() {
+        if entry.present() && !cow::release_shared(entry) {
             self.allocator.dealloc(entry.target());
         }
 
@@ -36,6 +36,10 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
     ) {
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
+            // share the frame until either side writes to it
+            if cow::clone_shared(pt, src_pt, addr, attr) {
+                return;
+            }
             // eager map and copy data
             let data = src_pt.get_page_slice_mut(addr);
             let target = self.allocator.alloc().expect("failed to alloc frame");
@@ -55,6 +59,13 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         addr: VirtAddr,
         access: super::AccessType,
     ) -> bool {
+        if access.write
+            && cow::handle_write_fault(pt, addr, || {
+                self.allocator.alloc().expect("failed to alloc frame")
+            })
+        {
+            return true;
+        }
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
             // permission check.
@@ -78,6 +89,10 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         pt.flush_cache_copy_user(addr, addr + len, false);
         true
     }
+
+    fn swappable(&self) -> bool {
+        true
+    }
 }
 
 impl<T: FrameAllocator> Delay<T> {
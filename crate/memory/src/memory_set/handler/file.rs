@@ -27,7 +27,7 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: usize) {
         let entry = pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() {
+        if entry.present() && !cow::release_shared(entry) {
             self.allocator.dealloc(entry.target());
         }
 
@@ -43,6 +43,11 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
         addr: usize,
         attr: &MemoryAttr,
     ) {
+        let entry = src_pt.get_entry(addr).expect("failed to get entry");
+        // share the frame until either side writes to it
+        if entry.present() && cow::clone_shared(pt, src_pt, addr, attr) {
+            return;
+        }
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
         if entry.present() && !attr.readonly {
             // eager map and copy data
@@ -65,6 +70,13 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
         access: super::AccessType,
     ) -> bool {
         let addr = addr & !(PAGE_SIZE - 1);
+        if access.write
+            && cow::handle_write_fault(pt, addr, || {
+                self.allocator.alloc().expect("failed to alloc frame")
+            })
+        {
+            return true;
+        }
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
             // permission check.
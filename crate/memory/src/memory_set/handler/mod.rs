@@ -76,6 +76,29 @@ pub trait MemoryHandler: Debug + Send + Sync + 'static {
     ) -> bool {
         self.handle_page_fault(pt, addr)
     }
+
+    /// Whether frames of the area can be swapped out.
+    /// A swapped page is not present, and is swapped in before the handler sees the fault.
+    fn swappable(&self) -> bool {
+        false
+    }
+
+    /// Whether the area may be made writable, like VM_MAYWRITE of Linux
+    fn may_write(&self) -> bool {
+        true
+    }
+
+    /// Write the changes to pages in `start..end` back to the object backing the area
+    fn sync(&self, _pt: &mut dyn PageTable, _start: VirtAddr, _end: VirtAddr) {}
+
+    /// Unmap pages in `start..end` that are gone from the object backing the area
+    fn invalidate(&self, _pt: &mut dyn PageTable, _start: VirtAddr, _end: VirtAddr) {}
+
+    /// Whether `addr` is beyond the end of the object backing the area,
+    /// so that accessing it is a bus error
+    fn is_past_end(&self, _addr: VirtAddr) -> bool {
+        false
+    }
 }
 
 impl Clone for Box<dyn MemoryHandler> {
@@ -95,6 +118,7 @@ mod delay;
 mod file;
 mod linear;
 mod shared;
+mod shared_file;
 //mod swap;
 
 pub use self::byframe::ByFrame;
@@ -102,3 +126,4 @@ pub use self::delay::Delay;
 pub use self::file::{File, Read};
 pub use self::linear::Linear;
 pub use self::shared::{Shared, SharedGuard};
+pub use self::shared_file::{FilePages, SharedFile};
@@ -0,0 +1,220 @@
+use super::*;
+
+/// Map pages of a file shared with all other shared mappings of it.
+/// Writes go to the shared frames, and are written back to the file on sync and unmap.
+#[derive(Clone)]
+pub struct SharedFile<F> {
+    pub file: F,
+    pub mem_start: usize,
+    /// Page aligned offset in the file of `mem_start`
+    pub file_start: usize,
+    /// Whether the file is open for writing, so that the area may be made writable
+    pub writable: bool,
+}
+
+/// The shared pages of a file
+pub trait FilePages: Clone + Send + Sync + 'static {
+    /// Get the frame of page `index` of the file for a mapping,
+    /// or None if the page is beyond the end of the file
+    fn get(&self, index: usize) -> Option<PhysAddr>;
+    /// Release page `index` got for a mapping, which has `written` to it
+    fn put(&self, index: usize, written: bool);
+    /// Write page `index` back to the file if it was written to
+    fn sync(&self, index: usize, written: bool);
+    /// Size of the file in bytes
+    fn size(&self) -> usize;
+}
+
+impl<F: FilePages> MemoryHandler for SharedFile<F> {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, 0);
+        entry.set_present(false);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            self.file.put(self.index(addr), entry.dirty());
+        }
+
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        // the child gets the same frame on page fault
+        self.map(pt, addr, attr);
+    }
+
+    fn handle_page_fault_ext(
+        &self,
+        pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        access: super::AccessType,
+    ) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            return access.check_access(entry);
+        }
+        let frame = match self.file.get(self.index(addr)) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let execute = entry.execute();
+        entry.set_target(frame);
+        entry.set_present(true);
+        entry.update();
+        pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+        true
+    }
+
+    fn sync(&self, pt: &mut dyn PageTable, start: VirtAddr, end: VirtAddr) {
+        for page in Page::range_of(start, end) {
+            let addr = page.start_address();
+            if let Some(entry) = pt.get_entry(addr) {
+                if entry.present() {
+                    let written = entry.dirty();
+                    if written {
+                        entry.clear_dirty();
+                        entry.update();
+                    }
+                    self.file.sync(self.index(addr), written);
+                }
+            }
+        }
+    }
+
+    fn invalidate(&self, pt: &mut dyn PageTable, start: VirtAddr, end: VirtAddr) {
+        let size = self.file.size();
+        for page in Page::range_of(start, end) {
+            let addr = page.start_address();
+            if addr - self.mem_start + self.file_start < size {
+                continue;
+            }
+            if let Some(entry) = pt.get_entry(addr) {
+                if entry.present() {
+                    self.file.put(self.index(addr), false);
+                    entry.set_present(false);
+                    entry.update();
+                }
+            }
+        }
+    }
+
+    fn may_write(&self) -> bool {
+        self.writable
+    }
+
+    fn is_past_end(&self, addr: VirtAddr) -> bool {
+        (addr & !(PAGE_SIZE - 1)) - self.mem_start + self.file_start >= self.file.size()
+    }
+}
+
+impl<F> SharedFile<F> {
+    fn index(&self, addr: VirtAddr) -> usize {
+        (addr - self.mem_start + self.file_start) / PAGE_SIZE
+    }
+}
+
+impl<F> Debug for SharedFile<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.debug_struct("SharedFileHandler")
+            .field("mem_start", &self.mem_start)
+            .field("file_start", &self.file_start)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paging::MockPageTable;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use spin::Mutex;
+
+    /// Log the calls, and give page `index` frame `index`
+    #[derive(Clone)]
+    struct MockFile(Arc<Mutex<(usize, Vec<(&'static str, usize, bool)>)>>);
+
+    impl FilePages for MockFile {
+        fn get(&self, index: usize) -> Option<PhysAddr> {
+            let mut file = self.0.lock();
+            if index * PAGE_SIZE >= file.0 {
+                return None;
+            }
+            file.1.push(("get", index, false));
+            Some(index * PAGE_SIZE)
+        }
+        fn put(&self, index: usize, written: bool) {
+            self.0.lock().1.push(("put", index, written));
+        }
+        fn sync(&self, index: usize, written: bool) {
+            self.0.lock().1.push(("sync", index, written));
+        }
+        fn size(&self) -> usize {
+            self.0.lock().0
+        }
+    }
+
+    #[test]
+    fn shared_file() {
+        let file = MockFile(Arc::new(Mutex::new((2 * PAGE_SIZE, Vec::new()))));
+        let handler = SharedFile {
+            file: file.clone(),
+            mem_start: 0x1000,
+            file_start: 0x1000,
+            writable: true,
+        };
+        let mut pt = MockPageTable::new();
+        for addr in (0x1000..0x4000).step_by(PAGE_SIZE) {
+            handler.map(&mut pt, addr, &MemoryAttr::default());
+        }
+        let fault_handler = handler.clone();
+        pt.set_handler(Box::new(move |pt, addr| {
+            assert!(fault_handler.handle_page_fault(pt, addr));
+        }));
+
+        // page 1 of the file is mapped at 0x1000, page 2 is beyond the end
+        assert_eq!(pt.read(0x1000), 0);
+        pt.write(0x1001, 1);
+        assert!(!handler.is_past_end(0x1fff));
+        assert!(handler.is_past_end(0x2000));
+        let entry = pt.get_entry(0x1000).unwrap();
+        assert!(entry.present() && entry.dirty());
+        assert_eq!(entry.target(), PAGE_SIZE);
+
+        // sync writes it back once
+        handler.sync(&mut pt, 0x1000, 0x4000);
+        handler.sync(&mut pt, 0x1000, 0x4000);
+        assert!(!pt.get_entry(0x1000).unwrap().dirty());
+
+        // truncation takes it away
+        file.0.lock().0 = PAGE_SIZE;
+        handler.invalidate(&mut pt, 0x1000, 0x4000);
+        assert!(!pt.get_entry(0x1000).unwrap().present());
+        assert!(handler.is_past_end(0x1000));
+        assert_eq!(
+            file.0.lock().1,
+            [
+                ("get", 1, false),
+                ("sync", 1, true),
+                ("sync", 1, false),
+                ("put", 1, false)
+            ]
+        );
+    }
+}
@@ -23,6 +23,22 @@ pub struct MemoryArea {
 }
 
 impl MemoryArea {
+    /// Get the start address of the memory area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    /// Get the end address of the memory area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Get the attributes of the memory area
+    pub fn attr(&self) -> MemoryAttr {
+        self.attr
+    }
+    /// Get the name of the memory area
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
     /// Test whether a virtual address is in the memory area
     pub fn contains(&self, addr: VirtAddr) -> bool {
         addr >= self.start_addr && addr < self.end_addr
@@ -30,6 +46,9 @@ impl MemoryArea {
     /// Check the array is within the readable memory.
     /// Return the size of space covered in the area.
     fn check_read_array<S>(&self, ptr: *const S, count: usize) -> usize {
+        if !self.attr.user {
+            return 0;
+        }
         // page align
         let min_bound = (ptr as usize).max(Page::of_addr(self.start_addr).start_address());
         let max_bound = unsafe { ptr.add(count) as usize }
@@ -65,8 +84,46 @@ impl MemoryArea {
     }
     /// Unmap all pages in the area from page table `pt`
     fn unmap(&self, pt: &mut dyn PageTable) {
+        let swappable = self.handler.swappable();
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.unmap(pt, page.start_address());
+            let addr = page.start_address();
+            if swappable && swap::swapped(pt, addr) {
+                if let Some(space) = swap::swap_space() {
+                    space.swap_free(pt, addr);
+                }
+            }
+            self.handler.unmap(pt, addr);
+        }
+    }
+    /// Clone the page at `addr` of page table `src_pt` to `pt`.
+    /// Swapped out pages share their space in the swap space instead of being read back.
+    /// Return false if that can not be done.
+    fn clone_page(
+        &self,
+        pt: &mut dyn PageTable,
+        src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+    ) -> bool {
+        if self.handler.swappable() && swap::swapped(src_pt, addr) {
+            return swap::swap_space().map_or(false, |space| {
+                space.swap_share(pt, src_pt, addr, &self.attr)
+            });
+        }
+        self.handler.clone_map(pt, src_pt, addr, &self.attr);
+        true
+    }
+    /// Apply the attributes to the mapped pages in the area of page table `pt`,
+    /// keeping shared frames read-only until they are copied on write.
+    /// Pages not present, like those not faulted in yet or swapped out, only get the attributes.
+    fn protect(&self, pt: &mut dyn PageTable) {
+        for page in Page::range_of(self.start_addr, self.end_addr) {
+            if let Some(entry) = pt.get_entry(page.start_address()) {
+                let mut attr = self.attr;
+                if entry.present() && cow::reshare(entry, !attr.readonly) {
+                    attr.readonly = true;
+                }
+                attr.apply(entry);
+            }
         }
     }
 }
@@ -101,6 +158,15 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    pub fn is_execute(&self) -> bool {
+        self.execute
+    }
     /// Apply the attributes to page table entry, then update it.
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut dyn Entry) {
@@ -228,6 +294,26 @@ impl<T: PageTableExt> MemorySet<T> {
         self.areas.insert(idx, area);
     }
 
+    /// Grow the area ending at `end_addr` up to `new_end`
+    ///
+    /// Return false if no area ends there, or the new pages are not free.
+    pub fn extend(&mut self, end_addr: VirtAddr, new_end: VirtAddr) -> bool {
+        let new_end = (new_end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if new_end <= end_addr || !self.test_free_area(end_addr, new_end) {
+            return false;
+        }
+        let area = match self.areas.iter_mut().find(|area| area.end_addr == end_addr) {
+            Some(area) => area,
+            None => return false,
+        };
+        for page in Page::range_of(end_addr, new_end) {
+            area.handler
+                .map(&mut self.page_table, page.start_address(), &area.attr);
+        }
+        area.end_addr = new_end;
+        true
+    }
+
     /// Remove the area `[start_addr, end_addr)` from `MemorySet`
     pub fn pop(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
@@ -328,6 +414,132 @@ impl<T: PageTableExt> MemorySet<T> {
         }
     }
 
+    /// Change the attributes of [`start_addr`, `end_addr`) to `attr`,
+    /// splitting the areas at the boundaries. The areas keep their MMIO type.
+    /// Fail with `InvalidPtr` if part of the range is not in any area,
+    /// or `PermissionDenied` if an area there may not be made writable.
+    pub fn protect(
+        &mut self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        attr: MemoryAttr,
+    ) -> VMResult<()> {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if !self.covers(start_addr, end_addr) {
+            return Err(VMError::InvalidPtr);
+        }
+        if !attr.readonly
+            && self
+                .areas
+                .iter()
+                .any(|area| area.is_overlap_with(start_addr, end_addr) && !area.handler.may_write())
+        {
+            return Err(VMError::PermissionDenied);
+        }
+        self.split(start_addr);
+        self.split(end_addr);
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ..
+        } = self;
+        for area in areas.iter_mut() {
+            if area.start_addr >= start_addr && area.end_addr <= end_addr {
+                area.attr = MemoryAttr {
+                    mmio: area.attr.mmio,
+                    ..attr
+                };
+                area.protect(page_table);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the changes to pages in `start_addr..end_addr` back to the objects backing them.
+    /// Return false if some of the pages are not mapped.
+    pub fn sync(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        for area in areas.iter() {
+            if area.is_overlap_with(start_addr, end_addr) {
+                let start = start_addr.max(area.start_addr);
+                let end = end_addr.min(area.end_addr);
+                area.handler.sync(page_table, start, end);
+            }
+        }
+        self.covers(start_addr, end_addr)
+    }
+
+    /// Write the changes to all pages back to the objects backing them
+    pub fn sync_all(&mut self) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        for area in areas.iter() {
+            area.handler
+                .sync(page_table, area.start_addr, area.end_addr);
+        }
+    }
+
+    /// Unmap pages that are gone from the objects backing them, such as truncated files
+    pub fn invalidate(&mut self) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        for area in areas.iter() {
+            area.handler
+                .invalidate(page_table, area.start_addr, area.end_addr);
+        }
+    }
+
+    /// Whether `addr` is beyond the end of the object backing its area
+    pub fn is_past_end(&self, addr: VirtAddr) -> bool {
+        self.areas
+            .iter()
+            .any(|area| area.contains(addr) && area.handler.is_past_end(addr))
+    }
+
+    /// Whether the areas cover `start_addr..end_addr` without holes
+    fn covers(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        // areas are in order, so they cover the range if there are no holes
+        let mut addr = start_addr;
+        for area in self.areas.iter() {
+            if area.contains(addr) {
+                addr = area.end_addr;
+            }
+        }
+        addr >= end_addr
+    }
+
+    /// Split the area containing `addr` into two at `addr`
+    fn split(&mut self, addr: VirtAddr) {
+        let i = match self
+            .areas
+            .iter()
+            .position(|area| area.start_addr < addr && addr < area.end_addr)
+        {
+            Some(i) => i,
+            None => return,
+        };
+        let area = &mut self.areas[i];
+        let right = MemoryArea {
+            start_addr: addr,
+            end_addr: area.end_addr,
+            attr: area.attr,
+            handler: area.handler.box_clone(),
+            name: area.name,
+        };
+        area.end_addr = addr;
+        self.areas.insert(i + 1, right);
+    }
+
     /// Get iterator of areas
     pub fn iter(&self) -> impl Iterator<Item = &MemoryArea> {
         self.areas.iter()
@@ -377,43 +589,160 @@ impl<T: PageTableExt> MemorySet<T> {
     }
 
     pub fn handle_page_fault_ext(&mut self, addr: VirtAddr, access: handler::AccessType) -> bool {
-        let area = self.areas.iter().find(|area| area.contains(addr));
-        match area {
-            Some(area) => area
-                .handler
-                .handle_page_fault_ext(&mut self.page_table, addr, access),
-            None => false,
-        }
+        self.handle_page_fault_with(addr, |handler, pt| {
+            handler.handle_page_fault_ext(pt, addr, access)
+        })
     }
     pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
-        let area = self.areas.iter().find(|area| area.contains(addr));
-        match area {
-            Some(area) => area.handler.handle_page_fault(&mut self.page_table, addr),
-            None => false,
+        self.handle_page_fault_with(addr, |handler, pt| handler.handle_page_fault(pt, addr))
+    }
+
+    /// Handle page fault on `addr` with `handle`.
+    /// Pages of swappable areas are swapped in before,
+    /// and handed to the swap space if they got a frame.
+    fn handle_page_fault_with(
+        &mut self,
+        addr: VirtAddr,
+        handle: impl FnOnce(&dyn MemoryHandler, &mut dyn PageTable) -> bool,
+    ) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let area = match areas.iter().find(|area| area.contains(addr)) {
+            Some(area) => area,
+            None => return false,
+        };
+        if !area.handler.swappable() {
+            return handle(&*area.handler, page_table);
+        }
+        let page = Page::of_addr(addr).start_address();
+        let was_resident = swap::resident(page_table, page);
+        if swap::swapped(page_table, page)
+            && !swap::swap_space().map_or(false, |space| space.swap_in(page_table, page))
+        {
+            return false;
+        }
+        if !handle(&*area.handler, page_table) {
+            return false;
+        }
+        if !was_resident && swap::resident(page_table, page) {
+            if let Some(space) = swap::swap_space() {
+                space.push(swap::Frame::new(page_table.token(), page));
+            }
+        }
+        true
+    }
+
+    /// Swap out the page at `addr` to `swapper`, if it is in memory, swappable and not shared.
+    /// Return the frame it had.
+    pub fn swap_out(
+        &mut self,
+        addr: VirtAddr,
+        swapper: &mut dyn swap::Swapper,
+    ) -> Result<PhysAddr, swap::SwapError> {
+        if !self.is_swappable(addr) {
+            return Err(swap::SwapError::NotMapped);
+        }
+        swap::swap_out(&mut self.page_table, addr, swapper)
+    }
+
+    /// The state of the page at `addr` for the swap manager,
+    /// clearing its accessed bit if `clear_accessed`
+    pub fn page_state(&mut self, addr: VirtAddr, clear_accessed: bool) -> swap::PageState {
+        if !self.is_swappable(addr) || !swap::resident(&mut self.page_table, addr) {
+            return swap::PageState::Gone;
+        }
+        let entry = self.page_table.get_entry(addr).unwrap();
+        let state = swap::PageState::Resident {
+            accessed: entry.accessed(),
+            dirty: entry.dirty(),
+        };
+        if clear_accessed && entry.accessed() {
+            entry.clear_accessed();
+            entry.update();
         }
+        state
     }
 
-    pub fn clone(&mut self) -> Self {
-        let mut new_page_table = T::new();
+    /// The pages that can be swapped out now
+    pub fn swappable_frames(&mut self) -> Vec<swap::Frame> {
         let Self {
             ref mut page_table,
             ref areas,
-            ..
         } = self;
-        for area in areas.iter() {
+        let token = page_table.token();
+        let mut frames = Vec::new();
+        for area in areas.iter().filter(|area| area.handler.swappable()) {
             for page in Page::range_of(area.start_addr, area.end_addr) {
-                area.handler.clone_map(
-                    &mut new_page_table,
-                    page_table,
-                    page.start_address(),
-                    &area.attr,
-                );
+                if swap::resident(page_table, page.start_address()) {
+                    frames.push(swap::Frame::new(token, page.start_address()));
+                }
             }
         }
-        MemorySet {
-            areas: areas.clone(),
-            page_table: new_page_table,
+        frames
+    }
+
+    /// Swap in all swapped out pages.
+    /// Return false if some of them can not be.
+    pub fn swap_in_all(&mut self) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let space = match swap::swap_space() {
+            Some(space) => space,
+            None => return true,
+        };
+        let mut ok = true;
+        for area in areas.iter().filter(|area| area.handler.swappable()) {
+            for page in Page::range_of(area.start_addr, area.end_addr) {
+                let addr = page.start_address();
+                if swap::swapped(page_table, addr) {
+                    if space.swap_in(page_table, addr) {
+                        space.push(swap::Frame::new(page_table.token(), addr));
+                    } else {
+                        ok = false;
+                    }
+                }
+            }
+        }
+        ok
+    }
+
+    fn is_swappable(&self, addr: VirtAddr) -> bool {
+        self.areas
+            .iter()
+            .any(|area| area.contains(addr) && area.handler.swappable())
+    }
+
+    /// Copy the memory set for a child process.
+    /// Return None if some swapped out pages can not be shared with it.
+    pub fn clone(&mut self) -> Option<Self> {
+        let mut new = MemorySet {
+            areas: Vec::new(),
+            page_table: T::new(),
+        };
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        for area in areas.iter() {
+            for page in Page::range_of(area.start_addr, area.end_addr) {
+                let addr = page.start_address();
+                if !area.clone_page(&mut new.page_table, page_table, addr) {
+                    // the pages cloned so far are not in the areas of the new one yet
+                    let cloned = MemoryArea {
+                        end_addr: addr,
+                        ..area.clone()
+                    };
+                    cloned.unmap(&mut new.page_table);
+                    return None;
+                }
+            }
+            new.areas.push(area.clone());
         }
+        Some(new)
     }
 }
 
@@ -82,23 +82,17 @@ impl Entry for MockEntry {
         self.swapped = value;
     }
     fn user(&self) -> bool {
-        unimplemented!()
-    }
-    fn set_user(&mut self, _value: bool) {
-        unimplemented!()
+        true
     }
+    fn set_user(&mut self, _value: bool) {}
     fn execute(&self) -> bool {
-        unimplemented!()
-    }
-    fn set_execute(&mut self, _value: bool) {
-        unimplemented!()
+        false
     }
+    fn set_execute(&mut self, _value: bool) {}
     fn mmio(&self) -> u8 {
-        unimplemented!()
-    }
-    fn set_mmio(&mut self, _value: u8) {
-        unimplemented!()
+        0
     }
+    fn set_mmio(&mut self, _value: u8) {}
 }
 
 type PageFaultHandler = Box<dyn FnMut(&mut MockPageTable, VirtAddr)>;
@@ -128,6 +122,7 @@ impl PageTable for MockPageTable {
         let data = unsafe { &mut *(&mut self.data as *mut [u8; PAGE_SIZE * PAGE_COUNT]) };
         &mut data[pa..pa + PAGE_SIZE]
     }
+    fn flush_cache_copy_user(&mut self, _start: VirtAddr, _end: VirtAddr, _execute: bool) {}
     fn read(&mut self, addr: usize) -> u8 {
         self._read(addr);
         self.data[self.translate(addr)]
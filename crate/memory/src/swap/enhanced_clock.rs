@@ -1,75 +1,85 @@
 //! Implememnt the swap manager with the enhanced clock page replacement algorithm
+//!
+//! The clock hand looks for a page neither accessed nor dirty, then for a page not
+//! accessed, clearing the accessed bit of the pages it passes, and starts over if both fail.
 
-use alloc::collections::VecDeque;
 use super::*;
-use crate::paging::Entry;
+use alloc::collections::{BTreeSet, VecDeque};
 
 #[derive(Default)]
 pub struct EnhancedClockSwapManager {
     clock_ptr: usize,
-    deque: VecDeque<VirtAddr>,
+    deque: VecDeque<Frame>,
+    set: BTreeSet<Frame>,
 }
 
-// FIXME: It's unusable. But can pass a simple test.
 impl SwapManager for EnhancedClockSwapManager {
-    fn tick(&mut self) {
-    }
+    fn tick(&mut self) {}
 
-    fn push(&mut self, addr: usize) {
-        let pos = if self.clock_ptr == 0 {self.deque.len()} else {self.clock_ptr};
-        self.deque.insert(pos, addr);
+    fn push(&mut self, frame: Frame) {
+        if !self.set.insert(frame) {
+            return;
+        }
+        // just behind the hand, so it is looked at last
+        self.deque.insert(self.clock_ptr, frame);
+        self.clock_ptr += 1;
+        if self.clock_ptr == self.deque.len() {
+            self.clock_ptr = 0;
+        }
     }
 
-    fn remove(&mut self, addr: usize) {
-        let id = self.deque.iter()
-            .position(|&x| x == addr)
-            .expect("address not found");
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        let frame = Frame::new(token, addr);
+        if !self.set.remove(&frame) {
+            return;
+        }
+        let id = self.deque.iter().position(|x| *x == frame).unwrap();
         if id < self.clock_ptr {
             self.clock_ptr -= 1;
         }
         self.deque.remove(id);
+        if self.clock_ptr == self.deque.len() {
+            self.clock_ptr = 0;
+        }
     }
 
-    fn pop<T, S>(&mut self, page_table: &mut T, _swapper: &mut S) -> Option<VirtAddr>
-        where T: PageTable, S: Swapper
-    {
-        loop {
-            let addr = self.deque[self.clock_ptr];
-            // FIXME: Once define `slice`, all modifies of `entry` below will fail.
-            //        Then the loop will never stop.
-            //        The reason may be `get_page_slice_mut()` contains unsafe operation,
-            //        which lead the compiler to do a wrong optimization.
-//            let slice = page_table.get_page_slice_mut(addr);
-            let entry = page_table.get_entry(addr).unwrap();
-//            println!("{:#x} , {}, {}", addr, entry.accessed(), entry.dirty());
-
-            match (entry.accessed(), entry.dirty()) {
-                (true, _) => {
-                    entry.clear_accessed();
-                },
-                (false, true) => {
-                    // FIXME:
-//                    if let Ok(token) = swapper.swap_out(slice) {
-//                        entry.clear_dirty();
-//                    }
-                },
-                _ => {
-                    return self.remove_current();
+    fn pop(&mut self, visit: &mut dyn FnMut(&Frame, bool) -> PageState) -> Option<Frame> {
+        // two rounds of both passes, so that the accessed bits cleared
+        // in the first round are seen in the second one
+        for round in 0..4 {
+            let clear_accessed = round % 2 == 1;
+            for _ in 0..self.deque.len() {
+                if self.deque.is_empty() {
+                    return None;
+                }
+                let frame = self.deque[self.clock_ptr];
+                match visit(&frame, clear_accessed) {
+                    PageState::Resident { accessed, dirty } => {
+                        if !accessed && (clear_accessed || !dirty) {
+                            return Some(self.remove_current());
+                        }
+                    }
+                    PageState::Busy => {}
+                    PageState::Gone => {
+                        self.remove_current();
+                        continue;
+                    }
                 }
+                self.move_next();
             }
-            self.move_next();
         }
+        None
     }
 }
 
-
 impl EnhancedClockSwapManager {
-    fn remove_current(&mut self) -> Option<VirtAddr> {
-        let addr = self.deque.remove(self.clock_ptr);
+    fn remove_current(&mut self) -> Frame {
+        let frame = self.deque.remove(self.clock_ptr).unwrap();
+        self.set.remove(&frame);
         if self.clock_ptr == self.deque.len() {
             self.clock_ptr = 0;
         }
-        return addr;
+        frame
     }
     fn move_next(&mut self) {
         self.clock_ptr += 1;
@@ -81,20 +91,29 @@ impl EnhancedClockSwapManager {
 
 #[cfg(test)]
 mod test {
+    use super::super::test::*;
     use super::*;
-    use swap::test::*;
 
     #[test]
     fn test() {
         use self::MemOp::{R, W};
         let ops = [
-            R(0x1000), R(0x2000), R(0x3000), R(0x4000),
-            R(0x3000), W(0x1000), R(0x4000), W(0x2000), R(0x5000),
-            R(0x2000), W(0x1000), R(0x2000), R(0x3000), R(0x4000)];
-        let pgfault_count = [
-            1, 2, 3, 4,
-            4, 4, 4, 4, 5,
-            5, 5, 5, 6, 7];
+            R(0x1000),
+            R(0x2000),
+            R(0x3000),
+            R(0x4000),
+            R(0x3000),
+            W(0x1000),
+            R(0x4000),
+            W(0x2000),
+            R(0x5000),
+            R(0x2000),
+            W(0x1000),
+            R(0x2000),
+            R(0x3000),
+            R(0x4000),
+        ];
+        let pgfault_count = [1, 2, 3, 4, 4, 4, 4, 4, 5, 5, 5, 5, 6, 7];
         test_manager(EnhancedClockSwapManager::default(), &ops, &pgfault_count);
     }
 }
@@ -12,6 +12,8 @@ const PAGE_SIZE: usize = 4096;
 #[derive(Default)]
 pub struct MockSwapper {
     map: BTreeMap<usize, [u8; PAGE_SIZE]>,
+    /// Number of references to shared locations besides the first one
+    shared: BTreeMap<usize, usize>,
 }
 
 impl Swapper for MockSwapper {
@@ -33,12 +35,27 @@ impl Swapper for MockSwapper {
         Ok(())
     }
     fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
-        match self.map.remove(&token) {
+        match self.map.get(&token) {
             Some(d) => data.copy_from_slice(d.as_ref()),
             None => return Err(()),
         }
+        self.swap_free(token);
         Ok(())
     }
+    fn swap_free(&mut self, token: usize) {
+        match self.shared.get_mut(&token) {
+            Some(1) => {
+                self.shared.remove(&token);
+            }
+            Some(count) => *count -= 1,
+            None => {
+                self.map.remove(&token);
+            }
+        }
+    }
+    fn swap_share(&mut self, token: usize) {
+        *self.shared.entry(token).or_insert(0) += 1;
+    }
 }
 
 impl MockSwapper {
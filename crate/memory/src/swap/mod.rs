@@ -1,28 +1,62 @@
 //! Swap extension for page table
 //! and generic interface for swap manager and swapper
 //!
-//! To use the SwapExt, make a wrapper over the original apge table using swap manager and swapper
-//! Like: SwapExt::new(origin_page_table,swap_manager,swapper)
-//! Invoke page_fault_handler() on the SwapExt to run the swap process
-//! If the method above returns true, a page is swapped in, else do your own things.
+//! Pages of swappable memory areas (see `MemoryHandler::swappable`) are handed to the
+//! `SwapSpace` set by the kernel when they get a frame. When frames run out, the kernel
+//! lets its `SwapManager` pick a victim and writes it to a `Swapper` with `swap_out()`,
+//! which leaves the page table entry not present and marked swapped.
+//! `MemorySet` reads swapped pages back through the `SwapSpace` on page fault,
+//! and frees their space on the device when they are unmapped.
+//! A forked child shares the space of the swapped out pages of its parent,
+//! and each reads them back when it needs them.
 
-use super::addr::Frame;
+use super::memory_set::MemoryAttr;
 use super::paging::*;
 use super::*;
-use core::ops::{Deref, DerefMut};
+use spin::Mutex;
 
-//pub use self::fifo::FifoSwapManager;
-//pub use self::enhanced_clock::EnhancedClockSwapManager;
+pub use self::enhanced_clock::EnhancedClockSwapManager;
+pub use self::fifo::FifoSwapManager;
 
-pub mod fifo;
-//mod enhanced_clock;
-pub mod mock_swapper;
-//#[cfg(test)]
-//mod mock_swapper;
+mod enhanced_clock;
+mod fifo;
+#[cfg(test)]
+mod mock_swapper;
+
+/// A swappable page: the page at virtual address `addr` of the page table with `token`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Frame {
+    token: usize,
+    addr: VirtAddr,
+}
+
+impl Frame {
+    pub fn new(token: usize, addr: VirtAddr) -> Self {
+        Frame { token, addr }
+    }
+
+    pub fn get_token(&self) -> usize {
+        self.token
+    }
+
+    pub fn get_virtaddr(&self) -> VirtAddr {
+        self.addr
+    }
+}
+
+/// The state of a swappable page, as the swap manager sees it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageState {
+    /// In memory, with the accessed and dirty bits of its entry
+    Resident { accessed: bool, dirty: bool },
+    /// Can not be looked at now, try again later
+    Busy,
+    /// Not in memory or not swappable any more, forget about it
+    Gone,
+}
 
 /// Manage all swappable pages, decide which to swap out
 pub trait SwapManager {
-    //type Inactive: InactivePageTable;
     /*
      **  @brief  update intarnal state pre tick
      **          Called when tick interrupt occured
@@ -39,22 +73,20 @@ pub trait SwapManager {
     /*
      **  @brief  update intarnal state when page is removed from memory
      **          Called to delete the addr entry from the swap manager
-     **  @param  token: usize         the inactive page table token for the virtual address
+     **  @param  token: usize         the page table token for the virtual address
      **  @param  addr: VirtAddr       the virual address of the page removed from memory
      **  @retval none
      */
     fn remove(&mut self, token: usize, addr: VirtAddr);
     /*
      **  @brief  select swap out victim when there is need to swap out a page
-     **          (The params is only used by `EnhancedClockSwapManager` currently)
-     **  @param  page_table: &mut T   the current page table
-     **  @param  swapper: &mut S      the swapper used
+     **          The victim is removed from the manager
+     **  @param  visit: &mut dyn FnMut(&Frame, bool) -> PageState
+     **                               get the state of a page,
+     **                               and clear its accessed bit if the second argument is true
      **  @retval Option<Frame>     the Frame of the victim page, if present
      */
-    fn pop<T, S>(&mut self, page_table: &mut T, swapper: &mut S) -> Option<Frame>
-    where
-        T: PageTable,
-        S: Swapper;
+    fn pop(&mut self, visit: &mut dyn FnMut(&Frame, bool) -> PageState) -> Option<Frame>;
 }
 
 /// Implement swap in & out execution
@@ -79,331 +111,233 @@ pub trait Swapper {
      **  @retval Result<(), ()>       the execute result
      */
     fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()>;
-}
-
-/// Wrapper for page table, supporting swap functions
-pub struct SwapExt<T: PageTable, M: SwapManager, S: Swapper> {
-    page_table: T,
-    swap_manager: M,
-    swapper: S,
-}
-
-impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
-    /*
-     **  @brief  create a swap extension
-     **  @param  page_table: T        the inner page table
-     **  @param  swap_manager: M      the SwapManager used
-     **  @param  swapper: S           the Swapper used
-     **  @retval SwapExt              the swap extension created
-     */
-    pub fn new(page_table: T, swap_manager: M, swapper: S) -> Self {
-        SwapExt {
-            page_table,
-            swap_manager,
-            swapper,
-        }
-    }
-
-    /*
-     **  @brief set a page swappable
-     **  @param pt: *mut T2           the raw pointer for the target page's inactive page table
-     **  @param addr: VirtAddr        the target page's virtual address
-     */
-    pub unsafe fn set_swappable<T2: InactivePageTable>(&mut self, pt: *mut T2, addr: VirtAddr) {
-        let Self {
-            ref mut page_table,
-            ref mut swap_manager,
-            ..
-        } = self;
-        let targetpt = &mut *(pt);
-        let pttoken = {
-            info!(
-                "SET_SWAPPABLE: the target page table token is {:x?}, addr is {:x?}",
-                targetpt.token(),
-                addr
-            );
-            targetpt.token()
-        };
-        targetpt.with(|| {
-            let entry = page_table
-                .get_entry(addr)
-                .expect("failed to get page entry when set swappable");
-            if entry.present() {
-                let frame = Frame::new(pt as usize, addr, pttoken);
-                swap_manager.push(frame);
-            }
-        });
-        /*
-        let token = unsafe{
-            (*pt).token()
-        };
-        let frame = Frame::new(pt as usize, addr, token);
-        self.swap_manager.push(frame);
-        */
-    }
-
     /*
-     **  @brief remove a page (given virtual address) from swappable pages, if the page is swapped, swap in at first
-     **  @param pt: *mut T2           the raw pointer for the target page's inactive page table
-     **  @param addr: VirtAddr        the target page's virtual address
-     **  @param alloc_frame:          the function to alloc a free physical frame for once
-     */
-    pub unsafe fn remove_from_swappable<T2: InactivePageTable>(
-        &mut self,
-        pt: *mut T2,
-        addr: VirtAddr,
-        alloc_frame: impl FnOnce() -> PhysAddr,
-    ) {
-        //info!("come into remove_from swappable");
-        let Self {
-            ref mut page_table,
-            ref mut swap_manager,
-            ref mut swapper,
-        } = self;
-        let targetpt = &mut *(pt);
-        let pttoken = {
-            info!(
-                "SET_UNSWAPPABLE: the target page table token is {:x?}, addr is {:x?}",
-                targetpt.token(),
-                addr
-            );
-            targetpt.token()
-        };
-        //info!("try to change pagetable");
-        targetpt.with(|| {
-            let token = {
-                let entry = page_table.get_entry(addr).unwrap();
-                if !entry.swapped() {
-                    if entry.present() {
-                        // if the addr isn't indicating a swapped page, panic occured here
-                        swap_manager.remove(pttoken, addr);
-                    }
-                    return;
-                }
-                let token = entry.target() / PAGE_SIZE;
-                let frame = alloc_frame();
-                entry.set_target(frame);
-                entry.set_swapped(false);
-                entry.set_present(true);
-                entry.update();
-                token
-            };
-            info!("swap in vaddr {:x?} at remove from swappable.", addr);
-            let data = page_table.get_page_slice_mut(addr);
-            swapper.swap_in(token, data).unwrap();
-        });
-        trace!("come out of femove_from swappable");
-    }
-
-    /*
-     **  @brief  map the virtual address to a target physics address as swappable
-     **  @param  addr: VirtAddr       the virual address to map
-     **  @param  target: VirtAddr     the target physics address
+     **  @brief  Deallocate the space on device without reading it.
+     **  @param  token: usize         the token indicating the location on the device
      **  @retval none
      */
+    fn swap_free(&mut self, token: usize);
     /*
-    pub fn map_to_swappable(&mut self, addr: VirtAddr, target: PhysAddr) -> &mut T::Entry {
-        self.swap_manager.push(addr);
-        self.map(addr, target)
-    }*/
-
-    /*
-     **  @brief  Swap out any one of the swapped pages
-     **  @retval Result<PhysAddr, SwapError>
-     **                               the physics address of released frame if success,
-     **                               the error if failed
-     */
-    pub fn swap_out_any<T2: InactivePageTable>(&mut self) -> Result<PhysAddr, SwapError> {
-        info!("COME in to swap_out_any");
-        let victim: Option<Frame> = {
-            let Self {
-                ref mut page_table,
-                ref mut swap_manager,
-                ref mut swapper,
-            } = self;
-            swap_manager.pop(page_table, swapper)
-        };
-        info!("swap out page {}", victim.unwrap().get_virtaddr());
-        match victim {
-            None => Err(SwapError::NoSwapped),
-            Some(frame) => self.swap_out::<T2>(&frame),
-        }
-    }
-
-    /*
-     **  @brief  Swap out page
-     **  @param  frame: Frame       the Frame of page recording the page info
-     **  @retval Result<PhysAddr, SwapError>
-     **                               the physics address of the original map target frame if success,
-     **                               the error if failed
-     */
-    fn swap_out<T2: InactivePageTable>(&mut self, frame: &Frame) -> Result<PhysAddr, SwapError> {
-        let Self {
-            ref mut page_table,
-            ref mut swapper,
-            ..
-        } = self;
-        let ret = unsafe {
-            let pt = &mut *(frame.get_page_table() as *mut T2);
-            pt.with(|| {
-                //use core::slice;
-                //let data = unsafe { slice::from_raw_parts_mut((frame.virtaddr & !(PAGE_SIZE - 1)) as *mut u8, PAGE_SIZE) };
-                let data = page_table.get_page_slice_mut(frame.get_virtaddr());
-                let entry = page_table
-                    .get_entry(frame.get_virtaddr())
-                    .ok_or(SwapError::NotMapped)?;
-                if entry.swapped() {
-                    return Err(SwapError::AlreadySwapped);
-                }
-                //assert!(!entry.swapped(), "Page already swapped!");
-                let token = swapper.swap_out(data).map_err(|_| SwapError::IOError)?;
-                //let token = swapper.swap_out(data).unwrap();
-                let target = entry.target();
-                entry.set_target(token * PAGE_SIZE);
-                entry.set_swapped(true);
-                entry.set_present(false);
-                entry.update();
-                Ok(target)
-            })
-        };
-        ret
-    }
-    /*
-     **  @brief  map the virtual address to a target physics address and then swap in page data, noted that the page should be in the current page table
-     **  @param pt: *mut T2           the raw pointer for the swapping page's inactive page table
-     **  @param  addr: VirtAddr       the virual address of beginning of page
-     **  @param  target: PhysAddr       the target physics address
-     **  @retval Result<()), SwapError>
-     **                               the execute result, and the error if failed
-     */
-    fn swap_in<T2: InactivePageTable>(
-        &mut self,
-        pt: *mut T2,
-        addr: VirtAddr,
-        target: PhysAddr,
-    ) -> Result<(), SwapError> {
-        info!("come in to swap in");
-        let entry = self
-            .page_table
-            .get_entry(addr)
-            .ok_or(SwapError::NotMapped)?;
-        if !entry.swapped() {
-            return Err(SwapError::NotSwapped);
-        }
-        let token = entry.target() / PAGE_SIZE;
-        entry.set_target(target);
-        entry.set_swapped(false);
-        entry.set_present(true);
-        entry.update();
-        let data = self.page_table.get_page_slice_mut(addr);
-        self.swapper
-            .swap_in(token, data)
-            .map_err(|_| SwapError::IOError)?;
-        let pttoken = unsafe { (*pt).token() };
-        let frame = Frame::new(pt as usize, addr, pttoken);
-;
-        self.swap_manager.push(frame);
-        Ok(())
-    }
-    /*
-     **  @brief  execute the frame delayed allocate and  swap process for page fault
-     **          This function must be called whenever PageFault happens.
-     **  @param  pt: *mut T2          the raw pointer for the target page's inactive page table (exactly the current page table)
-     **  @param  addr: VirtAddr       the virual address of the page fault
-     **  @param  swapin: bool         whether to set the page swappable if delayed allocate a frame for a page
-     **  @param  alloc_frame: impl FnOnce() -> PhysAddr
-     **                               the page allocation function
-     **                               that allocate a page and returns physics address
-     **                               of beginning of the page
-     **  @retval bool                 whether swap in happens.
+     **  @brief  Add a reference to the space on device.
+     **          The space is deallocated when all the references are swapped in or freed.
+     **  @param  token: usize         the token indicating the location on the device
+     **  @retval none
      */
-    pub fn page_fault_handler<T2: InactivePageTable>(
-        &mut self,
-        pt: *mut T2,
-        addr: VirtAddr,
-        swapin: bool,
-        alloc_frame: impl FnOnce() -> PhysAddr,
-    ) -> bool {
-        // handle page delayed allocating
-        {
-            info!("try handling delayed frame allocator");
-            let need_alloc = {
-                let entry = self.page_table.get_entry(addr).expect("fail to get entry");
-                //info!("got entry!");
-                !entry.present() && !entry.swapped()
-            };
-            info!("need_alloc got");
-            if need_alloc {
-                info!("need_alloc!");
-                let frame = alloc_frame();
-                {
-                    let entry = self.page_table.get_entry(addr).unwrap();
-                    entry.set_target(frame);
-                    //let new_entry = self.page_table.map(addr, frame);
-                    entry.set_present(true);
-                    entry.update();
-                }
-                if swapin {
-                    unsafe {
-                        self.set_swappable(pt, addr & 0xfffff000);
-                    }
-                }
-                //area.get_flags().apply(new_entry); this instruction may be used when hide attr is used
-                info!("allocated successfully");
-                return true;
-            }
-            info!("not need alloc!");
-        }
-        // now we didn't attach the cow so the present will be false when swapped(), to enable the cow some changes will be needed
-        match self.page_table.get_entry(addr) {
-            // infact the get_entry(addr) should not be None here
-            None => return false,
-            Some(entry) => {
-                if !entry.swapped() {
-                    return false;
-                }
-            }
-        }
-        // Allocate a frame, if failed, swap out a page
-        let frame = alloc_frame();
-        self.swap_in(pt, addr, frame).ok().unwrap();
-        true
-    }
+    fn swap_share(&mut self, token: usize);
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SwapError {
     /// attempt to swap out a page that is already swapped out
     AlreadySwapped,
-    ///
+    /// attempt to swap a page that is not mapped or not swappable
     NotMapped,
     /// attempt to swap in a page that is already in the memory
     NotSwapped,
+    /// attempt to swap out a frame shared with other page tables
+    Shared,
     /// there are no page to be swapped out
     NoSwapped,
     /// swap failed due to IO error while interact with device
     IOError,
 }
 
-impl<T: PageTable, M: SwapManager, S: Swapper> Deref for SwapExt<T, M, S> {
-    type Target = T;
+/// The swap space of the kernel, used by all memory sets
+pub trait SwapSpace: Sync {
+    /// Remember that the page `frame` got a frame and may be swapped out
+    fn push(&self, frame: Frame);
+    /// Read the swapped out page at `addr` of `pt` back into a new frame.
+    /// Return false if it can not be done.
+    fn swap_in(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool;
+    /// Free the space of the swapped out page at `addr` of `pt`
+    fn swap_free(&self, pt: &mut dyn PageTable, addr: VirtAddr);
+    /// Map `addr` in `pt` with `attr` to the space of the swapped out page at `addr` of `src_pt`.
+    /// Return false if it can not be done.
+    fn swap_share(
+        &self,
+        pt: &mut dyn PageTable,
+        src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) -> bool;
+}
+
+static SWAP_SPACE: Mutex<Option<&'static dyn SwapSpace>> = Mutex::new(None);
+
+/// Set the swap space used by memory sets for their swappable areas
+pub fn set_swap_space(space: &'static dyn SwapSpace) {
+    *SWAP_SPACE.lock() = Some(space);
+}
+
+/// The swap space, if the kernel has set one
+pub fn swap_space() -> Option<&'static dyn SwapSpace> {
+    *SWAP_SPACE.lock()
+}
+
+/// Whether the page at `addr` of `pt` is swapped out
+///
+/// Only entries not present are swapped out, as some architectures mark
+/// swapped out entries with the bits present entries use for sharing.
+pub fn swapped(pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+    pt.get_entry(addr)
+        .map_or(false, |entry| !entry.present() && entry.swapped())
+}
+
+/// Whether the page at `addr` of `pt` is in memory and owns its frame
+pub fn resident(pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+    pt.get_entry(addr).map_or(false, |entry| {
+        entry.present() && !entry.readonly_shared() && !entry.writable_shared()
+    })
+}
+
+/*
+ **  @brief  Swap out page
+ **  @param  pt: &mut dyn PageTable   the page table of the page
+ **  @param  addr: VirtAddr           the virtual address of the page
+ **  @param  swapper: &mut dyn Swapper the swapper to write the page to
+ **  @retval Result<PhysAddr, SwapError>
+ **                               the physics address of the original map target frame if success,
+ **                               the error if failed
+ */
+pub fn swap_out(
+    pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    swapper: &mut dyn Swapper,
+) -> Result<PhysAddr, SwapError> {
+    {
+        let entry = pt.get_entry(addr).ok_or(SwapError::NotMapped)?;
+        if !entry.present() && entry.swapped() {
+            return Err(SwapError::AlreadySwapped);
+        }
+        if !entry.present() {
+            return Err(SwapError::NotMapped);
+        }
+        if entry.readonly_shared() || entry.writable_shared() {
+            return Err(SwapError::Shared);
+        }
+    }
+    let data = pt.get_page_slice_mut(addr);
+    // unmap before writing, so that no one changes the page behind our back
+    let entry = pt.get_entry(addr).unwrap();
+    let target = entry.target();
+    entry.set_present(false);
+    entry.update();
+    match swapper.swap_out(data) {
+        Ok(token) => {
+            entry.set_target(token * PAGE_SIZE);
+            entry.set_swapped(true);
+            entry.update();
+            Ok(target)
+        }
+        Err(()) => {
+            entry.set_present(true);
+            entry.update();
+            Err(SwapError::IOError)
+        }
+    }
+}
 
-    fn deref(&self) -> &<Self as Deref>::Target {
-        &self.page_table
+/*
+ **  @brief  map the page to a target physics address and then swap in page data
+ **  @param  pt: &mut dyn PageTable   the page table of the page
+ **  @param  addr: VirtAddr           the virual address of beginning of page
+ **  @param  target: PhysAddr         the target physics address
+ **  @param  swapper: &mut dyn Swapper the swapper to read the page from
+ **  @retval Result<(), SwapError>
+ **                               the execute result, and the error if failed
+ */
+pub fn swap_in(
+    pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    target: PhysAddr,
+    swapper: &mut dyn Swapper,
+) -> Result<(), SwapError> {
+    let token = {
+        let entry = pt.get_entry(addr).ok_or(SwapError::NotMapped)?;
+        if entry.present() || !entry.swapped() {
+            return Err(SwapError::NotSwapped);
+        }
+        let token = entry.target() / PAGE_SIZE;
+        // map it for a moment only to get at the frame
+        entry.set_target(target);
+        entry.set_present(true);
+        token
+    };
+    let data = pt.get_page_slice_mut(addr);
+    let entry = pt.get_entry(addr).unwrap();
+    entry.set_present(false);
+    entry.update();
+    if swapper.swap_in(token, data).is_err() {
+        entry.set_target(token * PAGE_SIZE);
+        return Err(SwapError::IOError);
     }
+    entry.set_swapped(false);
+    entry.set_present(true);
+    entry.update();
+    Ok(())
 }
 
-impl<T: PageTable, M: SwapManager, S: Swapper> DerefMut for SwapExt<T, M, S> {
-    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target {
-        &mut self.page_table
+/*
+ **  @brief  free the space of a swapped out page on the device, leaving it unmapped
+ **  @param  pt: &mut dyn PageTable   the page table of the page
+ **  @param  addr: VirtAddr           the virual address of beginning of page
+ **  @param  swapper: &mut dyn Swapper the swapper holding the page
+ **  @retval Result<(), SwapError>
+ **                               the execute result, and the error if failed
+ */
+pub fn swap_free(
+    pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    swapper: &mut dyn Swapper,
+) -> Result<(), SwapError> {
+    let entry = pt.get_entry(addr).ok_or(SwapError::NotMapped)?;
+    if entry.present() || !entry.swapped() {
+        return Err(SwapError::NotSwapped);
     }
+    swapper.swap_free(entry.target() / PAGE_SIZE);
+    entry.set_target(0);
+    entry.set_swapped(false);
+    entry.update();
+    Ok(())
 }
 
 /*
+ **  @brief  map a page to the space of a swapped out page, shared by both until swapped in
+ **  @param  pt: &mut dyn PageTable   the page table to map the page in
+ **  @param  src_pt: &mut dyn PageTable
+ **                               the page table of the swapped out page
+ **  @param  addr: VirtAddr           the virual address of beginning of page
+ **  @param  attr: &MemoryAttr        the attributes of the page
+ **  @param  swapper: &mut dyn Swapper the swapper holding the page
+ **  @retval Result<(), SwapError>
+ **                               the execute result, and the error if failed
+ */
+pub fn swap_share(
+    pt: &mut dyn PageTable,
+    src_pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    attr: &MemoryAttr,
+    swapper: &mut dyn Swapper,
+) -> Result<(), SwapError> {
+    let entry = src_pt.get_entry(addr).ok_or(SwapError::NotMapped)?;
+    if entry.present() || !entry.swapped() {
+        return Err(SwapError::NotSwapped);
+    }
+    let token = entry.target() / PAGE_SIZE;
+    swapper.swap_share(token);
+    let entry = pt.map(addr, token * PAGE_SIZE);
+    entry.set_present(false);
+    attr.apply(entry);
+    entry.set_swapped(true);
+    entry.update();
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use super::*;
     use super::mock_swapper::MockSwapper;
-    use alloc::{sync::Arc, boxed::Box};
+    use super::*;
+    use alloc::{boxed::Box, sync::Arc};
     use core::cell::RefCell;
     use paging::MockPageTable;
 
@@ -425,41 +359,95 @@ mod test {
         }
     }
 
-    unsafe fn clone<'a, 'b, T>(x: &'a mut T) -> &'b mut T {
-        &mut *(x as *mut T)
-    }
-
     /// Test framework with different SwapManagers.
     /// See `fifo::test` mod for example.
-    pub fn test_manager(swap_manager: impl 'static + SwapManager, ops: &[MemOp], pgfault_count: &[u8]) {
+    pub fn test_manager(
+        swap_manager: impl 'static + SwapManager,
+        ops: &[MemOp],
+        pgfault_count: &[u8],
+    ) {
         use self::MemOp::{R, W};
         let page_fault_count = Arc::new(RefCell::new(0u8));
 
-        let mut pt = SwapExt::new(MockPageTable::new(), swap_manager, MockSwapper::default());
-
-        // Move to closure
-        let pt0 = unsafe{ clone(&mut pt) };
-        let page_fault_count1 = page_fault_count.clone();
+        let mut pt = MockPageTable::new();
+        let mut manager = swap_manager;
+        let mut swapper = MockSwapper::default();
         let mut alloc = FrameAlloc(4);
 
-        pt.set_handler(Box::new(move |_, addr: VirtAddr| {
+        let page_fault_count1 = page_fault_count.clone();
+        pt.set_handler(Box::new(move |pt, addr: VirtAddr| {
             *page_fault_count1.borrow_mut() += 1;
-            if pt0.page_fault_handler(addr, || alloc.alloc()) {
-                return;
-            }
-            // The page is not mapped, map it to a new frame, if no more frame, swap out.
-            let target = alloc.alloc().or_else(|| pt0.swap_out_any().ok())
+            let addr = addr & !(PAGE_SIZE - 1);
+            // if no more frame, swap out.
+            let target = alloc
+                .alloc()
+                .or_else(|| {
+                    let victim = manager.pop(&mut |frame, clear_accessed| {
+                        let entry = pt.get_entry(frame.get_virtaddr()).unwrap();
+                        let state = PageState::Resident {
+                            accessed: entry.accessed(),
+                            dirty: entry.dirty(),
+                        };
+                        if clear_accessed {
+                            entry.clear_accessed();
+                        }
+                        state
+                    })?;
+                    swap_out(pt, victim.get_virtaddr(), &mut swapper).ok()
+                })
                 .expect("no more frame in both allocator and swap_manager");
-            pt0.map_to_swappable(addr, target);
+            if swapped(pt, addr) {
+                swap_in(pt, addr, target, &mut swapper).unwrap();
+            } else {
+                pt.map(addr, target);
+            }
+            manager.push(Frame::new(0, addr));
         }));
 
         for (op, &count) in ops.iter().zip(pgfault_count.iter()) {
             match op {
-                R(addr) => { pt.read(*addr); }
-                W(addr) => pt.write(*addr, 0),
+                R(addr) => {
+                    pt.read(*addr);
+                }
+                W(addr) => pt.write(*addr, (*addr / PAGE_SIZE) as u8),
             }
             assert_eq!(*(*page_fault_count).borrow(), count);
         }
+        // every page keeps its data through swapping
+        for op in ops.iter() {
+            if let W(addr) = op {
+                assert_eq!(pt.read(*addr), (*addr / PAGE_SIZE) as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_share_space() {
+        let mut swapper = MockSwapper::default();
+        let mut pt = MockPageTable::new();
+        let mut child_pt = MockPageTable::new();
+        pt.map(0x1000, 0x1000);
+        pt.write(0x1000, 42);
+        swap_out(&mut pt, 0x1000, &mut swapper).unwrap();
+        let token = pt.get_entry(0x1000).unwrap().target() / PAGE_SIZE;
+        swap_share(
+            &mut child_pt,
+            &mut pt,
+            0x1000,
+            &MemoryAttr::default(),
+            &mut swapper,
+        )
+        .unwrap();
+        assert!(swapped(&mut child_pt, 0x1000));
+
+        // each reads its own copy back
+        swap_in(&mut child_pt, 0x1000, 0x2000, &mut swapper).unwrap();
+        assert_eq!(child_pt.read(0x1000), 42);
+        swap_in(&mut pt, 0x1000, 0x3000, &mut swapper).unwrap();
+        assert_eq!(pt.read(0x1000), 42);
+
+        // and the space is freed after the last one
+        let mut data = [0u8; PAGE_SIZE];
+        assert_eq!(swapper.swap_in(token, &mut data), Err(()));
     }
 }
-*/
@@ -3,6 +3,7 @@
 pub use self::handler::*;
 use crate::arch::board::timer::is_pending;
 use crate::process::thread::Thread;
+use crate::signal::*;
 use aarch64::regs::*;
 use alloc::sync::Arc;
 use trapframe::UserContext;
@@ -78,3 +79,36 @@ pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
     false
 }
+
+/// Signal, si_code and si_addr for an exception raised by user
+pub fn get_fault_signal(trap: usize, tf: &UserContext) -> (Signal, i32, usize) {
+    use self::syndrome::{Fault, Syndrome};
+    if trap != 0x2 {
+        // SError or FIQ from lower el
+        return (Signal::SIGBUS, BUS_OBJERR, 0);
+    }
+    let addr = FAR_EL1.get() as usize;
+    let pc = tf.elr;
+    match Syndrome::from(ESR_EL1.get() as u32) {
+        Syndrome::DataAbort { kind, level: _ } | Syndrome::InstructionAbort { kind, level: _ } => {
+            match kind {
+                Fault::Alignment => (Signal::SIGBUS, BUS_ADRALN, addr),
+                Fault::Permission => (Signal::SIGSEGV, SEGV_ACCERR, addr),
+                Fault::AddressSize | Fault::Translation | Fault::AccessFlag => {
+                    (Signal::SIGSEGV, SEGV_MAPERR, addr)
+                }
+                _ => (Signal::SIGBUS, BUS_OBJERR, addr),
+            }
+        }
+        Syndrome::PCAlignmentFault => (Signal::SIGBUS, BUS_ADRALN, pc),
+        Syndrome::SpAlignmentFault => (Signal::SIGBUS, BUS_ADRALN, tf.sp),
+        Syndrome::Breakpoint | Syndrome::Brk(_) | Syndrome::Watchpoint => {
+            (Signal::SIGTRAP, TRAP_BRKPT, pc)
+        }
+        Syndrome::Step => (Signal::SIGTRAP, TRAP_TRACE, pc),
+        Syndrome::SimdFp | Syndrome::TrappedFpu => (Signal::SIGILL, ILL_COPROC, pc),
+        Syndrome::MsrMrsSystem => (Signal::SIGILL, ILL_PRVOPC, pc),
+        Syndrome::Unknown | Syndrome::IllegalExecutionState => (Signal::SIGILL, ILL_ILLOPC, pc),
+        _ => (Signal::SIGSEGV, SI_KERNEL, 0),
+    }
+}
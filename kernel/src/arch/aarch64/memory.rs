@@ -2,11 +2,14 @@
 
 use super::paging::MMIOType;
 use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET};
-use crate::memory::{init_heap, kernel_offset, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR};
+use crate::memory::{
+    init_heap, kernel_offset, Linear, MemoryAttr, MemorySet, FRAMES_TOTAL, FRAME_ALLOCATOR,
+};
 use crate::sync::SpinNoIrqLock as Mutex;
 use aarch64::paging::frame::PhysFrame as Frame;
 use aarch64::regs::*;
 use aarch64::translation::{local_invalidate_tlb_all, ttbr_el1_write};
+use core::sync::atomic::Ordering;
 use log::*;
 use rcore_memory::PAGE_SIZE;
 
@@ -35,7 +38,9 @@ fn init_frame_allocator() {
         .1;
     let start = kernel_offset(_end as usize) + MEMORY_OFFSET + PAGE_SIZE;
     let mut ba = FRAME_ALLOCATOR.lock();
-    ba.insert(to_range(start, end));
+    let range = to_range(start, end);
+    FRAMES_TOTAL.fetch_add(range.len(), Ordering::Relaxed);
+    ba.insert(range);
     info!("FrameAllocator init end");
 
     /// Transform memory area `[start, end)` to integer range for `FrameAllocator`
@@ -38,6 +38,7 @@ pub extern "C" fn main_start() -> ! {
 
     board::early_final();
     crate::lkm::manager::ModuleManager::init();
+    crate::drivers::net::loopback::init();
     board::init();
 
     crate::process::init();
@@ -1,6 +1,7 @@
 use crate::arch::paging::get_root_page_table_ptr;
 use crate::drivers::IRQ_MANAGER;
 use crate::process::thread::Thread;
+use crate::signal::*;
 use alloc::sync::Arc;
 use log::*;
 use mips::addr::*;
@@ -205,6 +206,31 @@ pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
     false
 }
 
+/// Signal, si_code and si_addr for an exception raised by user
+pub fn get_fault_signal(trap: usize, tf: &UserContext) -> (Signal, i32, usize) {
+    let bad_vaddr = cp0::bad_vaddr::read_u32() as usize;
+    // ExcCode field of cause
+    match (trap >> 2) & 0x1f {
+        // AdEL, AdES
+        4 | 5 => (Signal::SIGBUS, BUS_ADRALN, bad_vaddr),
+        // IBE, DBE
+        6 | 7 => (Signal::SIGBUS, BUS_OBJERR, bad_vaddr),
+        // Bp
+        9 => (Signal::SIGTRAP, TRAP_BRKPT, tf.epc),
+        // RI
+        10 => (Signal::SIGILL, ILL_ILLOPC, tf.epc),
+        // CpU
+        11 => (Signal::SIGILL, ILL_COPROC, tf.epc),
+        // Ov
+        12 => (Signal::SIGFPE, FPE_INTOVF, tf.epc),
+        // Tr
+        13 => (Signal::SIGTRAP, TRAP_BRKPT, tf.epc),
+        // FPE
+        15 => (Signal::SIGFPE, FPE_FLTINV, tf.epc),
+        _ => (Signal::SIGSEGV, SI_KERNEL, 0),
+    }
+}
+
 pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
     let virt_addr = VirtAddr::new(addr);
     let root_table = unsafe { &mut *(get_root_page_table_ptr() as *mut MIPSPageTable) };
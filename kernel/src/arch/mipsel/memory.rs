@@ -1,6 +1,7 @@
 use crate::arch::paging::*;
 use crate::consts::{KERNEL_OFFSET, MEMORY_END, MEMORY_OFFSET};
-use crate::memory::{init_heap, FRAME_ALLOCATOR};
+use crate::memory::{init_heap, FRAMES_TOTAL, FRAME_ALLOCATOR};
+use core::sync::atomic::Ordering;
 use mips::registers::cp0;
 use rcore_memory::PAGE_SIZE;
 
@@ -31,6 +32,7 @@ fn init_frame_allocator() {
         (end as usize) - KERNEL_OFFSET + MEMORY_OFFSET + PAGE_SIZE,
         MEMORY_END,
     );
+    FRAMES_TOTAL.fetch_add(range.len(), Ordering::Relaxed);
     ba.insert(range);
 
     info!("frame allocator: init end");
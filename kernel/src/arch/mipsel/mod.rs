@@ -41,6 +41,7 @@ pub extern "C" fn rust_main() -> ! {
     interrupt::init();
     memory::init();
     timer::init();
+    crate::drivers::net::loopback::init();
     board::init(dtb_start);
 
     info!("Hello MIPS 32 from CPU {}, dtb @ {:#x}", cpu_id, dtb_start);
@@ -1,6 +1,7 @@
 use crate::arch::interrupt::consts::SupervisorExternal;
 use crate::drivers::IRQ_MANAGER;
 use crate::process::thread::Thread;
+use crate::signal::*;
 use alloc::sync::Arc;
 use log::*;
 use riscv::register::*;
@@ -135,3 +136,23 @@ pub fn handle_user_page_fault_ext(thread: &Arc<Thread>, addr: usize, access: Acc
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
     false
 }
+
+/// Signal, si_code and si_addr for an exception raised by user
+pub fn get_fault_signal(trap: usize, tf: &UserContext) -> (Signal, i32, usize) {
+    let addr = stval::read();
+    match trap {
+        // instruction address misaligned
+        0 => (Signal::SIGBUS, BUS_ADRALN, addr),
+        // instruction access fault
+        1 => (Signal::SIGSEGV, SEGV_ACCERR, addr),
+        // illegal instruction
+        2 => (Signal::SIGILL, ILL_ILLOPC, tf.sepc),
+        // breakpoint
+        3 => (Signal::SIGTRAP, TRAP_BRKPT, tf.sepc),
+        // load / store address misaligned
+        4 | 6 => (Signal::SIGBUS, BUS_ADRALN, addr),
+        // load / store access fault
+        5 | 7 => (Signal::SIGSEGV, SEGV_ACCERR, addr),
+        _ => (Signal::SIGSEGV, SI_KERNEL, 0),
+    }
+}
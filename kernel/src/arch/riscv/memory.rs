@@ -1,6 +1,7 @@
 use crate::consts::{KERNEL_OFFSET, MEMORY_END, MEMORY_OFFSET};
-use crate::memory::{init_heap, MemorySet, FRAME_ALLOCATOR};
+use crate::memory::{init_heap, MemorySet, FRAMES_TOTAL, FRAME_ALLOCATOR};
 use core::mem;
+use core::sync::atomic::Ordering;
 use log::*;
 use rcore_memory::PAGE_SIZE;
 use riscv::asm::sfence_vma_all;
@@ -35,6 +36,7 @@ fn init_frame_allocator() {
         (end as usize) - KERNEL_OFFSET + MEMORY_OFFSET + PAGE_SIZE,
         MEMORY_END,
     );
+    FRAMES_TOTAL.fetch_add(range.len(), Ordering::Relaxed);
     ba.insert(range);
 
     info!("frame allocator: init end");
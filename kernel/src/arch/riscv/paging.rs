@@ -116,10 +116,10 @@ impl Entry for PageEntry {
         self.0.set(frame, flags);
     }
     fn writable_shared(&self) -> bool {
-        self.0.flags().contains(EF::RESERVED1)
+        self.0.flags() & (EF::RESERVED1 | EF::RESERVED2) == EF::RESERVED1
     }
     fn readonly_shared(&self) -> bool {
-        self.0.flags().contains(EF::RESERVED2)
+        self.0.flags() & (EF::RESERVED1 | EF::RESERVED2) == EF::RESERVED2
     }
     fn set_shared(&mut self, writable: bool) {
         let flags = self.0.flags_mut();
@@ -129,11 +129,12 @@ impl Entry for PageEntry {
     fn clear_shared(&mut self) {
         self.0.flags_mut().remove(EF::RESERVED1 | EF::RESERVED2);
     }
+    // both software bits, which sharing never sets together
     fn swapped(&self) -> bool {
-        self.0.flags().contains(EF::RESERVED1)
+        self.0.flags().contains(EF::RESERVED1 | EF::RESERVED2)
     }
     fn set_swapped(&mut self, value: bool) {
-        self.0.flags_mut().set(EF::RESERVED1, value);
+        self.0.flags_mut().set(EF::RESERVED1 | EF::RESERVED2, value);
     }
     fn user(&self) -> bool {
         self.0.flags().contains(EF::USER)
@@ -52,6 +52,8 @@ pub fn init() {
             // enable fpu
             cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
             cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            // fault on kernel writes to copy-on-write user pages
+            cr0.insert(Cr0Flags::WRITE_PROTECT);
         });
     }
 }
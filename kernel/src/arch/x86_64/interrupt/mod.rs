@@ -4,6 +4,7 @@ mod handler;
 pub use self::handler::*;
 use crate::memory::phys_to_virt;
 use crate::process::thread::Thread;
+use crate::signal::*;
 use alloc::sync::Arc;
 use apic::*;
 use trapframe::{TrapFrame, UserContext};
@@ -70,3 +71,19 @@ pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
     false
 }
+
+/// Signal, si_code and si_addr for an exception raised by user
+pub fn get_fault_signal(trap: usize, tf: &UserContext) -> (Signal, i32, usize) {
+    use self::consts::*;
+    let pc = tf.general.rip;
+    match trap {
+        DivideError => (Signal::SIGFPE, FPE_INTDIV, pc),
+        Debug | Breakpoint => (Signal::SIGTRAP, TRAP_BRKPT, pc),
+        InvalidOpcode => (Signal::SIGILL, ILL_ILLOPN, pc),
+        DeviceNotAvailable => (Signal::SIGILL, ILL_COPROC, pc),
+        FloatingPointException | SIMDFloatingPointException => (Signal::SIGFPE, FPE_FLTINV, pc),
+        AlignmentCheck => (Signal::SIGBUS, BUS_ADRALN, 0),
+        // GeneralProtectionFault, SegmentNotPresent, StackSegmentFault and others
+        _ => (Signal::SIGSEGV, SI_KERNEL, 0),
+    }
+}
@@ -1,6 +1,7 @@
 use super::paging::PageTableImpl;
-use crate::memory::FRAME_ALLOCATOR;
+use crate::memory::{FRAMES_TOTAL, FRAME_ALLOCATOR};
 use bitmap_allocator::BitAlloc;
+use core::sync::atomic::Ordering;
 use rboot::{BootInfo, MemoryType};
 use rcore_memory::paging::*;
 use rcore_memory::PAGE_SIZE;
@@ -22,6 +23,7 @@ fn init_frame_allocator(boot_info: &BootInfo) {
         if region.ty == MemoryType::CONVENTIONAL {
             let start_frame = region.phys_start as usize / PAGE_SIZE;
             let end_frame = start_frame + region.page_count as usize;
+            FRAMES_TOTAL.fetch_add(end_frame - start_frame, Ordering::Relaxed);
             ba.insert(start_frame..end_frame);
         }
     }
@@ -63,6 +63,7 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
     // now we can start LKM.
     crate::lkm::manager::ModuleManager::init();
     // init board
+    crate::drivers::net::loopback::init();
     board::init(boot_info);
     // init cpu scheduler and process manager, and add user shell app in process manager
     crate::process::init();
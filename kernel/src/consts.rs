@@ -8,6 +8,9 @@ pub const USEC_PER_TICK: usize = 10000;
 
 pub const INFORM_PER_MSEC: usize = 50;
 
+/// Address space kept free above the program break for the heap to grow into
+pub const USER_HEAP_GAP: usize = 0x1000_0000;
+
 lazy_static! {
     pub static ref SMP_CORES: usize = {
         if let Some(smp_str) = option_env!("SMP") {
@@ -136,6 +136,16 @@ unsafe fn enable(loc: Location) -> Option<usize> {
     assigned_irq
 }
 
+/// Index of the next NIC, which its MAC and IP addresses are derived from.
+/// The loopback interface is not counted.
+fn nic_index() -> usize {
+    NET_DRIVERS
+        .read()
+        .iter()
+        .filter(|driver| !driver.is_loopback())
+        .count()
+}
+
 pub fn init_driver(dev: &PCIDevice) {
     let name = format!("enp{}s{}f{}", dev.loc.bus, dev.loc.device, dev.loc.function);
     match (dev.id.vendor_id, dev.id.device_id) {
@@ -149,7 +159,7 @@ pub fn init_driver(dev: &PCIDevice) {
             if let Some(BAR::Memory(addr, len, _, _)) = dev.bars[0] {
                 let irq = unsafe { enable(dev.loc) };
                 let vaddr = phys_to_virt(addr as usize);
-                let index = NET_DRIVERS.read().len();
+                let index = nic_index();
                 e1000::init(name, irq, vaddr, len as usize, index);
                 return;
             }
@@ -159,7 +169,7 @@ pub fn init_driver(dev: &PCIDevice) {
             if let Some(BAR::Memory(addr, len, _, _)) = dev.bars[0] {
                 let irq = unsafe { enable(dev.loc) };
                 let vaddr = phys_to_virt(addr as usize);
-                let index = NET_DRIVERS.read().len();
+                let index = nic_index();
                 PCI_DRIVERS.lock().insert(
                     dev.loc,
                     ixgbe::ixgbe_init(name, irq, vaddr, len as usize, index),
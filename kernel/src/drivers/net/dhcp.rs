@@ -0,0 +1,267 @@
+//! IPv4 configuration of ethernet interfaces by DHCP, which replaces the
+//! static address and gateway they are built with once a server answers
+//!
+//! `dhcp=off` on the kernel cmdline keeps the static configuration.
+//! The interfaces with clients are polled by a task when the clients want it.
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use smoltcp::dhcp::{Dhcpv4Client, Dhcpv4Config};
+use smoltcp::iface::EthernetInterface;
+use smoltcp::phy::Device;
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::time::Instant;
+use smoltcp::wire::*;
+
+use crate::arch::timer::timer_now;
+use crate::net::{netlink_notify_addr, netlink_notify_route, SOCKETS};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::trap::{uptime_msec, NAIVE_TIMER};
+
+use super::super::{CMDLINE, NET_DRIVERS};
+
+/// What a DHCP server gave an interface
+#[derive(Debug, Clone)]
+struct Lease {
+    cidr: Ipv4Cidr,
+    dns_servers: Vec<Ipv4Address>,
+}
+
+/// When the clients want the interfaces polled
+#[derive(Default)]
+struct PollState {
+    /// Uptime in milliseconds
+    at: Option<usize>,
+    /// The poller waiting for it
+    waker: Option<Waker>,
+}
+
+lazy_static! {
+    /// Leases by interface name
+    static ref LEASES: Mutex<BTreeMap<String, Lease>> = Mutex::new(BTreeMap::new());
+    /// Names of the interfaces with clients
+    static ref CLIENTS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+    static ref POLL: Mutex<PollState> = Mutex::new(PollState::default());
+}
+
+/// Whether interfaces without a static address from the cmdline use DHCP
+fn enabled() -> bool {
+    !CMDLINE
+        .read()
+        .split_whitespace()
+        .any(|arg| arg == "dhcp=off")
+}
+
+/// Poll the interfaces at `at` or earlier
+fn poll_at(at: usize) {
+    let waker = {
+        let mut poll = POLL.lock();
+        if poll.at.map_or(false, |other| other <= at) {
+            return;
+        }
+        poll.at = Some(at);
+        poll.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+static POLLER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Start the task polling the interfaces with clients
+fn start_poller() {
+    if POLLER_STARTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    executor::spawn(async {
+        loop {
+            PollDue.await;
+            for iface in NET_DRIVERS.read().iter() {
+                if CLIENTS.lock().contains(&iface.get_ifname()) {
+                    iface.poll();
+                }
+            }
+        }
+    });
+}
+
+/// Wait until the clients want the interfaces polled
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct PollDue;
+
+impl Future for PollDue {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut poll = POLL.lock();
+        let now = uptime_msec();
+        match poll.at {
+            Some(at) if at <= now => {
+                poll.at = None;
+                return Poll::Ready(());
+            }
+            Some(at) => {
+                let waker = cx.waker().clone();
+                let deadline = timer_now() + Duration::from_millis((at - now) as u64);
+                NAIVE_TIMER
+                    .lock()
+                    .add(deadline, Box::new(move |_| waker.wake()));
+            }
+            None => {}
+        }
+        // woken earlier if a client wants it earlier
+        poll.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Nameservers of the leases in the format of resolv.conf, as Linux shows
+/// them in /proc/net/pnp
+pub fn pnp() -> String {
+    let leases = LEASES.lock();
+    let mut s = String::new();
+    if leases.is_empty() {
+        writeln!(s, "#MANUAL").unwrap();
+        return s;
+    }
+    writeln!(s, "#PROTO: DHCP").unwrap();
+    let mut servers: Vec<Ipv4Address> = Vec::new();
+    for lease in leases.values() {
+        for server in lease.dns_servers.iter() {
+            if !servers.contains(server) {
+                servers.push(*server);
+                writeln!(s, "nameserver {}", server).unwrap();
+            }
+        }
+    }
+    s
+}
+
+/// The DHCP client of an interface
+pub struct DhcpClient {
+    ifname: String,
+    client: Dhcpv4Client,
+}
+
+impl DhcpClient {
+    /// A client for `ifname`, unless DHCP is off
+    pub fn new(ifname: &str) -> Option<Self> {
+        if !enabled() {
+            return None;
+        }
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 900]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+        let now = uptime_msec();
+        let client = Dhcpv4Client::new(
+            &mut SOCKETS.lock(),
+            rx_buffer,
+            tx_buffer,
+            Instant::from_millis(now as i64),
+        );
+        CLIENTS.lock().insert(String::from(ifname));
+        start_poller();
+        poll_at(now);
+        Some(DhcpClient {
+            ifname: String::from(ifname),
+            client,
+        })
+    }
+
+    /// Send and receive for the client after `iface` polled, and apply a
+    /// new lease
+    pub fn poll<DeviceT>(
+        &mut self,
+        iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+        sockets: &mut SocketSet<'static, 'static, 'static>,
+        timestamp: Instant,
+    ) where
+        DeviceT: for<'d> Device<'d>,
+    {
+        match self.client.poll(iface, sockets, timestamp) {
+            Ok(Some(config)) => self.configure(iface, config),
+            Ok(None) => {}
+            Err(err) => debug!("dhcp: poll got err {}", err),
+        }
+        let next = timestamp + self.client.next_poll(timestamp);
+        poll_at(next.total_millis() as usize);
+    }
+
+    fn configure<DeviceT>(
+        &self,
+        iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+        config: Dhcpv4Config,
+    ) where
+        DeviceT: for<'d> Device<'d>,
+    {
+        let mut leases = LEASES.lock();
+        let old = leases.get(&self.ifname).cloned();
+        let cidr = match config.address.or_else(|| old.as_ref().map(|old| old.cidr)) {
+            Some(cidr) => cidr,
+            None => return,
+        };
+
+        // the lease takes the place of the first IPv4 address
+        let new_cidr = IpCidr::Ipv4(cidr);
+        let old_cidr = iface
+            .ip_addrs()
+            .iter()
+            .cloned()
+            .find(|cidr| matches!(cidr, IpCidr::Ipv4(_)));
+        if old_cidr != Some(new_cidr) {
+            info!("dhcp: {} got addr {}", self.ifname, cidr);
+            match old_cidr {
+                Some(old_cidr) => {
+                    iface.update_ip_addrs(|addrs| {
+                        for addr in addrs.iter_mut() {
+                            if *addr == old_cidr {
+                                *addr = new_cidr;
+                            }
+                        }
+                    });
+                    netlink_notify_addr(&self.ifname, old_cidr, false);
+                }
+                None => {
+                    super::add_ip_address(iface, new_cidr, true);
+                }
+            }
+            netlink_notify_addr(&self.ifname, new_cidr, true);
+        }
+
+        let default = IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0);
+        let mut old_router = None;
+        iface.routes_mut().update(|map| {
+            old_router = map.get(&default).map(|route| route.via_router);
+        });
+        let router = config.router.map(IpAddress::Ipv4);
+        if router != old_router {
+            info!("dhcp: {} got gateway {:?}", self.ifname, config.router);
+            iface.routes_mut().update(|map| {
+                map.remove(&default);
+            });
+            if let Some(old_router) = old_router {
+                netlink_notify_route(&self.ifname, default, old_router, false);
+            }
+            if let Some(router) = config.router {
+                let _ = iface.routes_mut().add_default_ipv4_route(router);
+                netlink_notify_route(&self.ifname, default, IpAddress::Ipv4(router), true);
+            }
+        }
+
+        let dns_servers = config
+            .dns_servers
+            .iter()
+            .filter_map(|server| *server)
+            .collect();
+        leases.insert(self.ifname.clone(), Lease { cidr, dns_servers });
+    }
+}
@@ -5,6 +5,7 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use smoltcp::iface::*;
 use smoltcp::phy::{self, DeviceCapabilities};
@@ -22,7 +23,8 @@ use crate::sync::SpinNoIrqLock as Mutex;
 
 use super::{
     super::{DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS, SOCKET_ACTIVITY},
-    NetDriver,
+    dhcp::DhcpClient,
+    ipv6, NetDriver,
 };
 
 #[derive(Clone)]
@@ -33,6 +35,8 @@ pub struct E1000Interface {
     driver: E1000Driver,
     name: String,
     irq: Option<usize>,
+    up: AtomicBool,
+    dhcp: Mutex<Option<DhcpClient>>,
 }
 
 impl Driver for E1000Interface {
@@ -44,10 +48,11 @@ impl Driver for E1000Interface {
 
         let data = self.driver.0.lock().handle_interrupt();
 
-        if data {
+        if data && self.is_up() {
             let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
             let mut sockets = SOCKETS.lock();
-            match self.iface.lock().poll(&mut sockets, timestamp) {
+            let mut iface = self.iface.lock();
+            match iface.poll(&mut sockets, timestamp) {
                 Ok(_) => {
                     SOCKET_ACTIVITY.notify_all();
                 }
@@ -55,6 +60,10 @@ impl Driver for E1000Interface {
                     debug!("poll got err {}", err);
                 }
             }
+            ipv6::autoconf(&mut iface, &mut sockets);
+            if let Some(dhcp) = &mut *self.dhcp.lock() {
+                dhcp.poll(&mut iface, &mut sockets, timestamp);
+            }
         }
 
         return data;
@@ -96,9 +105,13 @@ impl NetDriver for E1000Interface {
     }
 
     fn poll(&self) {
+        if !self.is_up() {
+            return;
+        }
         let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
         let mut sockets = SOCKETS.lock();
-        match self.iface.lock().poll(&mut sockets, timestamp) {
+        let mut iface = self.iface.lock();
+        match iface.poll(&mut sockets, timestamp) {
             Ok(_) => {
                 SOCKET_ACTIVITY.notify_all();
             }
@@ -106,10 +119,17 @@ impl NetDriver for E1000Interface {
                 debug!("poll got err {}", err);
             }
         }
+        ipv6::autoconf(&mut iface, &mut sockets);
+        if let Some(dhcp) = &mut *self.dhcp.lock() {
+            dhcp.poll(&mut iface, &mut sockets, timestamp);
+        }
     }
 
     fn send(&self, data: &[u8]) -> Option<usize> {
         use smoltcp::phy::TxToken;
+        if !self.is_up() {
+            return None;
+        }
         let token = E1000TxToken(self.driver.clone());
         if token
             .consume(Instant::from_millis(0), data.len(), |buffer| {
@@ -129,6 +149,34 @@ impl NetDriver for E1000Interface {
         let cache = iface.neighbor_cache();
         cache.lookup_pure(&ip, Instant::from_millis(0))
     }
+
+    fn add_ip_address(&self, cidr: IpCidr) -> bool {
+        super::add_ip_address(&mut self.iface.lock(), cidr, true)
+    }
+
+    fn remove_ip_address(&self, cidr: IpCidr) -> bool {
+        super::remove_ip_address(&mut self.iface.lock(), cidr, true)
+    }
+
+    fn get_routes(&self) -> Vec<(IpCidr, IpAddress)> {
+        super::get_routes(&mut self.iface.lock())
+    }
+
+    fn add_route(&self, cidr: IpCidr, gateway: IpAddress) -> bool {
+        super::add_route(&mut self.iface.lock(), cidr, gateway)
+    }
+
+    fn remove_route(&self, cidr: IpCidr) -> bool {
+        super::remove_route(&mut self.iface.lock(), cidr)
+    }
+
+    fn is_up(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+
+    fn set_up(&self, up: bool) {
+        self.up.store(up, Ordering::SeqCst);
+    }
 }
 
 pub struct E1000RxToken(Vec<u8>);
@@ -197,20 +245,26 @@ pub fn init(name: String, irq: Option<usize>, header: usize, size: usize, index:
     let net_driver = E1000Driver(Arc::new(Mutex::new(e1000)));
 
     let ethernet_addr = EthernetAddress::from_bytes(&mac);
-    let ip_addrs = [IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24)];
+    let ipv4 = IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24);
+    let ip_addrs = ipv6::ip_addrs(ipv4, ethernet_addr);
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let routes = Routes::new(BTreeMap::new());
     let iface = EthernetInterfaceBuilder::new(net_driver.clone())
         .ethernet_addr(ethernet_addr)
         .ip_addrs(ip_addrs)
         .neighbor_cache(neighbor_cache)
+        .routes(routes)
         .finalize();
 
     info!("e1000 interface {} up with addr 10.0.{}.2/24", name, index);
+    let dhcp = DhcpClient::new(&name);
     let e1000_iface = E1000Interface {
         iface: Mutex::new(iface),
         driver: net_driver.clone(),
         name,
         irq,
+        up: AtomicBool::new(true),
+        dhcp: Mutex::new(dhcp),
     };
 
     let driver = Arc::new(e1000_iface);
@@ -0,0 +1,108 @@
+//! IPv6 addresses of ethernet interfaces: a link-local one from the MAC, and a
+//! global one by stateless autoconfiguration (SLAAC) from router advertisements
+
+use alloc::vec::Vec;
+
+use smoltcp::iface::EthernetInterface;
+use smoltcp::phy::{ChecksumCapabilities, Device};
+use smoltcp::socket::{RawPacketMetadata, RawSocket, RawSocketBuffer, SocketHandle, SocketSet};
+use smoltcp::time::Duration;
+use smoltcp::wire::*;
+
+use crate::net::SOCKETS;
+
+lazy_static! {
+    /// Raw socket seeing the ICMPv6 packets of all interfaces
+    static ref ROUTER_ADVERTS: SocketHandle = {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 16], vec![0; 4096]);
+        let tx_buffer = RawSocketBuffer::new(vec![], vec![]);
+        let socket = RawSocket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, rx_buffer, tx_buffer);
+        SOCKETS.lock().add(socket)
+    };
+}
+
+/// Interface identifier by modified EUI-64
+fn with_prefix(prefix: Ipv6Address, mac: EthernetAddress) -> Ipv6Address {
+    let mac = mac.as_bytes();
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&prefix.as_bytes()[..8]);
+    bytes[8..].copy_from_slice(&[
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]);
+    Ipv6Address::from_bytes(&bytes)
+}
+
+pub fn link_local(mac: EthernetAddress) -> Ipv6Address {
+    with_prefix(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Addresses to build an interface with: `ipv4`, the link-local one and a slot
+/// for SLAAC, which holds a copy of the link-local one until a router answers
+pub fn ip_addrs(ipv4: IpCidr, mac: EthernetAddress) -> Vec<IpCidr> {
+    // SOCKETS is locked when polling
+    lazy_static::initialize(&ROUTER_ADVERTS);
+    let link_local = IpCidr::new(IpAddress::Ipv6(link_local(mac)), 64);
+    vec![ipv4, link_local, link_local]
+}
+
+/// Configure `iface` from the router advertisements its last poll received
+pub fn autoconf<DeviceT>(
+    iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+    sockets: &mut SocketSet<'static, 'static, 'static>,
+) where
+    DeviceT: for<'d> Device<'d>,
+{
+    let mut socket = sockets.get::<RawSocket>(*ROUTER_ADVERTS);
+    while let Ok(packet) = socket.recv() {
+        let (router, prefix_info, router_lifetime) = match parse_router_advert(packet) {
+            Some(advert) => advert,
+            None => continue,
+        };
+        if let Some(info) = prefix_info {
+            if info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF) && info.prefix_len == 64 {
+                let addr = with_prefix(info.prefix, iface.ethernet_addr());
+                let cidr = IpCidr::new(IpAddress::Ipv6(addr), 64);
+                iface.update_ip_addrs(|addrs| {
+                    if let Some(slot) = addrs.last_mut() {
+                        if *slot != cidr {
+                            info!("slaac: got addr {}", cidr);
+                            *slot = cidr;
+                        }
+                    }
+                });
+            }
+        }
+        if router_lifetime != Duration::from_millis(0) {
+            let _ = iface.routes_mut().add_default_ipv6_route(router);
+        }
+    }
+}
+
+fn parse_router_advert(
+    packet: &[u8],
+) -> Option<(Ipv6Address, Option<NdiscPrefixInformation>, Duration)> {
+    let ip_packet = Ipv6Packet::new_checked(packet).ok()?;
+    let icmp_packet = Icmpv6Packet::new_checked(ip_packet.payload()).ok()?;
+    let repr = Icmpv6Repr::parse(
+        &IpAddress::Ipv6(ip_packet.src_addr()),
+        &IpAddress::Ipv6(ip_packet.dst_addr()),
+        &icmp_packet,
+        &ChecksumCapabilities::default(),
+    )
+    .ok()?;
+    match repr {
+        Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert {
+            router_lifetime,
+            prefix_info,
+            ..
+        }) => Some((ip_packet.src_addr(), prefix_info, router_lifetime)),
+        _ => None,
+    }
+}
@@ -4,6 +4,7 @@
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::collections::BTreeMap;
 use isomorphic_drivers::net::ethernet::intel::ixgbe;
@@ -23,7 +24,8 @@ use super::{
     super::{
         provider::Provider, DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS, SOCKET_ACTIVITY,
     },
-    NetDriver,
+    dhcp::DhcpClient,
+    ipv6, NetDriver,
 };
 
 #[derive(Clone)]
@@ -40,6 +42,8 @@ pub struct IXGBEInterface {
     ifname: String,
     irq: Option<usize>,
     id: String,
+    up: AtomicBool,
+    dhcp: Mutex<Option<DhcpClient>>,
 }
 
 impl Driver for IXGBEInterface {
@@ -54,10 +58,11 @@ impl Driver for IXGBEInterface {
             self.driver.inner.lock().try_handle_interrupt()
         };
 
-        if handled {
+        if handled && self.is_up() {
             let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
             let mut sockets = SOCKETS.lock();
-            match self.iface.lock().poll(&mut sockets, timestamp) {
+            let mut iface = self.iface.lock();
+            match iface.poll(&mut sockets, timestamp) {
                 Ok(_) => {
                     SOCKET_ACTIVITY.notify_all();
                 }
@@ -65,6 +70,10 @@ impl Driver for IXGBEInterface {
                     debug!("poll got err {}", err);
                 }
             }
+            ipv6::autoconf(&mut iface, &mut sockets);
+            if let Some(dhcp) = &mut *self.dhcp.lock() {
+                dhcp.poll(&mut iface, &mut sockets, timestamp);
+            }
         }
 
         return handled;
@@ -106,9 +115,13 @@ impl NetDriver for IXGBEInterface {
     }
 
     fn poll(&self) {
+        if !self.is_up() {
+            return;
+        }
         let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
         let mut sockets = SOCKETS.lock();
-        match self.iface.lock().poll(&mut sockets, timestamp) {
+        let mut iface = self.iface.lock();
+        match iface.poll(&mut sockets, timestamp) {
             Ok(_) => {
                 SOCKET_ACTIVITY.notify_all();
             }
@@ -116,9 +129,16 @@ impl NetDriver for IXGBEInterface {
                 debug!("poll got err {}", err);
             }
         }
+        ipv6::autoconf(&mut iface, &mut sockets);
+        if let Some(dhcp) = &mut *self.dhcp.lock() {
+            dhcp.poll(&mut iface, &mut sockets, timestamp);
+        }
     }
 
     fn send(&self, data: &[u8]) -> Option<usize> {
+        if !self.is_up() {
+            return None;
+        }
         self.driver.inner.lock().send(&data);
         Some(data.len())
     }
@@ -128,6 +148,34 @@ impl NetDriver for IXGBEInterface {
         let cache = iface.neighbor_cache();
         cache.lookup_pure(&ip, Instant::from_millis(0))
     }
+
+    fn add_ip_address(&self, cidr: IpCidr) -> bool {
+        super::add_ip_address(&mut self.iface.lock(), cidr, true)
+    }
+
+    fn remove_ip_address(&self, cidr: IpCidr) -> bool {
+        super::remove_ip_address(&mut self.iface.lock(), cidr, true)
+    }
+
+    fn get_routes(&self) -> Vec<(IpCidr, IpAddress)> {
+        super::get_routes(&mut self.iface.lock())
+    }
+
+    fn add_route(&self, cidr: IpCidr, gateway: IpAddress) -> bool {
+        super::add_route(&mut self.iface.lock(), cidr, gateway)
+    }
+
+    fn remove_route(&self, cidr: IpCidr) -> bool {
+        super::remove_route(&mut self.iface.lock(), cidr)
+    }
+
+    fn is_up(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+
+    fn set_up(&self, up: bool) {
+        self.up.store(up, Ordering::SeqCst);
+    }
 }
 pub struct IXGBERxToken(Vec<u8>);
 pub struct IXGBETxToken(IXGBEDriver);
@@ -214,7 +262,8 @@ pub fn ixgbe_init(
         mtu: 1500,
     };
 
-    let ip_addrs = [IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24)];
+    let ipv4 = IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24);
+    let ip_addrs = ipv6::ip_addrs(ipv4, ethernet_addr);
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
     let routes = Routes::new(BTreeMap::new());
     let iface = EthernetInterfaceBuilder::new(net_driver.clone())
@@ -226,12 +275,15 @@ pub fn ixgbe_init(
 
     info!("ixgbe interface {} up with addr 10.0.{}.2/24", name, index);
 
+    let dhcp = DhcpClient::new(&name);
     let ixgbe_iface = IXGBEInterface {
         iface: Mutex::new(iface),
         driver: net_driver.clone(),
         ifname: name.clone(),
         id: name,
         irq,
+        up: AtomicBool::new(true),
+        dhcp: Mutex::new(dhcp),
     };
 
     let driver = Arc::new(ixgbe_iface);
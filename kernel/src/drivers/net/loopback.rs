@@ -0,0 +1,156 @@
+//! Loopback network interface `lo`, present even without a NIC
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use smoltcp::iface::*;
+use smoltcp::phy::{Device, Loopback, TxToken};
+use smoltcp::time::Instant;
+use smoltcp::wire::*;
+
+use crate::drivers::BlockDriver;
+use crate::net::SOCKETS;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+use super::{
+    super::{DeviceType, Driver, DRIVERS, NET_DRIVERS, SOCKET_ACTIVITY},
+    NetDriver,
+};
+
+pub struct LoopbackInterface {
+    iface: Mutex<EthernetInterface<'static, 'static, 'static, Loopback>>,
+    up: AtomicBool,
+}
+
+impl Driver for LoopbackInterface {
+    fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
+        false
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+
+    fn get_id(&self) -> String {
+        String::from("loopback")
+    }
+
+    fn as_net(&self) -> Option<&dyn NetDriver> {
+        Some(self)
+    }
+
+    fn as_block(&self) -> Option<&dyn BlockDriver> {
+        None
+    }
+}
+
+impl NetDriver for LoopbackInterface {
+    fn get_mac(&self) -> EthernetAddress {
+        self.iface.lock().ethernet_addr()
+    }
+
+    fn get_ifname(&self) -> String {
+        String::from("lo")
+    }
+
+    fn get_ip_addresses(&self) -> Vec<IpCidr> {
+        Vec::from(self.iface.lock().ip_addrs())
+    }
+
+    fn ipv4_address(&self) -> Option<Ipv4Address> {
+        self.iface.lock().ipv4_address()
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+
+    fn poll(&self) {
+        if !self.is_up() {
+            return;
+        }
+        // frames sent in a poll are received in the same poll
+        let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
+        let mut sockets = SOCKETS.lock();
+        match self.iface.lock().poll(&mut sockets, timestamp) {
+            Ok(_) => {
+                SOCKET_ACTIVITY.notify_all();
+            }
+            Err(err) => {
+                debug!("poll got err {}", err);
+            }
+        }
+    }
+
+    fn send(&self, data: &[u8]) -> Option<usize> {
+        if !self.is_up() {
+            return None;
+        }
+        let mut iface = self.iface.lock();
+        let token = iface.device_mut().transmit()?;
+        token
+            .consume(Instant::from_millis(0), data.len(), |buffer| {
+                buffer.copy_from_slice(&data);
+                Ok(())
+            })
+            .ok()?;
+        Some(data.len())
+    }
+
+    fn get_arp(&self, _ip: IpAddress) -> Option<EthernetAddress> {
+        None
+    }
+
+    fn add_ip_address(&self, cidr: IpCidr) -> bool {
+        super::add_ip_address(&mut self.iface.lock(), cidr, false)
+    }
+
+    fn remove_ip_address(&self, cidr: IpCidr) -> bool {
+        super::remove_ip_address(&mut self.iface.lock(), cidr, false)
+    }
+
+    fn get_routes(&self) -> Vec<(IpCidr, IpAddress)> {
+        super::get_routes(&mut self.iface.lock())
+    }
+
+    fn add_route(&self, cidr: IpCidr, gateway: IpAddress) -> bool {
+        super::add_route(&mut self.iface.lock(), cidr, gateway)
+    }
+
+    fn remove_route(&self, cidr: IpCidr) -> bool {
+        super::remove_route(&mut self.iface.lock(), cidr)
+    }
+
+    fn is_up(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+
+    fn set_up(&self, up: bool) {
+        self.up.store(up, Ordering::SeqCst);
+    }
+}
+
+/// Register `lo` with 127.0.0.1/8 and ::1, before any NIC so that it comes first
+pub fn init() {
+    let ip_addrs = [
+        IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8),
+        IpCidr::new(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1), 128),
+    ];
+    let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let iface = EthernetInterfaceBuilder::new(Loopback::new())
+        .ethernet_addr(EthernetAddress::default())
+        .ip_addrs(ip_addrs)
+        .neighbor_cache(neighbor_cache)
+        .finalize();
+
+    info!("loopback interface lo up with addr 127.0.0.1/8 and ::1/128");
+    let driver = Arc::new(LoopbackInterface {
+        iface: Mutex::new(iface),
+        up: AtomicBool::new(true),
+    });
+    DRIVERS.write().push(driver.clone());
+    NET_DRIVERS.write().push(driver);
+}
@@ -1,10 +1,15 @@
 use super::Driver;
 use alloc::string::String;
 use alloc::vec::Vec;
+use smoltcp::iface::{EthernetInterface, Route};
+use smoltcp::phy::Device;
 use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
 
+pub mod dhcp;
 pub mod e1000;
+mod ipv6;
 pub mod ixgbe;
+pub mod loopback;
 pub mod virtio_net;
 
 pub trait NetDriver: Driver {
@@ -28,6 +33,11 @@ pub trait NetDriver: Driver {
         unimplemented!("not a net driver")
     }
 
+    // whether this is a loopback device
+    fn is_loopback(&self) -> bool {
+        false
+    }
+
     // manually trigger a poll, use it after sending packets
     fn poll(&self) {
         unimplemented!("not a net driver")
@@ -42,4 +52,143 @@ pub trait NetDriver: Driver {
     fn get_arp(&self, _ip: IpAddress) -> Option<EthernetAddress> {
         unimplemented!("not a net driver")
     }
+
+    // add an ip address, false if it is there already
+    fn add_ip_address(&self, _cidr: IpCidr) -> bool {
+        false
+    }
+
+    // remove an ip address, false if it is not there
+    fn remove_ip_address(&self, _cidr: IpCidr) -> bool {
+        false
+    }
+
+    // get routes through gateways, as (destination, gateway)
+    fn get_routes(&self) -> Vec<(IpCidr, IpAddress)> {
+        Vec::new()
+    }
+
+    // add a route through a gateway, false if the destination has one
+    fn add_route(&self, _cidr: IpCidr, _gateway: IpAddress) -> bool {
+        false
+    }
+
+    // remove the route to a destination, false if there is none
+    fn remove_route(&self, _cidr: IpCidr) -> bool {
+        false
+    }
+
+    // whether the interface sends and receives
+    fn is_up(&self) -> bool {
+        true
+    }
+
+    // bring the interface up or down
+    fn set_up(&self, _up: bool) {}
+}
+
+/// Add `cidr` to the addresses of `iface`, before the SLAAC slot that
+/// `ipv6::ip_addrs` puts last if `slaac_slot`
+fn add_ip_address<DeviceT>(
+    iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+    cidr: IpCidr,
+    slaac_slot: bool,
+) -> bool
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    let mut addrs = Vec::from(iface.ip_addrs());
+    if addrs.contains(&cidr) {
+        return false;
+    }
+    let index = if slaac_slot {
+        addrs.len().saturating_sub(1)
+    } else {
+        addrs.len()
+    };
+    addrs.insert(index, cidr);
+    iface.update_ip_addrs(|slice| *slice = addrs.into());
+    true
+}
+
+/// Remove `cidr` from the addresses of `iface`, leaving in the SLAAC slot a
+/// copy of another address until a router answers again
+fn remove_ip_address<DeviceT>(
+    iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+    cidr: IpCidr,
+    slaac_slot: bool,
+) -> bool
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    let mut addrs = Vec::from(iface.ip_addrs());
+    let slot = if slaac_slot { addrs.pop() } else { None };
+    let len = addrs.len();
+    addrs.retain(|addr| *addr != cidr);
+    let mut removed = addrs.len() < len;
+    if let Some(mut slot) = slot {
+        if slot == cidr {
+            slot = match addrs.first() {
+                Some(addr) => *addr,
+                None => return false,
+            };
+            removed = true;
+        }
+        addrs.push(slot);
+    }
+    if removed {
+        iface.update_ip_addrs(|slice| *slice = addrs.into());
+    }
+    removed
+}
+
+fn get_routes<DeviceT>(
+    iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+) -> Vec<(IpCidr, IpAddress)>
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    let mut routes = Vec::new();
+    iface.routes_mut().update(|map| {
+        for (cidr, route) in map.iter() {
+            routes.push((*cidr, route.via_router));
+        }
+    });
+    routes
+}
+
+fn add_route<DeviceT>(
+    iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+    cidr: IpCidr,
+    gateway: IpAddress,
+) -> bool
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    let mut added = false;
+    iface.routes_mut().update(|map| {
+        if map.get(&cidr).is_none() {
+            let route = Route {
+                via_router: gateway,
+                preferred_until: None,
+                expires_at: None,
+            };
+            added = map.insert(cidr, route).is_ok();
+        }
+    });
+    added
+}
+
+fn remove_route<DeviceT>(
+    iface: &mut EthernetInterface<'static, 'static, 'static, DeviceT>,
+    cidr: IpCidr,
+) -> bool
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    let mut removed = false;
+    iface
+        .routes_mut()
+        .update(|map| removed = map.remove(&cidr).is_some());
+    removed
 }
@@ -1,43 +1,137 @@
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use smoltcp::iface::*;
 use smoltcp::phy::{self, DeviceCapabilities};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, Ipv4Address};
+use smoltcp::wire::*;
 use smoltcp::Result;
 use virtio_drivers::{VirtIOHeader, VirtIONet};
 
 use super::{
-    super::{DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS},
-    NetDriver,
+    super::{DeviceType, Driver, CMDLINE, DRIVERS, IRQ_MANAGER, NET_DRIVERS, SOCKET_ACTIVITY},
+    dhcp::DhcpClient,
+    ipv6, NetDriver,
 };
+use crate::net::SOCKETS;
 use crate::{drivers::BlockDriver, sync::SpinNoIrqLock as Mutex};
 
 #[derive(Clone)]
 pub struct VirtIONetDriver(Arc<Mutex<VirtIONet<'static>>>);
 
-impl NetDriver for VirtIONetDriver {
+pub struct VirtIONetInterface {
+    iface: Mutex<EthernetInterface<'static, 'static, 'static, VirtIONetDriver>>,
+    driver: VirtIONetDriver,
+    name: String,
+    up: AtomicBool,
+    dhcp: Mutex<Option<DhcpClient>>,
+}
+
+impl VirtIONetInterface {
+    fn poll_iface(&self) {
+        if !self.is_up() {
+            return;
+        }
+        let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
+        let mut sockets = SOCKETS.lock();
+        let mut iface = self.iface.lock();
+        match iface.poll(&mut sockets, timestamp) {
+            Ok(_) => {
+                SOCKET_ACTIVITY.notify_all();
+            }
+            Err(err) => {
+                debug!("poll got err {}", err);
+            }
+        }
+        ipv6::autoconf(&mut iface, &mut sockets);
+        if let Some(dhcp) = &mut *self.dhcp.lock() {
+            dhcp.poll(&mut iface, &mut sockets, timestamp);
+        }
+    }
+}
+
+impl NetDriver for VirtIONetInterface {
     fn get_mac(&self) -> EthernetAddress {
-        EthernetAddress(self.0.lock().mac())
+        self.iface.lock().ethernet_addr()
     }
 
     fn get_ifname(&self) -> String {
-        format!("virtio{:?}", self.0.lock().mac())
+        self.name.clone()
+    }
+
+    fn get_ip_addresses(&self) -> Vec<IpCidr> {
+        Vec::from(self.iface.lock().ip_addrs())
     }
 
     fn ipv4_address(&self) -> Option<Ipv4Address> {
-        unimplemented!()
+        self.iface.lock().ipv4_address()
     }
 
     fn poll(&self) {
-        unimplemented!()
+        self.poll_iface();
+    }
+
+    fn send(&self, data: &[u8]) -> Option<usize> {
+        use smoltcp::phy::{Device, TxToken};
+        if !self.is_up() {
+            return None;
+        }
+        let token = self.driver.clone().transmit()?;
+        token
+            .consume(Instant::from_millis(0), data.len(), |buffer| {
+                buffer.copy_from_slice(&data);
+                Ok(())
+            })
+            .ok()?;
+        Some(data.len())
+    }
+
+    fn get_arp(&self, ip: IpAddress) -> Option<EthernetAddress> {
+        let iface = self.iface.lock();
+        let cache = iface.neighbor_cache();
+        cache.lookup_pure(&ip, Instant::from_millis(0))
+    }
+
+    fn add_ip_address(&self, cidr: IpCidr) -> bool {
+        super::add_ip_address(&mut self.iface.lock(), cidr, true)
+    }
+
+    fn remove_ip_address(&self, cidr: IpCidr) -> bool {
+        super::remove_ip_address(&mut self.iface.lock(), cidr, true)
+    }
+
+    fn get_routes(&self) -> Vec<(IpCidr, IpAddress)> {
+        super::get_routes(&mut self.iface.lock())
+    }
+
+    fn add_route(&self, cidr: IpCidr, gateway: IpAddress) -> bool {
+        super::add_route(&mut self.iface.lock(), cidr, gateway)
+    }
+
+    fn remove_route(&self, cidr: IpCidr) -> bool {
+        super::remove_route(&mut self.iface.lock(), cidr)
+    }
+
+    fn is_up(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+
+    fn set_up(&self, up: bool) {
+        self.up.store(up, Ordering::SeqCst);
     }
 }
 
-impl Driver for VirtIONetDriver {
+impl Driver for VirtIONetInterface {
     fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
-        self.0.lock().ack_interrupt()
+        let ack = self.driver.0.lock().ack_interrupt();
+        if ack {
+            self.poll_iface();
+        }
+        ack
     }
 
     fn device_type(&self) -> DeviceType {
@@ -107,15 +201,100 @@ impl phy::TxToken for VirtIONetDriver {
         let mut buffer = [0u8; 2000];
         let result = f(&mut buffer[..len]);
         let mut driver = self.0.lock();
-        driver.send(&buffer).expect("failed to send packet");
+        driver.send(&buffer[..len]).expect("failed to send packet");
         result
     }
 }
 
+/// Static address of an interface
+struct IpConfig {
+    cidr: Ipv4Cidr,
+    gateway: Option<Ipv4Address>,
+}
+
+impl IpConfig {
+    /// QEMU user networking
+    fn default() -> Self {
+        IpConfig {
+            cidr: Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24),
+            gateway: Some(Ipv4Address::new(10, 0, 2, 2)),
+        }
+    }
+
+    /// Parse `ip=<client-ip>:<server-ip>:<gw-ip>:<netmask>:<hostname>:<device>`
+    /// from the kernel cmdline, as Linux does
+    fn from_cmdline(ifname: &str) -> Option<Self> {
+        let cmdline = CMDLINE.read();
+        let arg = cmdline
+            .split_whitespace()
+            .find(|arg| arg.starts_with("ip="))?;
+        let fields: Vec<&str> = arg[3..].split(':').collect();
+        let field = |i: usize| fields.get(i).cloned().filter(|field| !field.is_empty());
+        if let Some(device) = field(5) {
+            if device != ifname {
+                return None;
+            }
+        }
+        let addr: Ipv4Address = field(0)?.parse().ok()?;
+        let gateway = match field(2) {
+            Some(gateway) => Some(gateway.parse().ok()?),
+            None => None,
+        };
+        let prefix_len = match field(3) {
+            Some(netmask) => {
+                let netmask: Ipv4Address = netmask.parse().ok()?;
+                u32::from_be_bytes(netmask.0).count_ones() as u8
+            }
+            None => 24,
+        };
+        Some(IpConfig {
+            cidr: Ipv4Cidr::new(addr, prefix_len),
+            gateway,
+        })
+    }
+}
+
 pub fn init(header: &'static mut VirtIOHeader) {
+    static INDEX: AtomicUsize = AtomicUsize::new(0);
+
     let net = VirtIONet::new(header).expect("failed to create net driver");
-    let driver = Arc::new(VirtIONetDriver(Arc::new(Mutex::new(net))));
+    let net_driver = VirtIONetDriver(Arc::new(Mutex::new(net)));
+    let name = format!("eth{}", INDEX.fetch_add(1, Ordering::SeqCst));
+
+    let ethernet_addr = EthernetAddress(net_driver.0.lock().mac());
+    let static_config = IpConfig::from_cmdline(&name);
+    // a static address from the cmdline turns DHCP off
+    let dhcp = match static_config {
+        Some(_) => None,
+        None => DhcpClient::new(&name),
+    };
+    let config = static_config.unwrap_or_else(IpConfig::default);
+    let ip_addrs = ipv6::ip_addrs(IpCidr::Ipv4(config.cidr), ethernet_addr);
+    let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let mut routes = Routes::new(BTreeMap::new());
+    if let Some(gateway) = config.gateway {
+        routes.add_default_ipv4_route(gateway).unwrap();
+    }
+    let iface = EthernetInterfaceBuilder::new(net_driver.clone())
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(ip_addrs)
+        .neighbor_cache(neighbor_cache)
+        .routes(routes)
+        .finalize();
+
+    info!(
+        "virtio-net interface {} up with addr {}, gateway {:?}",
+        name, config.cidr, config.gateway
+    );
+    let virtio_iface = VirtIONetInterface {
+        iface: Mutex::new(iface),
+        driver: net_driver,
+        name,
+        up: AtomicBool::new(true),
+        dhcp: Mutex::new(dhcp),
+    };
 
+    let driver = Arc::new(virtio_iface);
     DRIVERS.write().push(driver.clone());
     IRQ_MANAGER.write().register_all(driver.clone());
     NET_DRIVERS.write().push(driver);
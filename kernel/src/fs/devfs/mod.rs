@@ -1,12 +1,14 @@
 //! Device file system mounted at /dev
 
 mod fbdev;
+mod pty;
 mod random;
 mod serial;
 mod shm;
 mod tty;
 
 pub use fbdev::*;
+pub use pty::*;
 pub use random::*;
 pub use serial::*;
 pub use shm::*;
@@ -0,0 +1,432 @@
+//! Pseudo-terminals, see pty(7)
+//!
+//! Opening /dev/ptmx creates a master and its slave /dev/pts/N. What is written
+//! to the master is the input of the slave's line discipline, and the output
+//! of the slave is read from the master.
+
+use super::tty::{TtyDriver, TtyINode, TTY};
+use crate::fs::ioctl::*;
+use crate::process::{current_thread, Pgid};
+use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::any::Any;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use rcore_fs::vfs::*;
+use rcore_fs_devfs::DevFS;
+use spin::RwLock;
+
+/// Device number of devpts
+const DEVPTS_DEV: usize = 5;
+
+/// Major device number of pty slaves
+const PTY_SLAVE_MAJOR: usize = 136;
+
+/// Max number of ptys
+const PTY_MAX: usize = 4096;
+
+fn ptmx_rdev() -> usize {
+    make_rdev(5, 2)
+}
+
+lazy_static! {
+    /// Ptys whose master is open, by their numbers
+    static ref PTYS: RwLock<BTreeMap<usize, Arc<Pty>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Output of a slave waiting to be read from the master
+#[derive(Default)]
+struct PtyOutput {
+    buf: Mutex<VecDeque<u8>>,
+    eventbus: Mutex<EventBus>,
+}
+
+impl TtyDriver for PtyOutput {
+    fn write(&self, buf: &[u8]) {
+        self.buf.lock().extend(buf);
+        self.eventbus.lock().set(Event::READABLE);
+    }
+}
+
+struct Pty {
+    index: usize,
+    slave: Arc<TtyINode>,
+    output: Arc<PtyOutput>,
+    /// The slave can not be opened until unlocked by TIOCSPTLCK
+    locked: RwLock<bool>,
+}
+
+/// Open a pty device found at some path
+///
+/// /dev/ptmx gives a new master each time, and a locked slave fails with EIO.
+/// Other INodes are opened as they are.
+///
+/// `leader` is the session and process group of a session leader opening
+/// without O_NOCTTY, which gets the slave as its controlling terminal if its
+/// session has none.
+pub fn open_pty(inode: Arc<dyn INode>, leader: Option<(Pgid, Pgid)>) -> Result<Arc<dyn INode>> {
+    let metadata = inode.metadata()?;
+    if metadata.type_ != FileType::CharDevice {
+        return Ok(inode);
+    }
+    if metadata.rdev == ptmx_rdev() {
+        return Ok(PtyMaster::new()?);
+    }
+    let slave = match PTYS
+        .read()
+        .values()
+        .find(|pty| make_rdev(PTY_SLAVE_MAJOR, pty.index) == metadata.rdev)
+    {
+        Some(pty) if *pty.locked.read() => return Err(FsError::DeviceError),
+        Some(pty) => pty.slave.clone(),
+        None => return Ok(inode),
+    };
+    if let Some((sid, pgid)) = leader {
+        if !has_controlling_tty(sid) {
+            slave.acquire(sid, pgid);
+        }
+    }
+    Ok(inode)
+}
+
+/// Whether session `sid` has a controlling terminal
+fn has_controlling_tty(sid: Pgid) -> bool {
+    TTY.session() == Some(sid)
+        || PTYS
+            .read()
+            .values()
+            .any(|pty| pty.slave.session() == Some(sid))
+}
+
+/// /dev/ptmx, opening it is handled by `open_pty`
+#[derive(Default)]
+pub struct Ptmx;
+
+impl INode for Ptmx {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: false,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(pty_metadata(14, FileType::CharDevice, 0o666, ptmx_rdev()))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Master side of a pty, hangs up the slave when closed
+pub struct PtyMaster {
+    pty: Arc<Pty>,
+}
+
+impl PtyMaster {
+    fn new() -> Result<Arc<Self>> {
+        let mut ptys = PTYS.write();
+        let index = (0..PTY_MAX)
+            .find(|i| !ptys.contains_key(i))
+            .ok_or(FsError::NoDeviceSpace)?;
+        let output = Arc::new(PtyOutput::default());
+        let pty = Arc::new(Pty {
+            index,
+            slave: TtyINode::new(output.clone(), make_rdev(PTY_SLAVE_MAJOR, index)),
+            output,
+            locked: RwLock::new(true),
+        });
+        ptys.insert(index, pty.clone());
+        info!("pty: open /dev/pts/{}", index);
+        Ok(Arc::new(PtyMaster { pty }))
+    }
+
+    fn can_read(&self) -> bool {
+        !self.pty.output.buf.lock().is_empty()
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        info!("pty: close /dev/pts/{}", self.pty.index);
+        PTYS.write().remove(&self.pty.index);
+        // files are closed with their process unlocked, see `Process::exit`
+        self.pty.slave.hangup();
+    }
+}
+
+impl INode for PtyMaster {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let output = &self.pty.output;
+        let mut data = output.buf.lock();
+        if data.is_empty() {
+            drop(data);
+            if let Some(thread) = current_thread() {
+                if thread.has_signal_to_handle() {
+                    return Err(FsError::Interrupted);
+                }
+            }
+            return Err(FsError::Again);
+        }
+        let len = data.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(data.drain(..len)) {
+            *dst = src;
+        }
+        if data.is_empty() {
+            output.eventbus.lock().clear(Event::READABLE);
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        for &c in buf {
+            self.pty.slave.push(c);
+        }
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.can_read(),
+            write: true,
+            error: false,
+        })
+    }
+
+    fn async_poll<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct PtyMasterFuture<'a> {
+            master: &'a PtyMaster,
+        };
+
+        impl<'a> Future for PtyMasterFuture<'a> {
+            type Output = Result<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                if self.master.can_read() {
+                    return Poll::Ready(self.master.poll());
+                }
+                let waker = cx.waker().clone();
+                self.master.pty.output.eventbus.lock().subscribe(Box::new({
+                    move |_| {
+                        waker.wake_by_ref();
+                        true
+                    }
+                }));
+                Poll::Pending
+            }
+        }
+
+        Box::pin(PtyMasterFuture { master: self })
+    }
+
+    fn io_control(&self, cmd: u32, data: usize) -> Result<usize> {
+        match cmd as usize {
+            TIOCGPTN => {
+                let argp = data as *mut u32;
+                unsafe { *argp = self.pty.index as u32 };
+                Ok(0)
+            }
+            TIOCSPTLCK => {
+                let lock = unsafe { *(data as *const i32) };
+                *self.pty.locked.write() = lock != 0;
+                Ok(0)
+            }
+            FIONREAD => {
+                let argp = data as *mut i32;
+                unsafe { *argp = self.pty.output.buf.lock().len() as i32 };
+                Ok(0)
+            }
+            // terminal attributes and window size are shared with the slave
+            _ => self.pty.slave.io_control(cmd, data),
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(pty_metadata(14, FileType::CharDevice, 0o666, ptmx_rdev()))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn pty_metadata(inode: usize, type_: FileType, mode: u16, rdev: usize) -> Metadata {
+    Metadata {
+        dev: 1,
+        inode,
+        size: 0,
+        blk_size: 0,
+        blocks: 0,
+        atime: Timespec { sec: 0, nsec: 0 },
+        mtime: Timespec { sec: 0, nsec: 0 },
+        ctime: Timespec { sec: 0, nsec: 0 },
+        type_,
+        mode,
+        nlinks: 1,
+        uid: 0,
+        gid: 0,
+        rdev,
+    }
+}
+
+/// File system of pty slaves mounted at /dev/pts
+pub struct DevPtsFS;
+
+impl DevPtsFS {
+    pub fn new() -> Arc<Self> {
+        Arc::new(DevPtsFS)
+    }
+}
+
+impl FileSystem for DevPtsFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        Arc::new(DevPtsRootINode)
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            bsize: 0,
+            frsize: 0,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+/// The empty directory pts of DevFS, which DevPtsFS is mounted on
+pub struct DevPtsDirINode {
+    devfs: Weak<DevFS>,
+}
+
+impl DevPtsDirINode {
+    pub fn new(devfs: &Arc<DevFS>) -> Self {
+        DevPtsDirINode {
+            devfs: Arc::downgrade(devfs),
+        }
+    }
+}
+
+impl INode for DevPtsDirINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            nlinks: 2,
+            ..pty_metadata(15, FileType::Dir, 0o755, 0)
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        match name {
+            "." => Ok(Arc::new(DevPtsDirINode {
+                devfs: self.devfs.clone(),
+            })),
+            ".." => match self.devfs.upgrade() {
+                Some(devfs) => Ok(devfs.root_inode()),
+                None => Err(FsError::EntryNotFound),
+            },
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The root directory /dev/pts, listing slaves of opened masters
+struct DevPtsRootINode;
+
+impl INode for DevPtsRootINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: DEVPTS_DEV,
+            ..pty_metadata(1, FileType::Dir, 0o755, 0)
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        match name {
+            "." | ".." => Ok(Arc::new(DevPtsRootINode)),
+            _ => {
+                let index: usize = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                match PTYS.read().get(&index) {
+                    Some(pty) => Ok(pty.slave.clone()),
+                    None => Err(FsError::EntryNotFound),
+                }
+            }
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            i => PTYS
+                .read()
+                .keys()
+                .nth(i - 2)
+                .map(|index| format!("{}", index))
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,34 +1,160 @@
+use crate::arch::timer::timer_now;
 use crate::fs::ioctl::*;
-use crate::process::{process_group, Pgid};
-use crate::signal::{send_signal, Signal};
+use crate::process::{current_thread, process_group, Pgid};
+use crate::signal::{send_signal, Signal, SIG_IGN};
 use crate::signal::{Siginfo, SI_KERNEL};
-use crate::{sync::Event, sync::EventBus, syscall::SysError};
+use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::trap::NAIVE_TIMER;
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::any::Any;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::Context;
 use core::task::Poll;
+use core::time::Duration;
 use rcore_fs::vfs::FsError::NotSupported;
 use rcore_fs::vfs::*;
-use spin::{Mutex, RwLock};
+use spin::RwLock;
 
-/// console tty
-// Ref: [https://linux.die.net/man/4/tty]
+/// Where a terminal sends its output
+pub trait TtyDriver: Send + Sync {
+    fn write(&self, buf: &[u8]);
+}
+
+/// The kernel console
+pub struct Console;
+
+impl TtyDriver for Console {
+    fn write(&self, buf: &[u8]) {
+        use core::str;
+        // we do not care the utf-8 things, we just want to print it!
+        let s = unsafe { str::from_utf8_unchecked(buf) };
+        print!("{}", s);
+    }
+}
+
+/// Input queues of the line discipline
 #[derive(Default)]
+struct LineDiscipline {
+    /// Line being edited in canonical mode
+    line: Vec<u8>,
+    /// Input ready for reading
+    read_buf: VecDeque<u8>,
+    /// Lengths of complete lines in `read_buf` in canonical mode,
+    /// a zero length line is an end-of-file
+    line_ends: VecDeque<usize>,
+    /// Bytes a blocked non-canonical read waits for, i.e. VMIN
+    wanted: usize,
+    /// Time of the last input, VTIME is an inter-byte timer when VMIN > 0
+    last_input: Duration,
+    /// When a read with VMIN = 0 and VTIME > 0 times out
+    read_deadline: Option<Duration>,
+    /// The other end has gone, reads see end-of-file and writes fail
+    hung_up: bool,
+}
+
+impl LineDiscipline {
+    fn flush(&mut self) {
+        self.line.clear();
+        self.read_buf.clear();
+        self.line_ends.clear();
+    }
+
+    /// Move the line being edited to the readers
+    fn commit_line(&mut self) {
+        self.line_ends.push_back(self.line.len());
+        self.read_buf.extend(self.line.drain(..));
+    }
+
+    fn can_read(&self, termios: &Termios) -> bool {
+        if self.hung_up {
+            true
+        } else if is_canonical(termios) {
+            !self.line_ends.is_empty()
+        } else {
+            !self.read_buf.is_empty()
+        }
+    }
+
+    /// Whether a blocked read can return now
+    fn read_ready(&self, termios: &Termios) -> bool {
+        if self.hung_up {
+            return true;
+        }
+        if is_canonical(termios) {
+            return !self.line_ends.is_empty();
+        }
+        let avail = self.read_buf.len();
+        let (min, time) = (termios.cc[VMIN], vtime(termios));
+        match (min, time) {
+            (0, None) => true,
+            (0, Some(_)) => avail > 0 || self.read_deadline.map_or(false, |ddl| timer_now() >= ddl),
+            (_, None) => avail >= self.wanted,
+            (_, Some(time)) => {
+                avail >= self.wanted || (avail > 0 && timer_now() >= self.last_input + time)
+            }
+        }
+    }
+
+    /// When a blocked read should be woken up without new input
+    fn timeout(&self, termios: &Termios) -> Option<Duration> {
+        if is_canonical(termios) {
+            return None;
+        }
+        let time = vtime(termios)?;
+        if termios.cc[VMIN] == 0 {
+            self.read_deadline
+        } else if !self.read_buf.is_empty() {
+            Some(self.last_input + time)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_canonical(termios: &Termios) -> bool {
+    LocalModes::from_bits_truncate(termios.lflag).contains(LocalModes::ICANON)
+}
+
+/// VTIME in tenths of a second
+fn vtime(termios: &Termios) -> Option<Duration> {
+    match termios.cc[VTIME] {
+        0 => None,
+        time => Some(Duration::from_millis(time as u64 * 100)),
+    }
+}
+
+/// Whether `c` is the special character `index`, 0 disables it
+fn is_cc(termios: &Termios, index: usize, c: u8) -> bool {
+    termios.cc[index] != 0 && termios.cc[index] == c
+}
+
+/// terminal with a line discipline
+// Ref: [https://linux.die.net/man/4/tty]
+// Ref: [https://www.man7.org/linux/man-pages/man3/termios.3.html]
 pub struct TtyINode {
+    driver: Arc<dyn TtyDriver>,
+    /// session it is the controlling terminal of
+    session: RwLock<Option<Pgid>>,
     /// foreground process group
     foreground_pgid: RwLock<Pgid>,
-    buf: Mutex<VecDeque<u8>>,
+    ldisc: Mutex<LineDiscipline>,
     eventbus: Mutex<EventBus>,
     winsize: RwLock<Winsize>,
     termios: RwLock<Termios>,
+    rdev: usize,
 }
 
 lazy_static! {
-    pub static ref TTY: Arc<TtyINode> = Arc::new(TtyINode::default());
+    /// console tty, the controlling terminal of the init session
+    pub static ref TTY: Arc<TtyINode> = {
+        let tty = TtyINode::new(Arc::new(Console), make_rdev(5, 0));
+        *tty.session.write() = Some(0);
+        tty
+    };
 }
 
 pub fn foreground_pgid() -> Pgid {
@@ -36,66 +162,364 @@ pub fn foreground_pgid() -> Pgid {
 }
 
 impl TtyINode {
-    pub fn push(&self, c: u8) {
-        let lflag = LocalModes::from_bits_truncate(self.termios.read().lflag);
-        if lflag.contains(LocalModes::ISIG) && [0o3, 0o34, 0o32, 0o31].contains(&(c as i32)) {
-            use Signal::*;
-            let foregroud_processes = process_group(foreground_pgid());
-            match c as i32 {
-                // INTR
-                0o3 => {
-                    for proc in foregroud_processes {
-                        send_signal(
-                            proc,
-                            -1,
-                            Siginfo {
-                                signo: SIGINT as i32,
-                                errno: 0,
-                                code: SI_KERNEL,
-                                field: Default::default(),
-                            },
-                        );
-                    }
+    pub fn new(driver: Arc<dyn TtyDriver>, rdev: usize) -> Arc<Self> {
+        Arc::new(TtyINode {
+            driver,
+            session: RwLock::new(None),
+            foreground_pgid: RwLock::new(0),
+            ldisc: Mutex::new(LineDiscipline::default()),
+            eventbus: Mutex::new(EventBus::default()),
+            winsize: RwLock::new(Winsize::default()),
+            termios: RwLock::new(Termios::default()),
+            rdev,
+        })
+    }
+
+    /// Receive a character from the terminal device
+    pub fn push(&self, mut c: u8) {
+        let termios = *self.termios.read();
+        let iflag = InputModes::from_bits_truncate(termios.iflag);
+        let lflag = LocalModes::from_bits_truncate(termios.lflag);
+
+        if iflag.contains(InputModes::ISTRIP) {
+            c &= 0x7f;
+        }
+        if c == b'\r' {
+            if iflag.contains(InputModes::IGNCR) {
+                return;
+            }
+            if iflag.contains(InputModes::ICRNL) {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag.contains(InputModes::INLCR) {
+            c = b'\r';
+        }
+
+        if lflag.contains(LocalModes::ISIG) {
+            let signal = if is_cc(&termios, VINTR, c) {
+                Some(Signal::SIGINT)
+            } else if is_cc(&termios, VQUIT, c) {
+                Some(Signal::SIGQUIT)
+            } else if is_cc(&termios, VSUSP, c) {
+                Some(Signal::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                if !lflag.contains(LocalModes::NOFLSH) {
+                    self.ldisc.lock().flush();
+                }
+                self.echo(&termios, c);
+                self.signal_foreground(signal);
+                return;
+            }
+        }
+
+        let mut ldisc = self.ldisc.lock();
+        ldisc.last_input = timer_now();
+        if lflag.contains(LocalModes::ICANON) {
+            if is_cc(&termios, VERASE, c) {
+                if ldisc.line.pop().is_some() && lflag.contains(LocalModes::ECHOE) {
+                    self.erase(&termios, 1);
+                }
+            } else if is_cc(&termios, VWERASE, c) && lflag.contains(LocalModes::IEXTEN) {
+                let line = &mut ldisc.line;
+                let mut n = 0;
+                while line.last() == Some(&b' ') {
+                    line.pop();
+                    n += 1;
+                }
+                while line.last().map_or(false, |&c| c != b' ') {
+                    line.pop();
+                    n += 1;
+                }
+                if lflag.contains(LocalModes::ECHOE) {
+                    self.erase(&termios, n);
+                }
+            } else if is_cc(&termios, VKILL, c) {
+                let n = ldisc.line.len();
+                ldisc.line.clear();
+                if lflag.contains(LocalModes::ECHOKE) {
+                    self.erase(&termios, n);
+                } else if lflag.contains(LocalModes::ECHOK) {
+                    self.echo(&termios, c);
+                    self.output(b"\n");
                 }
-                _ => warn!("special char {} is unimplented", c),
+            } else if is_cc(&termios, VEOF, c) {
+                ldisc.commit_line();
+            } else if c == b'\n' || is_cc(&termios, VEOL, c) || is_cc(&termios, VEOL2, c) {
+                ldisc.line.push(c);
+                ldisc.commit_line();
+                if c == b'\n' && lflag.contains(LocalModes::ECHONL) {
+                    self.output(b"\n");
+                } else {
+                    self.echo(&termios, c);
+                }
+            } else {
+                ldisc.line.push(c);
+                self.echo(&termios, c);
             }
         } else {
-            self.buf.lock().push_back(c);
-            self.eventbus.lock().set(Event::READABLE);
+            ldisc.read_buf.push_back(c);
+            self.echo(&termios, c);
+        }
+        self.notify_readers(&ldisc, &termios);
+    }
+
+    /// Echo an input character, control characters are shown as ^X
+    fn echo(&self, termios: &Termios, c: u8) {
+        let lflag = LocalModes::from_bits_truncate(termios.lflag);
+        if !lflag.contains(LocalModes::ECHO) {
+            return;
+        }
+        if lflag.contains(LocalModes::ECHOCTL) && (c < b' ' && c != b'\n' && c != b'\t') {
+            self.output(&[b'^', c + b'@']);
+        } else if lflag.contains(LocalModes::ECHOCTL) && c == 0x7f {
+            self.output(b"^?");
+        } else {
+            self.output(&[c]);
         }
     }
 
-    pub fn pop(&self) -> u8 {
-        let mut buf_lock = self.buf.lock();
-        let c = buf_lock.pop_front().unwrap();
-        if buf_lock.len() == 0 {
-            self.eventbus.lock().clear(Event::READABLE);
+    /// Erase `n` echoed characters
+    fn erase(&self, termios: &Termios, n: usize) {
+        if LocalModes::from_bits_truncate(termios.lflag).contains(LocalModes::ECHO) {
+            for _ in 0..n {
+                self.output(b"\x08 \x08");
+            }
         }
-        return c;
+    }
+
+    /// Send `buf` to the driver, with NL mapped to CR-NL under OPOST and ONLCR
+    fn output(&self, buf: &[u8]) {
+        let oflag = OutputModes::from_bits_truncate(self.termios.read().oflag);
+        if !oflag.contains(OutputModes::OPOST | OutputModes::ONLCR) || !buf.contains(&b'\n') {
+            self.driver.write(buf);
+            return;
+        }
+        let mut out = Vec::with_capacity(buf.len() + 1);
+        for &c in buf {
+            if c == b'\n' {
+                out.push(b'\r');
+            }
+            out.push(c);
+        }
+        self.driver.write(&out);
+    }
+
+    /// Wake up blocked readers, and tell pollers whether there is input
+    fn notify_readers(&self, ldisc: &LineDiscipline, termios: &Termios) {
+        let mut eventbus = self.eventbus.lock();
+        eventbus.clear(Event::READABLE);
+        if ldisc.can_read(termios) {
+            eventbus.set(Event::READABLE);
+        }
+    }
+
+    fn signal_foreground(&self, signal: Signal) {
+        for proc in process_group(*self.foreground_pgid.read()) {
+            send_signal(
+                proc,
+                -1,
+                Siginfo {
+                    signo: signal as i32,
+                    errno: 0,
+                    code: SI_KERNEL,
+                    field: Default::default(),
+                },
+            );
+        }
+        // interrupt blocked readers
+        let mut eventbus = self.eventbus.lock();
+        eventbus.set(Event::RECEIVE_SIGNAL);
+        eventbus.clear(Event::RECEIVE_SIGNAL);
+    }
+
+    /// Stop background processes of our session from accessing the terminal
+    ///
+    /// The process group of the caller gets `signal` (SIGTTIN or SIGTTOU) and the
+    /// access is interrupted. If the signal is ignored or blocked, reading fails
+    /// with EIO while other accesses go on.
+    fn job_control(&self, signal: Signal) -> Result<()> {
+        let thread = match current_thread() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        let proc = thread.proc.lock();
+        if *self.session.read() != Some(proc.sid) || proc.pgid == *self.foreground_pgid.read() {
+            return Ok(());
+        }
+        let ignored = proc.dispositions[signal as usize].handler == SIG_IGN
+            || thread.inner.lock().sig_mask.contains(signal);
+        let pgid = proc.pgid;
+        drop(proc);
+        if ignored {
+            return match signal {
+                Signal::SIGTTIN => Err(FsError::DeviceError),
+                _ => Ok(()),
+            };
+        }
+        for proc in process_group(pgid) {
+            send_signal(
+                proc,
+                -1,
+                Siginfo {
+                    signo: signal as i32,
+                    errno: 0,
+                    code: SI_KERNEL,
+                    field: Default::default(),
+                },
+            );
+        }
+        Err(FsError::Interrupted)
+    }
+
+    /// Session id, process group id and pid of the caller
+    fn caller(&self) -> Result<(Pgid, Pgid, usize)> {
+        let thread = current_thread().ok_or(FsError::IOCTLError)?;
+        let proc = thread.proc.lock();
+        Ok((proc.sid, proc.pgid, proc.pid.get()))
+    }
+
+    /// Fail with ENOTTY unless we are the controlling terminal of the caller
+    fn check_controlling(&self) -> Result<()> {
+        let (sid, _, _) = self.caller()?;
+        if *self.session.read() == Some(sid) {
+            Ok(())
+        } else {
+            Err(FsError::IOCTLError)
+        }
+    }
+
+    fn set_termios(&self, termios: Termios, flush: bool) {
+        let mut ldisc = self.ldisc.lock();
+        if flush {
+            ldisc.flush();
+        }
+        let was_canonical = is_canonical(&self.termios.read());
+        match (was_canonical, is_canonical(&termios)) {
+            // unfinished line is available to raw readers
+            (true, false) => {
+                let line: Vec<u8> = ldisc.line.drain(..).collect();
+                ldisc.read_buf.extend(line);
+                ldisc.line_ends.clear();
+            }
+            // raw input forms a line
+            (false, true) if !ldisc.read_buf.is_empty() => {
+                let len = ldisc.read_buf.len();
+                ldisc.line_ends.push_back(len);
+            }
+            _ => {}
+        }
+        *self.termios.write() = termios;
+        self.notify_readers(&ldisc, &termios);
     }
 
     pub fn can_read(&self) -> bool {
-        return self.buf.lock().len() > 0;
+        self.ldisc.lock().can_read(&self.termios.read())
+    }
+
+    /// The other end of the terminal is gone, e.g. a pty master is closed
+    ///
+    /// The foreground process group gets SIGHUP and the terminal is no
+    /// longer controlling any session.
+    pub fn hangup(&self) {
+        let mut ldisc = self.ldisc.lock();
+        ldisc.hung_up = true;
+        self.notify_readers(&ldisc, &self.termios.read());
+        drop(ldisc);
+        if self.session.write().take().is_some() {
+            self.signal_foreground(Signal::SIGHUP);
+            self.signal_foreground(Signal::SIGCONT);
+        }
+    }
+
+    /// Session it is the controlling terminal of
+    pub fn session(&self) -> Option<Pgid> {
+        *self.session.read()
+    }
+
+    /// Become the controlling terminal of session `sid` in the foreground `pgid`,
+    /// unless it is controlling a session already
+    pub fn acquire(&self, sid: Pgid, pgid: Pgid) {
+        let mut session = self.session.write();
+        if session.is_none() {
+            *session = Some(sid);
+            *self.foreground_pgid.write() = pgid;
+            info!("tty: controlling terminal of session {}", sid);
+        }
+    }
+
+    /// Resize the terminal, the foreground process group gets SIGWINCH
+    pub fn set_winsize(&self, winsize: Winsize) {
+        let mut old = self.winsize.write();
+        let changed = *old != winsize;
+        *old = winsize;
+        drop(old);
+        if changed && self.session.read().is_some() {
+            self.signal_foreground(Signal::SIGWINCH);
+        }
     }
 }
 
 impl INode for TtyINode {
     /// Read bytes at `offset` into `buf`, return the number of bytes read.
-    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if self.can_read() {
-            buf[0] = self.pop() as u8;
-            Ok(1)
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.job_control(Signal::SIGTTIN)?;
+        let termios = *self.termios.read();
+        let mut ldisc = self.ldisc.lock();
+        if ldisc.hung_up && ldisc.read_buf.is_empty() {
+            return Ok(0);
+        }
+        if is_canonical(&termios) {
+            // at most one line each time
+            if let Some(&line_len) = ldisc.line_ends.front() {
+                let len = line_len.min(buf.len());
+                for (dst, src) in buf.iter_mut().zip(ldisc.read_buf.drain(..len)) {
+                    *dst = src;
+                }
+                if len == line_len {
+                    ldisc.line_ends.pop_front();
+                } else {
+                    ldisc.line_ends[0] -= len;
+                }
+                self.notify_readers(&ldisc, &termios);
+                return Ok(len);
+            }
         } else {
-            Err(FsError::Again)
+            ldisc.wanted = (termios.cc[VMIN] as usize).min(buf.len());
+            if termios.cc[VMIN] == 0 && ldisc.read_deadline.is_none() {
+                ldisc.read_deadline = vtime(&termios).map(|time| timer_now() + time);
+            }
+            if ldisc.read_ready(&termios) {
+                ldisc.read_deadline = None;
+                let len = ldisc.read_buf.len().min(buf.len());
+                for (dst, src) in buf.iter_mut().zip(ldisc.read_buf.drain(..len)) {
+                    *dst = src;
+                }
+                self.notify_readers(&ldisc, &termios);
+                return Ok(len);
+            }
         }
+        drop(ldisc);
+        if let Some(thread) = current_thread() {
+            if thread.has_signal_to_handle() {
+                return Err(FsError::Interrupted);
+            }
+        }
+        Err(FsError::Again)
     }
 
     /// Write bytes at `offset` from `buf`, return the number of bytes written.
-    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
-        use core::str;
-        // we do not care the utf-8 things, we just want to print it!
-        let s = unsafe { str::from_utf8_unchecked(buf) };
-        print!("{}", s);
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if self.ldisc.lock().hung_up {
+            return Err(FsError::DeviceError);
+        }
+        let lflag = LocalModes::from_bits_truncate(self.termios.read().lflag);
+        if lflag.contains(LocalModes::TOSTOP) {
+            self.job_control(Signal::SIGTTOU)?;
+        }
+        self.output(buf);
         Ok(buf.len())
     }
 
@@ -120,9 +544,18 @@ impl INode for TtyINode {
             type Output = Result<PollStatus>;
 
             fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-                if self.tty.can_read() {
+                let termios = *self.tty.termios.read();
+                let ldisc = self.tty.ldisc.lock();
+                if ldisc.read_ready(&termios) {
+                    drop(ldisc);
                     return Poll::Ready(self.tty.poll());
                 }
+                if let Some(deadline) = ldisc.timeout(&termios) {
+                    let waker = cx.waker().clone();
+                    NAIVE_TIMER
+                        .lock()
+                        .add(deadline, Box::new(move |_| waker.wake()));
+                }
                 let waker = cx.waker().clone();
                 self.tty.eventbus.lock().subscribe(Box::new({
                     move |_| {
@@ -147,11 +580,53 @@ impl INode for TtyINode {
                 Ok(0)
             }
             TIOCSPGRP => {
+                self.check_controlling()?;
+                self.job_control(Signal::SIGTTOU)?;
                 let fpgid = unsafe { *(data as *const i32) };
+                // the group must be in our session, Busy is reported as EPERM
+                let session = *self.session.read();
+                let group = process_group(fpgid);
+                if group.is_empty() || group.iter().any(|p| Some(p.lock().sid) != session) {
+                    return Err(FsError::Busy);
+                }
                 *self.foreground_pgid.write() = fpgid;
                 info!("tty: set foreground process group to {}", fpgid);
                 Ok(0)
             }
+            TIOCSCTTY => {
+                let (sid, pgid, pid) = self.caller()?;
+                let mut session = self.session.write();
+                if *session == Some(sid) {
+                    return Ok(0);
+                }
+                // only a session leader can acquire a terminal, and root can
+                // steal it from another session with arg 1
+                let root = current_thread().map_or(false, |t| t.proc.lock().cred.is_root());
+                if sid as usize != pid || (session.is_some() && !(data == 1 && root)) {
+                    return Err(FsError::Busy);
+                }
+                *session = Some(sid);
+                *self.foreground_pgid.write() = pgid;
+                info!("tty: controlling terminal of session {}", sid);
+                Ok(0)
+            }
+            TIOCNOTTY => {
+                self.check_controlling()?;
+                let (sid, _, pid) = self.caller()?;
+                if sid as usize == pid {
+                    // the session leader gives up the terminal
+                    *self.session.write() = None;
+                    self.signal_foreground(Signal::SIGHUP);
+                    self.signal_foreground(Signal::SIGCONT);
+                }
+                Ok(0)
+            }
+            TIOCGSID => {
+                let sid = self.session.read().ok_or(FsError::IOCTLError)?;
+                let argp = data as *mut i32; // pid_t
+                unsafe { *argp = sid };
+                Ok(0)
+            }
             TIOCGWINSZ => {
                 let winsize = data as *mut Winsize;
                 unsafe {
@@ -159,6 +634,11 @@ impl INode for TtyINode {
                 }
                 Ok(0)
             }
+            TIOCSWINSZ => {
+                let winsize = unsafe { *(data as *const Winsize) };
+                self.set_winsize(winsize);
+                Ok(0)
+            }
             TCGETS => {
                 let termois = data as *mut Termios;
                 unsafe {
@@ -168,15 +648,28 @@ impl INode for TtyINode {
                 info!("get lfags: {:?}", lflag);
                 Ok(0)
             }
-            TCSETS => {
-                let termois = data as *const Termios;
-                unsafe {
-                    *self.termios.write() = *termois;
-                }
-                let lflag = LocalModes::from_bits_truncate(self.termios.read().lflag);
+            TCSETS | TCSETSW | TCSETSF => {
+                self.job_control(Signal::SIGTTOU)?;
+                let termois = unsafe { *(data as *const Termios) };
+                // output is never queued, so TCSETSW does not have to drain it
+                self.set_termios(termois, cmd == TCSETSF);
+                let lflag = LocalModes::from_bits_truncate(termois.lflag);
                 info!("set lfags: {:?}", lflag);
                 Ok(0)
             }
+            TCFLSH => {
+                if data == TCIFLUSH || data == TCIOFLUSH {
+                    let mut ldisc = self.ldisc.lock();
+                    ldisc.flush();
+                    self.notify_readers(&ldisc, &self.termios.read());
+                }
+                Ok(0)
+            }
+            FIONREAD => {
+                let argp = data as *mut i32;
+                unsafe { *argp = self.ldisc.lock().read_buf.len() as i32 };
+                Ok(0)
+            }
             _ => Err(NotSupported),
         }
     }
@@ -197,7 +690,7 @@ impl INode for TtyINode {
             nlinks: 1,
             uid: 0,
             gid: 0,
-            rdev: make_rdev(5, 0),
+            rdev: self.rdev,
         })
     }
 
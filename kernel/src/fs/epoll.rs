@@ -1,68 +1,619 @@
+//! epoll(7): readiness notification for many files
+//!
+//! Every registered file wakes up its interest when its state may have
+//! changed, and `epoll_wait` only polls the woken interests and the ones
+//! which have to be reported again, so the cost does not grow with the
+//! number of idle files.
+
+use crate::arch::timer::timer_now;
+use crate::consts::USEC_PER_TICK;
+use crate::drivers::SOCKET_ACTIVITY;
+use crate::fs::fcntl::O_CLOEXEC;
+use crate::fs::pipe::Pipe;
 use crate::fs::FileLike;
-use crate::process::Process;
-use crate::sync::SpinNoIrqLock;
+use crate::process::{current_thread, Process};
+use crate::sync::{wait_for_condition, Event, EventBus, SpinNoIrqLock as Mutex};
 use crate::syscall::{SysError, SysResult};
-use alloc::{collections::BTreeMap, collections::BTreeSet};
+use crate::trap::NAIVE_TIMER;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::future::Future;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use rcore_fs::vfs::{FileType, PollStatus};
+use woke::{waker_ref, Woke};
 
-pub struct EpollInstance {
-    pub events: BTreeMap<usize, EpollEvent>,
-    pub ready_list: SpinNoIrqLock<BTreeSet<usize>>,
-    pub new_ctl_list: SpinNoIrqLock<BTreeSet<usize>>,
+/// Files without a wakeup are polled again after this long
+pub const POLL_INTERVAL: Duration = Duration::from_micros(USEC_PER_TICK as u64);
+
+/// Maximum depth of nested epoll instances
+const EP_MAX_NESTS: usize = 4;
+
+/// Events allowed together with EPOLLEXCLUSIVE
+const EPOLLEXCLUSIVE_OK: u32 = EpollEvent::EPOLLIN
+    | EpollEvent::EPOLLOUT
+    | EpollEvent::EPOLLERR
+    | EpollEvent::EPOLLHUP
+    | EpollEvent::EPOLLWAKEUP
+    | EpollEvent::EPOLLET
+    | EpollEvent::EPOLLEXCLUSIVE;
+
+lazy_static! {
+    /// Interests with EPOLLEXCLUSIVE in each open file description
+    static ref EXCLUSIVE_GROUPS: Mutex<Vec<Weak<ExclusiveGroup>>> = Mutex::new(Vec::new());
 }
 
-impl Clone for EpollInstance {
-    fn clone(&self) -> Self {
-        EpollInstance::new(0)
+struct EpollInner {
+    interests: Mutex<BTreeMap<usize, Arc<EpollInterest>>>,
+    /// Interests woken up since the last wait
+    ready: Mutex<BTreeSet<usize>>,
+    /// Interests to poll again in the next wait without a wakeup
+    recheck: Mutex<BTreeSet<usize>>,
+    /// Toggled when an interest is woken up, for waiters and outer instances
+    eventbus: Arc<Mutex<EventBus>>,
+    /// Number of threads waiting on the instance
+    waiters: AtomicUsize,
+}
+
+impl EpollInner {
+    fn mark_ready(&self, fd: usize) {
+        self.ready.lock().insert(fd);
+        let mut eventbus = self.eventbus.lock();
+        eventbus.set(Event::READABLE);
+        eventbus.clear(Event::READABLE);
     }
 }
 
+#[derive(Clone)]
+pub struct EpollInstance {
+    inner: Arc<EpollInner>,
+    pub fd_cloexec: bool,
+}
+
 impl EpollInstance {
-    pub fn new(_flags: usize) -> Self {
-        return EpollInstance {
-            events: BTreeMap::new(),
-            ready_list: Default::default(),
-            new_ctl_list: Default::default(),
-        };
+    pub fn new(flags: usize) -> Self {
+        EpollInstance {
+            inner: Arc::new(EpollInner {
+                interests: Mutex::new(BTreeMap::new()),
+                ready: Mutex::new(BTreeSet::new()),
+                recheck: Mutex::new(BTreeSet::new()),
+                eventbus: EventBus::new(),
+                waiters: AtomicUsize::new(0),
+            }),
+            fd_cloexec: flags & O_CLOEXEC != 0,
+        }
+    }
+
+    pub fn dup(&self, fd_cloexec: bool) -> Self {
+        EpollInstance {
+            inner: self.inner.clone(),
+            fd_cloexec,
+        }
+    }
+
+    pub fn eventbus(&self) -> Arc<Mutex<EventBus>> {
+        self.inner.eventbus.clone()
+    }
+
+    /// Identity of the open file description
+    pub fn description(&self) -> Arc<dyn Any + Send + Sync> {
+        self.inner.clone()
     }
 
-    pub fn control(&mut self, op: usize, fd: usize, event: &EpollEvent) -> SysResult {
+    pub fn control(&self, op: usize, fd: usize, file: &FileLike, event: &EpollEvent) -> SysResult {
+        let events = event.events;
+        let exclusive = events & EpollEvent::EPOLLEXCLUSIVE != 0;
+        if let FileLike::EpollInstance(target) = file {
+            if Arc::ptr_eq(&target.inner, &self.inner) || exclusive {
+                return Err(SysError::EINVAL);
+            }
+        }
         match op as i32 {
             EPollCtlOp::ADD => {
-                self.events.insert(fd, event.clone());
-                self.new_ctl_list.lock().insert(fd);
+                if exclusive && events & !EPOLLEXCLUSIVE_OK != 0 {
+                    return Err(SysError::EINVAL);
+                }
+                match file {
+                    FileLike::EpollInstance(target) => {
+                        nest_check(&target.inner, &self.inner, 1)?;
+                    }
+                    FileLike::File(handle) => {
+                        // regular files and directories are always ready
+                        let type_ = handle.metadata()?.type_;
+                        if type_ == FileType::File || type_ == FileType::Dir {
+                            return Err(SysError::EPERM);
+                        }
+                    }
+                    _ => {}
+                }
+                // subscribe before taking the lock, a signalfd locks the process
+                let interest = EpollInterest::new(&self.inner, fd, file, *event);
+                let mut interests = self.inner.interests.lock();
+                // an interest of a closed file can not match
+                if interests.get(&fd).map_or(false, |old| old.is_file(file)) {
+                    return Err(SysError::EEXIST);
+                }
+                interests.insert(fd, interest);
             }
-
             EPollCtlOp::MOD => {
-                if self.events.get(&fd).is_some() {
-                    self.events.remove(&fd);
-                    self.events.insert(fd, event.clone());
-                    self.new_ctl_list.lock().insert(fd);
-                } else {
-                    return Err(SysError::EPERM);
+                let interest = match self.inner.interests.lock().get(&fd) {
+                    Some(interest) if interest.is_file(file) => interest.clone(),
+                    _ => return Err(SysError::ENOENT),
+                };
+                if exclusive || interest.group.is_some() {
+                    return Err(SysError::EINVAL);
                 }
+                let mut state = interest.state.lock();
+                state.event = *event;
+                state.last = 0;
+                state.disabled = false;
             }
-
             EPollCtlOp::DEL => {
-                if self.events.get(&fd).is_some() {
-                    self.events.remove(&fd);
-                } else {
-                    return Err(SysError::EPERM);
+                let mut interests = self.inner.interests.lock();
+                match interests.get(&fd) {
+                    Some(interest) if interest.is_file(file) => {
+                        interests.remove(&fd);
+                    }
+                    _ => return Err(SysError::ENOENT),
                 }
+                drop(interests);
+                self.inner.recheck.lock().remove(&fd);
+                return Ok(0);
             }
             _ => {
-                return Err(SysError::EPERM);
+                return Err(SysError::EINVAL);
             }
         }
+        // poll the file in the next wait
+        self.inner.mark_ready(fd);
         Ok(0)
     }
+
+    /// Wait for events of the current process's files until `deadline`
+    pub async fn wait(
+        &self,
+        maxevents: usize,
+        deadline: Option<Duration>,
+    ) -> Result<Vec<EpollEvent>, SysError> {
+        let thread = current_thread().unwrap();
+        let signal_bus = thread.proc.lock().eventbus.clone();
+        // toggled at the deadline, or to poll files without a wakeup
+        let timer_bus = EventBus::new();
+        if let Some(deadline) = deadline {
+            wake_at(&timer_bus, deadline);
+        }
+        loop {
+            let events = self.scan(maxevents, true);
+            if !events.is_empty() || deadline.map_or(false, |deadline| timer_now() >= deadline) {
+                return Ok(events);
+            }
+            if thread.has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            let poll_at = if self.inner.recheck.lock().is_empty() {
+                None
+            } else {
+                let poll_at = timer_now() + POLL_INTERVAL;
+                wake_at(&timer_bus, poll_at);
+                Some(poll_at)
+            };
+            let buses = vec![
+                self.inner.eventbus.clone(),
+                signal_bus.clone(),
+                timer_bus.clone(),
+            ];
+            self.inner.waiters.fetch_add(1, Ordering::SeqCst);
+            wait_for_condition(buses, || {
+                let now = timer_now();
+                !self.inner.ready.lock().is_empty()
+                    || thread.has_signal_to_handle()
+                    || deadline.map_or(false, |deadline| now >= deadline)
+                    || poll_at.map_or(false, |poll_at| now >= poll_at)
+            })
+            .await;
+            self.inner.waiters.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Readable if an event is ready, as a file in an outer instance
+    pub fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: !self.scan(1, false).is_empty(),
+            write: false,
+            error: false,
+        }
+    }
+
+    /// Poll the woken up interests and the ones to check again.
+    ///
+    /// With `consume`, the reported events are taken as in `epoll_wait`,
+    /// otherwise this only peeks whether an event is ready.
+    fn scan(&self, maxevents: usize, consume: bool) -> Vec<EpollEvent> {
+        let inner = &self.inner;
+        let woken = if consume {
+            core::mem::take(&mut *inner.ready.lock())
+        } else {
+            inner.ready.lock().clone()
+        };
+        let mut candidates = woken.clone();
+        candidates.extend(inner.recheck.lock().iter().cloned());
+
+        // find the files, and remove the interests of closed ones
+        let thread = current_thread().unwrap();
+        let proc = thread.proc.lock();
+        let mut interests = inner.interests.lock();
+        let mut targets = Vec::new();
+        let mut gone = Vec::new();
+        for fd in candidates {
+            let interest = match interests.get(&fd) {
+                Some(interest) => interest.clone(),
+                None => {
+                    gone.push(fd);
+                    continue;
+                }
+            };
+            if interest.is_closed(&proc.files) {
+                interests.remove(&fd);
+                gone.push(fd);
+                continue;
+            }
+            // the file may be only open in another process
+            if let Some(file) = interest.find_file(&proc.files) {
+                targets.push((interest, file));
+            }
+        }
+        drop(interests);
+        // release the process so that signalfd can inspect it
+        drop(proc);
+
+        let mut events = Vec::new();
+        let mut again = BTreeSet::new();
+        let mut recheck = BTreeSet::new();
+        let mut settled = BTreeSet::new();
+        for (interest, file) in targets {
+            let fd = interest.fd;
+            if events.len() >= maxevents {
+                if woken.contains(&fd) {
+                    again.insert(fd);
+                }
+                continue;
+            }
+            let (revents, no_wakeup) = interest.poll(&file);
+            let mut state = interest.state.lock();
+            if state.disabled {
+                settled.insert(fd);
+                continue;
+            }
+            let mask = state.event.events;
+            let revents = revents & (mask | EpollEvent::EPOLLERR | EpollEvent::EPOLLHUP);
+            let edge_triggered = mask & EpollEvent::EPOLLET != 0;
+            let edge = woken.contains(&fd) || revents & !state.last != 0;
+            let report = revents != 0 && (edge || !edge_triggered);
+            if report {
+                events.push(EpollEvent {
+                    events: revents,
+                    data: state.event.data,
+                });
+            }
+            if !consume {
+                continue;
+            }
+            state.last = revents;
+            if report && mask & EpollEvent::EPOLLONESHOT != 0 {
+                state.disabled = true;
+            }
+            // a level-triggered interest is reported until it is not ready
+            if !state.disabled && (no_wakeup || revents != 0 && !edge_triggered) {
+                recheck.insert(fd);
+            } else {
+                settled.insert(fd);
+            }
+        }
+        if consume {
+            let mut checks = inner.recheck.lock();
+            for fd in gone.iter().chain(settled.iter()) {
+                checks.remove(fd);
+            }
+            checks.extend(recheck);
+            drop(checks);
+            inner.ready.lock().extend(again);
+        }
+        events
+    }
+}
+
+/// Mark `epoll` ready for `fd` whenever its file may have changed
+struct EpollInterest {
+    fd: usize,
+    epoll: Weak<EpollInner>,
+    /// The open file description, none for a socket which goes with its fd
+    description: Option<Weak<dyn Any + Send + Sync>>,
+    description_id: usize,
+    /// Shares the wakeups of the file with other instances for EPOLLEXCLUSIVE
+    group: Option<Arc<ExclusiveGroup>>,
+    state: Mutex<InterestState>,
+}
+
+struct InterestState {
+    event: EpollEvent,
+    /// Events of the last poll, an edge-triggered interest reports new ones
+    last: u32,
+    /// A oneshot interest has been reported, until EPOLL_CTL_MOD
+    disabled: bool,
+}
+
+impl EpollInterest {
+    fn new(epoll: &Arc<EpollInner>, fd: usize, file: &FileLike, event: EpollEvent) -> Arc<Self> {
+        let description = file.description();
+        let description_id = description.as_ref().map_or(0, description_id);
+        let group = match description {
+            Some(_) if event.events & EpollEvent::EPOLLEXCLUSIVE != 0 => {
+                Some(ExclusiveGroup::join(file, description_id))
+            }
+            _ => None,
+        };
+        let interest = Arc::new(EpollInterest {
+            fd,
+            epoll: Arc::downgrade(epoll),
+            description: description.as_ref().map(Arc::downgrade),
+            description_id,
+            group: group.clone(),
+            state: Mutex::new(InterestState {
+                event,
+                last: 0,
+                disabled: false,
+            }),
+        });
+        match group {
+            Some(group) => group.interests.lock().push(Arc::downgrade(&interest)),
+            None => {
+                let weak = Arc::downgrade(&interest);
+                subscribe(file, move || match weak.upgrade() {
+                    Some(interest) => {
+                        interest.mark_ready();
+                        true
+                    }
+                    None => false,
+                });
+            }
+        }
+        interest
+    }
+
+    fn mark_ready(&self) {
+        if let Some(epoll) = self.epoll.upgrade() {
+            epoll.mark_ready(self.fd);
+        }
+    }
+
+    /// The open file description is closed, or the fd of a socket
+    fn is_closed(&self, files: &BTreeMap<usize, FileLike>) -> bool {
+        match &self.description {
+            Some(description) => description.strong_count() == 0,
+            None => !matches!(files.get(&self.fd), Some(FileLike::Socket(_))),
+        }
+    }
+
+    fn is_file(&self, file: &FileLike) -> bool {
+        match file.description() {
+            Some(description) => description_id(&description) == self.description_id,
+            None => self.description.is_none(),
+        }
+    }
+
+    /// The registered file, which may be at another fd after dup and close
+    fn find_file(&self, files: &BTreeMap<usize, FileLike>) -> Option<FileLike> {
+        match files.get(&self.fd) {
+            Some(file) if self.is_file(file) => Some(file.clone()),
+            _ if self.description.is_none() => None,
+            _ => files.values().find(|file| self.is_file(file)).cloned(),
+        }
+    }
+
+    fn waker(self: &Arc<Self>) -> Waker {
+        match &self.group {
+            Some(group) => (*waker_ref(group)).clone(),
+            None => (*waker_ref(self)).clone(),
+        }
+    }
+
+    /// Poll the events of `file`, and whether it has no wakeup for the next change
+    fn poll(self: &Arc<Self>, file: &FileLike) -> (u32, bool) {
+        const ERROR: PollStatus = PollStatus {
+            read: false,
+            write: false,
+            error: true,
+        };
+        let (status, no_wakeup) = match file {
+            FileLike::File(handle) => {
+                // a file which is not ready wakes up the interest when it changes
+                let waker = self.waker();
+                let mut context = Context::from_waker(&waker);
+                match Box::pin(handle.async_poll()).as_mut().poll(&mut context) {
+                    Poll::Ready(status) => (status.unwrap_or(ERROR), true),
+                    Poll::Pending => (PollStatus::default(), false),
+                }
+            }
+            _ => (file.poll().unwrap_or(ERROR), false),
+        };
+        let mut events = hangup_events(file);
+        if status.read {
+            events |= EpollEvent::EPOLLIN | EpollEvent::EPOLLRDNORM;
+        }
+        if status.write {
+            events |= EpollEvent::EPOLLOUT | EpollEvent::EPOLLWRNORM;
+        }
+        if status.error {
+            events |= EpollEvent::EPOLLERR;
+        }
+        (events, no_wakeup)
+    }
+}
+
+impl Woke for EpollInterest {
+    fn wake_by_ref(interest: &Arc<Self>) {
+        interest.mark_ready();
+    }
 }
 
-#[derive(Clone, Copy)]
+/// EPOLLEXCLUSIVE interests of one open file description in several instances,
+/// a wakeup of the file goes to only one of them
+struct ExclusiveGroup {
+    description_id: usize,
+    interests: Mutex<Vec<Weak<EpollInterest>>>,
+}
+
+impl ExclusiveGroup {
+    fn join(file: &FileLike, description_id: usize) -> Arc<Self> {
+        let mut groups = EXCLUSIVE_GROUPS.lock();
+        groups.retain(|group| group.strong_count() > 0);
+        let found = groups
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|group| group.description_id == description_id);
+        if let Some(group) = found {
+            group
+                .interests
+                .lock()
+                .retain(|interest| interest.strong_count() > 0);
+            return group;
+        }
+        let group = Arc::new(ExclusiveGroup {
+            description_id,
+            interests: Mutex::new(Vec::new()),
+        });
+        groups.push(Arc::downgrade(&group));
+        drop(groups);
+        let weak = Arc::downgrade(&group);
+        subscribe(file, move || match weak.upgrade() {
+            Some(group) => {
+                group.wake_one();
+                true
+            }
+            None => false,
+        });
+        group
+    }
+
+    /// Prefer an instance with a waiter
+    fn wake_one(&self) {
+        let interests: Vec<_> = self
+            .interests
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let waiting = interests.iter().find(|interest| {
+            interest
+                .epoll
+                .upgrade()
+                .map_or(false, |epoll| epoll.waiters.load(Ordering::SeqCst) > 0)
+        });
+        if let Some(interest) = waiting.or_else(|| interests.first()) {
+            interest.mark_ready();
+        }
+    }
+}
+
+impl Woke for ExclusiveGroup {
+    fn wake_by_ref(group: &Arc<Self>) {
+        group.wake_one();
+    }
+}
+
+/// Call `wake` whenever `file` may have changed, until it returns false.
+///
+/// Files are not subscribed here, each poll of them registers a wakeup.
+fn subscribe(file: &FileLike, wake: impl Fn() -> bool + Send + 'static) {
+    let eventbus = match file {
+        FileLike::SignalFd(_) => current_thread().unwrap().proc.lock().eventbus.clone(),
+        FileLike::Socket(socket) if socket.eventbus().is_none() => {
+            SOCKET_ACTIVITY.register_epoll_list(Box::new(move || !wake()));
+            return;
+        }
+        _ => match file.eventbus() {
+            Some(eventbus) => eventbus,
+            None => return,
+        },
+    };
+    eventbus.lock().subscribe(Box::new(move |_| !wake()));
+}
+
+/// EPOLLHUP and EPOLLRDHUP, which `PollStatus` can not tell
+fn hangup_events(file: &FileLike) -> u32 {
+    match file {
+        FileLike::File(handle) => match handle.inode().as_any_ref().downcast_ref::<Pipe>() {
+            Some(pipe) if pipe.peer_closed() => {
+                if pipe.is_read_end() {
+                    EpollEvent::EPOLLHUP
+                } else {
+                    EpollEvent::EPOLLERR
+                }
+            }
+            _ => 0,
+        },
+        FileLike::Socket(socket) if socket.peer_closed() => {
+            EpollEvent::EPOLLIN | EpollEvent::EPOLLRDNORM | EpollEvent::EPOLLRDHUP
+        }
+        _ => 0,
+    }
+}
+
+/// Address of an open file description, to tell it from others
+fn description_id(description: &Arc<dyn Any + Send + Sync>) -> usize {
+    &**description as *const (dyn Any + Send + Sync) as *const u8 as usize
+}
+
+/// Adding `epoll` into `root` must not make a loop or nest too deep
+fn nest_check(
+    epoll: &Arc<EpollInner>,
+    root: &Arc<EpollInner>,
+    depth: usize,
+) -> Result<(), SysError> {
+    if Arc::ptr_eq(epoll, root) || depth > EP_MAX_NESTS {
+        return Err(SysError::ELOOP);
+    }
+    let nested: Vec<_> = epoll
+        .interests
+        .lock()
+        .values()
+        .filter_map(|interest| interest.description.as_ref()?.upgrade())
+        .filter_map(|description| description.downcast::<EpollInner>().ok())
+        .collect();
+    for nested in nested.iter() {
+        nest_check(nested, root, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Toggle `eventbus` at `time`, to wake up its waiters
+pub fn wake_at(eventbus: &Arc<Mutex<EventBus>>, time: Duration) {
+    let eventbus = eventbus.clone();
+    NAIVE_TIMER.lock().add(
+        time,
+        Box::new(move |_| {
+            let mut eventbus = eventbus.lock();
+            eventbus.set(Event::READABLE);
+            eventbus.clear(Event::READABLE);
+        }),
+    );
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
 pub struct EpollData {
     _ptr: u64,
 }
 
-#[derive(Clone)]
+/// Linux struct epoll_event, which is packed on x86_64
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Clone, Copy, Default)]
 pub struct EpollEvent {
     pub events: u32,     /* Epoll events */
     pub data: EpollData, /* User data variable */
@@ -97,28 +648,17 @@ impl EpollEvent {
 
 pub struct EPollCtlOp;
 impl EPollCtlOp {
-    const ADD: i32 = 1; /* Add a file descriptor to the interface.  */
-    const DEL: i32 = 2; /* Remove a file descriptor from the interface.  */
-    const MOD: i32 = 3; /* Change file descriptor epoll_event structure.  */
+    pub const ADD: i32 = 1; /* Add a file descriptor to the interface.  */
+    pub const DEL: i32 = 2; /* Remove a file descriptor from the interface.  */
+    pub const MOD: i32 = 3; /* Change file descriptor epoll_event structure.  */
 }
 
 impl Process {
-    pub fn get_epoll_instance_mut(&mut self, fd: usize) -> Result<&mut EpollInstance, SysError> {
-        match self.get_file_like(fd)? {
-            FileLike::EpollInstance(instance) => Ok(instance),
-            _ => Err(SysError::EPERM),
-        }
-    }
-
-    pub fn get_epoll_instance(&self, fd: usize) -> Result<&EpollInstance, SysError> {
+    pub fn get_epoll_instance(&self, fd: usize) -> Result<EpollInstance, SysError> {
         match self.files.get(&fd) {
-            Some(file_like) => match file_like {
-                FileLike::EpollInstance(instance) => Ok(&instance),
-                _ => Err(SysError::EPERM),
-            },
-            None => {
-                return Err(SysError::EPERM);
-            }
+            Some(FileLike::EpollInstance(instance)) => Ok(instance.clone()),
+            Some(_) => Err(SysError::EINVAL),
+            None => Err(SysError::EBADF),
         }
     }
 }
@@ -0,0 +1,158 @@
+//! eventfd(2): a 64-bit counter to notify events between threads
+
+use crate::fs::fcntl::{O_CLOEXEC, O_NONBLOCK};
+use crate::process::current_thread;
+use crate::sync::{wait_for_condition, Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::syscall::{SysError, SysResult};
+use alloc::sync::Arc;
+use alloc::vec;
+use bitflags::*;
+use core::any::Any;
+use core::convert::TryInto;
+use core::mem::size_of;
+use rcore_fs::vfs::PollStatus;
+use spin::RwLock;
+
+bitflags! {
+    pub struct EventFdFlags: usize {
+        /// Each read decrements the counter by one
+        const SEMAPHORE = 1;
+        const NONBLOCK = O_NONBLOCK;
+        const CLOEXEC = O_CLOEXEC;
+    }
+}
+
+/// The counter can not reach this value
+const COUNT_LIMIT: u64 = core::u64::MAX;
+
+struct EventFdInner {
+    count: Mutex<u64>,
+    semaphore: bool,
+    nonblock: RwLock<bool>,
+    /// READABLE and WRITABLE follow the counter
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+#[derive(Clone)]
+pub struct EventFd {
+    inner: Arc<EventFdInner>,
+    pub fd_cloexec: bool,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, flags: EventFdFlags) -> Self {
+        let eventfd = EventFd {
+            inner: Arc::new(EventFdInner {
+                count: Mutex::new(initval),
+                semaphore: flags.contains(EventFdFlags::SEMAPHORE),
+                nonblock: RwLock::new(flags.contains(EventFdFlags::NONBLOCK)),
+                eventbus: EventBus::new(),
+            }),
+            fd_cloexec: flags.contains(EventFdFlags::CLOEXEC),
+        };
+        eventfd.update(initval);
+        eventfd
+    }
+
+    pub fn dup(&self, fd_cloexec: bool) -> Self {
+        EventFd {
+            inner: self.inner.clone(),
+            fd_cloexec,
+        }
+    }
+
+    /// Identity of the open file description
+    pub fn description(&self) -> Arc<dyn Any + Send + Sync> {
+        self.inner.clone()
+    }
+
+    pub fn nonblock(&self) -> bool {
+        *self.inner.nonblock.read()
+    }
+
+    pub fn set_nonblock(&self, nonblock: bool) {
+        *self.inner.nonblock.write() = nonblock;
+    }
+
+    pub fn eventbus(&self) -> Arc<Mutex<EventBus>> {
+        self.inner.eventbus.clone()
+    }
+
+    /// Tell waiters about the new value of the counter
+    fn update(&self, count: u64) {
+        let mut set = Event::empty();
+        if count > 0 {
+            set |= Event::READABLE;
+        }
+        if count < COUNT_LIMIT - 1 {
+            set |= Event::WRITABLE;
+        }
+        self.inner
+            .eventbus
+            .lock()
+            .change(Event::READABLE | Event::WRITABLE, set);
+    }
+
+    /// Take the counter, or one from it in semaphore mode
+    fn try_read(&self) -> Option<u64> {
+        let mut count = self.inner.count.lock();
+        if *count == 0 {
+            return None;
+        }
+        let value = if self.inner.semaphore { 1 } else { *count };
+        *count -= value;
+        self.update(*count);
+        Some(value)
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> SysResult {
+        if buf.len() < size_of::<u64>() {
+            return Err(SysError::EINVAL);
+        }
+        let thread = current_thread().unwrap();
+        loop {
+            if let Some(value) = self.try_read() {
+                buf[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+                return Ok(size_of::<u64>());
+            }
+            if self.nonblock() {
+                return Err(SysError::EAGAIN);
+            }
+            if thread.has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            let signal_bus = thread.proc.lock().eventbus.clone();
+            wait_for_condition(vec![self.inner.eventbus.clone(), signal_bus], || {
+                *self.inner.count.lock() > 0 || thread.has_signal_to_handle()
+            })
+            .await;
+        }
+    }
+
+    /// Add to the counter, writes do not block and fail with EAGAIN on overflow
+    pub fn write(&self, buf: &[u8]) -> SysResult {
+        if buf.len() < size_of::<u64>() {
+            return Err(SysError::EINVAL);
+        }
+        let value = u64::from_ne_bytes(buf[..size_of::<u64>()].try_into().unwrap());
+        if value == COUNT_LIMIT {
+            return Err(SysError::EINVAL);
+        }
+        let mut count = self.inner.count.lock();
+        if COUNT_LIMIT - 1 - *count < value {
+            return Err(SysError::EAGAIN);
+        }
+        *count += value;
+        self.update(*count);
+        Ok(size_of::<u64>())
+    }
+
+    pub fn poll(&self) -> PollStatus {
+        let count = *self.inner.count.lock();
+        PollStatus {
+            read: count > 0,
+            write: count < COUNT_LIMIT - 1,
+            error: false,
+        }
+    }
+}
@@ -2,15 +2,19 @@
 
 use crate::memory::GlobalFrameAlloc;
 use crate::process::{current_thread, INodeForMap};
-use crate::syscall::{MmapProt, SysResult, TimeSpec};
+use crate::syscall::{MmapFlags, MmapProt, SysResult, TimeSpec};
 use alloc::{string::String, sync::Arc};
+use core::any::Any;
 use core::fmt;
 
 use rcore_fs::vfs::FsError::{Interrupted, NotSupported};
 use rcore_fs::vfs::{FileType, FsError, INode, MMapArea, Metadata, PollStatus, Result};
-use rcore_memory::memory_set::handler::File;
+use rcore_memory::memory_set::handler::{File, SharedFile};
+use rcore_memory::PAGE_SIZE;
 
 use crate::fs::fcntl::{O_APPEND, O_NONBLOCK};
+use crate::fs::mount;
+use crate::fs::page_cache::{self, INodePages};
 use crate::sync::SpinLock as Mutex;
 use crate::syscall::SysError::{EAGAIN, ESPIPE};
 use bitflags::_core::cell::Cell;
@@ -45,6 +49,8 @@ pub struct FileHandle {
     pub path: String,
     pub pipe: bool, // specify if this is pipe, socket, or FIFO
     pub fd_cloexec: bool,
+    /// Identity of the mounted file system the file was opened on
+    pub mount: Option<usize>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -72,6 +78,7 @@ impl FileHandle {
         fd_cloexec: bool,
     ) -> Self {
         return FileHandle {
+            mount: mount::fs_of(&inode),
             inode,
             description: OpenFileDescription::create(options),
             path,
@@ -88,6 +95,7 @@ impl FileHandle {
             path: self.path.clone(),
             pipe: self.pipe,
             fd_cloexec, // this field do not share
+            mount: self.mount,
         }
     }
 
@@ -115,6 +123,9 @@ impl FileHandle {
         if !self.description.read().options.read {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
+        if self.inode.metadata()?.type_ == FileType::File {
+            return page_cache::read_at(&self.inode, offset, buf);
+        }
         if !self.description.read().options.nonblock {
             // block
             loop {
@@ -152,7 +163,10 @@ impl FileHandle {
         if !self.description.read().options.write {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        let len = self.inode.write_at(offset, buf)?;
+        let len = match self.inode.metadata()?.type_ {
+            FileType::File => page_cache::write_at(&self.inode, offset, buf)?,
+            _ => self.inode.write_at(offset, buf)?,
+        };
         TimeSpec::update(&self.inode);
         Ok(len)
     }
@@ -171,15 +185,17 @@ impl FileHandle {
         if !self.description.read().options.write {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        self.inode.resize(len as usize)?;
+        page_cache::resize(&self.inode, len as usize)?;
         Ok(())
     }
 
     pub fn sync_all(&mut self) -> Result<()> {
+        page_cache::sync(&self.inode);
         self.inode.sync_all()
     }
 
     pub fn sync_data(&mut self) -> Result<()> {
+        page_cache::sync(&self.inode);
         self.inode.sync_data()
     }
 
@@ -230,7 +246,28 @@ impl FileHandle {
         match self.inode.metadata()?.type_ {
             FileType::File => {
                 let prot = MmapProt::from_bits_truncate(area.prot);
+                let flags = MmapFlags::from_bits_truncate(area.flags);
                 let thread = current_thread().unwrap();
+                if flags.contains(MmapFlags::SHARED) {
+                    if area.offset % PAGE_SIZE != 0 {
+                        return Err(FsError::InvalidParam);
+                    }
+                    // write access was checked by `FileLike::mmap`
+                    let writable = self.description.read().options.write;
+                    thread.vm.lock().push(
+                        area.start_vaddr,
+                        area.end_vaddr,
+                        prot.to_attr(),
+                        SharedFile {
+                            file: INodePages::new(self.inode.clone())?,
+                            mem_start: area.start_vaddr,
+                            file_start: area.offset,
+                            writable,
+                        },
+                        "mmap_file_shared",
+                    );
+                    return Ok(());
+                }
                 thread.vm.lock().push(
                     area.start_vaddr,
                     area.end_vaddr,
@@ -251,9 +288,18 @@ impl FileHandle {
         }
     }
 
+    /// Identity of the open file description
+    pub fn description(&self) -> Arc<dyn Any + Send + Sync> {
+        self.description.clone()
+    }
+
     pub fn inode(&self) -> Arc<dyn INode> {
         self.inode.clone()
     }
+
+    pub fn writable(&self) -> bool {
+        self.description.read().options.write
+    }
 }
 
 impl fmt::Debug for FileHandle {
@@ -1,19 +1,32 @@
+use core::any::Any;
 use core::fmt;
+use core::ops::{Deref, DerefMut};
 
 use super::ioctl::*;
 use super::FileHandle;
 use crate::fs::epoll::EpollInstance;
-use crate::net::Socket;
-use crate::syscall::{SysError, SysResult};
+use crate::fs::eventfd::EventFd;
+use crate::fs::signalfd::SignalFd;
+use crate::fs::timerfd::TimerFd;
+use crate::net::{block_on_socket, Socket};
+use crate::process::current_thread;
+use crate::sync::{wait_for_condition, Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::syscall::{MmapFlags, MmapProt, SysError, SysResult};
 use alloc::boxed::Box;
-use rcore_fs::vfs::{MMapArea, PollStatus};
+use alloc::sync::Arc;
+use alloc::vec;
+use rcore_fs::vfs::{FsError, MMapArea, PollStatus};
+use spin::RwLock;
 
 // TODO: merge FileLike to FileHandle ?
 #[derive(Clone)]
 pub enum FileLike {
     File(FileHandle),
-    Socket(Box<dyn Socket>),
+    Socket(SocketFile),
     EpollInstance(EpollInstance),
+    EventFd(EventFd),
+    TimerFd(TimerFd),
+    SignalFd(SignalFd),
 }
 
 impl FileLike {
@@ -21,43 +34,130 @@ impl FileLike {
         use FileLike::*;
         match self {
             File(file) => File(file.dup(fd_cloexec)),
-            Socket(s) => Socket(s.clone()),
-            EpollInstance(e) => EpollInstance(e.clone()),
+            Socket(s) => Socket(s.dup(fd_cloexec)),
+            EpollInstance(e) => EpollInstance(e.dup(fd_cloexec)),
+            EventFd(e) => EventFd(e.dup(fd_cloexec)),
+            TimerFd(t) => TimerFd(t.dup(fd_cloexec)),
+            SignalFd(s) => SignalFd(s.dup(fd_cloexec)),
+        }
+    }
+
+    /// Whether the file descriptor is closed on exec
+    pub fn fd_cloexec(&self) -> bool {
+        match self {
+            FileLike::File(file) => file.fd_cloexec,
+            FileLike::EventFd(eventfd) => eventfd.fd_cloexec,
+            FileLike::TimerFd(timerfd) => timerfd.fd_cloexec,
+            FileLike::SignalFd(signalfd) => signalfd.fd_cloexec,
+            FileLike::EpollInstance(epoll) => epoll.fd_cloexec,
+            FileLike::Socket(socket) => socket.fd_cloexec,
+            _ => false,
+        }
+    }
+
+    pub fn set_fd_cloexec(&mut self, fd_cloexec: bool) {
+        match self {
+            FileLike::File(file) => file.fd_cloexec = fd_cloexec,
+            FileLike::EventFd(eventfd) => eventfd.fd_cloexec = fd_cloexec,
+            FileLike::TimerFd(timerfd) => timerfd.fd_cloexec = fd_cloexec,
+            FileLike::SignalFd(signalfd) => signalfd.fd_cloexec = fd_cloexec,
+            FileLike::EpollInstance(epoll) => epoll.fd_cloexec = fd_cloexec,
+            FileLike::Socket(socket) => socket.fd_cloexec = fd_cloexec,
+            _ => {}
+        }
+    }
+
+    /// O_NONBLOCK of sockets, eventfd, timerfd and signalfd
+    pub fn nonblock(&self) -> bool {
+        match self {
+            FileLike::EventFd(eventfd) => eventfd.nonblock(),
+            FileLike::TimerFd(timerfd) => timerfd.nonblock(),
+            FileLike::SignalFd(signalfd) => signalfd.nonblock(),
+            FileLike::Socket(socket) => socket.nonblock(),
+            _ => false,
+        }
+    }
+
+    pub fn set_nonblock(&self, nonblock: bool) {
+        match self {
+            FileLike::EventFd(eventfd) => eventfd.set_nonblock(nonblock),
+            FileLike::TimerFd(timerfd) => timerfd.set_nonblock(nonblock),
+            FileLike::SignalFd(signalfd) => signalfd.set_nonblock(nonblock),
+            FileLike::Socket(socket) => socket.set_nonblock(nonblock),
+            _ => {}
         }
     }
 
     pub async fn read(&mut self, buf: &mut [u8]) -> SysResult {
         let len = match self {
             FileLike::File(file) => file.read(buf).await?,
-            FileLike::Socket(socket) => socket.read(buf).0?,
+            FileLike::Socket(socket) => {
+                let nonblock = socket.nonblock();
+                let socket = &***socket;
+                block_on_socket(socket, Event::READABLE, nonblock, || socket.read(buf).0).await?
+            }
             FileLike::EpollInstance(_) => {
                 return Err(SysError::ENOSYS);
             }
+            FileLike::EventFd(eventfd) => eventfd.read(buf).await?,
+            FileLike::TimerFd(timerfd) => timerfd.read(buf).await?,
+            FileLike::SignalFd(signalfd) => signalfd.read(buf).await?,
         };
         Ok(len)
     }
-    pub fn write(&mut self, buf: &[u8]) -> SysResult {
+    pub async fn write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
             FileLike::File(file) => file.write(buf)?,
-            FileLike::Socket(socket) => socket.write(buf, None)?,
+            FileLike::Socket(socket) => {
+                let nonblock = socket.nonblock();
+                let socket = &***socket;
+                block_on_socket(socket, Event::WRITABLE, nonblock, || {
+                    socket.write(buf, None)
+                })
+                .await?
+            }
             FileLike::EpollInstance(_) => {
                 return Err(SysError::ENOSYS);
             }
+            FileLike::EventFd(eventfd) => eventfd.write(buf)?,
+            FileLike::TimerFd(_) | FileLike::SignalFd(_) => {
+                return Err(SysError::EINVAL);
+            }
         };
         Ok(len)
     }
     pub fn ioctl(&mut self, request: usize, arg1: usize, arg2: usize, arg3: usize) -> SysResult {
         match self {
-            FileLike::File(file) => file.io_control(request as u32, arg1).map_err(Into::into),
+            FileLike::File(file) => {
+                file.io_control(request as u32, arg1)
+                    .map_err(|err| match err {
+                        // terminals refuse job control this way, as FsError has no EPERM
+                        FsError::Busy if request == TIOCSPGRP || request == TIOCSCTTY => {
+                            SysError::EPERM
+                        }
+                        err => err.into(),
+                    })
+            }
             FileLike::Socket(socket) => socket.ioctl(request, arg1, arg2, arg3),
-            FileLike::EpollInstance(_) => {
+            _ => {
                 return Err(SysError::ENOSYS);
             }
         }
     }
     pub fn mmap(&mut self, area: MMapArea) -> SysResult {
         match self {
-            FileLike::File(file) => file.mmap(area)?,
+            FileLike::File(file) => {
+                let prot = MmapProt::from_bits_truncate(area.prot);
+                let flags = MmapFlags::from_bits_truncate(area.flags);
+                // shared writable mappings write to the file
+                if flags.contains(MmapFlags::SHARED)
+                    && prot.contains(MmapProt::WRITE)
+                    && !file.writable()
+                {
+                    return Err(SysError::EACCES);
+                }
+                file.mmap(area)?
+            }
             _ => return Err(SysError::ENOSYS),
         };
         Ok(0)
@@ -69,33 +169,122 @@ impl FileLike {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
-            FileLike::EpollInstance(_) => {
-                return Err(SysError::ENOSYS);
-            }
+            FileLike::EpollInstance(epoll) => epoll.poll(),
+            FileLike::EventFd(eventfd) => eventfd.poll(),
+            FileLike::TimerFd(timerfd) => timerfd.poll(),
+            FileLike::SignalFd(signalfd) => signalfd.poll(),
         };
         Ok(status)
     }
     pub async fn async_poll(&self) -> Result<PollStatus, SysError> {
         let status = match self {
             FileLike::File(file) => file.async_poll().await?,
-            FileLike::Socket(socket) => {
+            FileLike::Socket(socket) if socket.eventbus().is_none() => {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
-            FileLike::EpollInstance(_) => {
-                return Err(SysError::ENOSYS);
+            FileLike::Socket(_)
+            | FileLike::EpollInstance(_)
+            | FileLike::EventFd(_)
+            | FileLike::TimerFd(_)
+            | FileLike::SignalFd(_) => {
+                // wake up on their own events or any signal to the process
+                let mut buses = vec![current_thread().unwrap().proc.lock().eventbus.clone()];
+                buses.extend(self.eventbus());
+                wait_for_condition(buses, || {
+                    self.poll()
+                        .map_or(true, |status| status.read || status.write || status.error)
+                })
+                .await;
+                self.poll()?
             }
         };
         Ok(status)
     }
+
+    /// The event bus to wait on, if the file has one
+    pub fn eventbus(&self) -> Option<Arc<Mutex<EventBus>>> {
+        match self {
+            FileLike::EventFd(eventfd) => Some(eventfd.eventbus()),
+            FileLike::TimerFd(timerfd) => Some(timerfd.eventbus()),
+            FileLike::EpollInstance(epoll) => Some(epoll.eventbus()),
+            FileLike::Socket(socket) => socket.eventbus(),
+            _ => None,
+        }
+    }
+
+    /// Identity of the open file description shared by dup, none for sockets
+    /// whose clones share nothing
+    pub fn description(&self) -> Option<Arc<dyn Any + Send + Sync>> {
+        match self {
+            FileLike::File(file) => Some(file.description()),
+            FileLike::Socket(socket) => socket.description(),
+            FileLike::EpollInstance(epoll) => Some(epoll.description()),
+            FileLike::EventFd(eventfd) => Some(eventfd.description()),
+            FileLike::TimerFd(timerfd) => Some(timerfd.description()),
+            FileLike::SignalFd(signalfd) => Some(signalfd.description()),
+        }
+    }
+}
+
+/// A socket as an open file, with the flags of its file descriptor
+#[derive(Clone)]
+pub struct SocketFile {
+    socket: Box<dyn Socket>,
+    /// O_NONBLOCK, shared by dup and fork
+    nonblock: Arc<RwLock<bool>>,
+    pub fd_cloexec: bool,
+}
+
+impl SocketFile {
+    pub fn new(socket: Box<dyn Socket>, nonblock: bool, fd_cloexec: bool) -> Self {
+        SocketFile {
+            socket,
+            nonblock: Arc::new(RwLock::new(nonblock)),
+            fd_cloexec,
+        }
+    }
+
+    pub fn dup(&self, fd_cloexec: bool) -> Self {
+        SocketFile {
+            socket: self.socket.clone(),
+            nonblock: self.nonblock.clone(),
+            fd_cloexec,
+        }
+    }
+
+    pub fn nonblock(&self) -> bool {
+        *self.nonblock.read()
+    }
+
+    pub fn set_nonblock(&self, nonblock: bool) {
+        *self.nonblock.write() = nonblock;
+    }
+}
+
+impl Deref for SocketFile {
+    type Target = Box<dyn Socket>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.socket
+    }
+}
+
+impl DerefMut for SocketFile {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.socket
+    }
 }
 
 impl fmt::Debug for FileLike {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FileLike::File(file) => write!(f, "File({:?})", file),
-            FileLike::Socket(socket) => write!(f, "Socket({:?})", socket),
+            FileLike::Socket(socket) => write!(f, "Socket({:?})", socket.socket),
             FileLike::EpollInstance(_) => write!(f, "EpollInstance()"),
+            FileLike::EventFd(_) => write!(f, "EventFd()"),
+            FileLike::TimerFd(_) => write!(f, "TimerFd()"),
+            FileLike::SignalFd(_) => write!(f, "SignalFd()"),
         }
     }
 }
@@ -15,6 +15,26 @@ pub const TCSETS: usize = 0x5402;
 #[cfg(target_arch = "mips")]
 pub const TCSETS: usize = 0x540E;
 
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETSW: usize = 0x5403;
+#[cfg(target_arch = "mips")]
+pub const TCSETSW: usize = 0x540F;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETSF: usize = 0x5404;
+#[cfg(target_arch = "mips")]
+pub const TCSETSF: usize = 0x5410;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TCFLSH: usize = 0x540B;
+#[cfg(target_arch = "mips")]
+pub const TCFLSH: usize = 0x5407;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSCTTY: usize = 0x540E;
+#[cfg(target_arch = "mips")]
+pub const TIOCSCTTY: usize = 0x5480;
+
 #[cfg(not(target_arch = "mips"))]
 pub const TIOCGPGRP: usize = 0x540F;
 // _IOR('t', 119, int)
@@ -33,6 +53,40 @@ pub const TIOCGWINSZ: usize = 0x5413;
 #[cfg(target_arch = "mips")]
 pub const TIOCGWINSZ: usize = 0x4_008_74_68;
 
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSWINSZ: usize = 0x5414;
+// _IOW('t', 103, struct winsize)
+#[cfg(target_arch = "mips")]
+pub const TIOCSWINSZ: usize = 0x8_008_74_67;
+
+#[cfg(not(target_arch = "mips"))]
+pub const FIONREAD: usize = 0x541B;
+#[cfg(target_arch = "mips")]
+pub const FIONREAD: usize = 0x467F;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCNOTTY: usize = 0x5422;
+#[cfg(target_arch = "mips")]
+pub const TIOCNOTTY: usize = 0x5471;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCGSID: usize = 0x5429;
+// _IOR('t', 22, pid_t)
+#[cfg(target_arch = "mips")]
+pub const TIOCGSID: usize = 0x4_004_74_16;
+
+// _IOR('T', 0x30, unsigned int)
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCGPTN: usize = 0x8_004_54_30;
+#[cfg(target_arch = "mips")]
+pub const TIOCGPTN: usize = 0x4_004_54_30;
+
+// _IOW('T', 0x31, int)
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSPTLCK: usize = 0x4_004_54_31;
+#[cfg(target_arch = "mips")]
+pub const TIOCSPTLCK: usize = 0x8_004_54_31;
+
 #[cfg(not(target_arch = "mips"))]
 pub const FIONCLEX: usize = 0x5450;
 #[cfg(target_arch = "mips")]
@@ -50,7 +104,54 @@ pub const FIONBIO: usize = 0x5421;
 #[cfg(target_arch = "mips")]
 pub const FIONBIO: usize = 0x667E;
 
+// TCFLSH queue selectors
+pub const TCIFLUSH: usize = 0;
+pub const TCOFLUSH: usize = 1;
+pub const TCIOFLUSH: usize = 2;
+
 // ref: https://www.man7.org/linux/man-pages/man3/termios.3.html
+// c_iflag constants
+bitflags! {
+    pub struct InputModes : u32 {
+        const IGNBRK = 0o000001;
+        const BRKINT = 0o000002;
+        const IGNPAR = 0o000004;
+        const PARMRK = 0o000010;
+        const INPCK = 0o000020;
+        const ISTRIP = 0o000040;
+        const INLCR = 0o000100;
+        const IGNCR = 0o000200;
+        const ICRNL = 0o000400;
+        const IUCLC = 0o001000;
+        const IXON = 0o002000;
+        const IXANY = 0o004000;
+        const IXOFF = 0o010000;
+        const IMAXBEL = 0o020000;
+        const IUTF8 = 0o040000;
+    }
+}
+
+// c_oflag constants
+bitflags! {
+    pub struct OutputModes : u32 {
+        const OPOST = 0o000001;
+        const ONLCR = 0o000004;
+    }
+}
+
+// indices of c_cc
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+pub const VEOL2: usize = 16;
+
 // c_lflag constants
 bitflags! {
     pub struct LocalModes : u32 {
@@ -111,12 +212,12 @@ impl Default for Termios {
                 17,  // VSTART
                 19,  // VSTOP
                 26,  // VSUSP Ctrl-Z
-                255, // VEOL
+                0,   // VEOL
                 18,  // VREPAINT
                 15,  // VDISCARD
                 23,  // VWERASE
                 22,  // VLNEXT
-                255, // VEOL2
+                0,   // VEOL2
                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             ],
             ispeed: 0,
@@ -126,7 +227,7 @@ impl Default for Termios {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq)]
 pub struct Winsize {
     row: u16,
     ws_col: u16,
@@ -6,12 +6,11 @@ use rcore_fs_devfs::{
     DevFS,
 };
 use rcore_fs_mountfs::MountFS;
-use rcore_fs_ramfs::RamFS;
 use rcore_fs_sfs::{INodeImpl, SimpleFileSystem};
 
-use self::devfs::{Fbdev, RandomINode};
+use self::devfs::{DevPtsDirINode, Fbdev, Ptmx, RandomINode};
 
-pub use self::devfs::{Serial, ShmINode, TTY};
+pub use self::devfs::{open_pty, DevPtsFS, Serial, ShmINode, TTY};
 pub use self::file::*;
 pub use self::file_like::*;
 pub use self::pipe::Pipe;
@@ -21,12 +20,18 @@ use crate::drivers::{BlockDriver, BlockDriverWrapper};
 mod devfs;
 mod device;
 pub mod epoll;
+pub mod eventfd;
 pub mod fcntl;
 mod file;
 mod file_like;
 pub mod ioctl;
+pub mod mount;
+pub mod page_cache;
 mod pipe;
+mod procfs;
 mod pseudo;
+pub mod signalfd;
+pub mod timerfd;
 
 // Hard link user programs
 #[cfg(feature = "link_user")]
@@ -72,46 +77,86 @@ lazy_static! {
         let sfs = SimpleFileSystem::open(device).expect("failed to open SFS");
         let rootfs = MountFS::new(sfs);
         let root = rootfs.root_inode();
-
-        // create DevFS
-        let devfs = DevFS::new();
-        devfs.add("null", Arc::new(NullINode::default())).expect("failed to mknod /dev/null");
-        devfs.add("zero", Arc::new(ZeroINode::default())).expect("failed to mknod /dev/zero");
-        devfs.add("random", Arc::new(RandomINode::new(false))).expect("failed to mknod /dev/random");
-        devfs.add("urandom", Arc::new(RandomINode::new(true))).expect("failed to mknod /dev/urandom");
-        devfs.add("tty", TTY.clone()).expect("failed to mknod /dev/tty");
-        devfs.add("fb0", Arc::new(Fbdev::default())).expect("failed to mknod /dev/fb0");
-        devfs.add("shm", Arc::new(ShmINode::default())).expect("failed to mkdir shm");
-        for (i, serial) in Serial::wrap_all_serial_devices().into_iter().enumerate(){
-            devfs.add(&format!("ttyS{}", i), Arc::new(serial)).expect("failed to add a serial");
-        }
-
-
-        #[cfg(feature = "hypervisor")]
-        devfs.add("rvm", Arc::new(crate::rvm::RvmINode::new())).expect("failed to mknod /dev/rvm");
+        #[cfg(not(feature = "link_user"))]
+        let source = "/dev/sda";
+        #[cfg(feature = "link_user")]
+        let source = "rootfs";
+        mount::mount_root(source, "sfs", rootfs);
 
         // mount DevFS at /dev
         let dev = root.find(true, "dev").unwrap_or_else(|_| {
             root.create("dev", FileType::Dir, 0o666).expect("failed to mkdir /dev")
         });
-        let devfs = dev.mount(devfs).expect("failed to mount DevFS");
+        let devfs = mount::mount_at(&dev, "devfs", "/dev", "devfs", 0).expect("failed to mount DevFS");
 
         // mount RamFS at /dev/shm
         let shm = devfs.root_inode().find(true, "shm").expect("cannot find shm");
-        let shmfs = RamFS::new();
-        shm.mount(shmfs).expect("failed to mount /dev/shm");
+        mount::mount_at(&shm, "ramfs", "/dev/shm", "ramfs", 0).expect("failed to mount /dev/shm");
+
+        // mount DevPtsFS at /dev/pts
+        let pts = devfs.root_inode().find(true, "pts").expect("cannot find pts");
+        mount::mount_at(&pts, "devpts", "/dev/pts", "devpts", 0).expect("failed to mount /dev/pts");
 
         // mount RamFS at /tmp
-        let ramfs = RamFS::new();
         let tmp = root.find(true, "tmp").unwrap_or_else(|_| {
             root.create("tmp", FileType::Dir, 0o666).expect("failed to mkdir /tmp")
         });
-        tmp.mount(ramfs).expect("failed to mount RamFS");
+        mount::mount_at(&tmp, "ramfs", "/tmp", "ramfs", 0).expect("failed to mount RamFS");
+
+        // mount ProcFS at /proc
+        let proc = root.find(true, "proc").unwrap_or_else(|_| {
+            root.create("proc", FileType::Dir, 0o666).expect("failed to mkdir /proc")
+        });
+        mount::mount_at(&proc, "proc", "/proc", "proc", 0).expect("failed to mount ProcFS");
 
         root
     };
 }
 
+/// Create a DevFS with all the device files
+pub fn create_devfs() -> Arc<DevFS> {
+    let devfs = DevFS::new();
+    devfs
+        .add("null", Arc::new(NullINode::default()))
+        .expect("failed to mknod /dev/null");
+    devfs
+        .add("zero", Arc::new(ZeroINode::default()))
+        .expect("failed to mknod /dev/zero");
+    devfs
+        .add("random", Arc::new(RandomINode::new(false)))
+        .expect("failed to mknod /dev/random");
+    devfs
+        .add("urandom", Arc::new(RandomINode::new(true)))
+        .expect("failed to mknod /dev/urandom");
+    devfs
+        .add("tty", TTY.clone())
+        .expect("failed to mknod /dev/tty");
+    devfs
+        .add("fb0", Arc::new(Fbdev::default()))
+        .expect("failed to mknod /dev/fb0");
+    devfs
+        .add("shm", Arc::new(ShmINode::default()))
+        .expect("failed to mkdir shm");
+    devfs
+        .add("ptmx", Arc::new(Ptmx::default()))
+        .expect("failed to mknod /dev/ptmx");
+    devfs
+        .add("pts", Arc::new(DevPtsDirINode::new(&devfs)))
+        .expect("failed to mkdir pts");
+    for (i, serial) in Serial::wrap_all_serial_devices().into_iter().enumerate() {
+        devfs
+            .add(&format!("ttyS{}", i), Arc::new(serial))
+            .expect("failed to add a serial");
+    }
+
+    #[cfg(feature = "hypervisor")]
+    devfs
+        .add("rvm", Arc::new(crate::rvm::RvmINode::new()))
+        .expect("failed to mknod /dev/rvm");
+
+    devfs
+}
+
 pub const FOLLOW_MAX_DEPTH: usize = 3;
 
 pub trait INodeExt {
@@ -0,0 +1,382 @@
+//! Mount table and file system type registry
+//!
+//! `MountFS` never forgets a mountpoint, so every file system is mounted
+//! through a `Detachable` wrapper which umount cuts off.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use rcore_fs::dev::block_cache::BlockCache;
+use rcore_fs::vfs::*;
+use rcore_fs_mountfs::{MNode, MountFS};
+use rcore_fs_ramfs::RamFS;
+use rcore_fs_sfs::SimpleFileSystem;
+use spin::RwLock;
+
+use super::procfs::ProcFS;
+use super::{create_devfs, DevPtsFS, ROOT_INODE};
+use crate::drivers::{BlockDriverWrapper, BLK_DRIVERS};
+use crate::process::PROCESSES;
+use crate::syscall::SysError;
+
+// mount flags
+pub const MS_RDONLY: usize = 1;
+pub const MS_REMOUNT: usize = 32;
+pub const MS_BIND: usize = 4096;
+pub const MS_MOVE: usize = 8192;
+
+// umount flags
+pub const MNT_FORCE: usize = 1;
+pub const MNT_DETACH: usize = 2;
+pub const UMOUNT_NOFOLLOW: usize = 8;
+
+/// Build a file system from the `source` argument of mount(2)
+pub type FsConstructor = fn(source: &str) -> Result<Arc<dyn FileSystem>, SysError>;
+
+#[derive(Clone, Copy)]
+pub struct FsType {
+    pub constructor: FsConstructor,
+    /// Whether `source` should be a block device
+    pub requires_dev: bool,
+}
+
+/// An entry of the mount table
+pub struct Mount {
+    pub source: String,
+    /// Absolute path of the mountpoint
+    pub target: String,
+    pub fstype: String,
+    pub flags: usize,
+    /// The mounted file system as seen by the VFS
+    vfs: Arc<MountFS>,
+    /// `None` for the root file system, which can not be unmounted
+    detachable: Option<Arc<Detachable>>,
+}
+
+lazy_static! {
+    static ref FS_TYPES: RwLock<BTreeMap<String, FsType>> = {
+        let mut types = BTreeMap::new();
+        let dev = |constructor| FsType { constructor, requires_dev: true };
+        let nodev = |constructor| FsType { constructor, requires_dev: false };
+        types.insert(String::from("sfs"), dev(new_sfs as FsConstructor));
+        types.insert(String::from("ramfs"), nodev(new_ramfs as FsConstructor));
+        types.insert(String::from("tmpfs"), nodev(new_ramfs as FsConstructor));
+        types.insert(String::from("devfs"), nodev(new_devfs as FsConstructor));
+        types.insert(String::from("proc"), nodev(new_procfs as FsConstructor));
+        types.insert(String::from("devpts"), nodev(new_devpts as FsConstructor));
+        RwLock::new(types)
+    };
+    /// Mounted file systems in mount order
+    pub static ref MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+}
+
+/// Make a file system type available to mount(2)
+pub fn register_filesystem(name: &str, fstype: FsType) {
+    FS_TYPES.write().insert(String::from(name), fstype);
+}
+
+/// List registered file system types
+pub fn filesystem_types() -> Vec<(String, FsType)> {
+    FS_TYPES
+        .read()
+        .iter()
+        .map(|(name, fstype)| (name.clone(), *fstype))
+        .collect()
+}
+
+fn new_sfs(source: &str) -> Result<Arc<dyn FileSystem>, SysError> {
+    let driver = block_device(source)?;
+    let device = Arc::new(BlockCache::new(BlockDriverWrapper(driver), 0x100));
+    let sfs = SimpleFileSystem::open(device).map_err(|_| SysError::EINVAL)?;
+    Ok(sfs)
+}
+
+fn new_ramfs(_source: &str) -> Result<Arc<dyn FileSystem>, SysError> {
+    Ok(RamFS::new())
+}
+
+fn new_devfs(_source: &str) -> Result<Arc<dyn FileSystem>, SysError> {
+    Ok(create_devfs())
+}
+
+fn new_procfs(_source: &str) -> Result<Arc<dyn FileSystem>, SysError> {
+    Ok(ProcFS::new())
+}
+
+fn new_devpts(_source: &str) -> Result<Arc<dyn FileSystem>, SysError> {
+    Ok(DevPtsFS::new())
+}
+
+/// Find the block device named `/dev/sdX`, X being its index in `BLK_DRIVERS`
+pub fn block_device(source: &str) -> Result<Arc<dyn crate::drivers::BlockDriver>, SysError> {
+    let name = source.trim_start_matches("/dev/");
+    let bytes = name.as_bytes();
+    if bytes.len() != 3 || &bytes[..2] != b"sd" || !bytes[2].is_ascii_lowercase() {
+        return Err(SysError::ENOTBLK);
+    }
+    let index = (bytes[2] - b'a') as usize;
+    BLK_DRIVERS
+        .read()
+        .get(index)
+        .cloned()
+        .ok_or(SysError::ENXIO)
+}
+
+/// Wrapper of a mounted file system which can be cut off by umount
+///
+/// Once detached, it shows the directory it used to cover.
+struct Detachable {
+    fs: RwLock<Option<Arc<dyn FileSystem>>>,
+    covered: Arc<dyn INode>,
+}
+
+impl Detachable {
+    fn detach(&self) -> Option<Arc<dyn FileSystem>> {
+        self.fs.write().take()
+    }
+}
+
+impl FileSystem for Detachable {
+    fn sync(&self) -> Result<()> {
+        match self.fs.read().as_ref() {
+            Some(fs) => fs.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        match self.fs.read().as_ref() {
+            Some(fs) => fs.root_inode(),
+            None => self.covered.clone(),
+        }
+    }
+
+    fn info(&self) -> FsInfo {
+        match self.fs.read().as_ref() {
+            Some(fs) => fs.info(),
+            None => self.covered.fs().info(),
+        }
+    }
+}
+
+/// Normalize `path` relative to `cwd` into an absolute path without `.` and `..`
+pub fn canonicalize(cwd: &str, path: &str) -> String {
+    let mut segs: Vec<&str> = Vec::new();
+    let full = if path.starts_with('/') { "" } else { cwd };
+    for seg in full.split('/').chain(path.split('/')) {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segs.pop();
+            }
+            seg => segs.push(seg),
+        }
+    }
+    if segs.is_empty() {
+        return String::from("/");
+    }
+    let mut result = String::new();
+    for seg in segs {
+        result.push('/');
+        result.push_str(seg);
+    }
+    result
+}
+
+/// Identity of a mounted file system
+fn fs_id(fs: &dyn FileSystem) -> usize {
+    fs as *const dyn FileSystem as *const u8 as usize
+}
+
+/// Identity of the mounted file system of `inode`
+///
+/// Return None for INodes out of the mount tree, like pipes.
+pub fn fs_of(inode: &Arc<dyn INode>) -> Option<usize> {
+    Some(fs_id(&*inode.as_any_ref().downcast_ref::<MNode>()?.fs()))
+}
+
+/// Identity of the mounted file system of `inode`, and whether it is on a block device
+///
+/// Return None for INodes out of the mount tree, like pipes.
+pub fn mount_of(inode: &Arc<dyn INode>) -> Option<(usize, bool)> {
+    let fs = fs_of(inode)?;
+    let types = FS_TYPES.read();
+    let on_block_device = MOUNTS
+        .read()
+        .iter()
+        .any(|m| fs_id(&*m.vfs) == fs && types.get(&m.fstype).map_or(false, |t| t.requires_dev));
+    Some((fs, on_block_device))
+}
+
+/// Fail with EROFS if `inode` is on a file system mounted read-only
+pub fn check_writable(inode: &Arc<dyn INode>) -> Result<(), SysError> {
+    let fs = match fs_of(inode) {
+        Some(fs) => fs,
+        None => return Ok(()),
+    };
+    let read_only = MOUNTS
+        .read()
+        .iter()
+        .any(|m| fs_id(&*m.vfs) == fs && m.flags & MS_RDONLY != 0);
+    if read_only {
+        return Err(SysError::EROFS);
+    }
+    Ok(())
+}
+
+/// Walk an absolute canonical path from the root, crossing mountpoints
+fn find_mnode(path: &str) -> Result<Arc<MNode>, SysError> {
+    // make sure the root file system has been mounted
+    lazy_static::initialize(&ROOT_INODE);
+    let mut node = MOUNTS.read()[0].vfs.root_inode();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        node = node.find(true, name)?;
+    }
+    Ok(node)
+}
+
+/// Record the root file system in the mount table
+pub(super) fn mount_root(source: &str, fstype: &str, vfs: Arc<MountFS>) {
+    MOUNTS.write().push(Mount {
+        source: String::from(source),
+        target: String::from("/"),
+        fstype: String::from(fstype),
+        flags: 0,
+        vfs,
+        detachable: None,
+    });
+}
+
+/// Mount a file system of `fstype` on the directory `mountpoint`
+///
+/// `target` is the absolute path of `mountpoint`, recorded in the mount table.
+pub(super) fn mount_at(
+    mountpoint: &Arc<MNode>,
+    source: &str,
+    target: &str,
+    fstype: &str,
+    flags: usize,
+) -> Result<Arc<MountFS>, SysError> {
+    if mountpoint.metadata()?.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    let fs_type = *FS_TYPES.read().get(fstype).ok_or(SysError::ENODEV)?;
+    // two instances over one device would overwrite each other's metadata
+    if fs_type.requires_dev && is_mounted_dev(source)? {
+        return Err(SysError::EBUSY);
+    }
+    let fs = (fs_type.constructor)(source)?;
+    let detachable = Arc::new(Detachable {
+        fs: RwLock::new(Some(fs)),
+        covered: mountpoint.clone(),
+    });
+    let vfs = mountpoint.mount(detachable.clone())?;
+    MOUNTS.write().push(Mount {
+        source: String::from(source),
+        target: String::from(target),
+        fstype: String::from(fstype),
+        flags,
+        vfs: vfs.clone(),
+        detachable: Some(detachable),
+    });
+    Ok(vfs)
+}
+
+/// mount(2) on an absolute canonical `target`
+///
+/// The caller must not hold any process lock.
+pub fn mount(source: &str, target: &str, fstype: &str, flags: usize) -> Result<(), SysError> {
+    if flags & MS_REMOUNT != 0 {
+        let mut mounts = MOUNTS.write();
+        let index = mounts
+            .iter()
+            .rposition(|m| m.target == target)
+            .ok_or(SysError::EINVAL)?;
+        if flags & MS_RDONLY != 0 && has_writers(&mounts[index]) {
+            return Err(SysError::EBUSY);
+        }
+        mounts[index].flags = flags & !MS_REMOUNT;
+        return Ok(());
+    }
+    if flags & (MS_BIND | MS_MOVE) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let mountpoint = find_mnode(target)?;
+    mount_at(&mountpoint, source, target, fstype, flags)?;
+    Ok(())
+}
+
+/// umount2(2) on an absolute canonical `target`
+///
+/// The caller must not hold any process lock.
+pub fn umount(target: &str, flags: usize) -> Result<(), SysError> {
+    let mut mounts = MOUNTS.write();
+    let index = mounts
+        .iter()
+        .rposition(|m| m.target == target)
+        .ok_or(SysError::EINVAL)?;
+    let detachable = mounts[index].detachable.clone().ok_or(SysError::EBUSY)?;
+    if flags & (MNT_FORCE | MNT_DETACH) == 0 && is_busy(&mounts, index) {
+        return Err(SysError::EBUSY);
+    }
+    let vfs = mounts.remove(index).vfs;
+    drop(mounts);
+    super::page_cache::forget(fs_id(&*vfs));
+    if let Some(fs) = detachable.detach() {
+        fs.sync()?;
+    }
+    Ok(())
+}
+
+/// Whether mount `index` has mounts below it, or open files and cwds in it
+fn is_busy(mounts: &[Mount], index: usize) -> bool {
+    let target = &mounts[index].target;
+    let prefix = format!("{}/", target.trim_end_matches('/'));
+    if mounts[index + 1..]
+        .iter()
+        .any(|m| m.target.starts_with(prefix.as_str()))
+    {
+        return true;
+    }
+    let fs = fs_id(&*mounts[index].vfs);
+    for proc in PROCESSES.read().values() {
+        let proc = proc.lock();
+        if proc.cwd == *target || proc.cwd.starts_with(prefix.as_str()) {
+            return true;
+        }
+        for file in proc.files.values() {
+            if let super::FileLike::File(file) = file {
+                if file.mount == Some(fs) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Whether a file on `mount` is open for writing
+fn has_writers(mount: &Mount) -> bool {
+    let fs = fs_id(&*mount.vfs);
+    for proc in PROCESSES.read().values() {
+        let proc = proc.lock();
+        for file in proc.files.values() {
+            if let super::FileLike::File(file) = file {
+                if file.mount == Some(fs) && file.writable() {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Whether the block device `source` backs a mounted file system
+fn is_mounted_dev(source: &str) -> Result<bool, SysError> {
+    let driver = block_device(source)?;
+    let types = FS_TYPES.read();
+    let mounted = MOUNTS.read().iter().any(|m| {
+        types.get(&m.fstype).map_or(false, |t| t.requires_dev)
+            && block_device(&m.source).map_or(false, |other| Arc::ptr_eq(&other, &driver))
+    });
+    Ok(mounted)
+}
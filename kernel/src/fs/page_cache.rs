@@ -0,0 +1,645 @@
+//! Kernel-wide cache of the pages of regular files
+//!
+//! Pages are cached by inode and page index, and used by reads and writes through
+//! file handles, sendfile, and both private and shared mappings of files.
+//! Only files in file systems on block devices stay cached. Other files are in
+//! memory already, so their pages only live while shared mappings have them.
+//!
+//! Written pages are written back by msync, fsync and sync, and by the flusher
+//! every `FLUSH_INTERVAL`, without the cache locked. Clean pages not mapped by
+//! shared mappings are evicted in least recently used order when frames run out.
+//! Sequential reads read ahead.
+//! When a file shrinks, its pages beyond the new end are dropped, and unmapped
+//! from shared mappings so that accessing them raises SIGBUS.
+//! Swap files are pinned out of the cache, as swapping writes them directly.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use rcore_fs::vfs::{FsError, INode, Result};
+use rcore_memory::memory_set::handler::FilePages;
+use rcore_memory::{PhysAddr, PAGE_SIZE};
+
+use super::mount;
+use crate::arch::timer::timer_now;
+use crate::memory::{alloc_frame, dealloc_frame, phys_to_virt};
+use crate::process::memory_sets;
+use crate::sync::{MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
+use crate::trap::NAIVE_TIMER;
+
+/// Interval between write backs of the flusher
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// Pages read ahead by the first sequential read, doubled by each following one
+const MIN_READAHEAD: usize = 4;
+const MAX_READAHEAD: usize = 32;
+
+/// Identity of an inode: its mounted file system and inode number
+type Key = (usize, usize);
+
+struct CachedPage {
+    frame: PhysAddr,
+    /// Number of shared mappings of it
+    maps: usize,
+    /// Written since it was last written back
+    dirty: bool,
+    /// Being written back
+    writing: bool,
+    /// Time of last use, while it is in the LRU list
+    stamp: usize,
+}
+
+struct CachedFile {
+    inode: Arc<dyn INode>,
+    /// Whether pages stay after use, or only while mapped
+    cached: bool,
+    pages: BTreeMap<usize, CachedPage>,
+    /// Offset after the last read, to tell sequential reads
+    read_end: usize,
+    /// Number of pages to read ahead
+    readahead: usize,
+}
+
+#[derive(Default)]
+struct PageCache {
+    files: BTreeMap<Key, CachedFile>,
+    /// Unmapped pages of cached files by time of last use
+    lru: BTreeMap<usize, (Key, usize)>,
+    clock: usize,
+    /// Files never cached, as they are read and written around the cache
+    pinned: BTreeSet<Key>,
+}
+
+impl PageCache {
+    fn page(&mut self, key: Key, index: usize) -> Option<&mut CachedPage> {
+        self.files.get_mut(&key)?.pages.get_mut(&index)
+    }
+
+    fn contains(&self, key: Key, index: usize) -> bool {
+        self.files
+            .get(&key)
+            .map_or(false, |file| file.pages.contains_key(&index))
+    }
+
+    fn insert(&mut self, pages: &INodePages, index: usize, frame: PhysAddr) {
+        let file = self.files.entry(pages.key).or_insert_with(|| CachedFile {
+            inode: pages.inode.clone(),
+            cached: pages.cached,
+            pages: BTreeMap::new(),
+            read_end: 0,
+            readahead: 0,
+        });
+        let page = CachedPage {
+            frame,
+            maps: 0,
+            dirty: false,
+            writing: false,
+            stamp: 0,
+        };
+        file.pages.insert(index, page);
+        self.touch(pages.key, index);
+    }
+
+    fn remove(&mut self, key: Key, index: usize) -> Option<CachedPage> {
+        let file = self.files.get_mut(&key)?;
+        let page = file.pages.remove(&index)?;
+        if page.maps == 0 {
+            self.lru.remove(&page.stamp);
+        }
+        if file.pages.is_empty() {
+            self.files.remove(&key);
+        }
+        Some(page)
+    }
+
+    /// Move an unmapped page of a cached file to the end of the LRU list
+    fn touch(&mut self, key: Key, index: usize) {
+        let file = match self.files.get_mut(&key) {
+            Some(file) if file.cached => file,
+            _ => return,
+        };
+        if let Some(page) = file.pages.get_mut(&index) {
+            if page.maps == 0 {
+                self.clock += 1;
+                self.lru.remove(&page.stamp);
+                page.stamp = self.clock;
+                self.lru.insert(self.clock, (key, index));
+            }
+        }
+    }
+
+    /// Whether some pages of `key` are mapped
+    fn is_mapped(&self, key: Key) -> bool {
+        self.files
+            .get(&key)
+            .map_or(false, |file| file.pages.values().any(|page| page.maps > 0))
+    }
+}
+
+lazy_static! {
+    static ref PAGE_CACHE: Mutex<PageCache> = Mutex::new(PageCache::default());
+}
+
+fn page_data<'a>(frame: PhysAddr) -> &'a mut [u8] {
+    unsafe { slice::from_raw_parts_mut(phys_to_virt(frame) as *mut u8, PAGE_SIZE) }
+}
+
+/// Write page `index` of the file `key` back if it is dirty, leaving the file size alone.
+/// The page is copied, and written without the cache locked.
+/// It stays dirty if writing fails.
+fn write_page(key: Key, index: usize) {
+    // allocate before locking, as the allocator may evict pages
+    let mut data = vec![0u8; PAGE_SIZE];
+    let inode = {
+        let mut cache = PAGE_CACHE.lock();
+        let file = match cache.files.get_mut(&key) {
+            Some(file) => file,
+            None => return,
+        };
+        let page = match file.pages.get_mut(&index) {
+            // a page being written is written again by whoever dirtied it meanwhile
+            Some(page) if page.dirty && !page.writing => page,
+            _ => return,
+        };
+        page.dirty = false;
+        page.writing = true;
+        data.copy_from_slice(page_data(page.frame));
+        file.inode.clone()
+    };
+    let size = inode.metadata().map_or(0, |metadata| metadata.size);
+    let offset = index * PAGE_SIZE;
+    let mut failed = false;
+    if offset < size {
+        let len = (size - offset).min(PAGE_SIZE);
+        if let Err(err) = inode.write_at(offset, &data[..len]) {
+            warn!("failed to write back page {} of file: {:?}", index, err);
+            failed = true;
+        }
+    }
+    let mut cache = PAGE_CACHE.lock();
+    if let Some(page) = cache.page(key, index) {
+        page.writing = false;
+        page.dirty |= failed;
+    }
+}
+
+/// The cached pages of an inode
+#[derive(Clone)]
+pub struct INodePages {
+    inode: Arc<dyn INode>,
+    key: Key,
+    /// Whether its file system is on a block device
+    cached: bool,
+}
+
+impl INodePages {
+    pub fn new(inode: Arc<dyn INode>) -> Result<Self> {
+        let metadata = inode.metadata()?;
+        let (key, cached) = match mount::mount_of(&inode) {
+            Some((fs, on_block_device)) => ((fs, metadata.inode), on_block_device),
+            None => ((metadata.dev, metadata.inode), false),
+        };
+        Ok(INodePages { inode, key, cached })
+    }
+
+    /// Whether reads and writes should go through the cache
+    fn use_cache(&self) -> bool {
+        let cache = PAGE_CACHE.lock();
+        !cache.pinned.contains(&self.key) && (self.cached || cache.files.contains_key(&self.key))
+    }
+
+    /// Lock the cache with page `index` in it, reading the page in if `load`
+    /// and the file was `size` bytes long
+    ///
+    /// Return None if the page is neither cached nor loaded, no frame is left for it,
+    /// or reading it fails.
+    fn lock_page(
+        &self,
+        index: usize,
+        load: bool,
+        size: usize,
+    ) -> Option<MutexGuard<'static, PageCache, SpinNoIrq>> {
+        {
+            let cache = PAGE_CACHE.lock();
+            if cache.contains(self.key, index) {
+                return Some(cache);
+            }
+            if cache.pinned.contains(&self.key) {
+                return None;
+            }
+        }
+        if !load {
+            return None;
+        }
+        // read it in without the lock, as allocating may evict other pages
+        let frame = alloc_frame()?;
+        let data = page_data(frame);
+        let offset = index * PAGE_SIZE;
+        let len = size.saturating_sub(offset).min(PAGE_SIZE);
+        let len = match self.inode.read_at(offset, &mut data[..len]) {
+            Ok(len) => len,
+            Err(_) => {
+                // left to the caller to read it again and report
+                dealloc_frame(frame);
+                return None;
+            }
+        };
+        data[len..].iter_mut().for_each(|x| *x = 0);
+        let mut cache = PAGE_CACHE.lock();
+        if cache.contains(self.key, index) {
+            // read in by someone else meanwhile
+            dealloc_frame(frame);
+        } else if cache.pinned.contains(&self.key) {
+            // pinned meanwhile
+            dealloc_frame(frame);
+            return None;
+        } else {
+            cache.insert(self, index, frame);
+        }
+        Some(cache)
+    }
+
+    /// Read the pages following a read of `offset..end` ahead, if reads are sequential
+    fn readahead(&self, offset: usize, end: usize, size: usize) {
+        let count = {
+            let mut cache = PAGE_CACHE.lock();
+            let file = match cache.files.get_mut(&self.key) {
+                Some(file) => file,
+                None => return,
+            };
+            file.readahead = if offset == file.read_end {
+                (file.readahead * 2).max(MIN_READAHEAD).min(MAX_READAHEAD)
+            } else {
+                0
+            };
+            file.read_end = end;
+            file.readahead
+        };
+        let next = (end + PAGE_SIZE - 1) / PAGE_SIZE;
+        for index in next..next + count {
+            if index * PAGE_SIZE >= size || self.lock_page(index, true, size).is_none() {
+                break;
+            }
+        }
+    }
+}
+
+impl FilePages for INodePages {
+    fn get(&self, index: usize) -> Option<PhysAddr> {
+        let size = self.size();
+        if index * PAGE_SIZE >= size {
+            return None;
+        }
+        let mut cache = self.lock_page(index, true, size)?;
+        let page = cache.page(self.key, index).unwrap();
+        page.maps += 1;
+        let (frame, stamp) = (page.frame, page.stamp);
+        cache.lru.remove(&stamp);
+        Some(frame)
+    }
+
+    fn put(&self, index: usize, written: bool) {
+        {
+            let mut cache = PAGE_CACHE.lock();
+            let file = match cache.files.get_mut(&self.key) {
+                Some(file) => file,
+                None => return,
+            };
+            let cached = file.cached;
+            let page = match file.pages.get_mut(&index) {
+                Some(page) => page,
+                None => return,
+            };
+            page.dirty |= written;
+            page.maps -= 1;
+            if page.maps > 0 {
+                return;
+            }
+            if cached {
+                cache.touch(self.key, index);
+                return;
+            }
+        }
+        write_page(self.key, index);
+        // unless mapped again or written again meanwhile, or writing it failed
+        let mut cache = PAGE_CACHE.lock();
+        match cache.page(self.key, index) {
+            Some(page) if page.maps == 0 && !page.dirty && !page.writing => {}
+            _ => return,
+        }
+        if let Some(page) = cache.remove(self.key, index) {
+            dealloc_frame(page.frame);
+        }
+    }
+
+    fn sync(&self, index: usize, written: bool) {
+        if let Some(page) = PAGE_CACHE.lock().page(self.key, index) {
+            page.dirty |= written;
+        }
+        write_page(self.key, index);
+    }
+
+    fn size(&self) -> usize {
+        self.inode.metadata().map_or(0, |metadata| metadata.size)
+    }
+}
+
+/// Read from the regular file `inode` at `offset` through the cache
+pub fn read_at(inode: &Arc<dyn INode>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    let pages = INodePages::new(inode.clone())?;
+    if !pages.use_cache() {
+        return inode.read_at(offset, buf);
+    }
+    let size = inode.metadata()?.size;
+    let end = (offset + buf.len()).min(size);
+    let mut pos = offset;
+    while pos < end {
+        let (index, start) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (PAGE_SIZE - start).min(end - pos);
+        let dst = &mut buf[pos - offset..pos - offset + len];
+        match pages.lock_page(index, pages.cached, size) {
+            Some(mut cache) => {
+                cache.touch(pages.key, index);
+                let frame = cache.page(pages.key, index).unwrap().frame;
+                dst.copy_from_slice(&page_data(frame)[start..start + len]);
+            }
+            None => {
+                let read_len = inode.read_at(pos, dst)?;
+                if read_len < len {
+                    return Ok(pos + read_len - offset);
+                }
+            }
+        }
+        pos += len;
+    }
+    if pages.cached && pos > offset {
+        pages.readahead(offset, end, size);
+    }
+    Ok(pos - offset)
+}
+
+/// Write to the regular file `inode` at `offset` through the cache
+pub fn write_at(inode: &Arc<dyn INode>, offset: usize, buf: &[u8]) -> Result<usize> {
+    let pages = INodePages::new(inode.clone())?;
+    if !pages.use_cache() || buf.is_empty() {
+        return inode.write_at(offset, buf);
+    }
+    // grow the file first, its new pages are written back later
+    let size = inode.metadata()?.size;
+    let end = offset + buf.len();
+    if end > size {
+        inode.resize(end)?;
+    }
+    let mut pos = offset;
+    while pos < end {
+        let (index, start) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (PAGE_SIZE - start).min(end - pos);
+        let src = &buf[pos - offset..pos - offset + len];
+        match pages.lock_page(index, pages.cached, size) {
+            Some(mut cache) => {
+                cache.touch(pages.key, index);
+                let page = cache.page(pages.key, index).unwrap();
+                page_data(page.frame)[start..start + len].copy_from_slice(src);
+                page.dirty = true;
+            }
+            None => {
+                inode.write_at(pos, src)?;
+            }
+        }
+        pos += len;
+    }
+    start_flusher();
+    Ok(buf.len())
+}
+
+/// Write back the dirty pages of the files matching `filter`
+fn write_back(filter: impl Fn(&Key) -> bool) {
+    let dirty: Vec<(Key, usize)> = {
+        let cache = PAGE_CACHE.lock();
+        cache
+            .files
+            .iter()
+            .filter(|(key, _)| filter(*key))
+            .flat_map(|(key, file)| {
+                let key = *key;
+                file.pages
+                    .iter()
+                    .filter(|(_, page)| page.dirty)
+                    .map(move |(index, _)| (key, *index))
+            })
+            .collect()
+    };
+    for (key, index) in dirty {
+        write_page(key, index);
+    }
+}
+
+/// Write back the written pages of `inode`.
+/// The caller must not hold any process.
+pub fn sync(inode: &Arc<dyn INode>) {
+    let pages = match INodePages::new(inode.clone()) {
+        Ok(pages) => pages,
+        Err(_) => return,
+    };
+    if PAGE_CACHE.lock().is_mapped(pages.key) {
+        // pages written through mappings are only known to the page tables mapping them
+        for vm in memory_sets() {
+            vm.lock().sync_all();
+        }
+    }
+    write_back(|key| *key == pages.key);
+}
+
+/// Write back the written pages of all files.
+/// The caller must not hold any process.
+pub fn sync_all() {
+    let mapped = {
+        let cache = PAGE_CACHE.lock();
+        cache.files.keys().any(|key| cache.is_mapped(*key))
+    };
+    if mapped {
+        for vm in memory_sets() {
+            vm.lock().sync_all();
+        }
+    }
+    write_back(|_| true);
+}
+
+/// Resize `inode` to `len`, dropping its pages beyond the new end.
+/// The caller must not hold any process.
+pub fn resize(inode: &Arc<dyn INode>, len: usize) -> Result<()> {
+    inode.resize(len)?;
+    let key = INodePages::new(inode.clone())?.key;
+    if PAGE_CACHE.lock().is_mapped(key) {
+        for vm in memory_sets() {
+            vm.lock().invalidate();
+        }
+    }
+    let mut cache = PAGE_CACHE.lock();
+    let beyond: Vec<usize> = match cache.files.get(&key) {
+        Some(file) => file
+            .pages
+            .range((len + PAGE_SIZE - 1) / PAGE_SIZE..)
+            .filter(|(_, page)| page.maps == 0)
+            .map(|(index, _)| *index)
+            .collect(),
+        None => return Ok(()),
+    };
+    for index in beyond {
+        if let Some(page) = cache.remove(key, index) {
+            dealloc_frame(page.frame);
+        }
+    }
+    // the rest of the last page reads as zeros if the file grows again
+    if len % PAGE_SIZE != 0 {
+        if let Some(page) = cache.page(key, len / PAGE_SIZE) {
+            page_data(page.frame)[len % PAGE_SIZE..]
+                .iter_mut()
+                .for_each(|x| *x = 0);
+        }
+    }
+    Ok(())
+}
+
+/// Write back and drop the pages of `inode`, and keep it out of the cache until unpinned,
+/// for a swap file written around the cache.
+/// Fail with `Busy` if some of its pages are mapped, or can not be written back.
+pub fn pin(inode: &Arc<dyn INode>) -> Result<()> {
+    let key = INodePages::new(inode.clone())?.key;
+    {
+        let mut cache = PAGE_CACHE.lock();
+        if cache.is_mapped(key) {
+            return Err(FsError::Busy);
+        }
+        // from now on, reads and writes go around the cache
+        cache.pinned.insert(key);
+    }
+    write_back(|k| *k == key);
+    let mut cache = PAGE_CACHE.lock();
+    let pages = cache.files.get(&key).map(|file| &file.pages);
+    let busy = pages.map_or(false, |pages| {
+        pages
+            .values()
+            .any(|page| page.maps > 0 || page.dirty || page.writing)
+    });
+    if busy {
+        // mapped meanwhile, or writing it back failed
+        cache.pinned.remove(&key);
+        return Err(FsError::Busy);
+    }
+    let indexes: Vec<usize> = pages.map_or(Vec::new(), |pages| pages.keys().cloned().collect());
+    for index in indexes {
+        if let Some(page) = cache.remove(key, index) {
+            dealloc_frame(page.frame);
+        }
+    }
+    Ok(())
+}
+
+/// Let `inode`, pinned by `pin`, be cached again
+pub fn unpin(inode: &Arc<dyn INode>) {
+    if let Ok(pages) = INodePages::new(inode.clone()) {
+        PAGE_CACHE.lock().pinned.remove(&pages.key);
+    }
+}
+
+/// Write back and drop the pages of the files in the file system `fs`, being unmounted.
+/// Pages still mapped go away with their last mapping.
+/// The caller must not hold any process.
+pub fn forget(fs: usize) {
+    sync_all();
+    let mut cache = PAGE_CACHE.lock();
+    let keys: Vec<Key> = cache
+        .files
+        .keys()
+        .filter(|key| key.0 == fs)
+        .cloned()
+        .collect();
+    for key in keys {
+        let file = cache.files.get_mut(&key).unwrap();
+        file.cached = false;
+        let unmapped: Vec<usize> = file
+            .pages
+            .iter()
+            .filter(|(_, page)| page.maps == 0)
+            .map(|(index, _)| *index)
+            .collect();
+        for index in unmapped {
+            if let Some(page) = cache.remove(key, index) {
+                dealloc_frame(page.frame);
+            }
+        }
+    }
+}
+
+/// Evict the least recently used clean page and return its frame, for the allocator
+/// running out of frames
+///
+/// Written pages are left to the flusher, sync and fsync, as the allocator may be
+/// called from anywhere, even with file systems locked.
+pub fn evict() -> Option<PhysAddr> {
+    // the allocator may be called with the cache locked
+    let mut cache = PAGE_CACHE.try_lock()?;
+    let (key, index) = cache
+        .lru
+        .values()
+        .find(|&&(key, index)| {
+            let page = &cache.files[&key].pages[&index];
+            !page.dirty && !page.writing
+        })
+        .cloned()?;
+    let page = cache.remove(key, index)?;
+    trace!("evict page {} of inode {:?}", index, key);
+    Some(page.frame)
+}
+
+/// Return `(cached, dirty)` number of pages
+pub fn cache_stats() -> (usize, usize) {
+    let cache = PAGE_CACHE.lock();
+    let pages = cache.files.values().flat_map(|file| file.pages.values());
+    pages.fold((0, 0), |(cached, dirty), page| {
+        (cached + 1, dirty + page.dirty as usize)
+    })
+}
+
+static FLUSHER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Start the flusher on the first write
+///
+/// It leaves pages written through shared mappings to msync, fsync and sync,
+/// as finding them takes every process.
+fn start_flusher() {
+    if FLUSHER_STARTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    executor::spawn(async {
+        loop {
+            Sleep(timer_now() + FLUSH_INTERVAL).await;
+            write_back(|_| true);
+        }
+    });
+}
+
+/// Wait until the deadline
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct Sleep(Duration);
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if timer_now() >= self.0 {
+            return Poll::Ready(());
+        }
+        let waker = cx.waker().clone();
+        NAIVE_TIMER
+            .lock()
+            .add(self.0, Box::new(move |_| waker.wake()));
+        Poll::Pending
+    }
+}
@@ -65,6 +65,15 @@ impl Pipe {
         )
     }
 
+    pub fn is_read_end(&self) -> bool {
+        self.direction == PipeEnd::Read
+    }
+
+    /// Whether the other end is closed
+    pub fn peer_closed(&self) -> bool {
+        self.data.lock().end_cnt < 2
+    }
+
     fn can_read(&self) -> bool {
         if let PipeEnd::Read = self.direction {
             // true
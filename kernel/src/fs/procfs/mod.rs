@@ -0,0 +1,265 @@
+//! Process file system mounted at /proc
+//!
+//! Nothing is stored here: every INode is generated on the fly
+//! from `PROCESSES`, `THREADS` and the frame allocator.
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use crate::process::{process, Process, PROCESSES};
+
+use self::net::NetDirINode;
+use self::pid::PidDirINode;
+use self::system::*;
+
+mod net;
+mod pid;
+mod system;
+
+/// Device number of procfs
+const PROCFS_DEV: usize = 4;
+
+pub struct ProcFS;
+
+impl ProcFS {
+    pub fn new() -> Arc<Self> {
+        Arc::new(ProcFS)
+    }
+}
+
+impl FileSystem for ProcFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        Arc::new(RootINode)
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            bsize: 0,
+            frsize: 0,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+/// Build the metadata of a procfs INode
+fn proc_metadata(inode: usize, type_: FileType, mode: u16) -> Metadata {
+    Metadata {
+        dev: PROCFS_DEV,
+        inode,
+        size: 0,
+        blk_size: 0,
+        blocks: 0,
+        atime: Timespec { sec: 0, nsec: 0 },
+        mtime: Timespec { sec: 0, nsec: 0 },
+        ctime: Timespec { sec: 0, nsec: 0 },
+        type_,
+        mode,
+        nlinks: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+    }
+}
+
+/// Copy `content[offset..]` into `buf`
+fn read_content(content: &[u8], offset: usize, buf: &mut [u8]) -> usize {
+    if offset >= content.len() {
+        return 0;
+    }
+    let len = (content.len() - offset).min(buf.len());
+    buf[..len].copy_from_slice(&content[offset..offset + len]);
+    len
+}
+
+/// Run `f` with the process `pid` locked
+fn with_process<T>(pid: usize, f: impl FnOnce(&mut Process) -> T) -> Result<T> {
+    let proc = process(pid).ok_or(FsError::EntryNotFound)?;
+    let mut proc = proc.lock();
+    Ok(f(&mut proc))
+}
+
+/// Get pid of current process without locking it
+///
+/// The current process is usually locked by the syscall which reaches procfs.
+fn current_pid() -> Option<usize> {
+    let thread = crate::process::current_thread()?;
+    PROCESSES
+        .read()
+        .iter()
+        .find(|(_, proc)| Arc::ptr_eq(proc, &thread.proc))
+        .map(|(pid, _)| *pid)
+}
+
+pub type Generator = Box<dyn Fn() -> Result<String> + Send + Sync>;
+
+/// A read-only file whose content is generated on every read
+pub struct ProcFile {
+    inode: usize,
+    generator: Generator,
+}
+
+impl ProcFile {
+    pub fn new(inode: usize, generator: Generator) -> Self {
+        ProcFile { inode, generator }
+    }
+}
+
+impl INode for ProcFile {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = (self.generator)()?;
+        Ok(read_content(content.as_bytes(), offset, buf))
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(proc_metadata(self.inode, FileType::File, 0o444))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A symbolic link whose target is generated on every read
+pub struct ProcSymLink {
+    inode: usize,
+    target: Generator,
+}
+
+impl ProcSymLink {
+    pub fn new(inode: usize, target: Generator) -> Self {
+        ProcSymLink { inode, target }
+    }
+}
+
+impl INode for ProcSymLink {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let target = (self.target)()?;
+        Ok(read_content(target.as_bytes(), offset, buf))
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(proc_metadata(self.inode, FileType::SymLink, 0o777))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Entries of /proc besides the pid directories
+const ROOT_ENTRIES: [&str; 9] = [
+    "self",
+    "meminfo",
+    "uptime",
+    "cpuinfo",
+    "mounts",
+    "stat",
+    "loadavg",
+    "filesystems",
+    "net",
+];
+
+/// The root directory /proc
+pub struct RootINode;
+
+impl INode for RootINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(proc_metadata(1, FileType::Dir, 0o555))
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let ino = 2 + ROOT_ENTRIES.iter().position(|&e| e == name).unwrap_or(0);
+        match name {
+            "." | ".." => Ok(Arc::new(RootINode)),
+            "self" => Ok(Arc::new(ProcSymLink::new(
+                ino,
+                Box::new(|| {
+                    current_pid()
+                        .map(|pid| format!("{}", pid))
+                        .ok_or(FsError::EntryNotFound)
+                }),
+            ))),
+            "meminfo" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(meminfo()))))),
+            "uptime" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(uptime()))))),
+            "cpuinfo" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(cpuinfo()))))),
+            "mounts" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(mounts()))))),
+            "stat" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(stat()))))),
+            "loadavg" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(loadavg()))))),
+            "filesystems" => Ok(Arc::new(ProcFile::new(ino, Box::new(|| Ok(filesystems()))))),
+            "net" => Ok(Arc::new(NetDirINode)),
+            _ => {
+                let pid: usize = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                if PROCESSES.read().contains_key(&pid) {
+                    Ok(Arc::new(PidDirINode::new(pid)))
+                } else {
+                    Err(FsError::EntryNotFound)
+                }
+            }
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            i if i - 2 < ROOT_ENTRIES.len() => Ok(String::from(ROOT_ENTRIES[i - 2])),
+            i => {
+                let pids: Vec<usize> = PROCESSES.read().keys().cloned().collect();
+                pids.get(i - 2 - ROOT_ENTRIES.len())
+                    .map(|pid| format!("{}", pid))
+                    .ok_or(FsError::EntryNotFound)
+            }
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
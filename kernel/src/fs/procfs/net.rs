@@ -0,0 +1,66 @@
+//! Network files in /proc/net
+
+use alloc::{boxed::Box, string::String, sync::Arc};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use super::*;
+use crate::drivers::net::dhcp;
+
+/// Entries of /proc/net
+const NET_ENTRIES: [&str; 1] = ["pnp"];
+
+/// Inode number of /proc/net, apart from those of /proc and the pid directories
+const NET_INODE: usize = 0x100;
+/// Inode number of /proc/net/pnp
+const PNP_INODE: usize = NET_INODE + 1;
+
+/// The directory /proc/net
+pub struct NetDirINode;
+
+impl INode for NetDirINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(proc_metadata(NET_INODE, FileType::Dir, 0o555))
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        match name {
+            "." => Ok(Arc::new(NetDirINode)),
+            ".." => Ok(Arc::new(RootINode)),
+            // nameservers from DHCP, which /etc/resolv.conf may link to
+            "pnp" => Ok(Arc::new(ProcFile::new(
+                PNP_INODE,
+                Box::new(|| Ok(dhcp::pnp())),
+            ))),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            i => NET_ENTRIES
+                .get(i - 2)
+                .map(|&e| String::from(e))
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,303 @@
+//! Per-process directories /proc/<pid>
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::fmt::Write;
+
+use rcore_fs::vfs::*;
+use rcore_memory::paging::{Entry, PageTable};
+use rcore_memory::{Page, PAGE_SIZE};
+
+use super::*;
+use crate::fs::FileLike;
+use crate::process::{Process, THREADS};
+
+/// Entries of /proc/<pid>
+const PID_ENTRIES: [&str; 8] = [
+    "stat", "status", "cmdline", "comm", "maps", "fd", "exe", "cwd",
+];
+
+/// Offset of fd symlinks in the inode number of a process
+const FD_INODE_BASE: usize = 0x100;
+
+/// Inode number of an entry in /proc/<pid>
+fn pid_inode(pid: usize, index: usize) -> usize {
+    ((pid + 1) << 16) | index
+}
+
+/// The directory /proc/<pid>
+pub struct PidDirINode {
+    pid: usize,
+}
+
+impl PidDirINode {
+    pub fn new(pid: usize) -> Self {
+        PidDirINode { pid }
+    }
+}
+
+impl INode for PidDirINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(proc_metadata(pid_inode(self.pid, 0), FileType::Dir, 0o555))
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let pid = self.pid;
+        let index = PID_ENTRIES
+            .iter()
+            .position(|&e| e == name)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let ino = pid_inode(pid, index);
+        let file = |f: fn(&mut Process) -> String| -> Arc<dyn INode> {
+            Arc::new(ProcFile::new(ino, Box::new(move || with_process(pid, f))))
+        };
+        match name {
+            "." => Ok(Arc::new(PidDirINode::new(pid))),
+            ".." => Ok(Arc::new(RootINode)),
+            "stat" => Ok(file(stat)),
+            "status" => Ok(file(status)),
+            "cmdline" => Ok(file(cmdline)),
+            "comm" => Ok(file(|proc| format!("{}\n", comm(proc)))),
+            "maps" => Ok(file(maps)),
+            "fd" => Ok(Arc::new(FdDirINode { pid })),
+            "exe" => Ok(Arc::new(ProcSymLink::new(
+                ino,
+                Box::new(move || with_process(pid, |proc| proc.exec_path.clone())),
+            ))),
+            "cwd" => Ok(Arc::new(ProcSymLink::new(
+                ino,
+                Box::new(move || with_process(pid, |proc| proc.cwd.clone())),
+            ))),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            i => PID_ENTRIES
+                .get(i - 2)
+                .map(|&e| String::from(e))
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The directory /proc/<pid>/fd
+pub struct FdDirINode {
+    pid: usize,
+}
+
+impl INode for FdDirINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(proc_metadata(pid_inode(self.pid, 6), FileType::Dir, 0o500))
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let pid = self.pid;
+        match name {
+            "." => Ok(Arc::new(FdDirINode { pid })),
+            ".." => Ok(Arc::new(PidDirINode::new(pid))),
+            _ => {
+                let fd: usize = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                if !with_process(pid, |proc| proc.files.contains_key(&fd))? {
+                    return Err(FsError::EntryNotFound);
+                }
+                Ok(Arc::new(ProcSymLink::new(
+                    pid_inode(pid, FD_INODE_BASE + fd),
+                    Box::new(move || {
+                        with_process(pid, |proc| proc.files.get(&fd).map(fd_target))?
+                            .ok_or(FsError::EntryNotFound)
+                    }),
+                )))
+            }
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            i => with_process(self.pid, |proc| {
+                proc.files.keys().nth(i - 2).map(|fd| format!("{}", fd))
+            })?
+            .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Target of the symlink /proc/<pid>/fd/<fd>
+pub fn fd_target(file_like: &FileLike) -> String {
+    match file_like {
+        FileLike::File(file) => file.path.clone(),
+        FileLike::Socket(_) => String::from("socket:[]"),
+        FileLike::EpollInstance(_) => String::from("anon_inode:[eventpoll]"),
+        FileLike::EventFd(_) => String::from("anon_inode:[eventfd]"),
+        FileLike::TimerFd(_) => String::from("anon_inode:[timerfd]"),
+        FileLike::SignalFd(_) => String::from("anon_inode:[signalfd]"),
+    }
+}
+
+/// Name of the executable, truncated like `TASK_COMM_LEN` in Linux
+fn comm(proc: &Process) -> String {
+    let name = proc.exec_path.rsplit('/').next().unwrap_or("");
+    String::from(&name[..name.len().min(15)])
+}
+
+/// Single character process state
+fn state(proc: &Process) -> char {
+    if proc.exited() {
+        'Z'
+    } else if proc.stopped {
+        'T'
+    } else {
+        'S'
+    }
+}
+
+/// Return `(vm_size, vm_rss)` in bytes
+fn memory_usage(proc: &Process) -> (usize, usize) {
+    let mut vm = proc.vm.lock();
+    let areas: Vec<(usize, usize)> = vm
+        .iter()
+        .map(|area| (area.start_addr(), area.end_addr()))
+        .collect();
+    let mut size = 0;
+    let mut rss = 0;
+    for (start, end) in areas {
+        size += end - start;
+        let page_table = vm.get_page_table_mut();
+        for page in Page::range_of(start, end) {
+            if let Some(entry) = page_table.get_entry(page.start_address()) {
+                if entry.present() {
+                    rss += PAGE_SIZE;
+                }
+            }
+        }
+    }
+    (size, rss)
+}
+
+fn stat(proc: &mut Process) -> String {
+    let (vm_size, vm_rss) = memory_usage(proc);
+    format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 0 0 0 0 20 0 {} 0 0 {} {} \
+         18446744073709551615 0 0 0 0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0 0 0 0 0 0 0 0 {}\n",
+        proc.pid,
+        comm(proc),
+        state(proc),
+        proc.parent.0,
+        proc.pgid,
+        proc.pgid,
+        proc.threads.len(),
+        vm_size,
+        vm_rss / PAGE_SIZE,
+        proc.exit_code,
+    )
+}
+
+fn status(proc: &mut Process) -> String {
+    let (vm_size, vm_rss) = memory_usage(proc);
+    let mut s = String::new();
+    writeln!(s, "Name:\t{}", comm(proc)).unwrap();
+    writeln!(
+        s,
+        "State:\t{} ({})",
+        state(proc),
+        match state(proc) {
+            'Z' => "zombie",
+            'T' => "stopped",
+            _ => "sleeping",
+        }
+    )
+    .unwrap();
+    writeln!(s, "Tgid:\t{}", proc.pid).unwrap();
+    writeln!(s, "Pid:\t{}", proc.pid).unwrap();
+    writeln!(s, "PPid:\t{}", proc.parent.0).unwrap();
+    let cred = &proc.cred;
+    // file system ids follow effective ids
+    writeln!(
+        s,
+        "Uid:\t{}\t{}\t{}\t{}",
+        cred.ruid, cred.euid, cred.suid, cred.euid
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "Gid:\t{}\t{}\t{}\t{}",
+        cred.rgid, cred.egid, cred.sgid, cred.egid
+    )
+    .unwrap();
+    writeln!(s, "FDSize:\t{}", proc.files.len()).unwrap();
+    writeln!(s, "VmSize:\t{} kB", vm_size / 1024).unwrap();
+    writeln!(s, "VmRSS:\t{} kB", vm_rss / 1024).unwrap();
+    writeln!(s, "Threads:\t{}", proc.threads.len()).unwrap();
+    let alive = THREADS.read();
+    for tid in proc.threads.iter().filter(|tid| alive.contains_key(tid)) {
+        writeln!(s, "Tid:\t{}", tid).unwrap();
+    }
+    s
+}
+
+fn cmdline(proc: &mut Process) -> String {
+    let mut s = String::new();
+    for arg in proc.args.iter() {
+        s.push_str(arg);
+        s.push('\0');
+    }
+    s
+}
+
+fn maps(proc: &mut Process) -> String {
+    let vm = proc.vm.lock();
+    let mut s = String::new();
+    for area in vm.iter() {
+        let attr = area.attr();
+        writeln!(
+            s,
+            "{:08x}-{:08x} {}{}{}p 00000000 00:00 0          [{}]",
+            area.start_addr(),
+            area.end_addr(),
+            if attr.is_user() { 'r' } else { '-' },
+            if attr.is_readonly() { '-' } else { 'w' },
+            if attr.is_execute() { 'x' } else { '-' },
+            area.name(),
+        )
+        .unwrap();
+    }
+    s
+}
@@ -0,0 +1,91 @@
+//! System wide files in /proc
+
+use alloc::string::String;
+use core::fmt::Write;
+
+use rcore_memory::PAGE_SIZE;
+
+use crate::consts::{ARCH, SMP_CORES};
+use crate::fs::mount::{filesystem_types, MOUNTS, MS_RDONLY};
+use crate::fs::page_cache::cache_stats;
+use crate::memory::{frame_stats, swap_stats};
+use crate::process::{PROCESSES, THREADS};
+use crate::trap::{cpu_tick, uptime_msec};
+
+pub fn meminfo() -> String {
+    let (total, free) = frame_stats();
+    let (swap_total, swap_free) = swap_stats();
+    let (cached, dirty) = cache_stats();
+    let available = free + cached;
+    let mut s = String::new();
+    writeln!(s, "MemTotal:       {:8} kB", total * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "MemFree:        {:8} kB", free * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "MemAvailable:   {:8} kB", available * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "Buffers:        {:8} kB", 0).unwrap();
+    writeln!(s, "Cached:         {:8} kB", cached * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "SwapTotal:      {:8} kB", swap_total * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "SwapFree:       {:8} kB", swap_free * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "Dirty:          {:8} kB", dirty * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "Shmem:          {:8} kB", 0).unwrap();
+    s
+}
+
+pub fn uptime() -> String {
+    let msec = uptime_msec();
+    format!("{}.{:02} 0.00\n", msec / 1000, msec % 1000 / 10)
+}
+
+pub fn cpuinfo() -> String {
+    let mut s = String::new();
+    for i in 0..*SMP_CORES {
+        writeln!(s, "processor\t: {}", i).unwrap();
+        writeln!(s, "arch\t\t: {}", ARCH).unwrap();
+        writeln!(s).unwrap();
+    }
+    s
+}
+
+pub fn mounts() -> String {
+    let mut s = String::new();
+    for mount in MOUNTS.read().iter() {
+        let mode = if mount.flags & MS_RDONLY != 0 {
+            "ro"
+        } else {
+            "rw"
+        };
+        writeln!(
+            s,
+            "{} {} {} {} 0 0",
+            mount.source, mount.target, mount.fstype, mode
+        )
+        .unwrap();
+    }
+    s
+}
+
+pub fn filesystems() -> String {
+    let mut s = String::new();
+    for (name, fstype) in filesystem_types() {
+        let nodev = if fstype.requires_dev { "" } else { "nodev" };
+        writeln!(s, "{}\t{}", nodev, name).unwrap();
+    }
+    s
+}
+
+pub fn stat() -> String {
+    // `cpu_tick` counts ticks of all processors, we have no idea where they are spent
+    let idle = cpu_tick();
+    let mut s = String::new();
+    writeln!(s, "cpu  0 0 0 {} 0 0 0 0 0 0", idle).unwrap();
+    writeln!(s, "btime 0").unwrap();
+    writeln!(s, "processes {}", PROCESSES.read().len()).unwrap();
+    writeln!(s, "procs_running {}", THREADS.read().len()).unwrap();
+    writeln!(s, "procs_blocked 0").unwrap();
+    s
+}
+
+pub fn loadavg() -> String {
+    let threads = THREADS.read();
+    let last = threads.keys().last().cloned().unwrap_or(0);
+    format!("0.00 0.00 0.00 0/{} {}\n", threads.len(), last)
+}
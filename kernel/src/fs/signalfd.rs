@@ -0,0 +1,206 @@
+//! signalfd(2): accept signals of the reading thread by a file descriptor
+//!
+//! Signals in the mask are taken from `Process::sig_queue` instead of being
+//! delivered to a handler.
+
+use crate::fs::fcntl::{O_CLOEXEC, O_NONBLOCK};
+use crate::process::{current_thread, Thread};
+use crate::signal::{Siginfo, Signal, Sigset};
+use crate::sync::wait_for_condition;
+use crate::syscall::{SysError, SysResult};
+use alloc::sync::Arc;
+use alloc::vec;
+use bitflags::*;
+use core::any::Any;
+use core::mem::size_of;
+use num::FromPrimitive;
+use rcore_fs::vfs::PollStatus;
+use spin::RwLock;
+
+bitflags! {
+    pub struct SignalFdFlags: usize {
+        const NONBLOCK = O_NONBLOCK;
+        const CLOEXEC = O_CLOEXEC;
+    }
+}
+
+/// Linux struct signalfd_siginfo
+#[repr(C)]
+#[derive(Default)]
+struct SignalFdSiginfo {
+    signo: u32,
+    errno: i32,
+    code: i32,
+    pid: u32,
+    uid: u32,
+    fd: i32,
+    tid: u32,
+    band: u32,
+    overrun: u32,
+    trapno: u32,
+    status: i32,
+    int: i32,
+    ptr: u64,
+    utime: u64,
+    stime: u64,
+    addr: u64,
+    addr_lsb: u16,
+    _pad2: u16,
+    syscall: i32,
+    call_addr: u64,
+    arch: u32,
+    _pad: [u8; 28],
+}
+
+impl SignalFdSiginfo {
+    fn new(info: &Siginfo) -> Self {
+        let signal = Signal::from_i32(info.signo).unwrap();
+        let mut ssi = SignalFdSiginfo {
+            signo: info.signo as u32,
+            errno: info.errno,
+            code: info.code,
+            ..SignalFdSiginfo::default()
+        };
+        match signal {
+            Signal::SIGILL | Signal::SIGFPE | Signal::SIGSEGV | Signal::SIGBUS => {
+                ssi.addr = unsafe { info.field.addr } as u64;
+            }
+            _ => {
+                // pid and uid of kill are where SIGCHLD has them
+                let fields = unsafe { info.field.sigchld };
+                ssi.pid = fields.pid as u32;
+                ssi.uid = fields.uid;
+                if signal == Signal::SIGCHLD {
+                    ssi.status = fields.status;
+                }
+            }
+        }
+        ssi
+    }
+}
+
+struct SignalFdInner {
+    mask: RwLock<Sigset>,
+    nonblock: RwLock<bool>,
+}
+
+#[derive(Clone)]
+pub struct SignalFd {
+    inner: Arc<SignalFdInner>,
+    pub fd_cloexec: bool,
+}
+
+impl SignalFd {
+    pub fn new(mask: Sigset, flags: SignalFdFlags) -> Self {
+        let signalfd = SignalFd {
+            inner: Arc::new(SignalFdInner {
+                mask: RwLock::new(Sigset::empty()),
+                nonblock: RwLock::new(flags.contains(SignalFdFlags::NONBLOCK)),
+            }),
+            fd_cloexec: flags.contains(SignalFdFlags::CLOEXEC),
+        };
+        signalfd.set_mask(mask);
+        signalfd
+    }
+
+    pub fn dup(&self, fd_cloexec: bool) -> Self {
+        SignalFd {
+            inner: self.inner.clone(),
+            fd_cloexec,
+        }
+    }
+
+    /// Identity of the open file description
+    pub fn description(&self) -> Arc<dyn Any + Send + Sync> {
+        self.inner.clone()
+    }
+
+    pub fn nonblock(&self) -> bool {
+        *self.inner.nonblock.read()
+    }
+
+    pub fn set_nonblock(&self, nonblock: bool) {
+        *self.inner.nonblock.write() = nonblock;
+    }
+
+    /// SIGKILL and SIGSTOP are silently ignored
+    pub fn set_mask(&self, mut mask: Sigset) {
+        mask.remove(Signal::SIGKILL);
+        mask.remove(Signal::SIGSTOP);
+        *self.inner.mask.write() = mask;
+    }
+
+    /// Whether a signal in the mask is pending for `thread`
+    fn has_pending(&self, thread: &Thread) -> bool {
+        let mask = *self.inner.mask.read();
+        thread.proc.lock().sig_queue.iter().any(|(info, tid)| {
+            (*tid == -1 || *tid as usize == thread.tid)
+                && mask.contains(Signal::from_i32(info.signo).unwrap())
+        })
+    }
+
+    /// Move pending signals in the mask into `buf`
+    fn dequeue(&self, thread: &Thread, buf: &mut [u8]) -> usize {
+        const SIZE: usize = size_of::<SignalFdSiginfo>();
+        let mask = *self.inner.mask.read();
+        let mut proc = thread.proc.lock();
+        let mut len = 0;
+        while len + SIZE <= buf.len() {
+            let index = proc.sig_queue.iter().position(|(info, tid)| {
+                (*tid == -1 || *tid as usize == thread.tid)
+                    && mask.contains(Signal::from_i32(info.signo).unwrap())
+            });
+            let (info, _) = match index {
+                Some(index) => proc.sig_queue.remove(index).unwrap(),
+                None => break,
+            };
+            // a real time signal may still be queued
+            if !proc.sig_queue.iter().any(|(i, _)| i.signo == info.signo) {
+                proc.pending_sigset
+                    .remove(Signal::from_i32(info.signo).unwrap());
+            }
+            let ssi = SignalFdSiginfo::new(&info);
+            let bytes = unsafe { core::slice::from_raw_parts(&ssi as *const _ as *const u8, SIZE) };
+            buf[len..len + SIZE].copy_from_slice(bytes);
+            len += SIZE;
+        }
+        len
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> SysResult {
+        if buf.len() < size_of::<SignalFdSiginfo>() {
+            return Err(SysError::EINVAL);
+        }
+        let thread = current_thread().unwrap();
+        loop {
+            let len = self.dequeue(&thread, buf);
+            if len > 0 {
+                return Ok(len);
+            }
+            if self.nonblock() {
+                return Err(SysError::EAGAIN);
+            }
+            if thread.has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            let signal_bus = thread.proc.lock().eventbus.clone();
+            wait_for_condition(vec![signal_bus], || {
+                self.has_pending(&thread) || thread.has_signal_to_handle()
+            })
+            .await;
+        }
+    }
+
+    /// Readable if the current thread has a signal in the mask
+    pub fn poll(&self) -> PollStatus {
+        let read = match current_thread() {
+            Some(thread) => self.has_pending(&thread),
+            None => false,
+        };
+        PollStatus {
+            read,
+            write: false,
+            error: false,
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! timerfd(2): a timer that delivers expirations by reading a file descriptor
+//!
+//! Expirations are counted lazily from the deadline, and the naive timer is
+//! only armed to wake up waiters at the next deadline.
+
+use crate::arch::timer::timer_now;
+use crate::fs::fcntl::{O_CLOEXEC, O_NONBLOCK};
+use crate::process::current_thread;
+use crate::sync::{wait_for_condition, Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::syscall::{SysError, SysResult, TimeSpec};
+use crate::trap::NAIVE_TIMER;
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use bitflags::*;
+use core::any::Any;
+use core::mem::size_of;
+use core::time::Duration;
+use rcore_fs::vfs::PollStatus;
+use spin::RwLock;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_BOOTTIME: usize = 7;
+
+bitflags! {
+    pub struct TimerFdFlags: usize {
+        const NONBLOCK = O_NONBLOCK;
+        const CLOEXEC = O_CLOEXEC;
+    }
+}
+
+bitflags! {
+    pub struct TimerSetFlags: usize {
+        /// The new value is an absolute time of the clock
+        const ABSTIME = 1;
+        /// Ignored, the realtime clock is never set
+        const CANCEL_ON_SET = 2;
+    }
+}
+
+/// Linux struct itimerspec
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ITimerSpec {
+    pub interval: TimeSpec,
+    pub value: TimeSpec,
+}
+
+#[derive(Default)]
+struct TimerState {
+    /// Next expiration in `timer_now` time, `None` if disarmed
+    deadline: Option<Duration>,
+    /// Period of the timer, zero for a one-shot timer
+    interval: Duration,
+    /// Expirations not read yet
+    expirations: u64,
+    /// Deadline the naive timer is armed for
+    armed: Option<Duration>,
+}
+
+impl TimerState {
+    /// Count expirations up to `now`
+    fn update(&mut self, now: Duration) {
+        let deadline = match self.deadline {
+            Some(deadline) if deadline <= now => deadline,
+            _ => return,
+        };
+        if self.interval == Duration::from_secs(0) {
+            self.expirations += 1;
+            self.deadline = None;
+        } else {
+            let interval = self.interval.as_nanos();
+            let n = (now - deadline).as_nanos() / interval + 1;
+            self.expirations += n as u64;
+            self.deadline = Some(deadline + Duration::from_nanos((n * interval) as u64));
+        }
+    }
+}
+
+struct TimerFdInner {
+    clock: usize,
+    state: Mutex<TimerState>,
+    nonblock: RwLock<bool>,
+    /// READABLE is set when expirations are counted in the timer interrupt
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+impl TimerFdInner {
+    /// Count expirations and make sure a waiter is woken up at the next deadline
+    fn refresh(self: &Arc<Self>) -> u64 {
+        let mut state = self.state.lock();
+        state.update(timer_now());
+        let expirations = state.expirations;
+        let arm = match state.deadline {
+            Some(deadline) if state.armed != Some(deadline) => {
+                state.armed = Some(deadline);
+                Some(deadline)
+            }
+            _ => None,
+        };
+        // the timer interrupt locks the naive timer before the state
+        drop(state);
+        if let Some(deadline) = arm {
+            let timerfd = Arc::downgrade(self);
+            NAIVE_TIMER.lock().add(
+                deadline,
+                Box::new(move |now| TimerFdInner::expire(timerfd, now)),
+            );
+        }
+        expirations
+    }
+
+    /// Called in the timer interrupt, the naive timer can not be armed again here
+    fn expire(timerfd: Weak<Self>, now: Duration) {
+        let timerfd = match timerfd.upgrade() {
+            Some(timerfd) => timerfd,
+            None => return,
+        };
+        let mut state = timerfd.state.lock();
+        state.update(now);
+        state.armed = None;
+        if state.expirations > 0 {
+            timerfd.eventbus.lock().set(Event::READABLE);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TimerFd {
+    inner: Arc<TimerFdInner>,
+    pub fd_cloexec: bool,
+}
+
+impl TimerFd {
+    pub fn new(clock: usize, flags: TimerFdFlags) -> Self {
+        TimerFd {
+            inner: Arc::new(TimerFdInner {
+                clock,
+                state: Mutex::new(TimerState::default()),
+                nonblock: RwLock::new(flags.contains(TimerFdFlags::NONBLOCK)),
+                eventbus: EventBus::new(),
+            }),
+            fd_cloexec: flags.contains(TimerFdFlags::CLOEXEC),
+        }
+    }
+
+    pub fn dup(&self, fd_cloexec: bool) -> Self {
+        TimerFd {
+            inner: self.inner.clone(),
+            fd_cloexec,
+        }
+    }
+
+    /// Identity of the open file description
+    pub fn description(&self) -> Arc<dyn Any + Send + Sync> {
+        self.inner.clone()
+    }
+
+    pub fn nonblock(&self) -> bool {
+        *self.inner.nonblock.read()
+    }
+
+    pub fn set_nonblock(&self, nonblock: bool) {
+        *self.inner.nonblock.write() = nonblock;
+    }
+
+    pub fn eventbus(&self) -> Arc<Mutex<EventBus>> {
+        self.inner.eventbus.clone()
+    }
+
+    /// Current value of the timer, the time until the next expiration is relative
+    pub fn get(&self) -> ITimerSpec {
+        let mut state = self.inner.state.lock();
+        let now = timer_now();
+        state.update(now);
+        let value = match state.deadline {
+            Some(deadline) => deadline - now,
+            None => Duration::from_secs(0),
+        };
+        ITimerSpec {
+            interval: duration_to_timespec(state.interval),
+            value: duration_to_timespec(value),
+        }
+    }
+
+    /// Arm or disarm the timer, returning its old value
+    pub fn set(&self, new: ITimerSpec, flags: TimerSetFlags) -> ITimerSpec {
+        let old = self.get();
+        let now = timer_now();
+        let value = new.value.to_duration();
+        let deadline = if new.value.is_zero() {
+            None
+        } else if flags.contains(TimerSetFlags::ABSTIME) {
+            // convert a time of the clock to `timer_now` time
+            let clock_now = match self.inner.clock {
+                CLOCK_REALTIME => TimeSpec::get_epoch().to_duration(),
+                _ => now,
+            };
+            Some(now + value.checked_sub(clock_now).unwrap_or_default())
+        } else {
+            Some(now + value)
+        };
+        let mut state = self.inner.state.lock();
+        state.deadline = deadline;
+        state.interval = new.interval.to_duration();
+        state.expirations = 0;
+        drop(state);
+        self.inner.eventbus.lock().clear(Event::READABLE);
+        self.inner.refresh();
+        old
+    }
+
+    /// Take the expirations
+    fn try_read(&self) -> Option<u64> {
+        if self.inner.refresh() == 0 {
+            return None;
+        }
+        let mut state = self.inner.state.lock();
+        let expirations = state.expirations;
+        state.expirations = 0;
+        self.inner.eventbus.lock().clear(Event::READABLE);
+        Some(expirations)
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> SysResult {
+        if buf.len() < size_of::<u64>() {
+            return Err(SysError::EINVAL);
+        }
+        let thread = current_thread().unwrap();
+        loop {
+            if let Some(expirations) = self.try_read() {
+                buf[..size_of::<u64>()].copy_from_slice(&expirations.to_ne_bytes());
+                return Ok(size_of::<u64>());
+            }
+            if self.nonblock() {
+                return Err(SysError::EAGAIN);
+            }
+            if thread.has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
+            let signal_bus = thread.proc.lock().eventbus.clone();
+            wait_for_condition(vec![self.inner.eventbus.clone(), signal_bus], || {
+                self.inner.refresh() > 0 || thread.has_signal_to_handle()
+            })
+            .await;
+        }
+    }
+
+    pub fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: self.inner.refresh() > 0,
+            write: false,
+            error: false,
+        }
+    }
+}
+
+fn duration_to_timespec(duration: Duration) -> TimeSpec {
+    TimeSpec {
+        sec: duration.as_secs() as usize,
+        nsec: duration.subsec_nanos() as usize,
+    }
+}
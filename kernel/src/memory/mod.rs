@@ -8,13 +8,17 @@ use bitmap_allocator::BitAlloc;
 use buddy_system_allocator::Heap;
 use core::mem;
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use log::*;
 use rcore_memory::*;
 
+pub use self::swap::swap_stats;
 pub use crate::arch::paging::*;
 pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
 pub type MemorySet = rcore_memory::memory_set::MemorySet<PageTableImpl>;
 
+pub mod swap;
+
 // x86_64 support up to 1T memory
 #[cfg(target_arch = "x86_64")]
 pub type FrameAlloc = bitmap_allocator::BitAlloc256M;
@@ -30,6 +34,19 @@ pub type FrameAlloc = bitmap_allocator::BitAlloc1M;
 
 pub static FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> = SpinNoIrqLock::new(FrameAlloc::DEFAULT);
 
+/// Number of frames inserted into `FRAME_ALLOCATOR` at boot
+pub static FRAMES_TOTAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of frames currently handed out by `GlobalFrameAlloc`
+static FRAMES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Return `(total, free)` number of physical frames
+pub fn frame_stats() -> (usize, usize) {
+    let total = FRAMES_TOTAL.load(Ordering::Relaxed);
+    let allocated = FRAMES_ALLOCATED.load(Ordering::Relaxed);
+    (total, total.saturating_sub(allocated))
+}
+
 /// Convert physical address to virtual address
 #[inline]
 #[cfg(not(mipsel))]
@@ -71,8 +88,14 @@ impl FrameAllocator for GlobalFrameAlloc {
             .alloc()
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
         trace!("Allocate frame: {:x?}", ret);
-        ret
-        // TODO: try to swap out when alloc failed
+        match ret {
+            Some(_) => {
+                FRAMES_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+                ret
+            }
+            // take the frame of a cached file page, or of a page swapped out
+            None => crate::fs::page_cache::evict().or_else(swap::swap_out_any),
+        }
     }
     fn alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<PhysAddr> {
         // get the real address of the alloc frame
@@ -81,6 +104,9 @@ impl FrameAllocator for GlobalFrameAlloc {
             .alloc_contiguous(size, align_log2)
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
         trace!("Allocate frame: {:x?}", ret);
+        if ret.is_some() {
+            FRAMES_ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        }
         ret
         // TODO: try to swap out when alloc failed
     }
@@ -89,6 +115,7 @@ impl FrameAllocator for GlobalFrameAlloc {
         FRAME_ALLOCATOR
             .lock()
             .dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        FRAMES_ALLOCATED.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
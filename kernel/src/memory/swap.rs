@@ -0,0 +1,457 @@
+//! Swap out user pages to a swap partition or a swap file
+//!
+//! The swap area is made by mkswap(8) and turned on by swapon(2).
+//! When `GlobalFrameAlloc` runs out of frames, it swaps out a page chosen by the
+//! enhanced clock algorithm among the swappable pages of all processes.
+//! A forked child shares the swapped out pages of its parent in the swap area.
+//! A swap file is written through its file system, around the page cache,
+//! which it is pinned out of while swapping is on.
+
+use super::{alloc_frame, dealloc_frame, MemoryAttr, MemorySet};
+use crate::drivers::BlockDriver;
+use crate::fs::page_cache;
+use crate::process::{active_elsewhere, memory_sets, PROCESSES};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::convert::TryInto;
+use log::*;
+use rcore_fs::vfs::{FileType, INode};
+use rcore_memory::paging::PageTable;
+use rcore_memory::swap::{
+    self, EnhancedClockSwapManager, Frame, PageState, SwapError, SwapManager, SwapSpace, Swapper,
+};
+use rcore_memory::{PhysAddr, VirtAddr, PAGE_SIZE};
+
+/// Size of the blocks of block devices
+const BLOCK_SIZE: usize = 512;
+/// Signature of a swap area, at the end of its first page
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+/// Offset of the number of the last page in the first page
+const LAST_PAGE_OFFSET: usize = 1028;
+
+/// Where a swap area is
+pub enum Backing {
+    /// A whole block device
+    Block(Arc<dyn BlockDriver>),
+    /// A regular file
+    File(Arc<dyn INode>),
+}
+
+impl Backing {
+    fn read_page(&self, page: usize, buf: &mut [u8]) -> bool {
+        match self {
+            Backing::Block(device) => buf
+                .chunks_mut(BLOCK_SIZE)
+                .enumerate()
+                .all(|(i, block)| device.read_block(page * PAGE_SIZE / BLOCK_SIZE + i, block)),
+            Backing::File(inode) => inode.read_at(page * PAGE_SIZE, buf).ok() == Some(PAGE_SIZE),
+        }
+    }
+
+    fn write_page(&self, page: usize, buf: &[u8]) -> bool {
+        match self {
+            Backing::Block(device) => buf
+                .chunks(BLOCK_SIZE)
+                .enumerate()
+                .all(|(i, block)| device.write_block(page * PAGE_SIZE / BLOCK_SIZE + i, block)),
+            Backing::File(inode) => inode.write_at(page * PAGE_SIZE, buf).ok() == Some(PAGE_SIZE),
+        }
+    }
+}
+
+/// A swap area turned on by swapon
+///
+/// Page 0 is the header, pages 1 to `pages` hold swapped out pages.
+/// The token of a swapped out page is its page in the area.
+/// A page is shared by the page tables of a forked process and its parent
+/// until each swaps it in or unmaps it.
+struct SwapArea {
+    /// Path given to swapon
+    path: String,
+    backing: Backing,
+    pages: usize,
+    /// Free pages below `next`
+    free: Vec<usize>,
+    /// Pages from `next` on have never been used
+    next: usize,
+    /// Number of page tables sharing a page besides the first one
+    shared: BTreeMap<usize, usize>,
+}
+
+impl SwapArea {
+    fn free_pages(&self) -> usize {
+        self.pages + 1 - self.next + self.free.len()
+    }
+
+    /// Drop a reference to `page`, freeing it after the last one
+    fn release(&mut self, page: usize) {
+        match self.shared.get_mut(&page) {
+            Some(1) => {
+                self.shared.remove(&page);
+            }
+            Some(count) => *count -= 1,
+            None => self.free.push(page),
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.free.pop().or_else(|| {
+            if self.next <= self.pages {
+                self.next += 1;
+                Some(self.next - 1)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Swapper for SwapArea {
+    fn swap_out(&mut self, data: &[u8]) -> Result<usize, ()> {
+        let page = self.alloc().ok_or(())?;
+        if !self.backing.write_page(page, data) {
+            self.free.push(page);
+            return Err(());
+        }
+        Ok(page)
+    }
+
+    fn swap_update(&mut self, token: usize, data: &[u8]) -> Result<(), ()> {
+        if !self.backing.write_page(token, data) {
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
+        if !self.backing.read_page(token, data) {
+            return Err(());
+        }
+        self.release(token);
+        Ok(())
+    }
+
+    fn swap_free(&mut self, token: usize) {
+        self.release(token);
+    }
+
+    fn swap_share(&mut self, token: usize) {
+        *self.shared.entry(token).or_insert(0) += 1;
+    }
+}
+
+/// The swap area taking a page of the page table with `token`
+///
+/// Other CPUs running the page table may still reach the page through their TLB
+/// after its entry is cleared, so the page is refused then, and stays mapped.
+struct Victim<'a> {
+    area: &'a mut SwapArea,
+    token: usize,
+    /// Whether the page was refused for that
+    active: bool,
+}
+
+impl Swapper for Victim<'_> {
+    fn swap_out(&mut self, data: &[u8]) -> Result<usize, ()> {
+        // the entry is cleared by now
+        if active_elsewhere(self.token) {
+            self.active = true;
+            return Err(());
+        }
+        self.area.swap_out(data)
+    }
+
+    fn swap_update(&mut self, token: usize, data: &[u8]) -> Result<(), ()> {
+        self.area.swap_update(token, data)
+    }
+
+    fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
+        self.area.swap_in(token, data)
+    }
+
+    fn swap_free(&mut self, token: usize) {
+        self.area.swap_free(token)
+    }
+
+    fn swap_share(&mut self, token: usize) {
+        self.area.swap_share(token)
+    }
+}
+
+struct Swap {
+    area: Option<SwapArea>,
+    /// Set by swapoff while it swaps everything in
+    closing: bool,
+    manager: EnhancedClockSwapManager,
+}
+
+lazy_static! {
+    static ref SWAP: Mutex<Swap> = Mutex::new(Swap {
+        area: None,
+        closing: false,
+        manager: EnhancedClockSwapManager::default(),
+    });
+}
+
+/// The swap space given to memory sets
+struct KernelSwapSpace;
+
+impl SwapSpace for KernelSwapSpace {
+    fn push(&self, frame: Frame) {
+        let mut swap = SWAP.lock();
+        if swap.area.is_some() && !swap.closing {
+            swap.manager.push(frame);
+        }
+    }
+
+    fn swap_in(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+        // allocate before locking, as the allocator may swap out another page
+        let target = match alloc_frame() {
+            Some(target) => target,
+            None => return false,
+        };
+        let result = match SWAP.lock().area.as_mut() {
+            Some(area) => swap::swap_in(pt, addr, target, area),
+            None => Err(SwapError::NoSwapped),
+        };
+        if let Err(err) = result {
+            warn!("failed to swap in page {:#x}: {:?}", addr, err);
+            dealloc_frame(target);
+            return false;
+        }
+        true
+    }
+
+    fn swap_free(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        if let Some(area) = SWAP.lock().area.as_mut() {
+            swap::swap_free(pt, addr, area).ok();
+        }
+    }
+
+    fn swap_share(
+        &self,
+        pt: &mut dyn PageTable,
+        src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) -> bool {
+        match SWAP.lock().area.as_mut() {
+            Some(area) => swap::swap_share(pt, src_pt, addr, attr, area).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Memory sets of all processes by page table token,
+/// and whether none of them was left out for being locked
+fn lockable_memory_sets() -> (BTreeMap<usize, Arc<Mutex<MemorySet>>>, bool) {
+    let mut vms = BTreeMap::new();
+    let processes = match PROCESSES.try_read() {
+        Some(processes) => processes,
+        None => return (vms, false),
+    };
+    let mut complete = true;
+    for proc in processes.values() {
+        let proc = match proc.try_lock() {
+            Some(proc) => proc,
+            None => {
+                complete = false;
+                continue;
+            }
+        };
+        let token = match proc.vm.try_lock() {
+            Some(vm) => vm.token(),
+            None => {
+                complete = false;
+                continue;
+            }
+        };
+        vms.insert(token, proc.vm.clone());
+    }
+    (vms, complete)
+}
+
+/// Swap out a page and return its frame, for the allocator running out of frames
+pub fn swap_out_any() -> Option<PhysAddr> {
+    // dropped after the lock is released,
+    // as dropping the last reference to a memory set frees its swap space
+    let vms: BTreeMap<usize, Arc<Mutex<MemorySet>>>;
+    // the allocator may be called with the lock held
+    let mut swap = SWAP.try_lock()?;
+    let Swap {
+        area,
+        closing,
+        manager,
+    } = &mut *swap;
+    let area = area.as_mut().filter(|_| !*closing)?;
+    let (lockable_vms, complete) = lockable_memory_sets();
+    vms = lockable_vms;
+    loop {
+        let frame = manager.pop(
+            &mut |frame, clear_accessed| match vms.get(&frame.get_token()) {
+                Some(_) if active_elsewhere(frame.get_token()) => PageState::Busy,
+                Some(vm) => match vm.try_lock() {
+                    Some(mut vm) => vm.page_state(frame.get_virtaddr(), clear_accessed),
+                    None => PageState::Busy,
+                },
+                None if complete => PageState::Gone,
+                None => PageState::Busy,
+            },
+        )?;
+        let mut victim = Victim {
+            area: &mut *area,
+            token: frame.get_token(),
+            active: false,
+        };
+        let result = match vms.get(&frame.get_token()).and_then(|vm| vm.try_lock()) {
+            Some(mut vm) => vm.swap_out(frame.get_virtaddr(), &mut victim),
+            None => Err(SwapError::NotMapped),
+        };
+        if victim.active {
+            // started running elsewhere meanwhile, try another one
+            manager.push(frame);
+            continue;
+        }
+        match result {
+            Ok(target) => {
+                debug!(
+                    "swap out page {:#x} of {:#x}",
+                    frame.get_virtaddr(),
+                    frame.get_token()
+                );
+                return Some(target);
+            }
+            Err(SwapError::IOError) => {
+                // the area is full, or broken
+                manager.push(frame);
+                return None;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Turn on swapping to the swap area at `path` in `backing`.
+/// The caller must not hold any process.
+pub fn swapon(path: String, backing: Backing) -> Result<(), SysError> {
+    let file = match &backing {
+        Backing::File(inode) => Some(inode.clone()),
+        Backing::Block(_) => None,
+    };
+    if let Some(inode) = &file {
+        if inode.metadata()?.type_ != FileType::File {
+            return Err(SysError::EINVAL);
+        }
+        // cached pages would go stale once swapping writes the file
+        page_cache::pin(inode)?;
+    }
+    let result = enable(path, backing);
+    if let (Err(_), Some(inode)) = (&result, &file) {
+        page_cache::unpin(inode);
+    }
+    result
+}
+
+fn enable(path: String, backing: Backing) -> Result<(), SysError> {
+    let mut pages = match &backing {
+        Backing::File(inode) => (inode.metadata()?.size / PAGE_SIZE).saturating_sub(1),
+        Backing::Block(_) => usize::max_value(),
+    };
+    let mut header = vec![0u8; PAGE_SIZE];
+    if !backing.read_page(0, &mut header) {
+        return Err(SysError::EIO);
+    }
+    if &header[PAGE_SIZE - SWAP_MAGIC.len()..] != SWAP_MAGIC {
+        return Err(SysError::EINVAL);
+    }
+    let last_page = &header[LAST_PAGE_OFFSET..LAST_PAGE_OFFSET + 4];
+    pages = pages.min(u32::from_ne_bytes(last_page.try_into().unwrap()) as usize);
+    if pages == 0 {
+        return Err(SysError::EINVAL);
+    }
+    {
+        let mut swap = SWAP.lock();
+        if swap.area.is_some() {
+            return Err(SysError::EBUSY);
+        }
+        info!("swapon: {:?}, {} pages", path, pages);
+        swap.area = Some(SwapArea {
+            path,
+            backing,
+            pages,
+            free: Vec::new(),
+            next: 1,
+            shared: BTreeMap::new(),
+        });
+    }
+    swap::set_swap_space(&KernelSwapSpace);
+    // pages already in memory can be swapped out as well
+    for vm in memory_sets() {
+        let frames = vm.lock().swappable_frames();
+        let mut swap = SWAP.lock();
+        for frame in frames {
+            swap.manager.push(frame);
+        }
+    }
+    Ok(())
+}
+
+/// Turn off swapping to the swap area at `path`, swapping everything back in
+pub fn swapoff(path: &str) -> Result<(), SysError> {
+    {
+        let mut swap = SWAP.lock();
+        match &swap.area {
+            Some(area) if area.path == path && !swap.closing => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        swap.closing = true;
+    }
+    let used_pages = || {
+        let swap = SWAP.lock();
+        let area = swap.area.as_ref().unwrap();
+        area.pages - area.free_pages()
+    };
+    // again while children forked meanwhile, not yet among the processes, share pages
+    let mut last_used = usize::max_value();
+    loop {
+        for vm in memory_sets() {
+            if !vm.lock().swap_in_all() {
+                SWAP.lock().closing = false;
+                return Err(SysError::ENOMEM);
+            }
+        }
+        let used = used_pages();
+        if used == 0 {
+            break;
+        }
+        if used == last_used {
+            SWAP.lock().closing = false;
+            return Err(SysError::EBUSY);
+        }
+        last_used = used;
+    }
+    let area = {
+        let mut swap = SWAP.lock();
+        info!("swapoff: {:?}", path);
+        swap.closing = false;
+        swap.manager = EnhancedClockSwapManager::default();
+        swap.area.take()
+    };
+    if let Some(SwapArea {
+        backing: Backing::File(inode),
+        ..
+    }) = area
+    {
+        page_cache::unpin(&inode);
+    }
+    Ok(())
+}
+
+/// Return `(total, free)` number of pages of the swap area
+pub fn swap_stats() -> (usize, usize) {
+    match &SWAP.lock().area {
+        Some(area) => (area.pages, area.free_pages()),
+        None => (0, 0),
+    }
+}
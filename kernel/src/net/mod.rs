@@ -1,5 +1,8 @@
+mod port;
 mod structs;
 mod test;
+mod unix;
 
 pub use self::structs::*;
 pub use self::test::server;
+pub use self::unix::*;
@@ -0,0 +1,201 @@
+//! Ports taken by TCP and UDP sockets, to pick free ephemeral ports and to
+//! refuse binding a port in use with EADDRINUSE
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use smoltcp::wire::IpAddress;
+
+use super::SocketOptions;
+use crate::arch::rand;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+
+const EPHEMERAL_PORT_MIN: u16 = 49152;
+const EPHEMERAL_PORT_MAX: u16 = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+/// A socket bound to a port
+#[derive(Debug)]
+struct PortUser {
+    id: usize,
+    addr: IpAddress,
+    reuse_addr: bool,
+    reuse_port: bool,
+    listening: bool,
+    /// SO_ERROR of the socket
+    error: Arc<Mutex<Option<SysError>>>,
+}
+
+impl PortUser {
+    /// Whether the two sockets may be bound to the same port
+    fn can_share(&self, other: &PortUser) -> bool {
+        !overlaps(self.addr, other.addr)
+            || (self.reuse_port && other.reuse_port)
+            || (self.reuse_addr && other.reuse_addr && !self.listening && !other.listening)
+    }
+}
+
+/// Whether two local addresses take some common address
+fn overlaps(a: IpAddress, b: IpAddress) -> bool {
+    match (a, b) {
+        (IpAddress::Unspecified, _) | (_, IpAddress::Unspecified) => true,
+        (IpAddress::Ipv4(a), IpAddress::Ipv4(b)) => {
+            a.is_unspecified() || b.is_unspecified() || a == b
+        }
+        (IpAddress::Ipv6(a), IpAddress::Ipv6(b)) => {
+            a.is_unspecified() || b.is_unspecified() || a == b
+        }
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct PortTable {
+    users: BTreeMap<(PortProtocol, u16), Vec<PortUser>>,
+    next_id: usize,
+    /// Where the search for an ephemeral port goes on
+    next_ephemeral: u16,
+}
+
+lazy_static! {
+    static ref PORTS: Mutex<PortTable> = Mutex::new(PortTable::default());
+}
+
+/// A port a socket has taken, shared by its clones and given back when the
+/// last one drops
+#[derive(Debug)]
+pub struct BoundPort {
+    protocol: PortProtocol,
+    pub port: u16,
+    id: usize,
+}
+
+impl Drop for BoundPort {
+    fn drop(&mut self) {
+        let mut table = PORTS.lock();
+        let key = (self.protocol, self.port);
+        if let Some(users) = table.users.get_mut(&key) {
+            users.retain(|user| user.id != self.id);
+            if users.is_empty() {
+                table.users.remove(&key);
+            }
+        }
+    }
+}
+
+impl PortTable {
+    /// A port of the ephemeral range that no socket has taken
+    fn ephemeral_port(&mut self, protocol: PortProtocol) -> Result<u16, SysError> {
+        let count = (EPHEMERAL_PORT_MAX - EPHEMERAL_PORT_MIN) as u64 + 1;
+        if self.next_ephemeral == 0 {
+            self.next_ephemeral = EPHEMERAL_PORT_MIN + (rand::rand() % count) as u16;
+        }
+        for _ in 0..count {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if port == EPHEMERAL_PORT_MAX {
+                EPHEMERAL_PORT_MIN
+            } else {
+                port + 1
+            };
+            if !self.users.contains_key(&(protocol, port)) {
+                return Ok(port);
+            }
+        }
+        Err(SysError::EADDRINUSE)
+    }
+}
+
+/// Take `port` at `addr` as SO_REUSEADDR and SO_REUSEPORT of `options` allow,
+/// or an ephemeral port if it is 0
+pub fn bind_port(
+    protocol: PortProtocol,
+    addr: IpAddress,
+    port: u16,
+    options: &SocketOptions,
+) -> Result<BoundPort, SysError> {
+    let mut table = PORTS.lock();
+    let user = PortUser {
+        id: table.next_id,
+        addr,
+        reuse_addr: options.reuse_addr,
+        reuse_port: options.reuse_port,
+        listening: false,
+        error: options.error.clone(),
+    };
+    let port = if port == 0 {
+        table.ephemeral_port(protocol)?
+    } else {
+        port
+    };
+    if let Some(users) = table.users.get(&(protocol, port)) {
+        if !users.iter().all(|other| user.can_share(other)) {
+            return Err(SysError::EADDRINUSE);
+        }
+    }
+    let id = user.id;
+    table.next_id += 1;
+    table
+        .users
+        .entry((protocol, port))
+        .or_insert_with(Vec::new)
+        .push(user);
+    Ok(BoundPort { protocol, port, id })
+}
+
+/// The port in `slot`, binding an ephemeral one if it is empty
+pub fn bound_port(
+    slot: &Mutex<Option<BoundPort>>,
+    protocol: PortProtocol,
+    options: &SocketOptions,
+) -> Result<u16, SysError> {
+    let mut slot = slot.lock();
+    if let Some(bound) = &*slot {
+        return Ok(bound.port);
+    }
+    let bound = bind_port(protocol, IpAddress::Unspecified, 0, options)?;
+    let port = bound.port;
+    *slot = Some(bound);
+    Ok(port)
+}
+
+/// Mark a TCP port as listened on, unless another socket listens there
+pub fn listen_port(bound: &BoundPort) -> Result<(), SysError> {
+    let mut table = PORTS.lock();
+    let users = table
+        .users
+        .get_mut(&(bound.protocol, bound.port))
+        .ok_or(SysError::EINVAL)?;
+    let index = users
+        .iter()
+        .position(|user| user.id == bound.id)
+        .ok_or(SysError::EINVAL)?;
+    let user = &users[index];
+    let taken = users.iter().any(|other| {
+        other.id != user.id
+            && other.listening
+            && overlaps(user.addr, other.addr)
+            && !(user.reuse_port && other.reuse_port)
+    });
+    if taken {
+        return Err(SysError::EADDRINUSE);
+    }
+    users[index].listening = true;
+    Ok(())
+}
+
+/// Set `error` as SO_ERROR of the sockets bound to `port` at `addr`
+pub fn report_error(protocol: PortProtocol, addr: IpAddress, port: u16, error: SysError) {
+    let table = PORTS.lock();
+    if let Some(users) = table.users.get(&(protocol, port)) {
+        for user in users.iter().filter(|user| overlaps(user.addr, addr)) {
+            *user.error.lock() = Some(error);
+        }
+    }
+}
@@ -1,17 +1,26 @@
-use crate::arch::rand;
-use crate::drivers::{NET_DRIVERS, SOCKET_ACTIVITY};
-use crate::sync::SpinNoIrqLock as Mutex;
+use crate::arch::timer::timer_now;
+use crate::drivers::{NetDriver, NET_DRIVERS};
+use crate::fs::epoll::{wake_at, POLL_INTERVAL};
+use crate::process::current_thread;
+use crate::sync::{wait_for_condition, Event, EventBus, SpinNoIrqLock as Mutex};
 use crate::syscall::*;
 use crate::util;
 use alloc::boxed::Box;
-use alloc::fmt::Debug;
-use alloc::sync::Arc;
+use alloc::collections::VecDeque;
+use alloc::fmt::{self, Debug};
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use bitflags::*;
-use core::cmp::min;
-use core::mem::size_of;
+use core::any::Any;
+use core::cmp::{max, min};
+use core::mem::{self, size_of};
+use core::ptr;
 use core::slice;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
 
+use super::port::{bind_port, bound_port, listen_port, report_error, BoundPort, PortProtocol};
+use super::{Ancillary, UCred, UnixAddr};
 use smoltcp::socket::*;
 use smoltcp::wire::*;
 
@@ -48,23 +57,67 @@ pub enum Endpoint {
     Ip(IpEndpoint),
     LinkLevel(LinkLevelEndpoint),
     Netlink(NetlinkEndpoint),
+    Unix(UnixAddr),
 }
 
 /// Common methods that a socket must have
 pub trait Socket: Send + Sync + Debug {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint);
     fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult;
+    /// Read a message with its ancillary data, leaving it queued if `peek`
+    ///
+    /// Return the length of the whole message, which may exceed `data` for
+    /// datagrams whose rest is discarded.
+    fn read_msg(&self, data: &mut [u8], peek: bool) -> (SysResult, Endpoint, Ancillary) {
+        if peek {
+            return (
+                Err(SysError::EOPNOTSUPP),
+                Endpoint::Ip(IpEndpoint::UNSPECIFIED),
+                Ancillary::default(),
+            );
+        }
+        let (result, endpoint) = self.read(data);
+        (result, endpoint, Ancillary::default())
+    }
+    /// Write a message with ancillary data, which only Unix sockets carry
+    fn write_msg(
+        &self,
+        data: &[u8],
+        sendto_endpoint: Option<Endpoint>,
+        ancillary: Ancillary,
+    ) -> SysResult {
+        if !ancillary.is_empty() {
+            return Err(SysError::EINVAL);
+        }
+        self.write(data, sendto_endpoint)
+    }
     fn poll(&self) -> (bool, bool, bool); // (in, out, err)
+    /// Whether messages are a byte stream, which MSG_WAITALL waits to fill
+    fn is_stream(&self) -> bool {
+        false
+    }
+    /// Whether the peer has shut down writing, for EPOLLRDHUP
+    fn peer_closed(&self) -> bool {
+        false
+    }
+    /// Connect to `endpoint`, failing with EINPROGRESS if it takes time
     fn connect(&mut self, endpoint: Endpoint) -> SysResult;
+    /// Whether the connection `connect` started is made, failing with EAGAIN
+    /// while it is on the way
+    fn connect_result(&self) -> SysResult {
+        Ok(0)
+    }
     fn bind(&mut self, _endpoint: Endpoint) -> SysResult {
         Err(SysError::EINVAL)
     }
-    fn listen(&mut self) -> SysResult {
+    /// Listen for connections, of which `backlog` may wait to be accepted
+    fn listen(&mut self, _backlog: usize) -> SysResult {
         Err(SysError::EINVAL)
     }
-    fn shutdown(&self) -> SysResult {
+    fn shutdown(&self, _how: usize) -> SysResult {
         Err(SysError::EINVAL)
     }
+    /// Take a connection made, failing with EAGAIN if there is none
     fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
         Err(SysError::EINVAL)
     }
@@ -78,10 +131,30 @@ pub trait Socket: Send + Sync + Debug {
         warn!("setsockopt is unimplemented");
         Ok(0)
     }
+    /// The value of an option, as getsockopt copies it out
+    fn getsockopt(&mut self, _level: usize, _opt: usize) -> Result<Vec<u8>, SysError> {
+        Err(SysError::ENOPROTOOPT)
+    }
+    /// How long blocking for `events` may take, by SO_RCVTIMEO or SO_SNDTIMEO
+    fn timeout(&self, _events: Event) -> Option<Duration> {
+        None
+    }
     fn ioctl(&mut self, _request: usize, _arg1: usize, _arg2: usize, _arg3: usize) -> SysResult {
         warn!("ioctl is unimplemented for this socket");
         Ok(0)
     }
+    /// Credentials of the connected peer, for SO_PEERCRED
+    fn peer_cred(&self) -> Option<UCred> {
+        None
+    }
+    /// Event bus of a socket that fails with EAGAIN instead of blocking
+    fn eventbus(&self) -> Option<Arc<Mutex<EventBus>>> {
+        None
+    }
+    /// Identity shared by the clones of a socket, if they share the socket
+    fn description(&self) -> Option<Arc<dyn Any + Send + Sync>> {
+        None
+    }
     fn box_clone(&self) -> Box<dyn Socket>;
 }
 
@@ -91,6 +164,64 @@ impl Clone for Box<dyn Socket> {
     }
 }
 
+/// Run `op` on a socket, waiting for `events` while it fails with EAGAIN
+///
+/// Socket methods fail with EAGAIN instead of blocking, so the caller waits
+/// here without holding the process, until the timeout of SO_RCVTIMEO or
+/// SO_SNDTIMEO if one is set.
+/// Sockets of smoltcp have no event bus and are polled now and then.
+pub async fn block_on_socket<T>(
+    socket: &dyn Socket,
+    events: Event,
+    nonblock: bool,
+    mut op: impl FnMut() -> Result<T, SysError>,
+) -> Result<T, SysError> {
+    let thread = current_thread().unwrap();
+    let deadline = socket.timeout(events).map(|timeout| timer_now() + timeout);
+    // toggled at the deadline, or to poll sockets without an event bus
+    let timer_bus = EventBus::new();
+    if let Some(deadline) = deadline {
+        wake_at(&timer_bus, deadline);
+    }
+    loop {
+        match op() {
+            Err(SysError::EAGAIN) if !nonblock => {}
+            result => return result,
+        }
+        let now = timer_now();
+        if deadline.map_or(false, |deadline| now >= deadline) {
+            return Err(SysError::EAGAIN);
+        }
+        if thread.has_signal_to_handle() {
+            return Err(SysError::EINTR);
+        }
+        let signal_bus = thread.proc.lock().eventbus.clone();
+        let mut buses = vec![signal_bus, timer_bus.clone()];
+        let poll_at = match socket.eventbus() {
+            Some(eventbus) => {
+                buses.push(eventbus);
+                None
+            }
+            None => {
+                let poll_at = now + POLL_INTERVAL;
+                wake_at(&timer_bus, poll_at);
+                Some(poll_at)
+            }
+        };
+        wait_for_condition(buses, || {
+            let (read, write, error) = socket.poll();
+            let now = timer_now();
+            (read && events.contains(Event::READABLE))
+                || (write && events.contains(Event::WRITABLE))
+                || error
+                || thread.has_signal_to_handle()
+                || deadline.map_or(false, |deadline| now >= deadline)
+                || poll_at.map_or(false, |poll_at| now >= poll_at)
+        })
+        .await;
+    }
+}
+
 lazy_static! {
     /// Global SocketSet in smoltcp.
     ///
@@ -104,13 +235,53 @@ lazy_static! {
 pub struct TcpSocketState {
     handle: GlobalSocketHandle,
     local_endpoint: Option<IpEndpoint>, // save local endpoint for bind()
-    is_listening: bool,
+    /// The port of bind(), or of connect() if it came first
+    port: Arc<Mutex<Option<BoundPort>>>,
+    /// Connections to accept, once listening
+    listen_queue: Option<Arc<Mutex<TcpListenQueue>>>,
+    /// A connect started, whose failure SO_ERROR has not reported
+    connecting: bool,
+    family: IpFamily,
+    options: SocketOptions,
+    /// TCP_NODELAY, which smoltcp behaves as anyway
+    nodelay: bool,
+    /// TCP_KEEPIDLE in seconds
+    keepalive_idle: u32,
+}
+
+/// Sockets of smoltcp listen for a single connection, so a listening socket
+/// queues the connection of its listener and listens again with a new one
+#[derive(Debug)]
+struct TcpListenQueue {
+    endpoint: IpEndpoint,
+    family: IpFamily,
+    /// The socket listening for the next connection
+    listener: GlobalSocketHandle,
+    /// Connections made, waiting to be accepted
+    pending: VecDeque<GlobalSocketHandle>,
+    backlog: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct UdpSocketState {
     handle: GlobalSocketHandle,
     remote_endpoint: Option<IpEndpoint>, // remember remote endpoint for connect()
+    /// The port of bind(), or of the first datagram sent
+    port: Arc<Mutex<Option<BoundPort>>>,
+    family: IpFamily,
+    options: SocketOptions,
+    /// IP_TTL, for unicast datagrams
+    ttl: Option<u8>,
+    /// IP_MULTICAST_TTL
+    multicast_ttl: u8,
+    /// IP_MULTICAST_LOOP, which smoltcp cannot do
+    multicast_loop: bool,
+    /// IP_MULTICAST_IF
+    multicast_if: Ipv4Address,
+    /// Receive IP_PKTINFO with datagrams
+    pktinfo: bool,
+    /// Receive SO_TIMESTAMP with datagrams
+    timestamp: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -124,9 +295,178 @@ pub struct PacketSocketState {
     // no state, only ethernet egress
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct NetlinkSocketState {
-    data: Arc<Mutex<Vec<Vec<u8>>>>,
+    inner: Arc<NetlinkSocketInner>,
+}
+
+struct NetlinkSocketInner {
+    /// Port id to take when bound without one, the process id
+    pid: u32,
+    /// Replies and notifications waiting to be read
+    data: Mutex<VecDeque<Vec<u8>>>,
+    /// Port id and multicast groups once bound
+    endpoint: Mutex<Option<NetlinkEndpoint>>,
+    /// READABLE follows `data`
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+/// IP versions spoken by an AF_INET or AF_INET6 socket
+#[derive(Debug, Clone, Copy)]
+pub struct IpFamily {
+    ipv6: bool,
+    /// AF_INET6 sockets refuse IPv4 peers instead of seeing them v4-mapped
+    v6only: bool,
+}
+
+impl IpFamily {
+    pub const V4: IpFamily = IpFamily {
+        ipv6: false,
+        v6only: false,
+    };
+    pub const V6: IpFamily = IpFamily {
+        ipv6: true,
+        v6only: false,
+    };
+
+    /// smoltcp hands unspecified listeners peers of both versions
+    fn accepts(&self, addr: IpAddress) -> bool {
+        match addr {
+            IpAddress::Ipv4(_) => !self.v6only,
+            IpAddress::Ipv6(_) => self.ipv6,
+            _ => true,
+        }
+    }
+
+    /// Turn a user endpoint into one for smoltcp, unmapping `::ffff:a.b.c.d`
+    /// and widening `::` to both versions
+    fn from_user(&self, mut endpoint: IpEndpoint) -> Result<IpEndpoint, SysError> {
+        endpoint.addr = match endpoint.addr {
+            IpAddress::Ipv4(addr) if !self.ipv6 => IpAddress::Ipv4(addr),
+            IpAddress::Ipv6(addr) if self.ipv6 => match addr.as_ipv4() {
+                Some(_) if self.v6only => return Err(SysError::EAFNOSUPPORT),
+                Some(addr) => IpAddress::Ipv4(addr),
+                None if addr.is_unspecified() && !self.v6only => IpAddress::Unspecified,
+                None => IpAddress::Ipv6(addr),
+            },
+            _ => return Err(SysError::EAFNOSUPPORT),
+        };
+        Ok(endpoint)
+    }
+
+    /// The endpoint as the user sees it, v4-mapped on AF_INET6 sockets
+    fn to_user(&self, mut endpoint: IpEndpoint) -> IpEndpoint {
+        endpoint.addr = match endpoint.addr {
+            IpAddress::Ipv4(addr) if self.ipv6 => {
+                let mut bytes = [0u8; 16];
+                bytes[10..12].copy_from_slice(&[0xff, 0xff]);
+                bytes[12..].copy_from_slice(addr.as_bytes());
+                IpAddress::Ipv6(Ipv6Address::from_bytes(&bytes))
+            }
+            IpAddress::Unspecified if self.ipv6 => IpAddress::Ipv6(Ipv6Address::UNSPECIFIED),
+            IpAddress::Unspecified => IpAddress::Ipv4(Ipv4Address::UNSPECIFIED),
+            addr => addr,
+        };
+        endpoint
+    }
+
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (IPPROTO_IPV6, IPV6_V6ONLY) if self.ipv6 => {
+                self.v6only = sockopt_u32(data)? != 0;
+                Ok(0)
+            }
+            (IPPROTO_IPV6, _) if !self.ipv6 => Err(SysError::ENOPROTOOPT),
+            _ => {
+                warn!("setsockopt is unimplemented");
+                Ok(0)
+            }
+        }
+    }
+
+    fn getsockopt(&self, level: usize, opt: usize) -> Result<Vec<u8>, SysError> {
+        match (level, opt) {
+            (IPPROTO_IPV6, IPV6_V6ONLY) if self.ipv6 => Ok(sockopt_value(self.v6only as u32)),
+            _ => Err(SysError::ENOPROTOOPT),
+        }
+    }
+}
+
+/// SOL_SOCKET options common to sockets
+#[derive(Debug, Clone, Default)]
+pub struct SocketOptions {
+    pub reuse_addr: bool,
+    pub reuse_port: bool,
+    pub keepalive: bool,
+    pub broadcast: bool,
+    /// SO_RCVTIMEO, or blocking without a timeout
+    pub recv_timeout: Option<Duration>,
+    /// SO_SNDTIMEO, or blocking without a timeout
+    pub send_timeout: Option<Duration>,
+    /// An error no call has reported, for SO_ERROR. The clones of the socket
+    /// and its port share it, as ICMP errors are reported to the port
+    pub error: Arc<Mutex<Option<SysError>>>,
+}
+
+impl SocketOptions {
+    pub fn timeout(&self, events: Event) -> Option<Duration> {
+        if events.contains(Event::READABLE) {
+            self.recv_timeout
+        } else {
+            self.send_timeout
+        }
+    }
+
+    pub fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_REUSEADDR) => self.reuse_addr = sockopt_u32(data)? != 0,
+            (SOL_SOCKET, SO_REUSEPORT) => self.reuse_port = sockopt_u32(data)? != 0,
+            (SOL_SOCKET, SO_KEEPALIVE) => self.keepalive = sockopt_u32(data)? != 0,
+            (SOL_SOCKET, SO_BROADCAST) => self.broadcast = sockopt_u32(data)? != 0,
+            (SOL_SOCKET, SO_RCVTIMEO) => self.recv_timeout = sockopt_timeout(data)?,
+            (SOL_SOCKET, SO_SNDTIMEO) => self.send_timeout = sockopt_timeout(data)?,
+            _ => warn!("setsockopt is unimplemented"),
+        }
+        Ok(0)
+    }
+
+    pub fn getsockopt(&self, level: usize, opt: usize) -> Result<Vec<u8>, SysError> {
+        let timeval = |timeout: Option<Duration>| {
+            let timeout = TimeVal::from_duration(timeout.unwrap_or_default());
+            struct_bytes(&timeout)
+        };
+        match (level, opt) {
+            (SOL_SOCKET, SO_REUSEADDR) => Ok(sockopt_value(self.reuse_addr as u32)),
+            (SOL_SOCKET, SO_REUSEPORT) => Ok(sockopt_value(self.reuse_port as u32)),
+            (SOL_SOCKET, SO_KEEPALIVE) => Ok(sockopt_value(self.keepalive as u32)),
+            (SOL_SOCKET, SO_BROADCAST) => Ok(sockopt_value(self.broadcast as u32)),
+            (SOL_SOCKET, SO_RCVTIMEO) => Ok(timeval(self.recv_timeout)),
+            (SOL_SOCKET, SO_SNDTIMEO) => Ok(timeval(self.send_timeout)),
+            (SOL_SOCKET, SO_ERROR) => {
+                let error = self.error.lock().take();
+                Ok(sockopt_value(error.map_or(0, |err| err as u32)))
+            }
+            _ => Err(SysError::ENOPROTOOPT),
+        }
+    }
+}
+
+/// Read IP_TTL and the like, where -1 is for the default
+fn sockopt_ttl(data: &[u8]) -> Result<Option<u8>, SysError> {
+    match sockopt_u32(data)? as i32 {
+        -1 => Ok(None),
+        ttl @ 1..=255 => Ok(Some(ttl as u8)),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+/// Read IP_MULTICAST_TTL and the like, which may also be passed as a char
+fn sockopt_u8(data: &[u8]) -> Result<u32, SysError> {
+    match data.len() {
+        0 => Err(SysError::EINVAL),
+        1..=3 => Ok(data[0] as u32),
+        _ => sockopt_u32(data),
+    }
 }
 
 /// A wrapper for `SocketHandle`.
@@ -154,47 +494,63 @@ impl Drop for GlobalSocketHandle {
 }
 
 impl TcpSocketState {
-    pub fn new() -> Self {
-        let rx_buffer = TcpSocketBuffer::new(vec![0; TCP_RECVBUF]);
-        let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
-        let socket = TcpSocket::new(rx_buffer, tx_buffer);
-        let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
+    pub fn new(family: IpFamily) -> Self {
+        let handle = GlobalSocketHandle(SOCKETS.lock().add(new_tcp_socket()));
 
         TcpSocketState {
             handle,
             local_endpoint: None,
-            is_listening: false,
+            port: Arc::new(Mutex::new(None)),
+            listen_queue: None,
+            connecting: false,
+            family,
+            options: SocketOptions::default(),
+            nodelay: false,
+            keepalive_idle: TCP_KEEPIDLE_DEFAULT,
         }
     }
 }
 
 impl Socket for TcpSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
-        spin_and_wait(&[&SOCKET_ACTIVITY], move || {
-            poll_ifaces();
-            let mut sockets = SOCKETS.lock();
-            let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+        let (result, endpoint, _) = self.read_msg(data, false);
+        (result, endpoint)
+    }
 
-            if socket.may_recv() {
-                if let Ok(size) = socket.recv_slice(data) {
-                    if size > 0 {
-                        let endpoint = socket.remote_endpoint();
-                        // avoid deadlock
-                        drop(socket);
-                        drop(sockets);
+    fn read_msg(&self, data: &mut [u8], peek: bool) -> (SysResult, Endpoint, Ancillary) {
+        poll_ifaces();
+        let mut sockets = SOCKETS.lock();
+        let mut socket = sockets.get::<TcpSocket>(self.handle.0);
 
-                        poll_ifaces();
-                        return Some((Ok(size), Endpoint::Ip(endpoint)));
-                    }
-                }
-            } else {
-                return Some((
-                    Err(SysError::ENOTCONN),
-                    Endpoint::Ip(IpEndpoint::UNSPECIFIED),
-                ));
+        let result = if !socket.may_recv() {
+            Err(SysError::ENOTCONN)
+        } else if peek {
+            socket.peek_slice(data).map_err(|_| SysError::EAGAIN)
+        } else {
+            socket.recv_slice(data).map_err(|_| SysError::EAGAIN)
+        };
+        match result {
+            Ok(size) if size > 0 || data.is_empty() => {
+                let endpoint = socket.remote_endpoint();
+                // avoid deadlock
+                drop(socket);
+                drop(sockets);
+
+                poll_ifaces();
+                let endpoint = Endpoint::Ip(self.family.to_user(endpoint));
+                (Ok(size), endpoint, Ancillary::default())
             }
-            None
-        })
+            Ok(_) => (
+                Err(SysError::EAGAIN),
+                Endpoint::Ip(IpEndpoint::UNSPECIFIED),
+                Ancillary::default(),
+            ),
+            Err(err) => (
+                Err(err),
+                Endpoint::Ip(IpEndpoint::UNSPECIFIED),
+                Ancillary::default(),
+            ),
+        }
     }
 
     fn write(&self, data: &[u8], _sendto_endpoint: Option<Endpoint>) -> SysResult {
@@ -215,7 +571,7 @@ impl Socket for TcpSocketState {
                     Err(_) => Err(SysError::ENOBUFS),
                 }
             } else {
-                Err(SysError::ENOBUFS)
+                Err(SysError::EAGAIN)
             }
         } else {
             Err(SysError::ENOTCONN)
@@ -223,14 +579,21 @@ impl Socket for TcpSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
+        if let Some(queue) = &self.listen_queue {
+            let mut queue = queue.lock();
+            let mut sockets = SOCKETS.lock();
+            queue.update(&mut sockets);
+            return (
+                !queue.pending.is_empty(),
+                false,
+                queue.is_closed(&mut sockets),
+            );
+        }
         let mut sockets = SOCKETS.lock();
         let socket = sockets.get::<TcpSocket>(self.handle.0);
 
         let (mut input, mut output, mut err) = (false, false, false);
-        if self.is_listening && socket.is_active() {
-            // a new connection
-            input = true;
-        } else if !socket.is_open() {
+        if !socket.is_open() {
             err = true;
         } else {
             if socket.can_recv() {
@@ -243,84 +606,131 @@ impl Socket for TcpSocketState {
         (input, output, err)
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn peer_closed(&self) -> bool {
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets.get::<TcpSocket>(self.handle.0);
+        match socket.state() {
+            TcpState::CloseWait | TcpState::LastAck | TcpState::Closing | TcpState::TimeWait => {
+                true
+            }
+            _ => false,
+        }
+    }
+
     fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        let ip = match endpoint {
+            Endpoint::Ip(ip) => self.family.from_user(ip)?,
+            _ => return Err(SysError::EINVAL),
+        };
+        if self.listen_queue.is_some() {
+            return Err(SysError::EINVAL);
+        }
+        let port = bound_port(&self.port, PortProtocol::Tcp, &self.options)?;
+        let local_endpoint = match self.local_endpoint {
+            Some(endpoint) if !endpoint.addr.is_unspecified() => endpoint,
+            _ => IpEndpoint::from(port),
+        };
         let mut sockets = SOCKETS.lock();
         let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+        match socket.state() {
+            TcpState::Closed => {}
+            TcpState::SynSent => return Err(SysError::EALREADY),
+            _ => return Err(SysError::EISCONN),
+        }
+        socket
+            .connect(ip, local_endpoint)
+            .map_err(|_| SysError::ENOBUFS)?;
+        self.connecting = true;
 
-        if let Endpoint::Ip(ip) = endpoint {
-            let temp_port = get_ephemeral_port();
-
-            match socket.connect(ip, temp_port) {
-                Ok(()) => {
-                    // avoid deadlock
-                    drop(socket);
-                    drop(sockets);
-
-                    // wait for connection result
-                    loop {
-                        poll_ifaces();
+        // avoid deadlock
+        drop(socket);
+        drop(sockets);
+        match self.connect_result() {
+            Err(SysError::EAGAIN) => Err(SysError::EINPROGRESS),
+            result => result,
+        }
+    }
 
-                        let mut sockets = SOCKETS.lock();
-                        let socket = sockets.get::<TcpSocket>(self.handle.0);
-                        match socket.state() {
-                            TcpState::SynSent => {
-                                // still connecting
-                                drop(socket);
-                                debug!("poll for connection wait");
-                                SOCKET_ACTIVITY.wait(sockets);
-                            }
-                            TcpState::Established => {
-                                break Ok(0);
-                            }
-                            _ => {
-                                break Err(SysError::ECONNREFUSED);
-                            }
-                        }
-                    }
-                }
-                Err(_) => Err(SysError::ENOBUFS),
-            }
-        } else {
-            Err(SysError::EINVAL)
+    fn connect_result(&self) -> SysResult {
+        poll_ifaces();
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets.get::<TcpSocket>(self.handle.0);
+        match socket.state() {
+            TcpState::SynSent | TcpState::SynReceived => Err(SysError::EAGAIN),
+            TcpState::Closed => Err(SysError::ECONNREFUSED),
+            _ => Ok(0),
         }
     }
 
     fn bind(&mut self, endpoint: Endpoint) -> SysResult {
-        if let Endpoint::Ip(mut ip) = endpoint {
-            if ip.port == 0 {
-                ip.port = get_ephemeral_port();
+        if let Endpoint::Ip(ip) = endpoint {
+            let mut ip = self.family.from_user(ip)?;
+            let mut port = self.port.lock();
+            if port.is_some() {
+                // bound already, or connected with an ephemeral port
+                return Err(SysError::EINVAL);
             }
+            let bound = bind_port(PortProtocol::Tcp, ip.addr, ip.port, &self.options)?;
+            ip.port = bound.port;
+            *port = Some(bound);
             self.local_endpoint = Some(ip);
-            self.is_listening = false;
             Ok(0)
         } else {
             Err(SysError::EINVAL)
         }
     }
 
-    fn listen(&mut self) -> SysResult {
-        if self.is_listening {
-            // it is ok to listen twice
+    fn listen(&mut self, backlog: usize) -> SysResult {
+        let backlog = min(max(backlog, 1), TCP_BACKLOG_MAX);
+        if let Some(queue) = &self.listen_queue {
+            // listening again changes the backlog
+            queue.lock().backlog = backlog;
             return Ok(0);
         }
-        let local_endpoint = self.local_endpoint.ok_or(SysError::EINVAL)?;
+        let endpoint = match self.local_endpoint {
+            Some(endpoint) => endpoint,
+            // listen on an ephemeral port if not bound
+            None => IpEndpoint::new(
+                IpAddress::Unspecified,
+                bound_port(&self.port, PortProtocol::Tcp, &self.options)?,
+            ),
+        };
         let mut sockets = SOCKETS.lock();
         let mut socket = sockets.get::<TcpSocket>(self.handle.0);
-
-        info!("socket listening on {:?}", local_endpoint);
-        if socket.is_listening() {
-            return Ok(0);
-        }
-        match socket.listen(local_endpoint) {
-            Ok(()) => {
-                self.is_listening = true;
-                Ok(0)
-            }
-            Err(_) => Err(SysError::EINVAL),
+        if socket.is_open() {
+            return Err(SysError::EINVAL);
         }
+        listen_port(self.port.lock().as_ref().unwrap())?;
+        socket.listen(endpoint).map_err(|_| SysError::EINVAL)?;
+        self.local_endpoint = Some(endpoint);
+        drop(socket);
+
+        info!("socket listening on {:?}", endpoint);
+        // the queue owns the listener, so that its connection closes once it
+        // is accepted and closed, and this socket keeps one without buffers
+        let idle = TcpSocket::new(TcpSocketBuffer::new(vec![]), TcpSocketBuffer::new(vec![]));
+        let idle = GlobalSocketHandle(sockets.add(idle));
+        let listener = mem::replace(&mut self.handle, idle);
+        self.listen_queue = Some(Arc::new(Mutex::new(TcpListenQueue {
+            endpoint,
+            family: self.family,
+            listener,
+            pending: VecDeque::new(),
+            backlog,
+        })));
+        Ok(0)
     }
 
-    fn shutdown(&self) -> SysResult {
+    fn shutdown(&self, _how: usize) -> SysResult {
+        if let Some(queue) = &self.listen_queue {
+            let queue = queue.lock();
+            let mut sockets = SOCKETS.lock();
+            sockets.get::<TcpSocket>(queue.listener.0).close();
+        }
         let mut sockets = SOCKETS.lock();
         let mut socket = sockets.get::<TcpSocket>(self.handle.0);
         socket.close();
@@ -328,73 +738,185 @@ impl Socket for TcpSocketState {
     }
 
     fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
-        let endpoint = self.local_endpoint.ok_or(SysError::EINVAL)?;
-        loop {
+        let queue = self.listen_queue.as_ref().ok_or(SysError::EINVAL)?;
+        let (handle, remote_endpoint) = {
+            let mut queue = queue.lock();
             let mut sockets = SOCKETS.lock();
-            let socket = sockets.get::<TcpSocket>(self.handle.0);
-
-            if socket.is_active() {
-                let remote_endpoint = socket.remote_endpoint();
-                drop(socket);
-
-                let new_socket = {
-                    let rx_buffer = TcpSocketBuffer::new(vec![0; TCP_RECVBUF]);
-                    let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
-                    let mut socket = TcpSocket::new(rx_buffer, tx_buffer);
-                    socket.listen(endpoint).unwrap();
-                    let new_handle = GlobalSocketHandle(sockets.add(socket));
-                    let old_handle = ::core::mem::replace(&mut self.handle, new_handle);
-
-                    Box::new(TcpSocketState {
-                        handle: old_handle,
-                        local_endpoint: self.local_endpoint,
-                        is_listening: false,
-                    })
-                };
-
-                drop(sockets);
-                poll_ifaces();
-                return Ok((new_socket, Endpoint::Ip(remote_endpoint)));
-            }
+            queue.update(&mut sockets);
+            let handle = match queue.pending.pop_front() {
+                Some(handle) => handle,
+                None if queue.is_closed(&mut sockets) => return Err(SysError::EINVAL),
+                None => return Err(SysError::EAGAIN),
+            };
+            // there is room for the next connection
+            queue.update(&mut sockets);
+            let remote_endpoint = sockets.get::<TcpSocket>(handle.0).remote_endpoint();
+            (handle, remote_endpoint)
+        };
+        let new_socket = TcpSocketState {
+            handle,
+            local_endpoint: None,
+            // the port stays with the listening socket
+            port: Arc::new(Mutex::new(None)),
+            listen_queue: None,
+            connecting: false,
+            family: self.family,
+            options: self.options.clone(),
+            nodelay: self.nodelay,
+            keepalive_idle: self.keepalive_idle,
+        };
+        new_socket.set_keep_alive();
 
-            drop(socket);
-            SOCKET_ACTIVITY.wait(sockets);
-        }
+        poll_ifaces();
+        let remote_endpoint = self.family.to_user(remote_endpoint);
+        Ok((Box::new(new_socket), Endpoint::Ip(remote_endpoint)))
     }
 
     fn endpoint(&self) -> Option<Endpoint> {
         self.local_endpoint
             .clone()
-            .map(|e| Endpoint::Ip(e))
             .or_else(|| {
                 let mut sockets = SOCKETS.lock();
                 let socket = sockets.get::<TcpSocket>(self.handle.0);
                 let endpoint = socket.local_endpoint();
                 if endpoint.port != 0 {
-                    Some(Endpoint::Ip(endpoint))
+                    Some(endpoint)
                 } else {
                     None
                 }
             })
+            .map(|e| Endpoint::Ip(self.family.to_user(e)))
     }
 
     fn remote_endpoint(&self) -> Option<Endpoint> {
         let mut sockets = SOCKETS.lock();
         let socket = sockets.get::<TcpSocket>(self.handle.0);
         if socket.is_open() {
-            Some(Endpoint::Ip(socket.remote_endpoint()))
+            Some(Endpoint::Ip(self.family.to_user(socket.remote_endpoint())))
         } else {
             None
         }
     }
 
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_KEEPALIVE) => {
+                self.options.keepalive = sockopt_u32(data)? != 0;
+                self.set_keep_alive();
+                Ok(0)
+            }
+            (IPPROTO_TCP, TCP_NODELAY) => {
+                self.nodelay = sockopt_u32(data)? != 0;
+                Ok(0)
+            }
+            (IPPROTO_TCP, TCP_KEEPIDLE) => {
+                let idle = sockopt_u32(data)?;
+                if idle < 1 || idle > TCP_KEEPIDLE_MAX {
+                    return Err(SysError::EINVAL);
+                }
+                self.keepalive_idle = idle;
+                self.set_keep_alive();
+                Ok(0)
+            }
+            (IPPROTO_TCP, TCP_CONGESTION) => Ok(0),
+            (IPPROTO_IP, IP_TTL) => {
+                let ttl = sockopt_ttl(data)?;
+                let mut sockets = SOCKETS.lock();
+                sockets.get::<TcpSocket>(self.handle.0).set_hop_limit(ttl);
+                Ok(0)
+            }
+            (IPPROTO_IPV6, _) => self.family.setsockopt(level, opt, data),
+            _ => self.options.setsockopt(level, opt, data),
+        }
+    }
+
+    fn getsockopt(&mut self, level: usize, opt: usize) -> Result<Vec<u8>, SysError> {
+        match (level, opt) {
+            // the failure of a connect, reported once
+            (SOL_SOCKET, SO_ERROR) if self.connecting => {
+                let error = match self.connect_result() {
+                    Err(SysError::EAGAIN) => 0,
+                    result => {
+                        self.connecting = false;
+                        result.err().map_or(0, |err| err as u32)
+                    }
+                };
+                Ok(sockopt_value(error))
+            }
+            (IPPROTO_TCP, TCP_NODELAY) => Ok(sockopt_value(self.nodelay as u32)),
+            (IPPROTO_TCP, TCP_KEEPIDLE) => Ok(sockopt_value(self.keepalive_idle)),
+            (IPPROTO_IP, IP_TTL) => {
+                let mut sockets = SOCKETS.lock();
+                let socket = sockets.get::<TcpSocket>(self.handle.0);
+                let ttl = socket.hop_limit().unwrap_or(IP_DEFAULT_TTL);
+                Ok(sockopt_value(ttl as u32))
+            }
+            (IPPROTO_IPV6, _) => self.family.getsockopt(level, opt),
+            _ => self.options.getsockopt(level, opt),
+        }
+    }
+
+    fn timeout(&self, events: Event) -> Option<Duration> {
+        self.options.timeout(events)
+    }
+
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
 }
 
+impl TcpSocketState {
+    /// Apply SO_KEEPALIVE and TCP_KEEPIDLE to smoltcp
+    fn set_keep_alive(&self) {
+        let interval = if self.options.keepalive {
+            Some(smoltcp::time::Duration::from_secs(
+                self.keepalive_idle as u64,
+            ))
+        } else {
+            None
+        };
+        let mut sockets = SOCKETS.lock();
+        sockets
+            .get::<TcpSocket>(self.handle.0)
+            .set_keep_alive(interval);
+    }
+}
+
+impl TcpListenQueue {
+    /// Queue the connection of the listener if there is room, and listen
+    /// again with a new socket
+    fn update(&mut self, sockets: &mut SocketSet<'static, 'static, 'static>) {
+        let mut socket = sockets.get::<TcpSocket>(self.listener.0);
+        match socket.state() {
+            TcpState::Closed | TcpState::Listen | TcpState::SynReceived => return,
+            _ => {}
+        }
+        if !self.family.accepts(socket.remote_endpoint().addr) {
+            // refuse it and keep listening
+            socket.abort();
+            socket.listen(self.endpoint).unwrap();
+            return;
+        }
+        if self.pending.len() >= self.backlog {
+            return;
+        }
+        drop(socket);
+        let mut listener = new_tcp_socket();
+        listener.listen(self.endpoint).unwrap();
+        let listener = GlobalSocketHandle(sockets.add(listener));
+        let connection = mem::replace(&mut self.listener, listener);
+        self.pending.push_back(connection);
+    }
+
+    /// Whether shutdown stopped listening
+    fn is_closed(&self, sockets: &mut SocketSet<'static, 'static, 'static>) -> bool {
+        let socket = sockets.get::<TcpSocket>(self.listener.0);
+        socket.state() == TcpState::Closed
+    }
+}
+
 impl UdpSocketState {
-    pub fn new() -> Self {
+    pub fn new(family: IpFamily) -> Self {
         let rx_buffer = UdpSocketBuffer::new(
             vec![UdpPacketMetadata::EMPTY; UDP_METADATA_BUF],
             vec![0; UDP_RECVBUF],
@@ -409,6 +931,15 @@ impl UdpSocketState {
         UdpSocketState {
             handle,
             remote_endpoint: None,
+            port: Arc::new(Mutex::new(None)),
+            family,
+            options: SocketOptions::default(),
+            ttl: None,
+            multicast_ttl: 1,
+            multicast_loop: true,
+            multicast_if: Ipv4Address::UNSPECIFIED,
+            pktinfo: false,
+            timestamp: false,
         }
     }
 }
@@ -424,54 +955,96 @@ struct ArpReq {
 
 impl Socket for UdpSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        let (result, endpoint, _) = self.read_msg(data, false);
+        (result.map(|len| min(len, data.len())), endpoint)
+    }
+
+    fn read_msg(&self, data: &mut [u8], peek: bool) -> (SysResult, Endpoint, Ancillary) {
         loop {
             let mut sockets = SOCKETS.lock();
             let mut socket = sockets.get::<UdpSocket>(self.handle.0);
 
             if socket.can_recv() {
-                if let Ok((size, remote_endpoint)) = socket.recv_slice(data) {
-                    let endpoint = remote_endpoint;
+                let result = if peek {
+                    socket
+                        .peek()
+                        .map(|(payload, endpoint)| (payload, *endpoint))
+                } else {
+                    socket.recv()
+                };
+                if let Ok((payload, remote_endpoint)) = result {
+                    if !self.family.accepts(remote_endpoint.addr) {
+                        if peek {
+                            let _ = socket.recv();
+                        }
+                        continue;
+                    }
+                    let len = payload.len();
+                    let size = min(len, data.len());
+                    data[..size].copy_from_slice(&payload[..size]);
+                    let local_addr = socket.endpoint().addr;
                     // avoid deadlock
                     drop(socket);
                     drop(sockets);
 
+                    let mut ancillary = Ancillary::default();
+                    if self.pktinfo {
+                        ancillary.pktinfo = ipv4_pktinfo(local_addr, remote_endpoint.addr);
+                    }
+                    if self.timestamp {
+                        // smoltcp does not keep the time datagrams arrive
+                        ancillary.timestamp = Some(TimeVal::get_epoch());
+                    }
+                    let endpoint = self.family.to_user(remote_endpoint);
                     poll_ifaces();
-                    return (Ok(size), Endpoint::Ip(endpoint));
+                    return (Ok(len), Endpoint::Ip(endpoint), ancillary);
                 }
-            } else {
-                return (
-                    Err(SysError::ENOTCONN),
-                    Endpoint::Ip(IpEndpoint::UNSPECIFIED),
-                );
             }
-
-            drop(socket);
-            SOCKET_ACTIVITY.wait(sockets);
+            // the error of a datagram sent, which poll reported
+            let error = self.options.error.lock().take();
+            return (
+                Err(error.unwrap_or(SysError::EAGAIN)),
+                Endpoint::Ip(IpEndpoint::UNSPECIFIED),
+                Ancillary::default(),
+            );
         }
     }
 
     fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
         let remote_endpoint = {
-            if let Some(Endpoint::Ip(ref endpoint)) = sendto_endpoint {
-                endpoint
-            } else if let Some(ref endpoint) = self.remote_endpoint {
+            if let Some(Endpoint::Ip(endpoint)) = sendto_endpoint {
+                self.family.from_user(endpoint)?
+            } else if let Some(endpoint) = self.remote_endpoint {
                 endpoint
             } else {
                 return Err(SysError::ENOTCONN);
             }
         };
+        let port = bound_port(&self.port, PortProtocol::Udp, &self.options)?;
         let mut sockets = SOCKETS.lock();
         let mut socket = sockets.get::<UdpSocket>(self.handle.0);
 
         if socket.endpoint().port == 0 {
-            let temp_port = get_ephemeral_port();
             socket
-                .bind(IpEndpoint::new(IpAddress::Unspecified, temp_port))
+                .bind(IpEndpoint::new(IpAddress::Unspecified, port))
                 .unwrap();
         }
 
+        if let IpAddress::Ipv4(addr) = remote_endpoint.addr {
+            if addr.is_broadcast() && !self.options.broadcast {
+                return Err(SysError::EACCES);
+            }
+        }
+        // smoltcp has one hop limit, taken when the datagrams go out, and
+        // refuses a limit of 0
+        if remote_endpoint.addr.is_multicast() {
+            socket.set_hop_limit(Some(max(self.multicast_ttl, 1)));
+        } else {
+            socket.set_hop_limit(self.ttl);
+        }
+
         if socket.can_send() {
-            match socket.send_slice(&data, *remote_endpoint) {
+            match socket.send_slice(&data, remote_endpoint) {
                 Ok(()) => {
                     // avoid deadlock
                     drop(socket);
@@ -488,10 +1061,12 @@ impl Socket for UdpSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
+        icmp_errors();
         let mut sockets = SOCKETS.lock();
         let socket = sockets.get::<UdpSocket>(self.handle.0);
 
-        let (mut input, mut output, err) = (false, false, false);
+        let (mut input, mut output) = (false, false);
+        let err = self.options.error.lock().is_some();
         if socket.can_recv() {
             input = true;
         }
@@ -503,7 +1078,7 @@ impl Socket for UdpSocketState {
 
     fn connect(&mut self, endpoint: Endpoint) -> SysResult {
         if let Endpoint::Ip(ip) = endpoint {
-            self.remote_endpoint = Some(ip);
+            self.remote_endpoint = Some(self.family.from_user(ip)?);
             Ok(0)
         } else {
             Err(SysError::EINVAL)
@@ -511,11 +1086,22 @@ impl Socket for UdpSocketState {
     }
 
     fn bind(&mut self, endpoint: Endpoint) -> SysResult {
-        let mut sockets = SOCKETS.lock();
-        let mut socket = sockets.get::<UdpSocket>(self.handle.0);
         if let Endpoint::Ip(ip) = endpoint {
+            let mut ip = self.family.from_user(ip)?;
+            let mut port = self.port.lock();
+            if port.is_some() {
+                // bound already, or by sending
+                return Err(SysError::EINVAL);
+            }
+            let bound = bind_port(PortProtocol::Udp, ip.addr, ip.port, &self.options)?;
+            ip.port = bound.port;
+            let mut sockets = SOCKETS.lock();
+            let mut socket = sockets.get::<UdpSocket>(self.handle.0);
             match socket.bind(ip) {
-                Ok(()) => Ok(0),
+                Ok(()) => {
+                    *port = Some(bound);
+                    Ok(0)
+                }
                 Err(_) => Err(SysError::EINVAL),
             }
         } else {
@@ -565,37 +1151,99 @@ impl Socket for UdpSocketState {
         let socket = sockets.get::<UdpSocket>(self.handle.0);
         let endpoint = socket.endpoint();
         if endpoint.port != 0 {
-            Some(Endpoint::Ip(endpoint))
+            Some(Endpoint::Ip(self.family.to_user(endpoint)))
         } else {
             None
         }
     }
 
     fn remote_endpoint(&self) -> Option<Endpoint> {
-        self.remote_endpoint.clone().map(|e| Endpoint::Ip(e))
-    }
-
-    fn box_clone(&self) -> Box<dyn Socket> {
-        Box::new(self.clone())
+        self.remote_endpoint
+            .clone()
+            .map(|e| Endpoint::Ip(self.family.to_user(e)))
     }
-}
 
-impl RawSocketState {
-    pub fn new(protocol: u8) -> Self {
-        let rx_buffer = RawSocketBuffer::new(
-            vec![RawPacketMetadata::EMPTY; RAW_METADATA_BUF],
-            vec![0; RAW_RECVBUF],
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (IPPROTO_IP, IP_PKTINFO) => {
+                self.pktinfo = sockopt_u32(data)? != 0;
+                Ok(0)
+            }
+            (SOL_SOCKET, SO_TIMESTAMP) => {
+                self.timestamp = sockopt_u32(data)? != 0;
+                Ok(0)
+            }
+            (IPPROTO_IP, IP_TTL) => {
+                self.ttl = sockopt_ttl(data)?;
+                Ok(0)
+            }
+            (IPPROTO_IP, IP_MULTICAST_TTL) => {
+                self.multicast_ttl = match sockopt_u8(data)? as i32 {
+                    -1 => 1,
+                    ttl @ 0..=255 => ttl as u8,
+                    _ => return Err(SysError::EINVAL),
+                };
+                Ok(0)
+            }
+            (IPPROTO_IP, IP_MULTICAST_LOOP) => {
+                self.multicast_loop = sockopt_u8(data)? != 0;
+                Ok(0)
+            }
+            (IPPROTO_IP, IP_MULTICAST_IF) => {
+                // struct in_addr, or the interface address of struct ip_mreq(n)
+                let addr = match data.len() {
+                    0..=3 => return Err(SysError::EINVAL),
+                    4..=7 => &data[..4],
+                    _ => &data[4..8],
+                };
+                self.multicast_if = Ipv4Address::from_bytes(addr);
+                Ok(0)
+            }
+            (IPPROTO_IPV6, _) => self.family.setsockopt(level, opt, data),
+            _ => self.options.setsockopt(level, opt, data),
+        }
+    }
+
+    fn getsockopt(&mut self, level: usize, opt: usize) -> Result<Vec<u8>, SysError> {
+        match (level, opt) {
+            (SOL_SOCKET, SO_ERROR) => {
+                icmp_errors();
+                self.options.getsockopt(level, opt)
+            }
+            (IPPROTO_IP, IP_PKTINFO) => Ok(sockopt_value(self.pktinfo as u32)),
+            (SOL_SOCKET, SO_TIMESTAMP) => Ok(sockopt_value(self.timestamp as u32)),
+            (IPPROTO_IP, IP_TTL) => {
+                let ttl = self.ttl.unwrap_or(IP_DEFAULT_TTL);
+                Ok(sockopt_value(ttl as u32))
+            }
+            (IPPROTO_IP, IP_MULTICAST_TTL) => Ok(sockopt_value(self.multicast_ttl as u32)),
+            (IPPROTO_IP, IP_MULTICAST_LOOP) => Ok(sockopt_value(self.multicast_loop as u32)),
+            (IPPROTO_IP, IP_MULTICAST_IF) => Ok(self.multicast_if.as_bytes().to_vec()),
+            (IPPROTO_IPV6, _) => self.family.getsockopt(level, opt),
+            _ => self.options.getsockopt(level, opt),
+        }
+    }
+
+    fn timeout(&self, events: Event) -> Option<Duration> {
+        self.options.timeout(events)
+    }
+
+    fn box_clone(&self) -> Box<dyn Socket> {
+        Box::new(self.clone())
+    }
+}
+
+impl RawSocketState {
+    pub fn new(version: IpVersion, protocol: u8) -> Self {
+        let rx_buffer = RawSocketBuffer::new(
+            vec![RawPacketMetadata::EMPTY; RAW_METADATA_BUF],
+            vec![0; RAW_RECVBUF],
         );
         let tx_buffer = RawSocketBuffer::new(
             vec![RawPacketMetadata::EMPTY; RAW_METADATA_BUF],
             vec![0; RAW_SENDBUF],
         );
-        let socket = RawSocket::new(
-            IpVersion::Ipv4,
-            IpProtocol::from(protocol),
-            rx_buffer,
-            tx_buffer,
-        );
+        let socket = RawSocket::new(version, IpProtocol::from(protocol), rx_buffer, tx_buffer);
         let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
 
         RawSocketState {
@@ -607,25 +1255,37 @@ impl RawSocketState {
 
 impl Socket for RawSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
-        loop {
-            let mut sockets = SOCKETS.lock();
-            let mut socket = sockets.get::<RawSocket>(self.handle.0);
+        let mut sockets = SOCKETS.lock();
+        let mut socket = sockets.get::<RawSocket>(self.handle.0);
 
-            if let Ok(size) = socket.recv_slice(data) {
-                let packet = Ipv4Packet::new_unchecked(data);
+        if socket.ip_version() == IpVersion::Ipv6 {
+            // no header for IPv6 raw sockets
+            if let Ok(packet) = socket.recv() {
+                let packet = Ipv6Packet::new_unchecked(packet);
+                let payload = packet.payload();
+                let len = min(payload.len(), data.len());
+                data[..len].copy_from_slice(&payload[..len]);
 
                 return (
-                    Ok(size),
+                    Ok(len),
                     Endpoint::Ip(IpEndpoint {
-                        addr: IpAddress::Ipv4(packet.src_addr()),
+                        addr: IpAddress::Ipv6(packet.src_addr()),
                         port: 0,
                     }),
                 );
             }
-
-            drop(socket);
-            SOCKET_ACTIVITY.wait(sockets);
+        } else if let Ok(size) = socket.recv_slice(data) {
+            let packet = Ipv4Packet::new_unchecked(data);
+
+            return (
+                Ok(size),
+                Endpoint::Ip(IpEndpoint {
+                    addr: IpAddress::Ipv4(packet.src_addr()),
+                    port: 0,
+                }),
+            );
         }
+        (Err(SysError::EAGAIN), Endpoint::Ip(IpEndpoint::UNSPECIFIED))
     }
 
     fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
@@ -639,38 +1299,58 @@ impl Socket for RawSocketState {
             }
         } else {
             if let Some(Endpoint::Ip(endpoint)) = sendto_endpoint {
-                // temporary solution
-                let iface = &*(NET_DRIVERS.read()[0]);
-                let v4_src = iface.ipv4_address().unwrap();
                 let mut sockets = SOCKETS.lock();
                 let mut socket = sockets.get::<RawSocket>(self.handle.0);
 
-                if let IpAddress::Ipv4(v4_dst) = endpoint.addr {
-                    let len = data.len();
-                    // using 20-byte IPv4 header
-                    let mut buffer = vec![0u8; len + 20];
-                    let mut packet = Ipv4Packet::new_unchecked(&mut buffer);
-                    packet.set_version(4);
-                    packet.set_header_len(20);
-                    packet.set_total_len((20 + len) as u16);
-                    packet.set_protocol(socket.ip_protocol().into());
-                    packet.set_src_addr(v4_src);
-                    packet.set_dst_addr(v4_dst);
-                    let payload = packet.payload_mut();
-                    payload.copy_from_slice(data);
-                    packet.fill_checksum();
-
-                    socket.send_slice(&buffer).unwrap();
-
-                    // avoid deadlock
-                    drop(socket);
-                    drop(sockets);
-                    iface.poll();
+                let len = data.len();
+                match (socket.ip_version(), endpoint.addr) {
+                    (IpVersion::Ipv4, IpAddress::Ipv4(v4_dst)) => {
+                        let v4_src = ipv4_source_for(v4_dst).ok_or(SysError::ENETUNREACH)?;
+                        // using 20-byte IPv4 header
+                        let mut buffer = vec![0u8; len + 20];
+                        let mut packet = Ipv4Packet::new_unchecked(&mut buffer);
+                        packet.set_version(4);
+                        packet.set_header_len(20);
+                        packet.set_total_len((20 + len) as u16);
+                        packet.set_protocol(socket.ip_protocol().into());
+                        packet.set_src_addr(v4_src);
+                        packet.set_dst_addr(v4_dst);
+                        let payload = packet.payload_mut();
+                        payload.copy_from_slice(data);
+                        packet.fill_checksum();
+
+                        socket.send_slice(&buffer).unwrap();
+                    }
+                    (IpVersion::Ipv6, IpAddress::Ipv6(v6_dst)) => {
+                        let v6_src = ipv6_source_for(v6_dst).ok_or(SysError::ENETUNREACH)?;
+                        // using 40-byte IPv6 header, without extension headers
+                        let mut buffer = vec![0u8; len + 40];
+                        let mut packet = Ipv6Packet::new_unchecked(&mut buffer);
+                        packet.set_version(6);
+                        packet.set_payload_len(len as u16);
+                        packet.set_next_header(socket.ip_protocol());
+                        packet.set_hop_limit(64);
+                        packet.set_src_addr(v6_src);
+                        packet.set_dst_addr(v6_dst);
+                        let payload = packet.payload_mut();
+                        payload.copy_from_slice(data);
+                        if socket.ip_protocol() == IpProtocol::Icmpv6 {
+                            // as Linux, fill the ICMPv6 checksum for the user
+                            let mut icmp = Icmpv6Packet::new_unchecked(payload);
+                            icmp.fill_checksum(&IpAddress::Ipv6(v6_src), &IpAddress::Ipv6(v6_dst));
+                        }
 
-                    Ok(len)
-                } else {
-                    unimplemented!("ip type")
+                        socket.send_slice(&buffer).unwrap();
+                    }
+                    _ => return Err(SysError::EAFNOSUPPORT),
                 }
+
+                // avoid deadlock
+                drop(socket);
+                drop(sockets);
+                poll_ifaces();
+
+                Ok(len)
             } else {
                 Err(SysError::ENOTCONN)
             }
@@ -678,7 +1358,9 @@ impl Socket for RawSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets.get::<RawSocket>(self.handle.0);
+        (socket.can_recv(), socket.can_send(), false)
     }
 
     fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
@@ -717,7 +1399,11 @@ impl Socket for PacketSocketState {
     fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
         if let Some(Endpoint::LinkLevel(endpoint)) = sendto_endpoint {
             let ifaces = NET_DRIVERS.read();
-            match ifaces[endpoint.interface_index].send(data) {
+            // indexes count from 1 as in netlink
+            let iface = ifaces
+                .get(endpoint.interface_index.wrapping_sub(1))
+                .ok_or(SysError::ENXIO)?;
+            match iface.send(data) {
                 Some(len) => Ok(len),
                 None => Err(SysError::ENOBUFS),
             }
@@ -727,7 +1413,8 @@ impl Socket for PacketSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+        // egress only
+        (false, true, false)
     }
 
     fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
@@ -753,6 +1440,91 @@ struct NetlinkMessageHeader {
     nlmsg_pid: u32,                   // sending process port id
 }
 
+/// Pick the address of the interface on `dst`'s network,
+/// or of the first interface other than `lo`
+fn ipv4_source_for(dst: Ipv4Address) -> Option<Ipv4Address> {
+    let ifaces = NET_DRIVERS.read();
+    if dst.is_loopback() {
+        return ifaces
+            .iter()
+            .find(|iface| iface.is_loopback())
+            .and_then(|iface| iface.ipv4_address());
+    }
+    for iface in ifaces.iter() {
+        for cidr in iface.get_ip_addresses() {
+            if let IpCidr::Ipv4(cidr) = cidr {
+                if cidr.contains_addr(&dst) {
+                    return Some(cidr.address());
+                }
+            }
+        }
+    }
+    ifaces
+        .iter()
+        .filter(|iface| !iface.is_loopback())
+        .find_map(|iface| iface.ipv4_address())
+}
+
+/// The index of the interface a datagram between `local` and `remote` went
+/// through, and the local address it came to, for IP_PKTINFO
+fn ipv4_pktinfo(local: IpAddress, remote: IpAddress) -> Option<(usize, Ipv4Address)> {
+    let remote = match remote {
+        IpAddress::Ipv4(remote) => remote,
+        _ => return None,
+    };
+    let ifaces = NET_DRIVERS.read();
+    if let IpAddress::Ipv4(local) = local {
+        if !local.is_unspecified() {
+            return ifaces
+                .iter()
+                .position(|iface| {
+                    iface
+                        .get_ip_addresses()
+                        .iter()
+                        .any(|cidr| cidr.address() == IpAddress::Ipv4(local))
+                })
+                .map(|index| (index + 1, local));
+        }
+    }
+    let index = if remote.is_loopback() {
+        ifaces.iter().position(|iface| iface.is_loopback())
+    } else {
+        ifaces
+            .iter()
+            .position(|iface| {
+                iface.get_ip_addresses().iter().any(|cidr| match cidr {
+                    IpCidr::Ipv4(cidr) => cidr.contains_addr(&remote),
+                    _ => false,
+                })
+            })
+            .or_else(|| ifaces.iter().position(|iface| !iface.is_loopback()))
+    }?;
+    Some((index + 1, ifaces[index].ipv4_address()?))
+}
+
+/// Pick an address of `dst`'s scope, on its network if there is one
+fn ipv6_source_for(dst: Ipv6Address) -> Option<Ipv6Address> {
+    let ifaces = NET_DRIVERS.read();
+    let addrs: Vec<Ipv6Cidr> = ifaces
+        .iter()
+        .filter(|iface| iface.is_loopback() == dst.is_loopback())
+        .flat_map(|iface| iface.get_ip_addresses())
+        .filter_map(|cidr| match cidr {
+            IpCidr::Ipv6(cidr) => Some(cidr),
+            _ => None,
+        })
+        .collect();
+    addrs
+        .iter()
+        .find(|cidr| cidr.contains_addr(&dst))
+        .or_else(|| {
+            addrs
+                .iter()
+                .find(|cidr| cidr.address().is_link_local() == dst.is_link_local())
+        })
+        .map(|cidr| cidr.address())
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 struct IfaceInfoMsg {
@@ -763,6 +1535,19 @@ struct IfaceInfoMsg {
     ifi_change: u32,
 }
 
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_LOOPBACK: u16 = 772;
+
+const IFF_UP: u32 = 0x1;
+const IFF_BROADCAST: u32 = 0x2;
+const IFF_LOOPBACK: u32 = 0x8;
+const IFF_RUNNING: u32 = 0x40;
+const IFF_MULTICAST: u32 = 0x1000;
+
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_SCOPE_LINK: u8 = 253;
+const RT_SCOPE_HOST: u8 = 254;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 struct IfaceAddrMsg {
@@ -831,6 +1616,12 @@ enum_with_unknown! {
         DelAddr = 21,
         /// Get addr
         GetAddr = 22,
+        /// New route
+        NewRoute = 24,
+        /// Delete route
+        DelRoute = 25,
+        /// Get route
+        GetRoute = 26,
     }
 }
 
@@ -852,18 +1643,768 @@ enum_with_unknown! {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct RouteMsg {
+    rtm_family: u8,
+    rtm_dst_len: u8,
+    rtm_src_len: u8,
+    rtm_tos: u8,
+    rtm_table: u8,
+    rtm_protocol: u8,
+    rtm_scope: u8,
+    rtm_type: u8,
+    rtm_flags: u32,
+}
+
+/// Payload of NLMSG_ERROR, an ack if `error` is 0
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct NetlinkErrorMsg {
+    error: i32,
+    msg: NetlinkMessageHeader,
+}
+
+const RT_TABLE_MAIN: u8 = 254;
+
+const RTPROT_KERNEL: u8 = 2;
+const RTPROT_BOOT: u8 = 3;
+
+const RTN_UNICAST: u8 = 1;
+
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+enum_with_unknown! {
+    /// Attribute types of ifaddrmsg
+    pub doc enum AddrAttrTypes(u16) {
+        /// Unspecified
+        Unspecified = 0,
+        /// Address of the peer, or the local one
+        Address = 1,
+        /// Local address
+        Local = 2,
+        /// Interface name
+        Label = 3,
+    }
+}
+
+enum_with_unknown! {
+    /// Attribute types of rtmsg
+    pub doc enum RouteMsgAttrTypes(u16) {
+        /// Unspecified
+        Unspecified = 0,
+        /// Destination
+        Dst = 1,
+        /// Source
+        Src = 2,
+        /// Input interface
+        Iif = 3,
+        /// Output interface
+        Oif = 4,
+        /// Gateway
+        Gateway = 5,
+        /// Priority
+        Priority = 6,
+        /// Preferred source address
+        Prefsrc = 7,
+    }
+}
+
+lazy_static! {
+    /// Bound netlink sockets, to which notifications are multicast
+    static ref NETLINK_SOCKETS: Mutex<Vec<Weak<NetlinkSocketInner>>> = Mutex::new(Vec::new());
+}
+
+/// Port id to try next when the process id is taken, counting down
+static NETLINK_NEXT_PORT_ID: AtomicU32 = AtomicU32::new(-4096i32 as u32);
+
 impl NetlinkSocketState {
-    pub fn new() -> Self {
+    /// A socket that takes `pid` as its port id if it is free
+    pub fn new(pid: u32) -> Self {
         NetlinkSocketState {
-            data: Arc::new(Mutex::new(Vec::new())),
+            inner: Arc::new(NetlinkSocketInner {
+                pid,
+                data: Mutex::new(VecDeque::new()),
+                endpoint: Mutex::new(None),
+                eventbus: EventBus::new(),
+            }),
+        }
+    }
+}
+
+impl NetlinkSocketInner {
+    fn push(&self, msg: Vec<u8>) {
+        self.data.lock().push_back(msg);
+        self.eventbus.lock().set(Event::READABLE);
+    }
+
+    fn groups(&self) -> u32 {
+        self.endpoint
+            .lock()
+            .as_ref()
+            .map_or(0, |endpoint| endpoint.multicast_groups_mask)
+    }
+
+    /// Take `port_id`, or a free one if it is 0, and listen to `groups`
+    fn bind(self: &Arc<Self>, port_id: u32, groups: u32) -> Result<u32, SysError> {
+        let mut sockets = NETLINK_SOCKETS.lock();
+        let mut endpoint = self.endpoint.lock();
+        if let Some(bound) = &mut *endpoint {
+            // binding again only changes the groups
+            if port_id != 0 && port_id != bound.port_id {
+                return Err(SysError::EINVAL);
+            }
+            bound.multicast_groups_mask = groups;
+            return Ok(bound.port_id);
+        }
+        sockets.retain(|socket| socket.strong_count() > 0);
+        let taken = |port_id: u32| {
+            sockets
+                .iter()
+                .filter_map(Weak::upgrade)
+                .any(|socket| match &*socket.endpoint.lock() {
+                    Some(endpoint) => endpoint.port_id == port_id,
+                    None => false,
+                })
+        };
+        let port_id = if port_id != 0 {
+            if taken(port_id) {
+                return Err(SysError::EADDRINUSE);
+            }
+            port_id
+        } else if self.pid != 0 && !taken(self.pid) {
+            self.pid
+        } else {
+            loop {
+                let port_id = NETLINK_NEXT_PORT_ID.fetch_sub(1, Ordering::SeqCst);
+                if port_id != 0 && !taken(port_id) {
+                    break port_id;
+                }
+            }
+        };
+        *endpoint = Some(NetlinkEndpoint::new(port_id, groups));
+        sockets.push(Arc::downgrade(self));
+        Ok(port_id)
+    }
+
+    /// The port id, binding one on first use
+    fn port_id(self: &Arc<Self>) -> Result<u32, SysError> {
+        if let Some(endpoint) = &*self.endpoint.lock() {
+            return Ok(endpoint.port_id);
+        }
+        self.bind(0, 0)
+    }
+
+    /// Answer a request from port `port_id`, changes need a `privileged` sender
+    fn handle(&self, header: &NetlinkMessageHeader, msg: &[u8], port_id: u32, privileged: bool) {
+        let message_type = NetlinkMessageType::from(header.nlmsg_type);
+        debug!("type: {:?}", message_type);
+        let reply = |message_type: NetlinkMessageType, flags: NetlinkMessageFlags| {
+            NetlinkMessageHeader::new(message_type, flags, header.nlmsg_seq, port_id)
+        };
+        let ifaces = NET_DRIVERS.read();
+        let result = match message_type {
+            NetlinkMessageType::GetLink
+                if !header.nlmsg_flags.contains(NetlinkMessageFlags::DUMP) =>
+            {
+                find_link(&ifaces, msg).map(|index| {
+                    let header = reply(NetlinkMessageType::NewLink, NetlinkMessageFlags::empty());
+                    self.push(link_message(header, index, &*ifaces[index - 1]));
+                })
+            }
+            NetlinkMessageType::GetLink => {
+                for (i, iface) in ifaces.iter().enumerate() {
+                    let header = reply(NetlinkMessageType::NewLink, NetlinkMessageFlags::MULTI);
+                    self.push(link_message(header, i + 1, &**iface));
+                }
+                self.push_done(reply(NetlinkMessageType::Done, NetlinkMessageFlags::MULTI));
+                return;
+            }
+            NetlinkMessageType::GetAddr => {
+                for (i, iface) in ifaces.iter().enumerate() {
+                    for cidr in distinct_addresses(&**iface) {
+                        let header = reply(NetlinkMessageType::NewAddr, NetlinkMessageFlags::MULTI);
+                        self.push(addr_message(header, i + 1, &**iface, cidr));
+                    }
+                }
+                self.push_done(reply(NetlinkMessageType::Done, NetlinkMessageFlags::MULTI));
+                return;
+            }
+            NetlinkMessageType::GetRoute
+                if header.nlmsg_flags.contains(NetlinkMessageFlags::DUMP) =>
+            {
+                let family =
+                    read_payload::<RouteMsg>(msg).map_or(0, |route_msg| route_msg.rtm_family);
+                for route in routes(&ifaces) {
+                    if family == 0 || family == ip_family(route.dst.address()) {
+                        let header =
+                            reply(NetlinkMessageType::NewRoute, NetlinkMessageFlags::MULTI);
+                        self.push(route_message(header, &route));
+                    }
+                }
+                self.push_done(reply(NetlinkMessageType::Done, NetlinkMessageFlags::MULTI));
+                return;
+            }
+            NetlinkMessageType::NewAddr
+            | NetlinkMessageType::DelAddr
+            | NetlinkMessageType::NewRoute
+            | NetlinkMessageType::DelRoute
+            | NetlinkMessageType::NewLink
+            | NetlinkMessageType::SetLink
+                if !privileged =>
+            {
+                Err(SysError::EPERM)
+            }
+            NetlinkMessageType::NewAddr | NetlinkMessageType::DelAddr => {
+                change_addr(&ifaces, header, msg, port_id)
+            }
+            NetlinkMessageType::NewRoute | NetlinkMessageType::DelRoute => {
+                change_route(&ifaces, header, msg, port_id)
+            }
+            NetlinkMessageType::NewLink | NetlinkMessageType::SetLink => {
+                set_link(&ifaces, header, msg, port_id)
+            }
+            _ => Err(SysError::EOPNOTSUPP),
+        };
+        let error = match result {
+            Ok(()) if !header.nlmsg_flags.contains(NetlinkMessageFlags::ACK) => return,
+            Ok(()) => 0,
+            Err(err) => -(err as i32),
+        };
+        let error_msg = NetlinkErrorMsg {
+            error,
+            msg: *header,
+        };
+        let header = reply(NetlinkMessageType::Error, NetlinkMessageFlags::empty());
+        self.push(netlink_message(header, error_msg, Vec::new()));
+    }
+
+    /// End a dump
+    fn push_done(&self, header: NetlinkMessageHeader) {
+        self.push(netlink_message(header, 0i32, Vec::new()));
+    }
+}
+
+impl NetlinkMessageHeader {
+    fn new(
+        message_type: NetlinkMessageType,
+        flags: NetlinkMessageFlags,
+        seq: u32,
+        pid: u32,
+    ) -> Self {
+        NetlinkMessageHeader {
+            nlmsg_len: 0, // to be determined later
+            nlmsg_type: message_type.into(),
+            nlmsg_flags: flags,
+            nlmsg_seq: seq,
+            nlmsg_pid: pid,
+        }
+    }
+}
+
+/// Queue `msg` on the bound netlink sockets listening to `group`
+fn netlink_notify(group: u32, msg: Vec<u8>) {
+    let sockets: Vec<_> = NETLINK_SOCKETS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for socket in sockets {
+        if socket.groups() & group != 0 {
+            socket.push(msg.clone());
+        }
+    }
+}
+
+/// Tell netlink listeners that `ifname` got or lost `cidr` without a
+/// request, as by a DHCP lease
+pub fn netlink_notify_addr(ifname: &str, cidr: IpCidr, added: bool) {
+    let ifaces = NET_DRIVERS.read();
+    if let Some(i) = ifaces.iter().position(|iface| iface.get_ifname() == ifname) {
+        let message_type = match added {
+            true => NetlinkMessageType::NewAddr,
+            false => NetlinkMessageType::DelAddr,
+        };
+        let header = NetlinkMessageHeader::new(message_type, NetlinkMessageFlags::empty(), 0, 0);
+        let group = match cidr {
+            IpCidr::Ipv6(_) => RTMGRP_IPV6_IFADDR,
+            _ => RTMGRP_IPV4_IFADDR,
+        };
+        netlink_notify(group, addr_message(header, i + 1, &*ifaces[i], cidr));
+    }
+}
+
+/// Tell netlink listeners that `ifname` got or lost the route to `dst`
+/// through `gateway` without a request
+pub fn netlink_notify_route(ifname: &str, dst: IpCidr, gateway: IpAddress, added: bool) {
+    let ifaces = NET_DRIVERS.read();
+    if let Some(i) = ifaces.iter().position(|iface| iface.get_ifname() == ifname) {
+        let message_type = match added {
+            true => NetlinkMessageType::NewRoute,
+            false => NetlinkMessageType::DelRoute,
+        };
+        let header = NetlinkMessageHeader::new(message_type, NetlinkMessageFlags::empty(), 0, 0);
+        let group = match dst {
+            IpCidr::Ipv6(_) => RTMGRP_IPV6_ROUTE,
+            _ => RTMGRP_IPV4_ROUTE,
+        };
+        let route = RouteEntry {
+            index: i + 1,
+            dst,
+            gateway: Some(gateway),
+            source: None,
+        };
+        netlink_notify(group, route_message(header, &route));
+    }
+}
+
+/// A message of `payload` and `attrs` after `header`
+fn netlink_message<T>(header: NetlinkMessageHeader, payload: T, mut attrs: Vec<u8>) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.push_ext(header);
+    msg.align4();
+    msg.push_ext(payload);
+    msg.align4();
+    msg.append(&mut attrs);
+    msg.align4();
+    msg.set_ext(0, msg.len() as u32);
+    msg
+}
+
+/// The `T` after the header of `msg`
+fn read_payload<T: Copy>(msg: &[u8]) -> Result<T, SysError> {
+    let offset = size_of::<NetlinkMessageHeader>();
+    if msg.len() < offset + size_of::<T>() {
+        return Err(SysError::EINVAL);
+    }
+    Ok(unsafe { ptr::read_unaligned(msg[offset..].as_ptr() as *const T) })
+}
+
+/// The attributes after the `T` of `msg`, with their types
+fn read_attrs<T>(msg: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    let mut offset = (size_of::<NetlinkMessageHeader>() + size_of::<T>() + 3) & !3;
+    while offset + size_of::<RouteAttr>() <= msg.len() {
+        let attr = unsafe { ptr::read_unaligned(msg[offset..].as_ptr() as *const RouteAttr) };
+        let len = attr.rta_len as usize;
+        if len < size_of::<RouteAttr>() || offset + len > msg.len() {
+            break;
+        }
+        attrs.push((
+            attr.rta_type,
+            &msg[offset + size_of::<RouteAttr>()..offset + len],
+        ));
+        offset += (len + 3) & !3;
+    }
+    attrs
+}
+
+/// The data of the first attribute of `rta_type`
+fn find_attr<'a>(attrs: &[(u16, &'a [u8])], rta_type: u16) -> Option<&'a [u8]> {
+    attrs
+        .iter()
+        .find(|(attr_type, _)| *attr_type == rta_type)
+        .map(|(_, data)| *data)
+}
+
+/// AF_INET or AF_INET6 of `addr`, as the family fields take it
+fn ip_family(addr: IpAddress) -> u8 {
+    let family: u16 = match addr {
+        IpAddress::Ipv6(_) => AddressFamily::Internet6.into(),
+        _ => AddressFamily::Internet.into(),
+    };
+    family as u8
+}
+
+/// The address of `family` in an attribute
+fn attr_address(family: u8, data: &[u8]) -> Result<IpAddress, SysError> {
+    if family == ip_family(IpAddress::Ipv4(Ipv4Address::UNSPECIFIED)) && data.len() == 4 {
+        Ok(IpAddress::Ipv4(Ipv4Address::from_bytes(data)))
+    } else if family == ip_family(IpAddress::Ipv6(Ipv6Address::UNSPECIFIED)) && data.len() == 16 {
+        Ok(IpAddress::Ipv6(Ipv6Address::from_bytes(data)))
+    } else {
+        Err(SysError::EINVAL)
+    }
+}
+
+/// `addr` with a prefix of `prefix_len`, failing if it is too long
+fn ip_cidr(addr: IpAddress, prefix_len: u8) -> Result<IpCidr, SysError> {
+    if prefix_len as usize > addr.as_bytes().len() * 8 {
+        return Err(SysError::EINVAL);
+    }
+    Ok(IpCidr::new(addr, prefix_len))
+}
+
+/// The network `cidr` is on
+fn network_of(cidr: IpCidr) -> IpCidr {
+    let mut bytes = Vec::from(cidr.address().as_bytes());
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let bits = min((cidr.prefix_len() as usize).saturating_sub(i * 8), 8);
+        *byte &= !0xffu8.checked_shr(bits as u32).unwrap_or(0);
+    }
+    let addr = match cidr.address() {
+        IpAddress::Ipv6(_) => IpAddress::Ipv6(Ipv6Address::from_bytes(&bytes)),
+        _ => IpAddress::Ipv4(Ipv4Address::from_bytes(&bytes)),
+    };
+    IpCidr::new(addr, cidr.prefix_len())
+}
+
+/// The interface at a netlink index, which counts from 1
+fn iface_at(ifaces: &[Arc<dyn NetDriver>], index: u32) -> Result<&dyn NetDriver, SysError> {
+    ifaces
+        .get((index as usize).wrapping_sub(1))
+        .map(|iface| &**iface)
+        .ok_or(SysError::ENODEV)
+}
+
+/// Addresses of `iface` but the copy in an unused SLAAC slot
+fn distinct_addresses(iface: &dyn NetDriver) -> Vec<IpCidr> {
+    let mut addrs = Vec::new();
+    for cidr in iface.get_ip_addresses() {
+        if !addrs.contains(&cidr) {
+            addrs.push(cidr);
+        }
+    }
+    addrs
+}
+
+/// RTM_NEWLINK describing the interface at `index`
+fn link_message(header: NetlinkMessageHeader, index: usize, iface: &dyn NetDriver) -> Vec<u8> {
+    let up = if iface.is_up() {
+        IFF_UP | IFF_RUNNING
+    } else {
+        0
+    };
+    let (ifi_type, ifi_flags) = if iface.is_loopback() {
+        (ARPHRD_LOOPBACK, up | IFF_LOOPBACK)
+    } else {
+        (ARPHRD_ETHER, up | IFF_BROADCAST | IFF_MULTICAST)
+    };
+    let if_info = IfaceInfoMsg {
+        ifi_family: AddressFamily::Unspecified.into(),
+        ifi_type,
+        ifi_index: index as u32,
+        ifi_flags,
+        ifi_change: 0,
+    };
+    let mut attrs = Vec::new();
+    attrs.push_attr(RouteAttrTypes::Address.into(), iface.get_mac().as_bytes());
+    attrs.push_attr(RouteAttrTypes::Ifname.into(), iface.get_ifname().as_bytes());
+    netlink_message(header, if_info, attrs)
+}
+
+/// RTM_NEWADDR or RTM_DELADDR for `cidr` of the interface at `index`
+fn addr_message(
+    header: NetlinkMessageHeader,
+    index: usize,
+    iface: &dyn NetDriver,
+    cidr: IpCidr,
+) -> Vec<u8> {
+    let ip_addr = cidr.address();
+    let ifa_scope = match ip_addr {
+        _ if iface.is_loopback() => RT_SCOPE_HOST,
+        IpAddress::Ipv6(addr) if addr.is_link_local() => RT_SCOPE_LINK,
+        _ => RT_SCOPE_UNIVERSE,
+    };
+    let if_addr = IfaceAddrMsg {
+        ifa_family: ip_family(ip_addr),
+        ifa_prefixlen: cidr.prefix_len(),
+        ifa_flags: 0,
+        ifa_scope,
+        ifa_index: index as u32,
+    };
+    let mut attrs = Vec::new();
+    attrs.push_attr(AddrAttrTypes::Address.into(), ip_addr.as_bytes());
+    if let IpAddress::Ipv4(_) = ip_addr {
+        attrs.push_attr(AddrAttrTypes::Local.into(), ip_addr.as_bytes());
+    }
+    netlink_message(header, if_addr, attrs)
+}
+
+/// A route of the main table
+struct RouteEntry {
+    /// Netlink index of the interface
+    index: usize,
+    dst: IpCidr,
+    /// None for the network of an address of the interface
+    gateway: Option<IpAddress>,
+    /// The address of the interface on `dst`, for such networks
+    source: Option<IpAddress>,
+}
+
+/// Networks of the interfaces' addresses, then routes through gateways
+fn routes(ifaces: &[Arc<dyn NetDriver>]) -> Vec<RouteEntry> {
+    let mut routes: Vec<RouteEntry> = Vec::new();
+    for (i, iface) in ifaces.iter().enumerate() {
+        if iface.is_loopback() {
+            continue;
+        }
+        for cidr in distinct_addresses(&**iface) {
+            let dst = network_of(cidr);
+            if !routes.iter().any(|route| route.dst == dst) {
+                routes.push(RouteEntry {
+                    index: i + 1,
+                    dst,
+                    gateway: None,
+                    source: Some(cidr.address()),
+                });
+            }
+        }
+    }
+    for (i, iface) in ifaces.iter().enumerate() {
+        for (dst, gateway) in iface.get_routes() {
+            routes.push(RouteEntry {
+                index: i + 1,
+                dst,
+                gateway: Some(gateway),
+                source: None,
+            });
+        }
+    }
+    routes
+}
+
+/// RTM_NEWROUTE or RTM_DELROUTE for `route`
+fn route_message(header: NetlinkMessageHeader, route: &RouteEntry) -> Vec<u8> {
+    let route_msg = RouteMsg {
+        rtm_family: ip_family(route.dst.address()),
+        rtm_dst_len: route.dst.prefix_len(),
+        rtm_table: RT_TABLE_MAIN,
+        rtm_protocol: match route.gateway {
+            Some(_) => RTPROT_BOOT,
+            None => RTPROT_KERNEL,
+        },
+        rtm_scope: match route.gateway {
+            Some(_) => RT_SCOPE_UNIVERSE,
+            None => RT_SCOPE_LINK,
+        },
+        rtm_type: RTN_UNICAST,
+        ..RouteMsg::default()
+    };
+    let mut attrs = Vec::new();
+    if route.dst.prefix_len() > 0 {
+        attrs.push_attr(
+            RouteMsgAttrTypes::Dst.into(),
+            route.dst.address().as_bytes(),
+        );
+    }
+    if let Some(gateway) = route.gateway {
+        attrs.push_attr(RouteMsgAttrTypes::Gateway.into(), gateway.as_bytes());
+    }
+    if let Some(source) = route.source {
+        attrs.push_attr(RouteMsgAttrTypes::Prefsrc.into(), source.as_bytes());
+    }
+    attrs.push_attr(
+        RouteMsgAttrTypes::Oif.into(),
+        &(route.index as u32).to_ne_bytes(),
+    );
+    netlink_message(header, route_msg, attrs)
+}
+
+/// The index of the interface a link request names by index or IFLA_IFNAME
+fn find_link(ifaces: &[Arc<dyn NetDriver>], msg: &[u8]) -> Result<usize, SysError> {
+    let if_info = read_payload::<IfaceInfoMsg>(msg)?;
+    if if_info.ifi_index != 0 {
+        iface_at(ifaces, if_info.ifi_index)?;
+        return Ok(if_info.ifi_index as usize);
+    }
+    let attrs = read_attrs::<IfaceInfoMsg>(msg);
+    let name = find_attr(&attrs, RouteAttrTypes::Ifname.into()).ok_or(SysError::EINVAL)?;
+    let name = name.split(|byte| *byte == 0).next().unwrap_or(name);
+    ifaces
+        .iter()
+        .position(|iface| iface.get_ifname().as_bytes() == name)
+        .map(|i| i + 1)
+        .ok_or(SysError::ENODEV)
+}
+
+/// RTM_NEWADDR and RTM_DELADDR
+fn change_addr(
+    ifaces: &[Arc<dyn NetDriver>],
+    header: &NetlinkMessageHeader,
+    msg: &[u8],
+    port_id: u32,
+) -> Result<(), SysError> {
+    let if_addr = read_payload::<IfaceAddrMsg>(msg)?;
+    let attrs = read_attrs::<IfaceAddrMsg>(msg);
+    let data = find_attr(&attrs, AddrAttrTypes::Local.into())
+        .or_else(|| find_attr(&attrs, AddrAttrTypes::Address.into()))
+        .ok_or(SysError::EINVAL)?;
+    let addr = attr_address(if_addr.ifa_family, data)?;
+    let cidr = ip_cidr(addr, if_addr.ifa_prefixlen)?;
+    let iface = iface_at(ifaces, if_addr.ifa_index)?;
+    let addrs = iface.get_ip_addresses();
+    let message_type = NetlinkMessageType::from(header.nlmsg_type);
+    let cidr = if message_type == NetlinkMessageType::NewAddr {
+        if addrs.contains(&cidr) {
+            return Err(SysError::EEXIST);
+        }
+        if !iface.add_ip_address(cidr) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        cidr
+    } else {
+        // a full length prefix takes any
+        let full = cidr.prefix_len() as usize == addr.as_bytes().len() * 8;
+        let cidr = *addrs
+            .iter()
+            .find(|other| *other == &cidr)
+            .or_else(|| addrs.iter().find(|other| full && other.address() == addr))
+            .ok_or(SysError::EADDRNOTAVAIL)?;
+        if !iface.remove_ip_address(cidr) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        cidr
+    };
+    let group = match addr {
+        IpAddress::Ipv6(_) => RTMGRP_IPV6_IFADDR,
+        _ => RTMGRP_IPV4_IFADDR,
+    };
+    let header = NetlinkMessageHeader::new(
+        message_type,
+        NetlinkMessageFlags::empty(),
+        header.nlmsg_seq,
+        port_id,
+    );
+    netlink_notify(
+        group,
+        addr_message(header, if_addr.ifa_index as usize, iface, cidr),
+    );
+    Ok(())
+}
+
+/// RTM_NEWROUTE and RTM_DELROUTE, for routes through gateways
+fn change_route(
+    ifaces: &[Arc<dyn NetDriver>],
+    header: &NetlinkMessageHeader,
+    msg: &[u8],
+    port_id: u32,
+) -> Result<(), SysError> {
+    let route_msg = read_payload::<RouteMsg>(msg)?;
+    let attrs = read_attrs::<RouteMsg>(msg);
+    let family = route_msg.rtm_family;
+    let dst = match find_attr(&attrs, RouteMsgAttrTypes::Dst.into()) {
+        Some(data) => attr_address(family, data)?,
+        None if family == ip_family(IpAddress::Ipv6(Ipv6Address::UNSPECIFIED)) => {
+            IpAddress::Ipv6(Ipv6Address::UNSPECIFIED)
+        }
+        None => IpAddress::Ipv4(Ipv4Address::UNSPECIFIED),
+    };
+    let dst = network_of(ip_cidr(dst, route_msg.rtm_dst_len)?);
+    let gateway = match find_attr(&attrs, RouteMsgAttrTypes::Gateway.into()) {
+        Some(data) => Some(attr_address(family, data)?),
+        None => None,
+    };
+    let oif = match find_attr(&attrs, RouteMsgAttrTypes::Oif.into()) {
+        Some(data) if data.len() == 4 => {
+            let index = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+            iface_at(ifaces, index)?;
+            Some(index as usize)
+        }
+        Some(_) => return Err(SysError::EINVAL),
+        None => None,
+    };
+    let message_type = NetlinkMessageType::from(header.nlmsg_type);
+    let route = if message_type == NetlinkMessageType::NewRoute {
+        // smoltcp reaches the networks of the addresses by itself
+        let gateway = gateway.ok_or(SysError::EOPNOTSUPP)?;
+        let index = match oif {
+            Some(index) => index,
+            None => ifaces
+                .iter()
+                .position(|iface| {
+                    distinct_addresses(&**iface)
+                        .iter()
+                        .any(|cidr| cidr.contains_addr(&gateway))
+                })
+                .map(|i| i + 1)
+                .ok_or(SysError::ENETUNREACH)?,
+        };
+        let replaced = ifaces
+            .iter()
+            .find(|iface| iface.get_routes().iter().any(|(other, _)| *other == dst));
+        if let Some(iface) = replaced {
+            if !header.nlmsg_flags.contains(NetlinkMessageFlags::REPLACE) {
+                return Err(SysError::EEXIST);
+            }
+            iface.remove_route(dst);
+        }
+        if !ifaces[index - 1].add_route(dst, gateway) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        RouteEntry {
+            index,
+            dst,
+            gateway: Some(gateway),
+            source: None,
+        }
+    } else {
+        let route = routes(ifaces)
+            .into_iter()
+            .find(|route| {
+                route.dst == dst
+                    && route.gateway.is_some()
+                    && oif.map_or(true, |index| index == route.index)
+                    && (gateway.is_none() || gateway == route.gateway)
+            })
+            .ok_or(SysError::ESRCH)?;
+        ifaces[route.index - 1].remove_route(dst);
+        route
+    };
+    let group = match dst {
+        IpCidr::Ipv6(_) => RTMGRP_IPV6_ROUTE,
+        _ => RTMGRP_IPV4_ROUTE,
+    };
+    let header = NetlinkMessageHeader::new(
+        message_type,
+        NetlinkMessageFlags::empty(),
+        header.nlmsg_seq,
+        port_id,
+    );
+    netlink_notify(group, route_message(header, &route));
+    Ok(())
+}
+
+/// RTM_SETLINK and RTM_NEWLINK of an interface there is, which can only
+/// bring it up or down
+fn set_link(
+    ifaces: &[Arc<dyn NetDriver>],
+    header: &NetlinkMessageHeader,
+    msg: &[u8],
+    port_id: u32,
+) -> Result<(), SysError> {
+    let if_info = read_payload::<IfaceInfoMsg>(msg)?;
+    let index = find_link(ifaces, msg)?;
+    let iface = &*ifaces[index - 1];
+    let change = match if_info.ifi_change {
+        0 => !0,
+        change => change,
+    };
+    if change & IFF_UP != 0 {
+        let up = if_info.ifi_flags & IFF_UP != 0;
+        if up != iface.is_up() {
+            iface.set_up(up);
+            let header = NetlinkMessageHeader::new(
+                NetlinkMessageType::NewLink,
+                NetlinkMessageFlags::empty(),
+                header.nlmsg_seq,
+                port_id,
+            );
+            netlink_notify(RTMGRP_LINK, link_message(header, index, iface));
         }
     }
+    Ok(())
 }
 
 trait VecExt {
     fn align4(&mut self);
     fn push_ext<T: Sized>(&mut self, data: T);
     fn set_ext<T: Sized>(&mut self, offset: usize, data: T);
+    fn push_attr(&mut self, rta_type: u16, data: &[u8]);
 }
 
 impl VecExt for Vec<u8> {
@@ -892,178 +2433,108 @@ impl VecExt for Vec<u8> {
             self[offset + i] = bytes[i];
         }
     }
+
+    fn push_attr(&mut self, rta_type: u16, data: &[u8]) {
+        let attr = RouteAttr {
+            rta_len: (data.len() + size_of::<RouteAttr>()) as u16,
+            rta_type,
+        };
+        self.align4();
+        self.push_ext(attr);
+        self.extend_from_slice(data);
+    }
 }
 
 impl Socket for NetlinkSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
-        let mut buffer = self.data.lock();
-        if buffer.len() > 0 {
-            let msg = buffer.remove(0);
-            let len = min(msg.len(), data.len());
-            data[..len].copy_from_slice(&msg[..len]);
-            (
-                Ok(len),
-                Endpoint::Netlink(NetlinkEndpoint {
-                    port_id: 0,
-                    multicast_groups_mask: 0,
-                }),
-            )
-        } else {
-            (
-                Ok(0),
-                Endpoint::Netlink(NetlinkEndpoint {
-                    port_id: 0,
-                    multicast_groups_mask: 0,
-                }),
-            )
+        let (result, endpoint, _) = self.read_msg(data, false);
+        (result.map(|len| min(len, data.len())), endpoint)
+    }
+
+    fn read_msg(&self, data: &mut [u8], peek: bool) -> (SysResult, Endpoint, Ancillary) {
+        // from the kernel
+        let endpoint = Endpoint::Netlink(NetlinkEndpoint::new(0, 0));
+        let mut buffer = self.inner.data.lock();
+        let len = match buffer.front() {
+            Some(msg) => {
+                let len = min(msg.len(), data.len());
+                data[..len].copy_from_slice(&msg[..len]);
+                msg.len()
+            }
+            None => return (Err(SysError::EAGAIN), endpoint, Ancillary::default()),
+        };
+        if !peek {
+            buffer.pop_front();
+            if buffer.is_empty() {
+                self.inner.eventbus.lock().clear(Event::READABLE);
+            }
         }
+        (Ok(len), endpoint, Ancillary::default())
     }
 
     fn write(&self, data: &[u8], _sendto_endpoint: Option<Endpoint>) -> SysResult {
-        if data.len() < size_of::<NetlinkMessageHeader>() {
-            return Err(SysError::EINVAL);
+        let header_len = size_of::<NetlinkMessageHeader>();
+        let port_id = self.inner.port_id()?;
+        // we have no CAP_NET_ADMIN, changes are for root only
+        let privileged = current_thread().map_or(true, |thread| thread.proc.lock().cred.is_root());
+        let mut offset = 0;
+        while data.len() - offset >= header_len {
+            let header = unsafe {
+                ptr::read_unaligned(data[offset..].as_ptr() as *const NetlinkMessageHeader)
+            };
+            let len = header.nlmsg_len as usize;
+            if len < header_len || len > data.len() - offset {
+                break;
+            }
+            self.inner
+                .handle(&header, &data[offset..offset + len], port_id, privileged);
+            offset += min((len + 3) & !3, data.len() - offset);
         }
-        let header = unsafe { &*(data.as_ptr() as *const NetlinkMessageHeader) };
-        if header.nlmsg_len as usize > data.len() {
+        if offset == 0 {
             return Err(SysError::EINVAL);
         }
-        let message_type = NetlinkMessageType::from(header.nlmsg_type);
-        debug!("type: {:?}", message_type);
-        let mut buffer = self.data.lock();
-        buffer.clear();
-        match message_type {
-            NetlinkMessageType::GetLink => {
-                let ifaces = NET_DRIVERS.read();
-                for i in 0..ifaces.len() {
-                    let mut msg = Vec::new();
-                    let new_header = NetlinkMessageHeader {
-                        nlmsg_len: 0, // to be determined later
-                        nlmsg_type: NetlinkMessageType::NewLink.into(),
-                        nlmsg_flags: NetlinkMessageFlags::MULTI,
-                        nlmsg_seq: header.nlmsg_seq,
-                        nlmsg_pid: header.nlmsg_pid,
-                    };
-                    msg.push_ext(new_header);
-
-                    let if_info = IfaceInfoMsg {
-                        ifi_family: AddressFamily::Unspecified.into(),
-                        ifi_type: 0,
-                        ifi_index: i as u32,
-                        ifi_flags: 0,
-                        ifi_change: 0,
-                    };
-                    msg.align4();
-                    msg.push_ext(if_info);
-
-                    let mut attrs = Vec::new();
-
-                    let mac_addr = ifaces[i].get_mac();
-                    let attr = RouteAttr {
-                        rta_len: (mac_addr.as_bytes().len() + size_of::<RouteAttr>()) as u16,
-                        rta_type: RouteAttrTypes::Address.into(),
-                    };
-                    attrs.align4();
-                    attrs.push_ext(attr);
-                    for byte in mac_addr.as_bytes() {
-                        attrs.push(*byte);
-                    }
-
-                    let ifname = ifaces[i].get_ifname();
-                    let attr = RouteAttr {
-                        rta_len: (ifname.as_bytes().len() + size_of::<RouteAttr>()) as u16,
-                        rta_type: RouteAttrTypes::Ifname.into(),
-                    };
-                    attrs.align4();
-                    attrs.push_ext(attr);
-                    for byte in ifname.as_bytes() {
-                        attrs.push(*byte);
-                    }
-
-                    msg.align4();
-                    msg.append(&mut attrs);
+        Ok(data.len())
+    }
 
-                    msg.align4();
-                    msg.set_ext(0, msg.len() as u32);
+    fn poll(&self) -> (bool, bool, bool) {
+        (!self.inner.data.lock().is_empty(), true, false)
+    }
 
-                    buffer.push(msg);
-                }
+    fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        match endpoint {
+            // only the kernel answers
+            Endpoint::Netlink(_) => {
+                self.inner.port_id()?;
+                Ok(0)
             }
-            NetlinkMessageType::GetAddr => {
-                let ifaces = NET_DRIVERS.read();
-                for i in 0..ifaces.len() {
-                    let ip_addrs = ifaces[i].get_ip_addresses();
-                    for j in 0..ip_addrs.len() {
-                        let mut msg = Vec::new();
-                        let new_header = NetlinkMessageHeader {
-                            nlmsg_len: 0, // to be determined later
-                            nlmsg_type: NetlinkMessageType::NewAddr.into(),
-                            nlmsg_flags: NetlinkMessageFlags::MULTI,
-                            nlmsg_seq: header.nlmsg_seq,
-                            nlmsg_pid: header.nlmsg_pid,
-                        };
-                        msg.push_ext(new_header);
-
-                        let family: u16 = AddressFamily::Internet.into();
-                        let if_addr = IfaceAddrMsg {
-                            ifa_family: family as u8,
-                            ifa_prefixlen: ip_addrs[j].prefix_len(),
-                            ifa_flags: 0,
-                            ifa_scope: 0,
-                            ifa_index: i as u32,
-                        };
-                        msg.align4();
-                        msg.push_ext(if_addr);
-
-                        let mut attrs = Vec::new();
-
-                        let ip_addr = ip_addrs[j].address();
-                        let attr = RouteAttr {
-                            rta_len: (ip_addr.as_bytes().len() + size_of::<RouteAttr>()) as u16,
-                            rta_type: RouteAttrTypes::Address.into(),
-                        };
-                        attrs.align4();
-                        attrs.push_ext(attr);
-                        for byte in ip_addr.as_bytes() {
-                            attrs.push(*byte);
-                        }
-
-                        msg.align4();
-                        msg.append(&mut attrs);
-
-                        msg.align4();
-                        msg.set_ext(0, msg.len() as u32);
+            _ => Err(SysError::EINVAL),
+        }
+    }
 
-                        buffer.push(msg);
-                    }
-                }
+    fn bind(&mut self, endpoint: Endpoint) -> SysResult {
+        match endpoint {
+            Endpoint::Netlink(endpoint) => {
+                self.inner
+                    .bind(endpoint.port_id, endpoint.multicast_groups_mask)?;
+                Ok(0)
             }
-            _ => {}
+            _ => Err(SysError::EINVAL),
         }
-        let mut msg = Vec::new();
-        let new_header = NetlinkMessageHeader {
-            nlmsg_len: 0, // to be determined later
-            nlmsg_type: NetlinkMessageType::Done.into(),
-            nlmsg_flags: NetlinkMessageFlags::MULTI,
-            nlmsg_seq: header.nlmsg_seq,
-            nlmsg_pid: header.nlmsg_pid,
-        };
-        msg.push_ext(new_header);
-        msg.align4();
-        msg.set_ext(0, msg.len() as u32);
-        buffer.push(msg);
-        Ok(data.len())
     }
 
-    fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+    fn endpoint(&self) -> Option<Endpoint> {
+        let endpoint = self.inner.endpoint.lock().clone();
+        Some(Endpoint::Netlink(
+            endpoint.unwrap_or_else(|| NetlinkEndpoint::new(0, 0)),
+        ))
     }
 
-    fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
-        unimplemented!()
+    fn eventbus(&self) -> Option<Arc<Mutex<EventBus>>> {
+        Some(self.inner.eventbus.clone())
     }
 
-    fn bind(&mut self, _endpoint: Endpoint) -> SysResult {
-        Ok(0)
+    fn description(&self) -> Option<Arc<dyn Any + Send + Sync>> {
+        Some(self.inner.clone())
     }
 
     fn box_clone(&self) -> Box<dyn Socket> {
@@ -1071,29 +2542,138 @@ impl Socket for NetlinkSocketState {
     }
 }
 
-fn get_ephemeral_port() -> u16 {
-    // TODO selects non-conflict high port
-    static mut EPHEMERAL_PORT: u16 = 0;
-    unsafe {
-        if EPHEMERAL_PORT == 0 {
-            EPHEMERAL_PORT = (49152 + rand::rand() % (65536 - 49152)) as u16;
-        }
-        if EPHEMERAL_PORT == 65535 {
-            EPHEMERAL_PORT = 49152;
-        } else {
-            EPHEMERAL_PORT = EPHEMERAL_PORT + 1;
-        }
-        EPHEMERAL_PORT
+impl Debug for NetlinkSocketState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NetlinkSocketState")
+            .field("endpoint", &*self.inner.endpoint.lock())
+            .finish()
     }
 }
 
+fn new_tcp_socket() -> TcpSocket<'static> {
+    let rx_buffer = TcpSocketBuffer::new(vec![0; TCP_RECVBUF]);
+    let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
+    TcpSocket::new(rx_buffer, tx_buffer)
+}
+
 /// Safety: call this without SOCKETS locked
 fn poll_ifaces() {
     for iface in NET_DRIVERS.read().iter() {
         iface.poll();
     }
+    icmp_errors();
+}
+
+lazy_static! {
+    /// Raw sockets seeing the ICMP packets of all interfaces
+    static ref ICMP_ERRORS: [SocketHandle; 2] = {
+        let mut sockets = SOCKETS.lock();
+        let mut socket = |version, protocol| {
+            let rx_buffer =
+                RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 16], vec![0; 4096]);
+            let tx_buffer = RawSocketBuffer::new(vec![], vec![]);
+            sockets.add(RawSocket::new(version, protocol, rx_buffer, tx_buffer))
+        };
+        [
+            socket(IpVersion::Ipv4, IpProtocol::Icmp),
+            socket(IpVersion::Ipv6, IpProtocol::Icmpv6),
+        ]
+    };
 }
 
+/// Report the destination unreachable messages received to the UDP sockets
+/// whose datagrams they answer, for SO_ERROR
+fn icmp_errors() {
+    // SOCKETS is locked when making the sockets
+    let handles = *ICMP_ERRORS;
+    let mut errors = Vec::new();
+    {
+        let mut sockets = SOCKETS.lock();
+        for handle in handles.iter() {
+            let mut socket = sockets.get::<RawSocket>(*handle);
+            while let Ok(packet) = socket.recv() {
+                errors.extend(parse_udp_error(packet));
+            }
+        }
+    }
+    for (addr, port, error) in errors {
+        report_error(PortProtocol::Udp, addr, port, error);
+    }
+}
+
+/// The local address and port of the UDP datagram an ICMP destination
+/// unreachable message in `packet` answers, with the error it means
+fn parse_udp_error(packet: &[u8]) -> Option<(IpAddress, u16, SysError)> {
+    let (icmp, error, ip_header_len) = match IpVersion::of_packet(packet).ok()? {
+        IpVersion::Ipv4 => {
+            let icmp = Ipv4Packet::new_checked(packet).ok()?.payload();
+            // destination unreachable
+            if icmp.len() < 8 || icmp[0] != 3 {
+                return None;
+            }
+            let error = match icmp[1] {
+                0 => SysError::ENETUNREACH,
+                2 => SysError::ENOPROTOOPT,
+                3 => SysError::ECONNREFUSED,
+                4 => SysError::EMSGSIZE,
+                _ => SysError::EHOSTUNREACH,
+            };
+            (icmp, error, 20)
+        }
+        IpVersion::Ipv6 => {
+            let icmp = Ipv6Packet::new_checked(packet).ok()?.payload();
+            if icmp.len() < 8 || icmp[0] != 1 {
+                return None;
+            }
+            let error = match icmp[1] {
+                0 => SysError::ENETUNREACH,
+                1 => SysError::EACCES,
+                4 => SysError::ECONNREFUSED,
+                _ => SysError::EHOSTUNREACH,
+            };
+            (icmp, error, 40)
+        }
+        _ => return None,
+    };
+    // the header of the datagram, and the first 8 bytes of its payload
+    let datagram = &icmp[8..];
+    if datagram.len() < ip_header_len {
+        return None;
+    }
+    let (addr, header_len) = match IpVersion::of_packet(datagram).ok()? {
+        IpVersion::Ipv4 => {
+            let header = Ipv4Packet::new_unchecked(datagram);
+            if header.protocol() != IpProtocol::Udp {
+                return None;
+            }
+            (
+                IpAddress::Ipv4(header.src_addr()),
+                header.header_len() as usize,
+            )
+        }
+        IpVersion::Ipv6 => {
+            let header = Ipv6Packet::new_unchecked(datagram);
+            if header.next_header() != IpProtocol::Udp {
+                return None;
+            }
+            (IpAddress::Ipv6(header.src_addr()), ip_header_len)
+        }
+        _ => return None,
+    };
+    let udp = datagram.get(header_len..header_len + 2)?;
+    let port = u16::from_be_bytes([udp[0], udp[1]]);
+    Some((addr, port, error))
+}
+
+/// Hop limit of smoltcp when none is set
+const IP_DEFAULT_TTL: u8 = 64;
+
+const TCP_KEEPIDLE_DEFAULT: u32 = 2 * 60 * 60;
+const TCP_KEEPIDLE_MAX: u32 = 32767;
+
+/// Connections waiting to be accepted hold their buffers
+const TCP_BACKLOG_MAX: usize = 8;
+
 pub const TCP_SENDBUF: usize = 512 * 1024; // 512K
 pub const TCP_RECVBUF: usize = 512 * 1024; // 512K
 
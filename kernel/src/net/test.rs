@@ -5,7 +5,7 @@ use core::fmt::Write;
 use smoltcp::socket::*;
 
 pub extern "C" fn server(_arg: usize) -> ! {
-    if NET_DRIVERS.read().len() < 1 {
+    if NET_DRIVERS.read().iter().all(|driver| driver.is_loopback()) {
         loop {
             //thread::yield_now();
         }
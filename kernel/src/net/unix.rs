@@ -0,0 +1,760 @@
+//! Unix domain sockets, see unix(7)
+//!
+//! A socket is shared by all the file descriptors referring to it. Bound
+//! sockets are found by name: the inode of a socket file, or a name in the
+//! abstract namespace. The methods never block but fail with EAGAIN, and the
+//! event bus tells waiters when to try again.
+
+use super::{Endpoint, Socket, SocketOptions};
+use crate::fs::FileLike;
+use crate::process::{current_thread, Process};
+use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::syscall::{
+    sockopt_u32, sockopt_value, SocketType, SysError, SysResult, TimeVal, SHUT_RD, SHUT_RDWR,
+    SHUT_WR, SOL_SOCKET, SO_PASSCRED, SO_TIMESTAMP,
+};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cmp::min;
+use core::fmt;
+use core::mem::take;
+use core::time::Duration;
+use smoltcp::wire::Ipv4Address;
+
+/// Bytes a socket can queue for receiving
+pub const UNIX_BUFSIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnixAddr {
+    /// Not bound, or asking for an autobind
+    Unnamed,
+    /// A socket file, with its (dev, inode) once it is looked up
+    Path(String, Option<(usize, usize)>),
+    /// A name in the abstract namespace
+    Abstract(Vec<u8>),
+}
+
+/// What a bound socket is found by
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum UnixName {
+    Inode(usize, usize),
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    fn name(&self) -> Option<UnixName> {
+        match self {
+            UnixAddr::Unnamed => None,
+            UnixAddr::Path(_, key) => key.map(|(dev, inode)| UnixName::Inode(dev, inode)),
+            UnixAddr::Abstract(name) => Some(UnixName::Abstract(name.clone())),
+        }
+    }
+}
+
+lazy_static! {
+    /// Bound sockets by their names
+    static ref UNIX_NAMES: Mutex<BTreeMap<UnixName, Weak<UnixSocketInner>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Find the socket bound to `addr`
+fn lookup(addr: &UnixAddr) -> Result<Arc<UnixSocketInner>, SysError> {
+    let name = addr.name().ok_or(SysError::EINVAL)?;
+    UNIX_NAMES
+        .lock()
+        .get(&name)
+        .and_then(Weak::upgrade)
+        .ok_or(SysError::ECONNREFUSED)
+}
+
+/// Linux struct ucred
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UCred {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl UCred {
+    pub fn new(proc: &Process) -> Self {
+        UCred {
+            pid: proc.pid.get() as u32,
+            uid: proc.cred.euid as u32,
+            gid: proc.cred.egid as u32,
+        }
+    }
+
+    /// Credentials of the current process, which must not be locked
+    pub fn current() -> Self {
+        UCred::new(&current_thread().unwrap().proc.lock())
+    }
+}
+
+/// Ancillary data of a message
+#[derive(Debug, Default, Clone)]
+pub struct Ancillary {
+    /// Files passed by SCM_RIGHTS
+    pub rights: Vec<FileLike>,
+    /// Credentials passed by SCM_CREDENTIALS
+    pub cred: Option<UCred>,
+    /// When the message arrived, for SO_TIMESTAMP
+    pub timestamp: Option<TimeVal>,
+    /// Index of the interface an IPv4 datagram came in on, and the local
+    /// address it was sent to, for IP_PKTINFO
+    pub pktinfo: Option<(usize, Ipv4Address)>,
+}
+
+impl Ancillary {
+    pub fn is_empty(&self) -> bool {
+        self.rights.is_empty()
+            && self.cred.is_none()
+            && self.timestamp.is_none()
+            && self.pktinfo.is_none()
+    }
+}
+
+/// A message queued on the receiving socket
+struct UnixMessage {
+    data: Vec<u8>,
+    from: UnixAddr,
+    /// Told when the message is taken, as it may be waiting for room
+    sender: Weak<UnixSocketInner>,
+    rights: Vec<FileLike>,
+    cred: UCred,
+    time: TimeVal,
+}
+
+struct UnixState {
+    addr: UnixAddr,
+    /// The connected peer, or the default destination of a datagram socket
+    peer: Option<Weak<UnixSocketInner>>,
+    /// A stream or seqpacket socket has been connected, the peer may be gone
+    connected: bool,
+    listening: bool,
+    /// Connections waiting to be accepted
+    pending: VecDeque<Arc<UnixSocketInner>>,
+    recv: VecDeque<UnixMessage>,
+    /// Bytes in `recv`
+    recv_len: usize,
+    /// Nothing more will be received from the peer
+    eof: bool,
+    read_shutdown: bool,
+    write_shutdown: bool,
+    /// Receive credentials with messages
+    passcred: bool,
+    /// Receive the time of messages
+    timestamp: bool,
+    options: SocketOptions,
+}
+
+struct UnixSocketInner {
+    type_: SocketType,
+    /// Credentials of the creator, which SO_PEERCRED tells the peer
+    cred: UCred,
+    state: Mutex<UnixState>,
+    /// READABLE, WRITABLE and CLOSED follow `poll`
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+impl UnixSocketInner {
+    fn new(type_: SocketType, cred: UCred) -> Arc<Self> {
+        Arc::new(UnixSocketInner {
+            type_,
+            cred,
+            state: Mutex::new(UnixState {
+                addr: UnixAddr::Unnamed,
+                peer: None,
+                connected: false,
+                listening: false,
+                pending: VecDeque::new(),
+                recv: VecDeque::new(),
+                recv_len: 0,
+                eof: false,
+                read_shutdown: false,
+                write_shutdown: false,
+                passcred: false,
+                timestamp: false,
+                options: SocketOptions::default(),
+            }),
+            eventbus: EventBus::new(),
+        })
+    }
+
+    fn is_stream(&self) -> bool {
+        self.type_ == SocketType::Stream
+    }
+
+    fn is_datagram(&self) -> bool {
+        self.type_ == SocketType::Datagram
+    }
+
+    fn peer(&self) -> Option<Arc<UnixSocketInner>> {
+        self.state.lock().peer.as_ref()?.upgrade()
+    }
+
+    /// Whether the peer has shut down writing or gone
+    fn peer_closed(&self) -> bool {
+        let state = self.state.lock();
+        !self.is_datagram() && state.connected && state.eof
+    }
+
+    fn poll(&self) -> (bool, bool, bool) {
+        let state = self.state.lock();
+        let read = !state.recv.is_empty()
+            || (state.listening && !state.pending.is_empty())
+            || (!self.is_datagram() && state.eof)
+            || state.read_shutdown;
+        let connected = state.connected || self.is_datagram();
+        let write_shutdown = state.write_shutdown;
+        let peer = state.peer.clone();
+        drop(state);
+
+        let write = if !connected {
+            false
+        } else if write_shutdown {
+            // fails at once
+            true
+        } else {
+            match peer.map(|peer| peer.upgrade()) {
+                Some(Some(peer)) => peer.has_room(),
+                Some(None) => true,
+                // an unconnected datagram socket sends anywhere
+                None => true,
+            }
+        };
+        (read, write, false)
+    }
+
+    fn has_room(&self) -> bool {
+        let state = self.state.lock();
+        state.recv_len < UNIX_BUFSIZE || state.read_shutdown
+    }
+
+    /// Make the event bus follow the state
+    fn update(&self) {
+        let (read, write, _) = self.poll();
+        let mut events = Event::empty();
+        if read {
+            events |= Event::READABLE;
+        }
+        if write {
+            events |= Event::WRITABLE;
+        }
+        if self.peer_closed() {
+            events |= Event::CLOSED;
+        }
+        self.eventbus
+            .lock()
+            .change(Event::READABLE | Event::WRITABLE | Event::CLOSED, events);
+    }
+
+    fn bind(self: &Arc<Self>, addr: UnixAddr) -> SysResult {
+        let mut state = self.state.lock();
+        if state.addr != UnixAddr::Unnamed {
+            return Err(SysError::EINVAL);
+        }
+        let mut names = UNIX_NAMES.lock();
+        let addr = match addr {
+            UnixAddr::Unnamed => autobind_addr(&names),
+            addr => addr,
+        };
+        let name = addr.name().ok_or(SysError::EINVAL)?;
+        if names.get(&name).and_then(Weak::upgrade).is_some() {
+            return Err(SysError::EADDRINUSE);
+        }
+        names.insert(name, Arc::downgrade(self));
+        state.addr = addr;
+        Ok(0)
+    }
+
+    fn listen(self: &Arc<Self>) -> SysResult {
+        if self.is_datagram() {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        let state = self.state.lock();
+        if state.connected {
+            return Err(SysError::EINVAL);
+        }
+        let unnamed = state.addr == UnixAddr::Unnamed;
+        drop(state);
+        if unnamed {
+            self.bind(UnixAddr::Unnamed)?;
+        }
+        self.state.lock().listening = true;
+        Ok(0)
+    }
+
+    fn connect(self: &Arc<Self>, addr: &UnixAddr) -> SysResult {
+        let target = lookup(addr)?;
+        if target.type_ != self.type_ {
+            return Err(SysError::EPROTOTYPE);
+        }
+        if self.is_datagram() {
+            self.state.lock().peer = Some(Arc::downgrade(&target));
+            self.update();
+            return Ok(0);
+        }
+
+        let state = self.state.lock();
+        if state.connected {
+            return Err(SysError::EISCONN);
+        }
+        if state.listening {
+            return Err(SysError::EINVAL);
+        }
+        drop(state);
+
+        // the socket to be accepted, named after the listening one
+        let server = UnixSocketInner::new(self.type_, target.cred);
+        let mut target_state = target.state.lock();
+        if !target_state.listening {
+            return Err(SysError::ECONNREFUSED);
+        }
+        {
+            let mut server_state = server.state.lock();
+            server_state.addr = target_state.addr.clone();
+            server_state.peer = Some(Arc::downgrade(self));
+            server_state.connected = true;
+        }
+        let mut state = self.state.lock();
+        state.peer = Some(Arc::downgrade(&server));
+        state.connected = true;
+        drop(state);
+        target_state.pending.push_back(server);
+        drop(target_state);
+
+        target.update();
+        self.update();
+        Ok(0)
+    }
+
+    fn accept(&self) -> Result<(Arc<UnixSocketInner>, UnixAddr), SysError> {
+        let mut state = self.state.lock();
+        if !state.listening {
+            return Err(SysError::EINVAL);
+        }
+        let server = state.pending.pop_front().ok_or(SysError::EAGAIN)?;
+        drop(state);
+        self.update();
+        server.update();
+        let addr = match server.peer() {
+            Some(client) => client.state.lock().addr.clone(),
+            None => UnixAddr::Unnamed,
+        };
+        Ok((server, addr))
+    }
+
+    fn shutdown(&self, how: usize) -> SysResult {
+        let (read, write) = match how {
+            SHUT_RD => (true, false),
+            SHUT_WR => (false, true),
+            SHUT_RDWR => (true, true),
+            _ => return Err(SysError::EINVAL),
+        };
+        let mut state = self.state.lock();
+        if !self.is_datagram() && !state.connected {
+            return Err(SysError::ENOTCONN);
+        }
+        state.read_shutdown |= read;
+        state.write_shutdown |= write;
+        drop(state);
+        if let Some(peer) = self.peer() {
+            if write {
+                peer.state.lock().eof = true;
+            }
+            peer.update();
+        }
+        self.update();
+        Ok(0)
+    }
+
+    fn send(
+        self: &Arc<Self>,
+        data: &[u8],
+        to: Option<&UnixAddr>,
+        ancillary: Ancillary,
+    ) -> SysResult {
+        let state = self.state.lock();
+        if state.write_shutdown {
+            return Err(SysError::EPIPE);
+        }
+        let from = state.addr.clone();
+        let peer = state.peer.clone();
+        let connected = state.connected;
+        drop(state);
+
+        let target = if self.is_datagram() {
+            match (to, peer) {
+                (Some(addr), _) => lookup(addr)?,
+                (None, Some(peer)) => peer.upgrade().ok_or(SysError::ECONNREFUSED)?,
+                (None, None) => return Err(SysError::ENOTCONN),
+            }
+        } else if !connected {
+            return Err(SysError::ENOTCONN);
+        } else {
+            peer.and_then(|peer| peer.upgrade())
+                .ok_or(SysError::EPIPE)?
+        };
+        if self.is_datagram() {
+            // a connected socket only takes datagrams from its peer
+            if let Some(peer) = target.peer() {
+                if !Arc::ptr_eq(&peer, self) {
+                    return Err(SysError::EPERM);
+                }
+            }
+        }
+        if self.is_stream() && data.is_empty() {
+            return Ok(0);
+        }
+
+        let cred = match ancillary.cred {
+            Some(cred) => cred,
+            None => UCred::current(),
+        };
+        let mut target_state = target.state.lock();
+        if target_state.read_shutdown {
+            return Err(SysError::EPIPE);
+        }
+        let len = if self.is_stream() {
+            min(
+                data.len(),
+                UNIX_BUFSIZE - min(target_state.recv_len, UNIX_BUFSIZE),
+            )
+        } else if data.len() > UNIX_BUFSIZE {
+            return Err(SysError::EMSGSIZE);
+        } else if target_state.recv_len + data.len() > UNIX_BUFSIZE {
+            0
+        } else {
+            data.len()
+        };
+        if len == 0 && !data.is_empty() {
+            return Err(SysError::EAGAIN);
+        }
+        target_state.recv.push_back(UnixMessage {
+            data: data[..len].to_vec(),
+            from,
+            sender: Arc::downgrade(self),
+            rights: ancillary.rights,
+            cred,
+            time: TimeVal::get_epoch(),
+        });
+        target_state.recv_len += len;
+        drop(target_state);
+
+        // new data is a new edge for EPOLLET
+        target.eventbus.lock().clear(Event::READABLE);
+        target.update();
+        self.update();
+        Ok(len)
+    }
+
+    /// Take data into `data`, or copy it if `peek`
+    ///
+    /// The length of a datagram is its whole, whose rest has been discarded.
+    fn recv(&self, data: &mut [u8], peek: bool) -> Result<(usize, UnixAddr, Ancillary), SysError> {
+        let mut state = self.state.lock();
+        if state.recv.is_empty() {
+            if state.listening {
+                return Err(SysError::EINVAL);
+            }
+            if !self.is_datagram() && !state.connected {
+                return Err(SysError::ENOTCONN);
+            }
+            if state.read_shutdown || (!self.is_datagram() && state.eof) {
+                return Ok((0, UnixAddr::Unnamed, Ancillary::default()));
+            }
+            return Err(SysError::EAGAIN);
+        }
+
+        let passcred = state.passcred;
+        let timestamp = state.timestamp;
+        let mut ancillary = Ancillary::default();
+        let mut senders = Vec::new();
+        let mut from = UnixAddr::Unnamed;
+        let mut len = 0;
+        if self.is_stream() {
+            // messages read to the end
+            let mut finished = 0;
+            for message in state.recv.iter_mut() {
+                if len == data.len() {
+                    break;
+                }
+                // passed files are read along with their own data only
+                if len > 0 && !message.rights.is_empty() {
+                    break;
+                }
+                if len == 0 {
+                    from = message.from.clone();
+                    ancillary.rights = if peek {
+                        message.rights.clone()
+                    } else {
+                        take(&mut message.rights)
+                    };
+                    if passcred {
+                        ancillary.cred = Some(message.cred);
+                    }
+                    if timestamp {
+                        ancillary.timestamp = Some(message.time);
+                    }
+                }
+                let size = min(data.len() - len, message.data.len());
+                data[len..len + size].copy_from_slice(&message.data[..size]);
+                len += size;
+                if !peek {
+                    message.data.drain(..size);
+                    senders.push(message.sender.clone());
+                    if message.data.is_empty() {
+                        finished += 1;
+                    }
+                }
+            }
+            if !peek {
+                state.recv.drain(..finished);
+                state.recv_len -= len;
+            }
+        } else {
+            let message = state.recv.front_mut().unwrap();
+            len = message.data.len();
+            let size = min(data.len(), len);
+            data[..size].copy_from_slice(&message.data[..size]);
+            from = message.from.clone();
+            ancillary.rights = if peek {
+                message.rights.clone()
+            } else {
+                take(&mut message.rights)
+            };
+            if passcred {
+                ancillary.cred = Some(message.cred);
+            }
+            if timestamp {
+                ancillary.timestamp = Some(message.time);
+            }
+            if !peek {
+                // the rest of a message is discarded
+                let message = state.recv.pop_front().unwrap();
+                state.recv_len -= message.data.len();
+                senders.push(message.sender);
+            }
+        }
+        drop(state);
+
+        self.update();
+        for sender in senders {
+            if let Some(sender) = sender.upgrade() {
+                sender.update();
+            }
+        }
+        Ok((len, from, ancillary))
+    }
+}
+
+impl Drop for UnixSocketInner {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        let addr = state.addr.clone();
+        let peer = state.peer.take();
+        let pending = take(&mut state.pending);
+        let recv = take(&mut state.recv);
+        drop(state);
+
+        if let Some(name) = addr.name() {
+            let mut names = UNIX_NAMES.lock();
+            // the name may have been taken by another socket
+            if names.get(&name).map_or(false, |w| w.upgrade().is_none()) {
+                names.remove(&name);
+            }
+        }
+        if let Some(peer) = peer.and_then(|peer| peer.upgrade()) {
+            if !self.is_datagram() {
+                peer.state.lock().eof = true;
+            }
+            peer.update();
+        }
+        // passed files and unaccepted connections may drop sockets too
+        drop(pending);
+        drop(recv);
+    }
+}
+
+/// Pick an unused name of 5 hex digits in the abstract namespace
+fn autobind_addr(names: &BTreeMap<UnixName, Weak<UnixSocketInner>>) -> UnixAddr {
+    (0..0x100000usize)
+        .map(|i| UnixAddr::Abstract(format!("{:05x}", i).into_bytes()))
+        .find(|addr| {
+            names
+                .get(&addr.name().unwrap())
+                .and_then(Weak::upgrade)
+                .is_none()
+        })
+        .unwrap()
+}
+
+#[derive(Clone)]
+pub struct UnixSocketState {
+    inner: Arc<UnixSocketInner>,
+}
+
+impl UnixSocketState {
+    pub fn new(type_: SocketType, cred: UCred) -> Self {
+        UnixSocketState {
+            inner: UnixSocketInner::new(type_, cred),
+        }
+    }
+
+    /// A pair of connected sockets, for socketpair(2)
+    pub fn new_pair(type_: SocketType, cred: UCred) -> (Self, Self) {
+        let a = UnixSocketInner::new(type_, cred);
+        let b = UnixSocketInner::new(type_, cred);
+        for (this, that) in [(&a, &b), (&b, &a)].iter() {
+            let mut state = this.state.lock();
+            state.peer = Some(Arc::downgrade(that));
+            state.connected = true;
+        }
+        a.update();
+        b.update();
+        (UnixSocketState { inner: a }, UnixSocketState { inner: b })
+    }
+}
+
+impl fmt::Debug for UnixSocketState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UnixSocketState")
+            .field("type", &self.inner.type_)
+            .field("addr", &self.inner.state.lock().addr)
+            .finish()
+    }
+}
+
+/// The Unix address of an endpoint from a syscall
+fn unix_addr(endpoint: Endpoint) -> Result<UnixAddr, SysError> {
+    match endpoint {
+        Endpoint::Unix(addr) => Ok(addr),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+impl Socket for UnixSocketState {
+    fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        let (result, endpoint, _) = self.read_msg(data, false);
+        (result.map(|len| min(len, data.len())), endpoint)
+    }
+
+    fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
+        self.write_msg(data, sendto_endpoint, Ancillary::default())
+    }
+
+    fn read_msg(&self, data: &mut [u8], peek: bool) -> (SysResult, Endpoint, Ancillary) {
+        match self.inner.recv(data, peek) {
+            Ok((len, from, ancillary)) => (Ok(len), Endpoint::Unix(from), ancillary),
+            Err(err) => (
+                Err(err),
+                Endpoint::Unix(UnixAddr::Unnamed),
+                Ancillary::default(),
+            ),
+        }
+    }
+
+    fn write_msg(
+        &self,
+        data: &[u8],
+        sendto_endpoint: Option<Endpoint>,
+        ancillary: Ancillary,
+    ) -> SysResult {
+        let to = match sendto_endpoint {
+            Some(endpoint) => Some(unix_addr(endpoint)?),
+            None => None,
+        };
+        self.inner.send(data, to.as_ref(), ancillary)
+    }
+
+    fn poll(&self) -> (bool, bool, bool) {
+        self.inner.poll()
+    }
+
+    fn is_stream(&self) -> bool {
+        self.inner.is_stream()
+    }
+
+    fn peer_closed(&self) -> bool {
+        self.inner.peer_closed()
+    }
+
+    fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        self.inner.connect(&unix_addr(endpoint)?)
+    }
+
+    fn bind(&mut self, endpoint: Endpoint) -> SysResult {
+        self.inner.bind(unix_addr(endpoint)?)
+    }
+
+    fn listen(&mut self, _backlog: usize) -> SysResult {
+        self.inner.listen()
+    }
+
+    fn shutdown(&self, how: usize) -> SysResult {
+        self.inner.shutdown(how)
+    }
+
+    fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
+        let (inner, addr) = self.inner.accept()?;
+        Ok((Box::new(UnixSocketState { inner }), Endpoint::Unix(addr)))
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        Some(Endpoint::Unix(self.inner.state.lock().addr.clone()))
+    }
+
+    fn remote_endpoint(&self) -> Option<Endpoint> {
+        let peer = self.inner.peer()?;
+        let addr = peer.state.lock().addr.clone();
+        Some(Endpoint::Unix(addr))
+    }
+
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_PASSCRED) => {
+                self.inner.state.lock().passcred = sockopt_u32(data)? != 0;
+                Ok(0)
+            }
+            (SOL_SOCKET, SO_TIMESTAMP) => {
+                self.inner.state.lock().timestamp = sockopt_u32(data)? != 0;
+                Ok(0)
+            }
+            _ => self.inner.state.lock().options.setsockopt(level, opt, data),
+        }
+    }
+
+    fn getsockopt(&mut self, level: usize, opt: usize) -> Result<Vec<u8>, SysError> {
+        let state = self.inner.state.lock();
+        match (level, opt) {
+            (SOL_SOCKET, SO_PASSCRED) => Ok(sockopt_value(state.passcred as u32)),
+            (SOL_SOCKET, SO_TIMESTAMP) => Ok(sockopt_value(state.timestamp as u32)),
+            _ => state.options.getsockopt(level, opt),
+        }
+    }
+
+    fn timeout(&self, events: Event) -> Option<Duration> {
+        self.inner.state.lock().options.timeout(events)
+    }
+
+    fn peer_cred(&self) -> Option<UCred> {
+        Some(self.inner.peer()?.cred)
+    }
+
+    fn eventbus(&self) -> Option<Arc<Mutex<EventBus>>> {
+        Some(self.inner.eventbus.clone())
+    }
+
+    fn description(&self) -> Option<Arc<dyn Any + Send + Sync>> {
+        Some(self.inner.clone())
+    }
+
+    fn box_clone(&self) -> Box<dyn Socket> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,256 @@
+//! User and group identities of a process, see credentials(7)
+
+use alloc::vec::Vec;
+use bitflags::*;
+use rcore_fs::vfs::{FileType, Metadata};
+
+use crate::syscall::SysError;
+
+pub type Uid = usize;
+pub type Gid = usize;
+
+/// Passed as an id to setresuid(2) and friends to leave it unchanged
+pub const ID_UNCHANGED: usize = core::u32::MAX as usize;
+
+/// Max number of supplementary groups
+pub const NGROUPS_MAX: usize = 65536;
+
+// special mode bits
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+pub const S_ISVTX: u16 = 0o1000;
+
+bitflags! {
+    /// Access to a file, same as the mode of access(2)
+    pub struct Access: usize {
+        const EXEC = 1;
+        const WRITE = 2;
+        const READ = 4;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// Real user id
+    pub ruid: Uid,
+    /// Effective user id
+    pub euid: Uid,
+    /// Saved set-user-id
+    pub suid: Uid,
+    /// Real group id
+    pub rgid: Gid,
+    /// Effective group id
+    pub egid: Gid,
+    /// Saved set-group-id
+    pub sgid: Gid,
+    /// Supplementary groups
+    pub groups: Vec<Gid>,
+}
+
+impl Credentials {
+    pub fn root() -> Self {
+        Credentials {
+            ruid: 0,
+            euid: 0,
+            suid: 0,
+            rgid: 0,
+            egid: 0,
+            sgid: 0,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Whether the process is privileged, we have no capabilities
+    pub fn is_root(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Whether `gid` is the effective group or a supplementary group
+    pub fn in_group(&self, gid: Gid) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+
+    /// Credentials with effective ids replaced by real ids, used by access(2)
+    pub fn real(&self) -> Self {
+        Credentials {
+            euid: self.ruid,
+            egid: self.rgid,
+            ..self.clone()
+        }
+    }
+
+    /// Check `access` to a file with `metadata` by its mode bits
+    pub fn check_access(&self, metadata: &Metadata, access: Access) -> Result<(), SysError> {
+        if self.is_root() {
+            // root can execute a file only if anyone can
+            if access.contains(Access::EXEC)
+                && metadata.type_ != FileType::Dir
+                && metadata.mode & 0o111 == 0
+            {
+                return Err(SysError::EACCES);
+            }
+            return Ok(());
+        }
+        let perm = if self.euid == metadata.uid {
+            (metadata.mode >> 6) & 0o7
+        } else if self.in_group(metadata.gid) {
+            (metadata.mode >> 3) & 0o7
+        } else {
+            metadata.mode & 0o7
+        };
+        if Access::from_bits_truncate(perm as usize).contains(access) {
+            Ok(())
+        } else {
+            Err(SysError::EACCES)
+        }
+    }
+
+    /// Whether the process owns a file, or is privileged
+    pub fn is_owner(&self, metadata: &Metadata) -> bool {
+        self.is_root() || self.euid == metadata.uid
+    }
+
+    /// Check removing or renaming `file` in the directory `dir`
+    ///
+    /// In a sticky directory only owners of the file or the directory can do it.
+    pub fn check_delete(&self, dir: &Metadata, file: &Metadata) -> Result<(), SysError> {
+        self.check_access(dir, Access::WRITE | Access::EXEC)?;
+        if dir.mode & S_ISVTX != 0 && !self.is_owner(dir) && !self.is_owner(file) {
+            return Err(SysError::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Whether the process may set one of its user ids to `uid`
+    fn may_setuid(&self, uid: Uid) -> bool {
+        self.is_root() || uid == self.ruid || uid == self.euid || uid == self.suid
+    }
+
+    /// Whether the process may set one of its group ids to `gid`
+    fn may_setgid(&self, gid: Gid) -> bool {
+        self.is_root() || gid == self.rgid || gid == self.egid || gid == self.sgid
+    }
+
+    /// setuid(2): privileged processes set all user ids
+    pub fn setuid(&mut self, uid: Uid) -> Result<(), SysError> {
+        if self.is_root() {
+            self.ruid = uid;
+            self.suid = uid;
+        } else if uid != self.ruid && uid != self.suid {
+            return Err(SysError::EPERM);
+        }
+        self.euid = uid;
+        Ok(())
+    }
+
+    /// setgid(2): privileged processes set all group ids
+    pub fn setgid(&mut self, gid: Gid) -> Result<(), SysError> {
+        if self.is_root() {
+            self.rgid = gid;
+            self.sgid = gid;
+        } else if gid != self.rgid && gid != self.sgid {
+            return Err(SysError::EPERM);
+        }
+        self.egid = gid;
+        Ok(())
+    }
+
+    /// setreuid(2)
+    pub fn setreuid(&mut self, ruid: Uid, euid: Uid) -> Result<(), SysError> {
+        if ruid != ID_UNCHANGED && !self.is_root() && ruid != self.ruid && ruid != self.euid {
+            return Err(SysError::EPERM);
+        }
+        if euid != ID_UNCHANGED && !self.may_setuid(euid) {
+            return Err(SysError::EPERM);
+        }
+        let old_ruid = self.ruid;
+        if ruid != ID_UNCHANGED {
+            self.ruid = ruid;
+        }
+        if euid != ID_UNCHANGED {
+            self.euid = euid;
+        }
+        // the saved id follows the effective id when the real id is touched
+        // or the effective id differs from the old real id
+        if ruid != ID_UNCHANGED || (euid != ID_UNCHANGED && euid != old_ruid) {
+            self.suid = self.euid;
+        }
+        Ok(())
+    }
+
+    /// setregid(2)
+    pub fn setregid(&mut self, rgid: Gid, egid: Gid) -> Result<(), SysError> {
+        if rgid != ID_UNCHANGED && !self.is_root() && rgid != self.rgid && rgid != self.egid {
+            return Err(SysError::EPERM);
+        }
+        if egid != ID_UNCHANGED && !self.may_setgid(egid) {
+            return Err(SysError::EPERM);
+        }
+        let old_rgid = self.rgid;
+        if rgid != ID_UNCHANGED {
+            self.rgid = rgid;
+        }
+        if egid != ID_UNCHANGED {
+            self.egid = egid;
+        }
+        if rgid != ID_UNCHANGED || (egid != ID_UNCHANGED && egid != old_rgid) {
+            self.sgid = self.egid;
+        }
+        Ok(())
+    }
+
+    /// setresuid(2)
+    pub fn setresuid(&mut self, ruid: Uid, euid: Uid, suid: Uid) -> Result<(), SysError> {
+        let ids = [ruid, euid, suid];
+        if ids
+            .iter()
+            .any(|&id| id != ID_UNCHANGED && !self.may_setuid(id))
+        {
+            return Err(SysError::EPERM);
+        }
+        if ruid != ID_UNCHANGED {
+            self.ruid = ruid;
+        }
+        if euid != ID_UNCHANGED {
+            self.euid = euid;
+        }
+        if suid != ID_UNCHANGED {
+            self.suid = suid;
+        }
+        Ok(())
+    }
+
+    /// setresgid(2)
+    pub fn setresgid(&mut self, rgid: Gid, egid: Gid, sgid: Gid) -> Result<(), SysError> {
+        let ids = [rgid, egid, sgid];
+        if ids
+            .iter()
+            .any(|&id| id != ID_UNCHANGED && !self.may_setgid(id))
+        {
+            return Err(SysError::EPERM);
+        }
+        if rgid != ID_UNCHANGED {
+            self.rgid = rgid;
+        }
+        if egid != ID_UNCHANGED {
+            self.egid = egid;
+        }
+        if sgid != ID_UNCHANGED {
+            self.sgid = sgid;
+        }
+        Ok(())
+    }
+
+    /// Update ids on execve(2) of a file with `metadata`
+    pub fn exec(&mut self, metadata: &Metadata) {
+        if metadata.mode & S_ISUID != 0 {
+            self.euid = metadata.uid;
+        }
+        // without group execute permission, the bit means mandatory locking
+        if metadata.mode & S_ISGID != 0 && metadata.mode & 0o010 != 0 {
+            self.egid = metadata.gid;
+        }
+        self.suid = self.euid;
+        self.sgid = self.egid;
+    }
+}
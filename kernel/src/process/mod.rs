@@ -5,11 +5,12 @@ use crate::{
     memory::phys_to_virt,
     syscall::handle_syscall,
 };
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use log::*;
 use trapframe::UserContext;
 
 mod abi;
+pub mod cred;
 pub mod futex;
 pub mod proc;
 pub mod structs;
@@ -19,8 +20,11 @@ use crate::sync::SpinNoIrqLock as Mutex;
 use core::{
     future::Future,
     pin::Pin,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{fence, spin_loop_hint, Ordering},
     task::{Context, Poll},
 };
+pub use cred::*;
 pub use futex::*;
 pub use proc::*;
 pub use structs::*;
@@ -45,3 +49,52 @@ pub fn current_thread() -> Option<Arc<Thread>> {
     let cpu_id = cpu::id();
     unsafe { PROCESSORS[cpu_id].clone() }
 }
+
+/// Page table token of the thread running on each CPU, 0 if none
+static mut ACTIVE_TOKENS: [usize; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+/// Number of times each CPU set its active token
+static mut ACTIVE_SWITCHES: [usize; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// Record that this CPU runs the page table with `token` from now on, or none if 0.
+/// Must be called before switching to the page table, which flushes the TLB.
+fn set_active_token(token: usize) {
+    let cpu_id = cpu::id();
+    unsafe {
+        write_volatile(&mut ACTIVE_TOKENS[cpu_id], token);
+        write_volatile(
+            &mut ACTIVE_SWITCHES[cpu_id],
+            ACTIVE_SWITCHES[cpu_id].wrapping_add(1),
+        );
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// Wait until the CPUs other than this one running the page table with `token`
+/// have switched page tables, flushing the entries changed in it from their TLB.
+/// Call it after changing the entries, holding no lock.
+pub fn sync_tlb_elsewhere(token: usize) {
+    fence(Ordering::SeqCst);
+    let cpu_id = cpu::id();
+    let running: Vec<(usize, usize)> = (0..MAX_CPU_NUM)
+        .filter(|&id| id != cpu_id && unsafe { read_volatile(&ACTIVE_TOKENS[id]) } == token)
+        .map(|id| (id, unsafe { read_volatile(&ACTIVE_SWITCHES[id]) }))
+        .collect();
+    for (id, switches) in running {
+        // threads yield on timer interrupts at the latest
+        while unsafe {
+            read_volatile(&ACTIVE_TOKENS[id]) == token
+                && read_volatile(&ACTIVE_SWITCHES[id]) == switches
+        } {
+            spin_loop_hint();
+        }
+    }
+}
+
+/// Whether a CPU other than this one runs the page table with `token`,
+/// so that its TLB may still have entries changed in the page table.
+/// Ask after changing the entries.
+pub fn active_elsewhere(token: usize) -> bool {
+    fence(Ordering::SeqCst);
+    let cpu_id = cpu::id();
+    (0..MAX_CPU_NUM).any(|id| id != cpu_id && unsafe { read_volatile(&ACTIVE_TOKENS[id]) } == token)
+}
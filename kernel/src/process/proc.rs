@@ -1,6 +1,6 @@
 use super::{
     abi::{self, ProcInitInfo},
-    Futex, Tid,
+    Credentials, Futex, Tid,
 };
 use crate::arch::paging::*;
 use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
@@ -11,8 +11,11 @@ use crate::memory::{
 use crate::process::thread::THREADS;
 use crate::sync::{Event, EventBus, SpinLock, SpinNoIrqLock as Mutex};
 use crate::{
-    signal::{Siginfo, Signal, SignalAction, SignalStack, Sigset},
-    syscall::handle_syscall,
+    signal::{
+        send_signal, Siginfo, Signal, SignalAction, SignalActionFlags, SignalStack, Sigset,
+        CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED,
+    },
+    syscall::{handle_syscall, RLimit},
 };
 use alloc::{
     boxed::Box, collections::BTreeMap, collections::VecDeque, string::String, sync::Arc,
@@ -83,6 +86,21 @@ pub struct Process {
     /// Executable path
     pub exec_path: String,
 
+    /// Command line arguments of the executable
+    pub args: Vec<String>,
+
+    /// Start of the heap, right after the loaded ELF images
+    pub brk_start: usize,
+
+    /// Current program break
+    pub brk: usize,
+
+    /// Limit of the heap size, i.e. RLIMIT_DATA
+    pub data_limit: RLimit,
+
+    /// User and group ids
+    pub cred: Credentials,
+
     /// Futex
     pub futexes: BTreeMap<usize, Arc<Futex>>,
 
@@ -95,6 +113,9 @@ pub struct Process {
     //// Process group id
     pub pgid: Pgid,
 
+    /// Session id
+    pub sid: Pgid,
+
     /// Parent process
     /// Avoid deadlock, put pid out
     pub parent: (Pid, Weak<Mutex<Process>>),
@@ -109,9 +130,18 @@ pub struct Process {
     /// Events like exiting
     pub eventbus: Arc<Mutex<EventBus>>,
 
-    /// Exit code
+    /// Exit code, 128 + signo if killed by a signal
     pub exit_code: usize,
 
+    /// How the process exited, reported by wait4
+    pub exit_status: WaitStatus,
+
+    /// Stopped by a signal
+    pub stopped: bool,
+
+    /// Stop or continue not yet reported by wait4
+    pub status_change: Option<WaitStatus>,
+
     // delivered signals, tid specified thread, -1 stands for any thread
     // TODO: implement with doubly linked list, but how to do it in rust safely? [doggy]
     pub sig_queue: VecDeque<(Siginfo, isize)>,
@@ -154,6 +184,15 @@ pub fn process_group(pgid: Pgid) -> Vec<Arc<Mutex<Process>>> {
         .collect::<Vec<_>>()
 }
 
+/// Get the memory sets of all processes
+pub fn memory_sets() -> Vec<Arc<Mutex<MemorySet>>> {
+    PROCESSES
+        .read()
+        .values()
+        .map(|proc| proc.lock().vm.clone())
+        .collect()
+}
+
 /// Set pid and put itself to global process table.
 pub fn add_to_process_table(proc: Arc<Mutex<Process>>, pid: Pid) {
     let mut process_table = PROCESSES.write();
@@ -183,6 +222,11 @@ impl Process {
         fd
     }
 
+    /// Lowest address searched for mmap areas, above the room left for the heap
+    pub fn mmap_base(&self) -> usize {
+        self.brk_start + crate::consts::USER_HEAP_GAP
+    }
+
     /// Get futex by addr
     pub fn get_futex(&mut self, uaddr: usize) -> Arc<Futex> {
         if !self.futexes.contains_key(&uaddr) {
@@ -193,22 +237,39 @@ impl Process {
 
     /// Exit the process.
     /// Kill all threads and notify parent with the exit code.
-    pub fn exit(&mut self, exit_code: usize) {
-        // avoid some strange dead lock
-        // self.files.clear(); this does not work sometime, for unknown reason
-        // manually drop
-        let fds = self.files.iter().map(|(fd, _)| *fd).collect::<Vec<_>>();
-        for fd in fds.iter() {
-            let file = self.files.remove(fd).unwrap();
-            drop(file);
-        }
+    ///
+    /// Return the closed files, to be dropped once the process is unlocked.
+    #[must_use = "closing a pty master signals its session, which may include this process"]
+    pub fn exit(&mut self, exit_code: usize) -> Vec<FileLike> {
+        self.exit_with(exit_code, WaitStatus::Exited(exit_code as u8))
+    }
+
+    /// Exit the process killed by `signal`.
+    ///
+    /// Return the closed files, to be dropped once the process is unlocked.
+    #[must_use = "closing a pty master signals its session, which may include this process"]
+    pub fn exit_by_signal(&mut self, signal: Signal, core_dumped: bool) -> Vec<FileLike> {
+        self.exit_with(
+            signal as usize + 128,
+            WaitStatus::Signaled(signal, core_dumped),
+        )
+    }
+
+    fn exit_with(&mut self, exit_code: usize, exit_status: WaitStatus) -> Vec<FileLike> {
+        let files = core::mem::take(&mut self.files);
 
         // notify parent and fill exit code
-        self.eventbus.lock().set(Event::PROCESS_QUIT);
-        if let Some(parent) = self.parent.1.upgrade() {
-            parent.lock().eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
-        }
         self.exit_code = exit_code;
+        self.exit_status = exit_status;
+        self.stopped = false;
+        self.status_change = None;
+        self.eventbus.lock().set(Event::PROCESS_QUIT);
+        let (code, status) = match exit_status {
+            WaitStatus::Signaled(signal, true) => (CLD_DUMPED, signal as i32),
+            WaitStatus::Signaled(signal, false) => (CLD_KILLED, signal as i32),
+            _ => (CLD_EXITED, exit_code as i32),
+        };
+        self.notify_parent(code, status);
 
         // quit all threads
         // this must be after setting the value of subprocess, or the threads will be treated exit before actually exits
@@ -220,9 +281,70 @@ impl Process {
         self.threads.clear();
 
         info!("process {} exit with {}", self.pid.get(), exit_code);
+        files.into_iter().map(|(_, file)| file).collect()
     }
 
     pub fn exited(&self) -> bool {
         self.threads.is_empty()
     }
+
+    /// Stop the process by `signal`, until SIGCONT or SIGKILL arrives
+    pub fn stop(&mut self, signal: Signal) {
+        self.stopped = true;
+        self.status_change = Some(WaitStatus::Stopped(signal));
+        self.eventbus.lock().clear(Event::PROCESS_CONTINUE);
+        self.notify_parent(CLD_STOPPED, signal as i32);
+    }
+
+    /// Continue the stopped process
+    pub fn cont(&mut self) {
+        self.stopped = false;
+        self.status_change = Some(WaitStatus::Continued);
+        self.eventbus.lock().set(Event::PROCESS_CONTINUE);
+        self.notify_parent(CLD_CONTINUED, Signal::SIGCONT as i32);
+    }
+
+    /// Wake up parent in wait4 and send it SIGCHLD
+    fn notify_parent(&self, code: i32, status: i32) {
+        let parent = match self.parent.1.upgrade() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let sigchld = {
+            let parent = parent.lock();
+            parent.eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
+            let action = parent.dispositions[Signal::SIGCHLD as usize];
+            let flags = SignalActionFlags::from_bits_truncate(action.flags);
+            let stop_or_cont = code == CLD_STOPPED || code == CLD_CONTINUED;
+            !(stop_or_cont && flags.contains(SignalActionFlags::NOCLDSTOP))
+        };
+        if sigchld {
+            let info = Siginfo::child(code, self.pid.get(), self.cred.ruid, status);
+            send_signal(parent, -1, info);
+        }
+    }
+}
+
+/// A state change of process reported by wait4
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WaitStatus {
+    Exited(u8),
+    /// Killed by a signal, and whether core dumped
+    Signaled(Signal, bool),
+    Stopped(Signal),
+    Continued,
+}
+
+impl WaitStatus {
+    /// Encode as `wstatus` of wait4, see <sys/wait.h>
+    pub fn encode(self) -> i32 {
+        match self {
+            WaitStatus::Exited(code) => (code as i32) << 8,
+            WaitStatus::Signaled(signal, core_dumped) => {
+                signal as i32 | if core_dumped { 0x80 } else { 0 }
+            }
+            WaitStatus::Stopped(signal) => ((signal as i32) << 8) | 0x7f,
+            WaitStatus::Continued => 0xffff,
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use super::abi::{self, ProcInitInfo};
 use crate::arch::paging::*;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::fs::{page_cache, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
 use crate::ipc::SemProc;
 use crate::memory::{
     phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
@@ -63,12 +63,13 @@ pub trait ElfExt {
 
     /// Append current ELF file as interpreter into given memory set.
     /// This will insert the interpreter it a place which is "good enough" (since ld.so should be PIC).
+    /// Return the page aligned end address of the interpreter.
     fn append_as_interpreter(
         &self,
         inode: &Arc<dyn INode>,
         memory_set: &mut MemorySet,
         bias: usize,
-    );
+    ) -> usize;
 
     /// Get virtual address of PHDR section if it has.
     fn get_phdr_vaddr(&self) -> Option<u64>;
@@ -102,8 +103,14 @@ impl ElfExt for ElfFile<'_> {
 
         Page::of_addr(farthest_memory + PAGE_SIZE).start_address()
     }
-    fn append_as_interpreter(&self, inode: &Arc<dyn INode>, ms: &mut MemorySet, bias: usize) {
+    fn append_as_interpreter(
+        &self,
+        inode: &Arc<dyn INode>,
+        ms: &mut MemorySet,
+        bias: usize,
+    ) -> usize {
         debug!("inserting interpreter from ELF");
+        let mut farthest_memory: usize = bias;
 
         for ph in self.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
@@ -121,8 +128,13 @@ impl ElfExt for ElfFile<'_> {
                     allocator: GlobalFrameAlloc,
                 },
                 "elf-interp",
-            )
+            );
+            if ph.virtual_addr() as usize + ph.mem_size() as usize + bias > farthest_memory {
+                farthest_memory = ph.virtual_addr() as usize + ph.mem_size() as usize + bias;
+            }
         }
+
+        Page::of_addr(farthest_memory + PAGE_SIZE - 1).start_address()
     }
     fn get_interpreter(&self) -> Result<&str, &str> {
         let header = self
@@ -167,6 +179,6 @@ pub struct INodeForMap(pub Arc<dyn INode>);
 
 impl Read for INodeForMap {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        self.0.read_at(offset, buf).unwrap()
+        page_cache::read_at(&self.0, offset, buf).unwrap()
     }
 }
@@ -1,11 +1,12 @@
 use super::{
     abi::{self, ProcInitInfo},
-    add_to_process_table, Pid, Process, PROCESSORS,
+    add_to_process_table, set_active_token, sync_tlb_elsewhere, Credentials, Pid, Process,
+    WaitStatus, PROCESSORS,
 };
 use crate::arch::interrupt::consts::{
     is_intr, is_page_fault, is_reserved_inst, is_syscall, is_timer_intr,
 };
-use crate::arch::interrupt::{get_trap_num, handle_reserved_inst};
+use crate::arch::interrupt::{get_fault_signal, get_trap_num, handle_reserved_inst};
 use crate::arch::{
     cpu,
     fp::FpState,
@@ -13,16 +14,19 @@ use crate::arch::{
     paging::*,
 };
 use crate::drivers::IRQ_MANAGER;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::fs::{page_cache, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
 use crate::ipc::{SemProc, ShmProc};
 use crate::memory::{
     phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
 };
 use crate::process::structs::ElfExt;
-use crate::sync::{EventBus, SpinLock, SpinNoIrqLock as Mutex};
+use crate::sync::{wait_for_event, Event, EventBus, SpinLock, SpinNoIrqLock as Mutex};
 use crate::{
-    signal::{handle_signal, Siginfo, Signal, SignalAction, SignalStack, Sigset},
-    syscall::handle_syscall,
+    signal::{
+        handle_signal, send_fault_signal, Siginfo, Signal, SignalAction, SignalStack, Sigset,
+        BUS_ADRERR, SEGV_ACCERR, SEGV_MAPERR,
+    },
+    syscall::{handle_syscall, RLimit},
 };
 use alloc::{
     boxed::Box, collections::BTreeMap, collections::VecDeque, string::String, sync::Arc,
@@ -112,19 +116,17 @@ impl Thread {
     }
 
     /// Construct virtual memory of a new user process from ELF at `inode`.
-    /// Return `(MemorySet, entry_point, ustack_top)`
+    /// Return `(entry_point, ustack_top, brk_start)`
     pub fn new_user_vm(
         inode: &Arc<dyn INode>,
         args: Vec<String>,
         envs: Vec<String>,
         vm: &mut MemorySet,
-    ) -> Result<(usize, usize), &'static str> {
+    ) -> Result<(usize, usize, usize), &'static str> {
         // Read ELF header
         // 0x3c0: magic number from ld-musl.so
         let mut data = [0u8; 0x3c0];
-        inode
-            .read_at(0, &mut data)
-            .map_err(|_| "failed to read from INode")?;
+        page_cache::read_at(inode, 0, &mut data).map_err(|_| "failed to read from INode")?;
 
         // Parse ELF
         let elf = ElfFile::new(&data)?;
@@ -165,11 +167,14 @@ impl Thread {
         let mut entry_addr = elf.header.pt2.entry_point() as usize;
         // Make page table
         vm.clear();
-        let bias = elf.make_memory_set(vm, inode);
+        // program break starts after the executable
+        let brk_start = elf.make_memory_set(vm, inode);
 
         // Check interpreter (for dynamic link)
         // When interpreter is used, map both dynamic linker and executable
         if let Ok(loader_path) = elf.get_interpreter() {
+            // like mmap areas, keep the interpreter clear of the heap
+            let bias = brk_start + crate::consts::USER_HEAP_GAP;
             info!("Handling interpreter... offset={:x}", bias);
             // assuming absolute path
             let interp_inode = crate::fs::ROOT_INODE
@@ -177,8 +182,7 @@ impl Thread {
                 .map_err(|_| "interpreter not found")?;
             // load loader by bias and set aux vector.
             let mut interp_data: [u8; 0x3c0] = unsafe { MaybeUninit::zeroed().assume_init() };
-            interp_inode
-                .read_at(0, &mut interp_data)
+            page_cache::read_at(&interp_inode, 0, &mut interp_data)
                 .map_err(|_| "failed to read from INode")?;
             let elf_interp = ElfFile::new(&interp_data)?;
             elf_interp.append_as_interpreter(&interp_inode, vm, bias);
@@ -224,7 +228,7 @@ impl Thread {
             vm.with(|| ustack_top = init_info.push_at(ustack_top));
         }
 
-        Ok((entry_addr, ustack_top))
+        Ok((entry_addr, ustack_top, brk_start))
     }
 
     /// Make a new user process from ELF `data`
@@ -236,7 +240,8 @@ impl Thread {
     ) -> Arc<Thread> {
         // get virtual memory info
         let mut vm = MemorySet::new();
-        let (entry_addr, ustack_top) = Self::new_user_vm(inode, args, envs, &mut vm).unwrap();
+        let (entry_addr, ustack_top, brk_start) =
+            Self::new_user_vm(inode, args.clone(), envs, &mut vm).unwrap();
 
         let vm_token = vm.token();
         let vm = Arc::new(Mutex::new(vm));
@@ -336,14 +341,23 @@ impl Thread {
                 files,
                 cwd: String::from("/"),
                 exec_path: String::from(exec_path),
+                args,
+                brk_start,
+                brk: brk_start,
+                data_limit: RLimit::INFINITY,
+                cred: Credentials::root(),
                 futexes: BTreeMap::default(),
                 semaphores: SemProc::default(),
                 pid: Pid::new(), // allocated later
                 pgid: 0,
+                sid: 0,
                 parent: (Pid::new(), Weak::new()),
                 children: Vec::new(),
                 threads: Vec::new(),
                 exit_code: 0,
+                exit_status: WaitStatus::Exited(0),
+                stopped: false,
+                status_change: None,
                 pending_sigset: Sigset::empty(),
                 sig_queue: VecDeque::new(),
                 dispositions: [SignalAction::default(); Signal::RTMAX + 1],
@@ -362,9 +376,17 @@ impl Thread {
 
     /// Fork a new process from current one
     /// Only current process is persisted
-    pub fn fork(&self, tf: &UserContext) -> Arc<Thread> {
+    ///
+    /// Return None if swapped out memory can not be shared with the child.
+    pub fn fork(&self, tf: &UserContext) -> Option<Arc<Thread>> {
         // clone virtual memory
-        let vm = self.vm.lock().clone();
+        let (vm, parent_token) = {
+            let mut vm = self.vm.lock();
+            (vm.clone()?, vm.token())
+        };
+        // other threads of this process may still write to the pages now shared
+        // with the child, through their TLB entries made before the pages were write-protected
+        sync_tlb_elsewhere(parent_token);
         let vm_token = vm.token();
         let vm = Arc::new(Mutex::new(vm));
 
@@ -379,14 +401,23 @@ impl Thread {
             files: proc.files.clone(), // share open file descriptions
             cwd: proc.cwd.clone(),
             exec_path: proc.exec_path.clone(),
+            args: proc.args.clone(),
+            brk_start: proc.brk_start,
+            brk: proc.brk,
+            data_limit: proc.data_limit,
+            cred: proc.cred.clone(),
             futexes: BTreeMap::default(),
             semaphores: proc.semaphores.clone(),
             pid: Pid::new(), // assigned later
             pgid: proc.pgid,
+            sid: proc.sid,
             parent: (proc.pid.clone(), Arc::downgrade(&self.proc)),
             children: Vec::new(),
             threads: Vec::new(),
             exit_code: 0,
+            exit_status: WaitStatus::Exited(0),
+            stopped: false,
+            status_change: None,
             pending_sigset: Sigset::empty(),
             sig_queue: VecDeque::new(),
             dispositions: proc.dispositions.clone(),
@@ -426,7 +457,7 @@ impl Thread {
         proc.children
             .push((child_pid, Arc::downgrade(&new_thread.proc)));
 
-        new_thread
+        Some(new_thread)
     }
 
     /// Create a new thread in the same process.
@@ -516,7 +547,7 @@ pub fn spawn(thread: Arc<Thread>) {
                     let addr = get_page_fault_addr();
                     info!("page fault from user @ {:#x}", addr);
                     #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
-                    {
+                    let handled = {
                         use crate::arch::interrupt::consts::{
                             is_execute_page_fault, is_read_page_fault, is_write_page_fault,
                         };
@@ -533,18 +564,24 @@ pub fn spawn(thread: Arc<Thread>) {
                             }
                             _ => unreachable!(),
                         };
-                        if !handle_user_page_fault_ext(&thread, addr, access_type) {
-                            // TODO: SIGSEGV
-                            panic!("page fault handle failed");
-                        }
-                    }
+                        handle_user_page_fault_ext(&thread, addr, access_type)
+                    };
                     #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
-                    {
+                    let handled = {
                         use crate::arch::interrupt::handle_user_page_fault;
-                        if !handle_user_page_fault(&thread, addr) {
-                            // TODO: SIGSEGV
-                            panic!("page fault handle failed");
-                        }
+                        handle_user_page_fault(&thread, addr)
+                    };
+                    if !handled && thread.vm.lock().is_past_end(addr) {
+                        warn!("thread {} bus error @ {:#x}", thread.tid, addr);
+                        send_fault_signal(&thread, Signal::SIGBUS, BUS_ADRERR, addr);
+                    } else if !handled {
+                        let mapped = thread.vm.lock().iter().any(|area| area.contains(addr));
+                        let code = if mapped { SEGV_ACCERR } else { SEGV_MAPERR };
+                        warn!(
+                            "thread {} segmentation fault @ {:#x}, code {}",
+                            thread.tid, addr, code
+                        );
+                        send_fault_signal(&thread, Signal::SIGSEGV, code, addr);
                     }
                 }
                 _ if is_syscall(trap_num) => exit = handle_syscall(&thread, cx).await,
@@ -557,19 +594,14 @@ pub fn spawn(thread: Arc<Thread>) {
                     }
                     IRQ_MANAGER.read().try_handle_interrupt(Some(trap_num));
                 }
-                _ if is_reserved_inst(trap_num) => {
-                    if !handle_reserved_inst(cx) {
-                        panic!(
-                            "unhandled reserved intr in thread {} trap {:#x} {:x?}",
-                            thread.tid, trap_num, cx
-                        );
-                    }
-                }
+                _ if is_reserved_inst(trap_num) && handle_reserved_inst(cx) => {}
                 _ => {
-                    panic!(
-                        "unhandled trap in thread {} trap {:#x} {:x?}",
-                        thread.tid, trap_num, cx
+                    let (signal, code, addr) = get_fault_signal(trap_num, cx);
+                    warn!(
+                        "unhandled trap in thread {} trap {:#x}, send {:?} code {} addr {:#x}",
+                        thread.tid, trap_num, signal, code, addr
                     );
+                    send_fault_signal(&thread, signal, code, addr);
                 }
             }
 
@@ -578,6 +610,13 @@ pub fn spawn(thread: Arc<Thread>) {
                 exit = handle_signal(&thread, cx);
             }
 
+            // stay stopped until SIGCONT or SIGKILL
+            while !exit && thread.proc.lock().stopped {
+                let eventbus = thread.proc.lock().eventbus.clone();
+                wait_for_event(eventbus, Event::PROCESS_CONTINUE).await;
+                exit = handle_signal(&thread, cx);
+            }
+
             thread.end_running(thread_context);
             if exit {
                 info!("thread {} stopped", thread.tid);
@@ -620,11 +659,13 @@ impl Future for PageTableSwitchWrapper {
             PROCESSORS[cpu_id] = Some(self.thread.clone());
         }
         // vmtoken won't change
+        set_active_token(self.vmtoken);
         set_page_table(self.vmtoken);
         let res = self.inner.lock().as_mut().poll(cx);
         unsafe {
             PROCESSORS[cpu_id] = None;
         }
+        set_active_token(0);
         res
     }
 }
@@ -19,6 +19,34 @@ pub const SI_USER: i32 = 0;
 pub const SI_KERNEL: i32 = 128;
 /// from kernel
 
+// si_code for SIGILL
+pub const ILL_ILLOPC: i32 = 1;
+pub const ILL_ILLOPN: i32 = 2;
+pub const ILL_PRVOPC: i32 = 5;
+pub const ILL_COPROC: i32 = 7;
+// si_code for SIGFPE
+pub const FPE_INTDIV: i32 = 1;
+pub const FPE_INTOVF: i32 = 2;
+pub const FPE_FLTDIV: i32 = 3;
+pub const FPE_FLTINV: i32 = 7;
+// si_code for SIGSEGV
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+// si_code for SIGBUS
+pub const BUS_ADRALN: i32 = 1;
+pub const BUS_ADRERR: i32 = 2;
+pub const BUS_OBJERR: i32 = 3;
+// si_code for SIGTRAP
+pub const TRAP_BRKPT: i32 = 1;
+pub const TRAP_TRACE: i32 = 2;
+// si_code for SIGCHLD
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_TRAPPED: i32 = 4;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
 // yet there's a bug because of mismatching bits: https://sourceware.org/bugzilla/show_bug.cgi?id=25657
 // just support 64bits size sigset
 /// Linux struct sigset_t
@@ -74,9 +102,23 @@ impl Debug for SignalAction {
 #[derive(Copy, Clone)]
 pub union SiginfoFields {
     pad: [u8; Self::PAD_SIZE],
+    /// Faulting address for SIGILL, SIGFPE, SIGSEGV and SIGBUS
+    pub addr: usize,
+    pub sigchld: SiginfoSigchld,
     // TODO: fill this union
 }
 
+/// Fields of Siginfo for SIGCHLD
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SiginfoSigchld {
+    pub pid: i32,
+    pub uid: u32,
+    pub status: i32,
+    pub utime: isize,
+    pub stime: isize,
+}
+
 impl SiginfoFields {
     const PAD_SIZE: usize = 128 - 2 * core::mem::size_of::<i32>() - core::mem::size_of::<usize>();
 }
@@ -98,6 +140,38 @@ pub struct Siginfo {
     pub field: SiginfoFields,
 }
 
+impl Siginfo {
+    /// Siginfo of a hardware fault at `addr`
+    pub fn fault(signal: Signal, code: i32, addr: usize) -> Self {
+        let mut field = SiginfoFields::default();
+        field.addr = addr;
+        Siginfo {
+            signo: signal as i32,
+            errno: 0,
+            code,
+            field,
+        }
+    }
+
+    /// Siginfo of SIGCHLD for a state change of child `pid`, whose real user
+    /// is `uid`
+    pub fn child(code: i32, pid: usize, uid: usize, status: i32) -> Self {
+        let mut field = SiginfoFields::default();
+        field.sigchld = SiginfoSigchld {
+            pid: pid as i32,
+            uid: uid as u32,
+            status,
+            ..SiginfoSigchld::default()
+        };
+        Siginfo {
+            signo: Signal::SIGCHLD as i32,
+            errno: 0,
+            code,
+            field,
+        }
+    }
+}
+
 bitflags! {
     pub struct SignalActionFlags : usize {
         const NOCLDSTOP = 1;
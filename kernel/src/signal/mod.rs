@@ -89,24 +89,104 @@ impl Signal {
     pub fn is_standard(self) -> bool {
         (self as usize) < Self::RTMIN
     }
+
+    /// Default action when the disposition is SIG_DFL, see signal(7)
+    pub fn default_action(self) -> DefaultAction {
+        use self::Signal::*;
+        match self {
+            SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU
+            | SIGXFSZ | SIGSYS => DefaultAction::Core,
+            SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ign,
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+            SIGCONT => DefaultAction::Cont,
+            _ => DefaultAction::Term,
+        }
+    }
+
+    /// Whether the signal stops a process
+    pub fn is_stop(self) -> bool {
+        self.default_action() == DefaultAction::Stop
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum DefaultAction {
+    /// Terminate the process
+    Term,
+    /// Terminate the process and dump core
+    Core,
+    /// Ignore the signal
+    Ign,
+    /// Stop the process
+    Stop,
+    /// Continue the process if it is stopped
+    Cont,
 }
 
 // process and tid must be checked
 pub fn send_signal(process: Arc<Mutex<Process>>, tid: isize, info: Siginfo) {
     let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
     let mut process = process.lock();
+    if signal == Signal::SIGCONT {
+        // SIGCONT discards pending stop signals and resumes the process at once,
+        // whatever its disposition is
+        discard_signals(&mut process, Signal::is_stop);
+        if process.stopped {
+            process.cont();
+        }
+    } else if signal.is_stop() {
+        discard_signals(&mut process, |signal| signal == Signal::SIGCONT);
+    } else if signal == Signal::SIGKILL {
+        // a stopped process must wake up to die
+        process.stopped = false;
+        process.eventbus.lock().set(Event::PROCESS_CONTINUE);
+    }
     if signal.is_standard() && process.pending_sigset.contains(signal) {
         return;
     }
     process.sig_queue.push_back((info, tid));
     process.pending_sigset.add(signal);
-    process.eventbus.lock().set(Event::RECEIVE_SIGNAL);
+    // toggle the event to wake up sleepers and signalfd readers every time
+    let mut eventbus = process.eventbus.lock();
+    eventbus.set(Event::RECEIVE_SIGNAL);
+    eventbus.clear(Event::RECEIVE_SIGNAL);
+    drop(eventbus);
     info!(
         "send signal {} to pid {} tid {}",
         info.signo, process.pid, tid
     )
 }
 
+/// Remove pending signals of `process` matching `filter`
+fn discard_signals(process: &mut Process, filter: impl Fn(Signal) -> bool) {
+    let pending = &mut process.pending_sigset;
+    process.sig_queue.retain(|(info, _)| {
+        let signal: Signal = FromPrimitive::from_i32(info.signo).unwrap();
+        if filter(signal) {
+            pending.remove(signal);
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Send a signal raised by a fault of `thread`
+///
+/// Like Linux force_sig_fault, the signal can be neither blocked nor ignored.
+pub fn send_fault_signal(thread: &Arc<Thread>, signal: Signal, code: i32, addr: usize) {
+    thread.inner.lock().sig_mask.remove(signal);
+    {
+        let mut process = thread.proc.lock();
+        let action = &mut process.dispositions[signal as usize];
+        if action.handler == SIG_IGN {
+            *action = SignalAction::default();
+        }
+    }
+    let info = Siginfo::fault(signal, code, addr);
+    send_signal(thread.proc.clone(), thread.tid as isize, info);
+}
+
 /// See musl struct __ucontext
 /// Not exactly the same for now
 #[repr(C)]
@@ -137,13 +217,12 @@ pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
             .iter()
             .enumerate()
             .find_map(|(idx, &(info, tid))| {
-                if (tid == -1 || tid as usize == thread.tid)
-                    && !thread
-                        .inner
-                        .lock()
-                        .sig_mask
-                        .contains(FromPrimitive::from_i32(info.signo).unwrap())
-                {
+                let signal: Signal = FromPrimitive::from_i32(info.signo).unwrap();
+                // SIGKILL and SIGSTOP can not be blocked
+                let blocked = signal != Signal::SIGKILL
+                    && signal != Signal::SIGSTOP
+                    && thread.inner.lock().sig_mask.contains(signal);
+                if (tid == -1 || tid as usize == thread.tid) && !blocked {
                     Some((idx, info))
                 } else {
                     None
@@ -167,26 +246,42 @@ pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
 
         // enter signal handler
         match action.handler {
-            // TODO: complete default actions
-            x if x == SIG_DFL => {
-                match signal {
-                    SIGALRM | SIGHUP | SIGINT => {
-                        info!("default action: Term");
-                        // TODO: exit code ref please?
-                        process.exit(info.signo as usize + 128);
-                        return true;
-                    }
-                    _ => (),
+            x if x == SIG_DFL || x == SIG_ERR => match signal.default_action() {
+                DefaultAction::Term => {
+                    info!("default action: Term");
+                    let closed = process.exit_by_signal(signal, false);
+                    drop(process);
+                    drop(closed);
+                    return true;
                 }
-            }
+                DefaultAction::Core => {
+                    info!("default action: Core");
+                    error!(
+                        "process {} thread {} killed by {:?}, code {}, addr {:#x} (core dumped)",
+                        process.pid,
+                        thread.tid,
+                        signal,
+                        info.code,
+                        unsafe { info.field.addr },
+                    );
+                    error!("{:#x?}", tf);
+                    let closed = process.exit_by_signal(signal, true);
+                    drop(process);
+                    drop(closed);
+                    return true;
+                }
+                DefaultAction::Stop => {
+                    info!("default action: Stop");
+                    process.stop(signal);
+                    // the caller waits until SIGCONT or SIGKILL
+                    return false;
+                }
+                // process has been continued in `send_signal`
+                DefaultAction::Ign | DefaultAction::Cont => info!("default action: Ign"),
+            },
             x if x == SIG_IGN => {
-                // TODO: handle SIGCHLD
                 info!("ignore");
             }
-            x if x == SIG_ERR => {
-                // TODO
-                unimplemented!();
-            }
             _ => {
                 info!("goto handler at {:#x}", action.handler);
 
@@ -230,15 +325,24 @@ pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
                     }
                 } - core::mem::size_of::<SignalFrame>();
 
-                let frame = if let Ok(frame) = unsafe {
+                let frame = unsafe {
                     process
                         .vm
                         .lock()
                         .check_write_ptr(sig_sp as *mut SignalFrame)
-                } {
+                };
+                let frame = if let Ok(frame) = frame {
                     frame
                 } else {
-                    unimplemented!()
+                    // no room for the signal frame, like a stack overflow in SIGSEGV handler
+                    error!(
+                        "process {} thread {} failed to deliver {:?} at sp {:#x}, killed by SIGSEGV",
+                        process.pid, thread.tid, signal, sig_sp
+                    );
+                    let closed = process.exit_by_signal(SIGSEGV, true);
+                    drop(process);
+                    drop(closed);
+                    return true;
                 };
                 frame.info = info;
                 frame.ucontext = SignalUserContext {
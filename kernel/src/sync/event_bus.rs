@@ -21,6 +21,7 @@ bitflags! {
         const PROCESS_QUIT                  = 1 << 10;
         const CHILD_PROCESS_QUIT            = 1 << 11;
         const RECEIVE_SIGNAL                = 1 << 12;
+        const PROCESS_CONTINUE              = 1 << 13;
 
         /// Semaphore
         const SEMAPHORE_REMOVED             = 1 << 20;
@@ -99,3 +100,35 @@ impl Future for EventBusFuture {
         Poll::Pending
     }
 }
+
+/// Wait until `condition` holds, checking it again whenever one of `buses` changes
+pub fn wait_for_condition<F: Fn() -> bool>(
+    buses: Vec<Arc<Mutex<EventBus>>>,
+    condition: F,
+) -> impl Future<Output = ()> {
+    ConditionFuture { buses, condition }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct ConditionFuture<F> {
+    buses: Vec<Arc<Mutex<EventBus>>>,
+    condition: F,
+}
+
+impl<F: Fn() -> bool> Future for ConditionFuture<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if (self.condition)() {
+            return Poll::Ready(());
+        }
+        for bus in self.buses.iter() {
+            let waker = cx.waker().clone();
+            bus.lock().subscribe(Box::new(move |_| {
+                waker.wake_by_ref();
+                true
+            }));
+        }
+        Poll::Pending
+    }
+}
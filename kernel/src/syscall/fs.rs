@@ -7,7 +7,9 @@ use core::cmp::min;
 use core::mem::size_of;
 #[cfg(not(target_arch = "mips"))]
 use rcore_fs::vfs::Timespec;
+use rcore_memory::PAGE_SIZE;
 
+use crate::arch::timer::timer_now;
 use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::*;
 use crate::memory::MemorySet;
@@ -17,16 +19,17 @@ use alloc::boxed::Box;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
+use core::time::Duration;
 
 use bitvec::prelude::{BitSlice, BitVec, Lsb0};
 
 use super::*;
-use crate::fs::epoll::EpollInstance;
-use crate::fs::fcntl::{FD_CLOEXEC, F_SETFD, O_CLOEXEC, O_NONBLOCK};
+use crate::fs::epoll::{EPollCtlOp, EpollInstance};
+use crate::fs::eventfd::{EventFd, EventFdFlags};
+use crate::fs::fcntl::{FD_CLOEXEC, F_SETFD, F_SETFL, O_CLOEXEC, O_NONBLOCK};
 use crate::fs::FileLike;
 use crate::process::Process;
 use crate::syscall::SysError::{EINTR, EINVAL, ESPIPE};
-use rcore_fs::vfs::PollStatus;
 
 impl Syscall<'_> {
     pub async fn sys_read(&mut self, fd: usize, base: UserOutPtr<u8>, len: usize) -> SysResult {
@@ -37,20 +40,25 @@ impl Syscall<'_> {
         }
         let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
 
-        let file_like = proc.get_file_like(fd)?;
+        // release the process so that procfs can inspect it while reading
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let len = file_like.read(slice).await?;
         Ok(len)
     }
 
-    pub fn sys_write(&mut self, fd: usize, base: *const u8, len: usize) -> SysResult {
+    pub async fn sys_write(&mut self, fd: usize, base: UserInPtr<u8>, len: usize) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             //we trust pid 0 process
             info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
         }
-        let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(slice)?;
+        let slice = unsafe { self.vm().check_read_array(base.ptr(), len)? };
+
+        // release the process so that the tty can check job control
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let len = file_like.write(slice).await?;
         Ok(len)
     }
 
@@ -67,7 +75,9 @@ impl Syscall<'_> {
         );
         let mut proc = self.process();
         let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
-        let len = proc.get_file(fd)?.read_at(offset, slice).await?;
+        let file = proc.get_file(fd)?.clone();
+        drop(proc);
+        let len = file.read_at(offset, slice).await?;
         Ok(len)
     }
 
@@ -84,7 +94,9 @@ impl Syscall<'_> {
         );
         let mut proc = self.process();
         let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let len = proc.get_file(fd)?.write_at(offset, slice)?;
+        let file = proc.get_file(fd)?.clone();
+        drop(proc);
+        let len = file.write_at(offset, slice)?;
         Ok(len)
     }
 
@@ -152,12 +164,19 @@ impl Syscall<'_> {
             fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
                 use PollEvents as PE;
                 let proc = self.syscall.process();
+                // release the process so that signalfd can inspect it
+                let files: Vec<_> = self
+                    .polls
+                    .iter()
+                    .map(|poll| proc.files.get(&(poll.fd as usize)).cloned())
+                    .collect();
+                drop(proc);
                 let mut events = 0;
 
                 // iterate each poll to check whether it is ready
-                for poll in self.as_mut().polls.iter_mut() {
+                for (poll, file_like) in self.as_mut().polls.iter_mut().zip(files.iter()) {
                     poll.revents = PE::empty();
-                    if let Some(file_like) = proc.files.get(&(poll.fd as usize)) {
+                    if let Some(file_like) = file_like {
                         let mut fut = Box::pin(file_like.async_poll());
                         let status = match fut.as_mut().poll(cx) {
                             Poll::Ready(Ok(ret)) => ret,
@@ -181,7 +200,6 @@ impl Syscall<'_> {
                         events += 1;
                     }
                 }
-                drop(proc);
 
                 // some event happens, so evoke the process
                 if events > 0 {
@@ -251,14 +269,19 @@ impl Syscall<'_> {
         let begin_time_ms = crate::trap::uptime_msec();
         Condvar::wait_events(condvars.as_slice(), move || {
             let proc = self.process();
+            // release the process so that signalfd can inspect it
+            let files: Vec<_> = proc
+                .files
+                .iter()
+                .filter(|(&fd, _)| {
+                    err_fds.contains(fd) || read_fds.contains(fd) || write_fds.contains(fd)
+                })
+                .map(|(&fd, file_like)| (fd, file_like.clone()))
+                .collect();
+            drop(proc);
             let mut events = 0;
-            for (&fd, file_like) in proc.files.iter() {
-                //                if fd >= nfds {
-                //                    continue;
-                //                }
-                if !err_fds.contains(fd) && !read_fds.contains(fd) && !write_fds.contains(fd) {
-                    continue;
-                }
+            for (fd, file_like) in files.iter() {
+                let fd = *fd;
                 let status = match file_like.poll() {
                     Ok(ret) => ret,
                     Err(err) => return Some(Err(err)),
@@ -276,7 +299,6 @@ impl Syscall<'_> {
                     events += 1;
                 }
             }
-            drop(proc);
 
             if events > 0 {
                 return Some(Ok(events));
@@ -309,12 +331,23 @@ impl Syscall<'_> {
 
     pub fn sys_epoll_create1(&mut self, flags: usize) -> SysResult {
         info!("epoll_create1: flags: {:?}", flags);
+        if flags & !O_CLOEXEC != 0 {
+            return Err(SysError::EINVAL);
+        }
         let mut proc = self.process();
         let epoll_instance = EpollInstance::new(flags);
         let fd = proc.add_file(FileLike::EpollInstance(epoll_instance));
         Ok(fd)
     }
 
+    pub fn sys_eventfd2(&mut self, initval: usize, flags: usize) -> SysResult {
+        info!("eventfd2: initval: {}, flags: {:#x}", initval, flags);
+        let flags = EventFdFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        let eventfd = EventFd::new(initval as u32 as u64, flags);
+        let fd = self.process().add_file(FileLike::EventFd(eventfd));
+        Ok(fd)
+    }
+
     pub fn sys_epoll_ctl(
         &mut self,
         epfd: usize,
@@ -327,204 +360,55 @@ impl Syscall<'_> {
             // we trust pid 0 process
             info!("sys_epoll_ctl: epfd: {}, op: {:?}, fd: {:#x}", epfd, op, fd);
         }
+        let epoll_instance = proc.get_epoll_instance(epfd)?;
+        let file_like = proc.get_file_like(fd)?.clone();
+        // release the process so that a signalfd can be registered
+        drop(proc);
 
-        let _event = unsafe { self.vm().check_read_ptr(event)? };
-
-        if proc.files.get(&fd).is_none() {
-            return Err(SysError::EPERM);
-        }
-
-        let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-            Ok(ins) => ins,
-            Err(err) => {
-                return Err(err);
-            }
+        // the event is ignored by EPOLL_CTL_DEL
+        let event = if op as i32 == EPollCtlOp::DEL {
+            EpollEvent::default()
+        } else {
+            unsafe { *self.vm().check_read_ptr(event)? }
         };
-
-        let ret = epoll_instance.control(op, fd, &_event)?;
-        return Ok(ret);
+        epoll_instance.control(op, fd, &file_like, &event)
     }
 
-    pub fn sys_epoll_wait(
+    pub async fn sys_epoll_wait(
         &mut self,
         epfd: usize,
-        events: *mut EpollEvent,
+        events: UserOutPtr<EpollEvent>,
         maxevents: usize,
         timeout: usize,
     ) -> SysResult {
         self.sys_epoll_pwait(epfd, events, maxevents, timeout, 0)
+            .await
     }
 
-    pub fn sys_epoll_pwait(
+    pub async fn sys_epoll_pwait(
         &mut self,
         epfd: usize,
-        events: *mut EpollEvent,
+        events: UserOutPtr<EpollEvent>,
         maxevents: usize,
         timeout_msecs: usize,
         _sigset_t: usize,
     ) -> SysResult {
         info!("epoll_pwait: epfd: {}, timeout: {:?}", epfd, timeout_msecs);
-
-        let proc = self.process();
-        let events = unsafe { self.vm().check_write_array(events, maxevents)? };
-        let epoll_instance = proc.get_epoll_instance(epfd)?;
-
-        // add new fds which are registered by epoll_ctl after latest epoll_pwait
-        epoll_instance.ready_list.lock().clear();
-        epoll_instance
-            .ready_list
-            .lock()
-            .extend(epoll_instance.new_ctl_list.lock().clone());
-        epoll_instance.new_ctl_list.lock().clear();
-
-        // if registered fd has data to handle and its mode isn't epollet, we need
-        // to add it to the list.
-        let keys: Vec<_> = epoll_instance.events.keys().cloned().collect();
-        for (k, v) in epoll_instance.events.iter() {
-            if !v.contains(EpollEvent::EPOLLET) {
-                match &proc.files.get(k) {
-                    None => {
-                        //      return Err(SysError::EINVAL);
-                    }
-                    Some(file_like) => {
-                        let status = file_like.poll()?;
-                        if status.write || status.read || status.error {
-                            let mut ready_list = epoll_instance.ready_list.lock();
-                            ready_list.insert(*k);
-                        }
-                    }
-                }
-            }
-        }
-        drop(proc);
-
-        let mut callbacks = alloc::vec![];
-        for fd in &keys {
-            let proc = self.process();
-            match proc.files.get(&fd) {
-                Some(file_like) => {
-                    match file_like {
-                        FileLike::File(_file) => {
-                            callbacks.push((
-                                0, 0, // thread::current().id(),
-                                epfd, *fd,
-                            ));
-                        }
-                        FileLike::Socket(_socket) => {
-                            &(*crate::drivers::SOCKET_ACTIVITY).register_epoll_list(
-                                self.thread.proc.clone(),
-                                0,
-                                //thread::current().id(),
-                                epfd,
-                                *fd,
-                            );
-                            callbacks.push((
-                                1, 0, //thread::current().id(),
-                                epfd, *fd,
-                            ));
-                        }
-                        FileLike::EpollInstance(_) => {
-                            return Err(SysError::EINVAL);
-                        }
-                    };
-                }
-                None => {}
-            }
-            drop(proc);
+        if maxevents as i32 <= 0 {
+            return Err(SysError::EINVAL);
         }
+        let events = unsafe { self.vm().check_write_array(events.ptr(), maxevents)? };
+        let epoll_instance = self.process().get_epoll_instance(epfd)?;
 
-        let condvars = alloc::vec![&(*TICK_ACTIVITY), &(*SOCKET_ACTIVITY)];
-
-        let begin_time_ms = crate::trap::uptime_msec();
-        let condition = move || {
-            let mut proc = self.process();
-
-            let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-                Ok(ins) => ins,
-                Err(err) => {
-                    return Some(Err(err));
-                }
-            };
-            let ready_list = epoll_instance.ready_list.lock().clone();
-            let mut events_num = 0;
-
-            for infd in ready_list.iter() {
-                let mut status: PollStatus = Default::default();
-                {
-                    if let Some(file_like) = proc.files.get(&infd) {
-                        let _status = match file_like.poll() {
-                            Ok(ret) => ret,
-                            Err(err) => return Some(Err(err)),
-                        };
-                        status.write = _status.write;
-                        status.read = _status.read;
-                        status.error = _status.error;
-                    }
-                }
-
-                {
-                    let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-                        Ok(ins) => ins,
-                        Err(err) => {
-                            return Some(Err(err));
-                        }
-                    };
-                    let epollevent = epoll_instance.events.get_mut(&infd)?;
-
-                    if status.error {
-                        events[events_num].events = EpollEvent::EPOLLERR;
-                        events[events_num].data = epollevent.data;
-
-                        events_num += 1;
-                    }
-                    if status.read && epollevent.contains(EpollEvent::EPOLLIN) {
-                        events[events_num].events = EpollEvent::EPOLLIN;
-                        events[events_num].data = epollevent.data;
-                        events_num += 1;
-                    }
-                    if status.write && epollevent.contains(EpollEvent::EPOLLOUT) {
-                        events[events_num].events = EpollEvent::EPOLLOUT;
-                        events[events_num].data = epollevent.data;
-
-                        events_num += 1;
-                    }
-                }
-            }
-
-            {
-                let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-                    Ok(ins) => ins,
-                    Err(err) => {
-                        return Some(Err(err));
-                    }
-                };
-                epoll_instance.ready_list.lock().clear();
-            }
-
-            drop(proc);
-
-            // some event happens, so evoke the process
-            if events_num > 0 {
-                return Some(Ok(events_num));
-            }
-
-            let current_time_ms = crate::trap::uptime_msec();
-            // time runs out, so the evoke the process
-            if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
-                return Some(Ok(0));
-            }
-            return None;
+        // a negative timeout is infinity
+        let deadline = if (timeout_msecs as i32) < 0 {
+            None
+        } else {
+            Some(timer_now() + Duration::from_millis(timeout_msecs as u64))
         };
-
-        let num = Condvar::wait_events(condvars.as_slice(), condition).unwrap();
-
-        for cb in callbacks.iter() {
-            match cb.0 {
-                1 => &(*crate::drivers::SOCKET_ACTIVITY).unregister_epoll_list(cb.1, cb.2, cb.3),
-                _ => panic!("cb error"),
-            };
-        }
-        Ok(num)
+        let ready = epoll_instance.wait(maxevents, deadline).await?;
+        events[..ready.len()].copy_from_slice(&ready);
+        Ok(ready.len())
     }
 
     pub async fn sys_readv(
@@ -542,7 +426,8 @@ impl Syscall<'_> {
             unsafe { IoVecs::check_and_new(iov_ptr.ptr(), iov_count, &self.vm(), true)? };
 
         // read all data to a buf
-        let file_like = proc.get_file_like(fd)?;
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let mut buf = iovs.new_buf(true);
         let len = file_like.read(buf.as_mut_slice()).await?;
         // copy data to user
@@ -550,7 +435,12 @@ impl Syscall<'_> {
         Ok(len)
     }
 
-    pub fn sys_writev(&mut self, fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
+    pub async fn sys_writev(
+        &mut self,
+        fd: usize,
+        iov_ptr: UserInPtr<IoVec>,
+        iov_count: usize,
+    ) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
@@ -564,11 +454,12 @@ impl Syscall<'_> {
             //     fd, iov_ptr, iov_count
             // );
         }
-        let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), false)? };
+        let iovs = unsafe { IoVecs::check_and_new(iov_ptr.ptr(), iov_count, &self.vm(), false)? };
 
         let buf = iovs.read_all_to_vec();
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(buf.as_slice())?;
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let len = file_like.write(buf.as_slice()).await?;
         Ok(len)
     }
 
@@ -600,15 +491,16 @@ impl Syscall<'_> {
                     if flags.contains(OpenFlags::EXCLUSIVE) {
                         return Err(SysError::EEXIST);
                     }
-                    if flags.contains(OpenFlags::TRUNCATE) {
-                        if let Err(e) = file_inode.resize(0) {
-                            // TODO: do something? what about device file?
-                        }
-                    }
+                    check_open_access(&proc.cred, &file_inode, flags)?;
                     file_inode
                 }
                 Err(FsError::EntryNotFound) => {
+                    mount::check_writable(&dir_inode)?;
+                    // the new file is not checked against its own mode
+                    proc.cred
+                        .check_access(&dir_inode.metadata()?, Access::WRITE | Access::EXEC)?;
                     let inode = dir_inode.create(file_name, FileType::File, mode as u32)?;
+                    set_owner(&inode, &dir_inode, &proc.cred);
                     TimeSpec::update(&inode);
                     TimeSpec::update(&dir_inode);
                     inode
@@ -616,16 +508,38 @@ impl Syscall<'_> {
                 Err(e) => return Err(SysError::from(e)),
             }
         } else {
-            proc.lookup_inode_at(dir_fd, &path, true)?
+            let inode = proc.lookup_inode_at(dir_fd, &path, true)?;
+            check_open_access(&proc.cred, &inode, flags)?;
+            inode
         };
+        if flags.contains(OpenFlags::CREATE | OpenFlags::TRUNCATE) {
+            // dropping truncated pages inspects every process, including this one
+            drop(proc);
+            if inode.metadata()?.type_ == FileType::File {
+                page_cache::resize(&inode, 0)?;
+            } else {
+                // devices and pipes have nothing to truncate
+                let _ = page_cache::resize(&inode, 0);
+            }
+            proc = self.process();
+        }
+        // /dev/ptmx creates a new pty
+        let owner = mount::fs_of(&inode);
+        let leader = if !flags.contains(OpenFlags::NOCTTY) && proc.sid as usize == proc.pid.get() {
+            Some((proc.sid, proc.pgid))
+        } else {
+            None
+        };
+        let inode = open_pty(inode, leader)?;
 
-        let file = FileHandle::new(
+        let mut file = FileHandle::new(
             inode,
             flags.to_options(),
             String::from(path),
             false,
             flags.contains(OpenFlags::CLOEXEC),
         );
+        file.mount = owner;
 
         // for debugging
         if cfg!(debug_assertions) {
@@ -645,7 +559,10 @@ impl Syscall<'_> {
             debug!("files before close {:#?}", proc.files);
         }
 
-        proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        let file = proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        // closing a pty master signals its session, which may include us
+        drop(proc);
+        drop(file);
         Ok(0)
     }
 
@@ -660,7 +577,6 @@ impl Syscall<'_> {
         mode: usize,
         flags: usize,
     ) -> SysResult {
-        // TODO: check permissions based on uid/gid
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         let flags = AtFlags::from_bits_truncate(flags);
@@ -671,8 +587,19 @@ impl Syscall<'_> {
                 dirfd as isize, path, mode, flags
             );
         }
-        let _inode =
+        let access = Access::from_bits(mode).ok_or(SysError::EINVAL)?;
+        let inode =
             proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+        // F_OK only tests existence
+        if !access.is_empty() {
+            // checked with real ids unless AT_EACCESS is given
+            let cred = if flags.contains(AtFlags::EACCESS) {
+                proc.cred.clone()
+            } else {
+                proc.cred.real()
+            };
+            cred.check_access(&inode.metadata()?, access)?;
+        }
         Ok(0)
     }
 
@@ -782,7 +709,9 @@ impl Syscall<'_> {
 
     pub fn sys_fsync(&mut self, fd: usize) -> SysResult {
         info!("fsync: fd: {}", fd);
-        self.process().get_file(fd)?.sync_all()?;
+        // syncing shared mappings inspects every process, including this one
+        let mut file = self.process().get_file(fd)?.clone();
+        file.sync_all()?;
         Ok(0)
     }
 
@@ -805,7 +734,8 @@ impl Syscall<'_> {
 
     pub fn sys_fdatasync(&mut self, fd: usize) -> SysResult {
         info!("fdatasync: fd: {}", fd);
-        self.process().get_file(fd)?.sync_data()?;
+        let mut file = self.process().get_file(fd)?.clone();
+        file.sync_data()?;
         Ok(0)
     }
 
@@ -813,13 +743,18 @@ impl Syscall<'_> {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         info!("truncate: path: {:?}, len: {}", path, len);
-        proc.lookup_inode(&path)?.resize(len)?;
+        let inode = proc.lookup_inode(&path)?;
+        mount::check_writable(&inode)?;
+        // unmapping truncated pages inspects every process, including this one
+        drop(proc);
+        page_cache::resize(&inode, len)?;
         Ok(0)
     }
 
     pub fn sys_ftruncate(&mut self, fd: usize, len: usize) -> SysResult {
         info!("ftruncate: fd: {}, len: {}", fd, len);
-        self.process().get_file(fd)?.set_len(len as u64)?;
+        let mut file = self.process().get_file(fd)?.clone();
+        file.set_len(len as u64)?;
         Ok(0)
     }
 
@@ -835,7 +770,8 @@ impl Syscall<'_> {
         );
         let mut proc = self.process();
         let buf = unsafe { self.vm().check_write_array(buf as *mut u8, buf_size)? };
-        let file = proc.get_file(fd)?;
+        let mut file = proc.get_file(fd)?.clone();
+        drop(proc);
         let info = file.metadata()?;
         if info.type_ != FileType::Dir {
             return Err(SysError::ENOTDIR);
@@ -866,11 +802,11 @@ impl Syscall<'_> {
 
     fn dup_impl(&mut self, fd1: usize, fd2: usize, flags: usize) -> SysResult {
         let mut proc = self.process();
-        // close fd2 first if it is opened
-        proc.files.remove(&fd2);
-
         let mut file_like = proc.get_file_like(fd1)?.dup(flags != 0);
-        proc.files.insert(fd2, file_like);
+        // close fd2 if it is opened, once we are unlocked
+        let closed = proc.files.insert(fd2, file_like);
+        drop(proc);
+        drop(closed);
         Ok(fd2)
     }
 
@@ -899,14 +835,14 @@ impl Syscall<'_> {
                 let data = arg1 as *const i32;
                 let val = unsafe { *data };
                 if val == 0 {
-                    self.sys_fcntl(fd, F_SETFD, 0)
+                    self.sys_fcntl(fd, F_SETFL, 0)
                 } else {
-                    self.sys_fcntl(fd, F_SETFD, O_NONBLOCK)
+                    self.sys_fcntl(fd, F_SETFL, O_NONBLOCK)
                 }
             }
             _ => {
-                let mut proc = self.process();
-                let file_like = proc.get_file_like(fd)?;
+                // release the process so that the tty can inspect the caller
+                let mut file_like = self.process().get_file_like(fd)?.clone();
                 file_like.ioctl(request, arg1, arg2, arg3)
             }
         }
@@ -978,6 +914,19 @@ impl Syscall<'_> {
         let (new_dir_path, new_file_name) = split_path(&newpath);
         let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, false)?;
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, false)?;
+        let old_inode = old_dir_inode.find(old_file_name)?;
+        mount::check_writable(&old_dir_inode)?;
+        mount::check_writable(&new_dir_inode)?;
+        proc.cred
+            .check_delete(&old_dir_inode.metadata()?, &old_inode.metadata()?)?;
+        match new_dir_inode.find(new_file_name) {
+            Ok(new_inode) => proc
+                .cred
+                .check_delete(&new_dir_inode.metadata()?, &new_inode.metadata()?)?,
+            Err(_) => proc
+                .cred
+                .check_access(&new_dir_inode.metadata()?, Access::WRITE | Access::EXEC)?,
+        }
         old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
         Ok(0)
     }
@@ -1000,7 +949,11 @@ impl Syscall<'_> {
         if dir_inode.find(file_name).is_ok() {
             return Err(SysError::EEXIST);
         }
+        mount::check_writable(&dir_inode)?;
+        proc.cred
+            .check_access(&dir_inode.metadata()?, Access::WRITE | Access::EXEC)?;
         let inode = dir_inode.create(file_name, FileType::Dir, mode as u32)?;
+        set_owner(&inode, &dir_inode, &proc.cred);
         TimeSpec::update(&inode);
         TimeSpec::update(&dir_inode);
         Ok(0)
@@ -1014,9 +967,12 @@ impl Syscall<'_> {
         let (dir_path, file_name) = split_path(&path);
         let dir_inode = proc.lookup_inode(dir_path)?;
         let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ != FileType::Dir {
+        let file_info = file_inode.metadata()?;
+        if file_info.type_ != FileType::Dir {
             return Err(SysError::ENOTDIR);
         }
+        mount::check_writable(&dir_inode)?;
+        proc.cred.check_delete(&dir_inode.metadata()?, &file_info)?;
         dir_inode.unlink(file_name)?;
         Ok(0)
     }
@@ -1045,6 +1001,10 @@ impl Syscall<'_> {
         let (new_dir_path, new_file_name) = split_path(&newpath);
         let inode = proc.lookup_inode_at(olddirfd, &oldpath, true)?;
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
+        mount::check_writable(&new_dir_inode)?;
+        // the new name shares the owner of `inode`, so there is nothing to set
+        proc.cred
+            .check_access(&new_dir_inode.metadata()?, Access::WRITE | Access::EXEC)?;
         new_dir_inode.link(new_file_name, &inode)?;
         Ok(0)
     }
@@ -1078,7 +1038,11 @@ impl Syscall<'_> {
             Ok(_) => Err(SysError::EEXIST),
             Err(e) => match e {
                 FsError::EntryNotFound => {
+                    mount::check_writable(&dir_inode)?;
+                    proc.cred
+                        .check_access(&dir_inode.metadata()?, Access::WRITE | Access::EXEC)?;
                     let symlink = dir_inode.create(filename, FileType::SymLink, 0o777)?;
+                    set_owner(&symlink, &dir_inode, &proc.cred);
                     symlink.write_at(0, target.as_bytes())?;
                     TimeSpec::update(&symlink);
                     TimeSpec::update(&dir_inode);
@@ -1101,9 +1065,12 @@ impl Syscall<'_> {
         let (dir_path, file_name) = split_path(&path);
         let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
         let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ == FileType::Dir {
+        let file_info = file_inode.metadata()?;
+        if file_info.type_ == FileType::Dir {
             return Err(SysError::EISDIR);
         }
+        mount::check_writable(&dir_inode)?;
+        proc.cred.check_delete(&dir_inode.metadata()?, &file_info)?;
         dir_inode.unlink(file_name)?;
         Ok(0)
     }
@@ -1191,6 +1158,7 @@ impl Syscall<'_> {
             };
             proc.lookup_inode_at(dirfd, &pathname, follow)?
         };
+        mount::check_writable(&inode)?;
         let mut metadata = inode.metadata()?;
         if times[0].nsec != UTIME_OMIT {
             if times[0].nsec == UTIME_NOW {
@@ -1214,11 +1182,122 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    pub fn sys_chmod(&mut self, path: *const u8, mode: usize) -> SysResult {
+        self.sys_fchmodat(AT_FDCWD, path, mode)
+    }
+
+    pub fn sys_fchmod(&mut self, fd: usize, mode: usize) -> SysResult {
+        info!("fchmod: fd: {}, mode: {:#o}", fd, mode);
+        let mut proc = self.process();
+        let inode = proc.get_file(fd)?.inode();
+        chmod(&proc.cred, &inode, mode)
+    }
+
+    pub fn sys_fchmodat(&mut self, dirfd: usize, path: *const u8, mode: usize) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!(
+            "fchmodat: dirfd: {}, path: {:?}, mode: {:#o}",
+            dirfd as isize, path, mode
+        );
+        let inode = proc.lookup_inode_at(dirfd, &path, true)?;
+        chmod(&proc.cred, &inode, mode)
+    }
+
+    pub fn sys_chown(&mut self, path: *const u8, uid: usize, gid: usize) -> SysResult {
+        self.sys_fchownat(AT_FDCWD, path, uid, gid, 0)
+    }
+
+    pub fn sys_lchown(&mut self, path: *const u8, uid: usize, gid: usize) -> SysResult {
+        self.sys_fchownat(AT_FDCWD, path, uid, gid, AtFlags::SYMLINK_NOFOLLOW.bits())
+    }
+
+    pub fn sys_fchown(&mut self, fd: usize, uid: usize, gid: usize) -> SysResult {
+        info!(
+            "fchown: fd: {}, uid: {}, gid: {}",
+            fd, uid as i32, gid as i32
+        );
+        let mut proc = self.process();
+        let inode = proc.get_file(fd)?.inode();
+        chown(&proc.cred, &inode, uid, gid)
+    }
+
+    pub fn sys_fchownat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        uid: usize,
+        gid: usize,
+        flags: usize,
+    ) -> SysResult {
+        let mut proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        let flags = AtFlags::from_bits_truncate(flags);
+        info!(
+            "fchownat: dirfd: {}, path: {:?}, uid: {}, gid: {}, flags: {:?}",
+            dirfd as isize, path, uid as i32, gid as i32, flags
+        );
+        let inode = if path.is_empty() && flags.contains(AtFlags::EMPTY_PATH) {
+            proc.get_file(dirfd)?.inode()
+        } else {
+            proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?
+        };
+        chown(&proc.cred, &inode, uid, gid)
+    }
+
     pub fn sys_sync(&mut self) -> SysResult {
+        page_cache::sync_all();
         ROOT_INODE.fs().sync()?;
         Ok(0)
     }
 
+    pub fn sys_mount(
+        &mut self,
+        source: *const u8,
+        target: *const u8,
+        fstype: *const u8,
+        flags: usize,
+        _data: *const u8,
+    ) -> SysResult {
+        let proc = self.process();
+        if !proc.cred.is_root() {
+            return Err(SysError::EPERM);
+        }
+        let source = if source.is_null() {
+            String::from("none")
+        } else {
+            check_and_clone_cstr(source)?
+        };
+        let target = check_and_clone_cstr(target)?;
+        let fstype = if fstype.is_null() {
+            String::new()
+        } else {
+            check_and_clone_cstr(fstype)?
+        };
+        info!(
+            "mount: source: {:?}, target: {:?}, fstype: {:?}, flags: {:#x}",
+            source, target, fstype, flags
+        );
+        let target = mount::canonicalize(&proc.cwd, &target);
+        drop(proc);
+        mount::mount(&source, &target, &fstype, flags)?;
+        Ok(0)
+    }
+
+    pub fn sys_umount2(&mut self, target: *const u8, flags: usize) -> SysResult {
+        let proc = self.process();
+        if !proc.cred.is_root() {
+            return Err(SysError::EPERM);
+        }
+        let target = check_and_clone_cstr(target)?;
+        info!("umount2: target: {:?}, flags: {:#x}", target, flags);
+        let target = mount::canonicalize(&proc.cwd, &target);
+        // umount inspects every process, including this one
+        drop(proc);
+        mount::umount(&target, flags)?;
+        Ok(0)
+    }
+
     pub async fn sys_sendfile(
         &mut self,
         out_fd: usize,
@@ -1249,7 +1328,8 @@ impl Syscall<'_> {
         let in_file = unsafe { (*proc_cell.get()).get_file(in_fd)? };
         let out_file = unsafe { (*proc_cell.get()).get_file(out_fd)? };
         drop(proc_cell);
-        let mut buffer = [0u8; 1024];
+        // a page at a time, as the page cache has them
+        let mut buffer = vec![0u8; PAGE_SIZE];
 
         // for in_offset and out_offset
         // null means update file offset
@@ -1342,11 +1422,33 @@ impl Syscall<'_> {
                     _ => Ok(0),
                 }
             }
-            FileLike::Socket(_) => {
-                Ok(0)
-                //TODO
+            FileLike::Socket(_)
+            | FileLike::EpollInstance(_)
+            | FileLike::EventFd(_)
+            | FileLike::TimerFd(_)
+            | FileLike::SignalFd(_) => {
+                use crate::fs::fcntl::*;
+                match cmd {
+                    F_SETFD => {
+                        file_like.set_fd_cloexec((arg & 1) != 0);
+                        Ok(0)
+                    }
+                    F_GETFD => Ok(file_like.fd_cloexec() as usize),
+                    F_SETFL => {
+                        file_like.set_nonblock((arg & O_NONBLOCK) != 0);
+                        Ok(0)
+                    }
+                    // opened read-write
+                    F_GETFL if file_like.nonblock() => Ok(O_RDWR | O_NONBLOCK),
+                    F_GETFL => Ok(O_RDWR),
+                    F_DUPFD_CLOEXEC => {
+                        let new_fd = proc.get_free_fd_from(arg);
+                        core::mem::drop(proc);
+                        self.dup_impl(fd, new_fd, 1)
+                    }
+                    _ => Ok(0),
+                }
             }
-            FileLike::EpollInstance(_) => Ok(0),
         }
     }
 }
@@ -1388,11 +1490,23 @@ impl Process {
             "lookup_inode_at: dirfd: {:?}, cwd: {:?}, path: {:?}, follow: {:?}",
             dirfd as isize, self.cwd, path, follow
         );
+        // procfs INodes lock the process they describe, which we are holding now,
+        // so serve our own entries here
+        let own_prefix = format!("/proc/{}/", self.pid);
+        let path = if path.starts_with(own_prefix.as_str()) {
+            format!("/proc/self/{}", &path[own_prefix.len()..])
+        } else {
+            String::from(path)
+        };
+        let path = path.as_str();
         // hard code special path
         match path {
             "/proc/self/exe" => {
                 return Ok(Arc::new(Pseudo::new(&self.exec_path, FileType::SymLink)));
             }
+            "/proc/self/cwd" => {
+                return Ok(Arc::new(Pseudo::new(&self.cwd, FileType::SymLink)));
+            }
             _ => {}
         }
         let (fd_dir_path, fd_name) = split_path(&path);
@@ -1406,16 +1520,65 @@ impl Process {
         }
 
         let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
-        if dirfd == AT_FDCWD {
-            Ok(ROOT_INODE
-                .lookup(&self.cwd)?
-                .lookup_follow(path, follow_max_depth)?)
+        let base = if dirfd == AT_FDCWD {
+            ROOT_INODE.lookup(&self.cwd)?
         } else {
-            let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
-                FileLike::File(file) => file,
+            match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
+                FileLike::File(file) => file.inode(),
                 _ => return Err(SysError::EBADF),
+            }
+        };
+        self.lookup_follow(base, path, follow_max_depth)
+    }
+
+    /// Walk `path` from the directory `base` like `INode::lookup_follow`,
+    /// checking search permission on every directory crossed
+    fn lookup_follow(
+        &self,
+        base: Arc<dyn INode>,
+        path: &str,
+        mut follow_times: usize,
+    ) -> Result<Arc<dyn INode>, SysError> {
+        let mut result = base;
+        let mut rest_path = String::from(path);
+        loop {
+            let info = result.metadata()?;
+            if info.type_ != FileType::Dir {
+                return Err(SysError::ENOTDIR);
+            }
+            if rest_path.is_empty() {
+                return Ok(result);
+            }
+            if rest_path.starts_with('/') {
+                result = ROOT_INODE.clone();
+                rest_path = String::from(&rest_path[1..]);
+                continue;
+            }
+            let (name, rest) = match rest_path.find('/') {
+                Some(pos) => (
+                    String::from(&rest_path[..pos]),
+                    String::from(&rest_path[pos + 1..]),
+                ),
+                None => (rest_path.clone(), String::new()),
             };
-            Ok(file.lookup_follow(path, follow_max_depth)?)
+            rest_path = rest;
+            if name.is_empty() {
+                continue;
+            }
+            self.cred.check_access(&info, Access::EXEC)?;
+            let inode = result.find(&name)?;
+            if inode.metadata()?.type_ == FileType::SymLink && follow_times > 0 {
+                follow_times -= 1;
+                let mut content = [0u8; 256];
+                let len = inode.read_at(0, &mut content)?;
+                let target = core::str::from_utf8(&content[..len]).map_err(|_| SysError::ENOENT)?;
+                // resolved against the directory holding the link
+                rest_path = format!("{}/{}", target.trim_end_matches('/'), rest_path);
+            } else if rest_path.is_empty() {
+                return Ok(inode);
+            } else {
+                result = inode;
+            }
         }
     }
 
@@ -1425,7 +1588,7 @@ impl Process {
 }
 
 /// Split a `path` str to `(base_path, file_name)`
-fn split_path(path: &str) -> (&str, &str) {
+pub(super) fn split_path(path: &str) -> (&str, &str) {
     let mut split = path.trim_end_matches('/').rsplitn(2, '/');
     let file_name = split.next().unwrap();
     let mut dir_path = split.next().unwrap_or(".");
@@ -1435,6 +1598,109 @@ fn split_path(path: &str) -> (&str, &str) {
     (dir_path, file_name)
 }
 
+/// Check permissions of opening `inode` with `flags`
+fn check_open_access(
+    cred: &Credentials,
+    inode: &Arc<dyn INode>,
+    flags: OpenFlags,
+) -> Result<(), SysError> {
+    let mut access = Access::empty();
+    if flags.readable() {
+        access |= Access::READ;
+    }
+    if flags.writable() || flags.contains(OpenFlags::TRUNCATE) {
+        access |= Access::WRITE;
+    }
+    let metadata = inode.metadata()?;
+    // devices stay writable on a read-only file system
+    let is_device = match metadata.type_ {
+        FileType::CharDevice | FileType::BlockDevice | FileType::NamedPipe | FileType::Socket => {
+            true
+        }
+        _ => false,
+    };
+    if access.contains(Access::WRITE) && !is_device {
+        mount::check_writable(inode)?;
+    }
+    cred.check_access(&metadata, access)
+}
+
+/// Give a newly created `inode` in `dir` to the caller
+///
+/// The group is inherited from a set-group-ID directory.
+pub(super) fn set_owner(inode: &Arc<dyn INode>, dir: &Arc<dyn INode>, cred: &Credentials) {
+    let (mut metadata, dir_info) = match (inode.metadata(), dir.metadata()) {
+        (Ok(metadata), Ok(dir_info)) => (metadata, dir_info),
+        _ => return,
+    };
+    metadata.uid = cred.euid;
+    if dir_info.mode & S_ISGID != 0 {
+        metadata.gid = dir_info.gid;
+        if metadata.type_ == FileType::Dir {
+            metadata.mode |= S_ISGID;
+        }
+    } else {
+        metadata.gid = cred.egid;
+    }
+    // not every file system records owners
+    inode.set_metadata(&metadata).ok();
+}
+
+/// Change permission bits of `inode`, only allowed to its owner
+fn chmod(cred: &Credentials, inode: &Arc<dyn INode>, mode: usize) -> SysResult {
+    mount::check_writable(inode)?;
+    let mut metadata = inode.metadata()?;
+    if !cred.is_owner(&metadata) {
+        return Err(SysError::EPERM);
+    }
+    let mut mode = mode as u16 & 0o7777;
+    // set-group-ID is silently dropped for groups the caller is not in
+    if !cred.is_root() && !cred.in_group(metadata.gid) {
+        mode &= !S_ISGID;
+    }
+    metadata.mode = mode;
+    inode.set_metadata(&metadata)?;
+    Ok(0)
+}
+
+/// Change owner and group of `inode`, -1 leaves an id unchanged
+///
+/// Only root can give a file away. Owners can change its group to any group
+/// they are in.
+fn chown(cred: &Credentials, inode: &Arc<dyn INode>, uid: usize, gid: usize) -> SysResult {
+    let uid = uid as u32 as usize;
+    let gid = gid as u32 as usize;
+    mount::check_writable(inode)?;
+    let mut metadata = inode.metadata()?;
+    if !cred.is_root() {
+        let owner = cred.euid == metadata.uid;
+        if uid != ID_UNCHANGED && !(owner && uid == metadata.uid) {
+            return Err(SysError::EPERM);
+        }
+        if gid != ID_UNCHANGED && !(owner && (gid == metadata.gid || cred.in_group(gid))) {
+            return Err(SysError::EPERM);
+        }
+    }
+    if uid == ID_UNCHANGED && gid == ID_UNCHANGED {
+        return Ok(0);
+    }
+    if uid != ID_UNCHANGED {
+        metadata.uid = uid;
+    }
+    if gid != ID_UNCHANGED {
+        metadata.gid = gid;
+    }
+    // changing owner drops the privileges of an executable
+    if metadata.type_ != FileType::Dir {
+        metadata.mode &= !S_ISUID;
+        if metadata.mode & 0o010 != 0 {
+            metadata.mode &= !S_ISGID;
+        }
+    }
+    inode.set_metadata(&metadata)?;
+    Ok(0)
+}
+
 impl From<FsError> for SysError {
     fn from(error: FsError) -> Self {
         match error {
@@ -1465,6 +1731,7 @@ bitflags! {
     struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
+        const EACCESS = 0x200;
     }
 }
 
@@ -1480,6 +1747,8 @@ bitflags! {
         const CREATE = 1 << 6;
         /// error if CREATE and the file exists
         const EXCLUSIVE = 1 << 7;
+        /// do not become the controlling terminal
+        const NOCTTY = 1 << 8;
         /// truncate file upon open
         const TRUNCATE = 1 << 9;
         /// append on each write
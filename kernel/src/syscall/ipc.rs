@@ -134,7 +134,7 @@ impl Syscall<'_> {
         }
         let size = shm_identifier.shared_guard.lock().size;
         info!("shmat: id: {}, addr = {:#x}, size = {}", id, addr, size);
-        addr = self.vm().find_free_area(addr, size);
+        addr = self.vm().find_free_area(addr.max(proc.mmap_base()), size);
         self.vm().push(
             addr,
             addr + size,
@@ -4,6 +4,9 @@ use rcore_memory::memory_set::MemoryAttr;
 use rcore_memory::PAGE_SIZE;
 
 use super::*;
+use crate::consts::USER_STACK_OFFSET;
+use crate::fs::mount;
+use crate::memory::swap::{self, Backing};
 use crate::memory::GlobalFrameAlloc;
 
 impl Syscall<'_> {
@@ -36,7 +39,7 @@ impl Syscall<'_> {
             // we have to map it to addr, so remove the old mapping first
             self.vm().pop_with_split(addr, addr + len);
         } else {
-            addr = self.vm().find_free_area(addr, len);
+            addr = self.vm().find_free_area(addr.max(proc.mmap_base()), len);
         }
 
         if flags.contains(MmapFlags::ANONYMOUS) {
@@ -74,20 +77,35 @@ impl Syscall<'_> {
     }
 
     pub fn sys_mprotect(&mut self, addr: usize, len: usize, prot: usize) -> SysResult {
-        let prot = MmapProt::from_bits_truncate(prot);
+        let prot = MmapProt::from_bits(prot).ok_or(SysError::EINVAL)?;
         info!(
             "mprotect: addr={:#x}, size={:#x}, prot={:?}",
             addr, len, prot
         );
-        let _attr = prot.to_attr();
-
-        // TODO: properly set the attribute of the area
-        //        now some mut ptr check is fault
-        let vm = self.vm();
-        let memory_area = vm
-            .iter()
-            .find(|area| area.is_overlap_with(addr, addr + len));
-        if memory_area.is_none() {
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        match self.vm().protect(addr, addr + len, prot.to_attr()) {
+            Ok(()) => Ok(0),
+            Err(VMError::InvalidPtr) => Err(SysError::ENOMEM),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn sys_msync(&mut self, addr: usize, len: usize, flags: usize) -> SysResult {
+        let flags = MsyncFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        info!(
+            "msync: addr={:#x}, size={:#x}, flags={:?}",
+            addr, len, flags
+        );
+        if addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) {
+            return Err(SysError::EINVAL);
+        }
+        // pages are written back right away, whether asked to or not
+        if !self.vm().sync(addr, addr + len) {
             return Err(SysError::ENOMEM);
         }
         Ok(0)
@@ -98,6 +116,81 @@ impl Syscall<'_> {
         self.vm().pop_with_split(addr, addr + len);
         Ok(0)
     }
+
+    /// Set the program break to `addr`, return the new program break
+    ///
+    /// Like Linux, failure is reported by returning the old program break,
+    /// which libc turns into ENOMEM.
+    pub fn sys_brk(&mut self, addr: usize) -> SysResult {
+        info!("brk: addr={:#x}", addr);
+        let mut proc = self.process();
+        let old_brk = proc.brk;
+        // brk(0) queries the current program break
+        if addr < proc.brk_start || addr >= USER_STACK_OFFSET {
+            return Ok(old_brk);
+        }
+        if (addr - proc.brk_start) as u64 > proc.data_limit.cur {
+            return Ok(old_brk);
+        }
+
+        let page_up = |addr: usize| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let old_end = page_up(old_brk);
+        let new_end = page_up(addr);
+        let mut vm = self.vm();
+        if new_end > old_end && old_end == page_up(proc.brk_start) {
+            // do not grow into mmap areas or the stack
+            if vm.iter().any(|area| area.is_overlap_with(old_end, new_end)) {
+                return Ok(old_brk);
+            }
+            vm.push(
+                old_end,
+                new_end,
+                MemoryAttr::default().user(),
+                Delay::new(GlobalFrameAlloc),
+                "heap",
+            );
+        } else if new_end > old_end {
+            if !vm.extend(old_end, new_end) {
+                return Ok(old_brk);
+            }
+        } else if new_end < old_end {
+            vm.pop_with_split(new_end, old_end);
+        }
+        proc.brk = addr;
+        Ok(addr)
+    }
+
+    pub fn sys_swapon(&mut self, path: *const u8, flags: usize) -> SysResult {
+        let proc = self.process();
+        if !proc.cred.is_root() {
+            return Err(SysError::EPERM);
+        }
+        let path = check_and_clone_cstr(path)?;
+        info!("swapon: path: {:?}, flags: {:#x}", path, flags);
+        let path = mount::canonicalize(&proc.cwd, &path);
+        let backing = if path.starts_with("/dev/") {
+            Backing::Block(mount::block_device(&path)?)
+        } else {
+            Backing::File(proc.lookup_inode(&path)?)
+        };
+        // swapon inspects every process, including this one
+        drop(proc);
+        swap::swapon(path, backing)?;
+        Ok(0)
+    }
+
+    pub fn sys_swapoff(&mut self, path: *const u8) -> SysResult {
+        let proc = self.process();
+        if !proc.cred.is_root() {
+            return Err(SysError::EPERM);
+        }
+        let path = check_and_clone_cstr(path)?;
+        info!("swapoff: path: {:?}", path);
+        let path = mount::canonicalize(&proc.cwd, &path);
+        drop(proc);
+        swap::swapoff(&path)?;
+        Ok(0)
+    }
 }
 
 bitflags! {
@@ -141,14 +234,31 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct MsyncFlags: usize {
+        /// Schedule the write back
+        const ASYNC = 1 << 0;
+        /// Invalidate other mappings of the same file
+        const INVALIDATE = 1 << 1;
+        /// Write back and wait for it
+        const SYNC = 1 << 2;
+    }
+}
+
 impl MmapProt {
     pub fn to_attr(self) -> MemoryAttr {
-        let mut attr = MemoryAttr::default().user();
+        let mut attr = MemoryAttr::default();
+        // pages can't be writable or executable but not readable,
+        // so only PROT_NONE hides them from the user
+        if !self.is_empty() {
+            attr = attr.user();
+        }
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
         }
-        // TODO: see sys_mprotect
-        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if !self.contains(MmapProt::WRITE) {
+            attr = attr.readonly();
+        }
         attr
     }
 }
@@ -3,10 +3,12 @@
 use super::*;
 use crate::arch::cpu;
 use crate::consts::{ARCH, USER_STACK_SIZE};
+use crate::memory::{frame_stats, swap_stats};
 use crate::syscall::SysError::ETIMEDOUT;
 use crate::trap::TICK_ACTIVITY;
 use core::mem::size_of;
 use core::sync::atomic::{AtomicI32, Ordering};
+use rcore_memory::PAGE_SIZE;
 
 impl Syscall<'_> {
     #[cfg(target_arch = "x86_64")]
@@ -56,7 +58,16 @@ impl Syscall<'_> {
     pub fn sys_sysinfo(&mut self, sys_info: *mut SysInfo) -> SysResult {
         let sys_info = unsafe { self.vm().check_write_ptr(sys_info)? };
 
-        let sysinfo = SysInfo::default();
+        let (total, free) = frame_stats();
+        let (swap_total, swap_free) = swap_stats();
+        let sysinfo = SysInfo {
+            totalram: (total * PAGE_SIZE) as u64,
+            freeram: (free * PAGE_SIZE) as u64,
+            totalswap: (swap_total * PAGE_SIZE) as u64,
+            freeswap: (swap_free * PAGE_SIZE) as u64,
+            mem_unit: 1,
+            ..SysInfo::default()
+        };
         *sys_info = sysinfo;
         Ok(0)
     }
@@ -78,7 +89,7 @@ impl Syscall<'_> {
         if uaddr % size_of::<u32>() != 0 {
             return Err(SysError::EINVAL);
         }
-        let atomic = unsafe { self.vm().check_write_ptr(uaddr as *mut AtomicI32)? };
+        let atomic = unsafe { self.vm().check_read_ptr(uaddr as *const AtomicI32)? };
 
         const OP_WAIT: u32 = 0;
         const OP_WAKE: u32 = 1;
@@ -148,6 +159,28 @@ impl Syscall<'_> {
             pid, resource, new_limit, old_limit
         );
         match resource {
+            RLIMIT_DATA => {
+                if pid != 0 && pid != self.process().pid.get() {
+                    return Err(SysError::ESRCH);
+                }
+                if !old_limit.is_null() {
+                    let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
+                    *old_limit = self.process().data_limit;
+                }
+                if !new_limit.is_null() {
+                    let new_limit = unsafe { *self.vm().check_read_ptr(new_limit)? };
+                    if new_limit.cur > new_limit.max {
+                        return Err(SysError::EINVAL);
+                    }
+                    let mut proc = self.process();
+                    // raising the hard limit needs privilege
+                    if new_limit.max > proc.data_limit.max {
+                        return Err(SysError::EPERM);
+                    }
+                    proc.data_limit = new_limit;
+                }
+                Ok(0)
+            }
             RLIMIT_STACK => {
                 if !old_limit.is_null() {
                     let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
@@ -225,14 +258,23 @@ pub struct SysInfo {
     mem_unit: u32,
 }
 
+const RLIMIT_DATA: usize = 2;
 const RLIMIT_STACK: usize = 3;
 const RLIMIT_RSS: usize = 5;
 const RLIMIT_NOFILE: usize = 7;
 const RLIMIT_AS: usize = 9;
 
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct RLimit {
-    cur: u64, // soft limit
-    max: u64, // hard limit
+    pub cur: u64, // soft limit
+    pub max: u64, // hard limit
+}
+
+impl RLimit {
+    /// RLIM_INFINITY for both limits
+    pub const INFINITY: RLimit = RLimit {
+        cur: u64::MAX,
+        max: u64::MAX,
+    };
 }
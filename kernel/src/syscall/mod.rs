@@ -122,7 +122,10 @@ impl Syscall<'_> {
                 self.sys_read(args[0], UserOutPtr::from(args[1]), args[2])
                     .await
             }
-            SYS_WRITE => self.sys_write(args[0], args[1] as *const u8, args[2]),
+            SYS_WRITE => {
+                self.sys_write(args[0], UserInPtr::from(args[1]), args[2])
+                    .await
+            }
             SYS_OPENAT => self.sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_CLOSE => self.sys_close(args[0]),
             SYS_FSTAT => self.sys_fstat(args[0], args[1] as *mut Stat),
@@ -140,7 +143,10 @@ impl Syscall<'_> {
                 self.sys_readv(args[0], UserInPtr::from(args[1]), args[2])
                     .await
             }
-            SYS_WRITEV => self.sys_writev(args[0], args[1] as *const IoVec, args[2]),
+            SYS_WRITEV => {
+                self.sys_writev(args[0], UserInPtr::from(args[1]), args[2])
+                    .await
+            }
             SYS_SENDFILE => {
                 self.sys_sendfile(args[0], args[1], UserInOutPtr::from(args[2]), args[3])
                     .await
@@ -172,10 +178,12 @@ impl Syscall<'_> {
             SYS_READLINKAT => {
                 self.sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
             }
-            SYS_FCHMOD => self.unimplemented("fchmod", Ok(0)),
-            SYS_FCHMODAT => self.unimplemented("fchmodat", Ok(0)),
-            SYS_FCHOWN => self.unimplemented("fchown", Ok(0)),
-            SYS_FCHOWNAT => self.unimplemented("fchownat", Ok(0)),
+            SYS_FCHMOD => self.sys_fchmod(args[0], args[1]),
+            SYS_FCHMODAT => self.sys_fchmodat(args[0], args[1] as *const u8, args[2]),
+            SYS_FCHOWN => self.sys_fchown(args[0], args[1], args[2]),
+            SYS_FCHOWNAT => {
+                self.sys_fchownat(args[0], args[1] as *const u8, args[2], args[3], args[4])
+            }
             SYS_FACCESSAT => self.sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_DUP3 => self.sys_dup3(args[0], args[1], args[2]),
             SYS_PIPE2 => self.sys_pipe2(args[0] as *mut u32, args[1]), // TODO: handle `flags`
@@ -220,29 +228,51 @@ impl Syscall<'_> {
             SYS_EPOLL_CTL => {
                 self.sys_epoll_ctl(args[0], args[1], args[2], args[3] as *mut EpollEvent)
             }
-            SYS_EPOLL_PWAIT => self.sys_epoll_pwait(
+            SYS_EPOLL_PWAIT => {
+                self.sys_epoll_pwait(
+                    args[0],
+                    UserOutPtr::from(args[1]),
+                    args[2],
+                    args[3],
+                    args[4],
+                )
+                .await
+            }
+            SYS_EVENTFD2 => self.sys_eventfd2(args[0], args[1]),
+            SYS_TIMERFD_CREATE => self.sys_timerfd_create(args[0], args[1]),
+            SYS_TIMERFD_SETTIME => self.sys_timerfd_settime(
                 args[0],
-                args[1] as *mut EpollEvent,
-                args[2],
-                args[3],
-                args[4],
+                args[1],
+                UserInPtr::from(args[2]),
+                UserOutPtr::from(args[3]),
             ),
-            SYS_EVENTFD2 => self.unimplemented("eventfd2", Err(SysError::EACCES)),
+            SYS_TIMERFD_GETTIME => self.sys_timerfd_gettime(args[0], UserOutPtr::from(args[1])),
+            SYS_SIGNALFD4 => {
+                self.sys_signalfd4(args[0], UserInPtr::from(args[1]), args[2], args[3])
+            }
 
-            SYS_SOCKETPAIR => self.unimplemented("socketpair", Err(SysError::EACCES)),
             // file system
             SYS_STATFS => self.unimplemented("statfs", Err(SysError::EACCES)),
             SYS_FSTATFS => self.unimplemented("fstatfs", Err(SysError::EACCES)),
             SYS_SYNC => self.sys_sync(),
-            SYS_MOUNT => self.unimplemented("mount", Err(SysError::EACCES)),
-            SYS_UMOUNT2 => self.unimplemented("umount2", Err(SysError::EACCES)),
+            SYS_MOUNT => self.sys_mount(
+                args[0] as *const u8,
+                args[1] as *const u8,
+                args[2] as *const u8,
+                args[3],
+                args[4] as *const u8,
+            ),
+            SYS_UMOUNT2 => self.sys_umount2(args[0] as *const u8, args[1]),
 
             // memory
-            SYS_BRK => self.unimplemented("brk", Err(SysError::ENOMEM)),
+            SYS_BRK => self.sys_brk(args[0]),
             SYS_MMAP => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
             SYS_MPROTECT => self.sys_mprotect(args[0], args[1], args[2]),
             SYS_MUNMAP => self.sys_munmap(args[0], args[1]),
+            SYS_MSYNC => self.sys_msync(args[0], args[1], args[2]),
             SYS_MADVISE => self.unimplemented("madvise", Ok(0)),
+            SYS_SWAPON => self.sys_swapon(args[0] as *const u8, args[1]),
+            SYS_SWAPOFF => self.sys_swapoff(args[0] as *const u8),
 
             // signal
             SYS_RT_SIGACTION => self.sys_rt_sigaction(
@@ -271,27 +301,73 @@ impl Syscall<'_> {
 
             // socket
             SYS_SOCKET => self.sys_socket(args[0], args[1], args[2]),
-            SYS_CONNECT => self.sys_connect(args[0], args[1] as *const SockAddr, args[2]),
-            SYS_ACCEPT => self.sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
-            SYS_ACCEPT4 => self.sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32), // use accept for accept4
-            SYS_SENDTO => self.sys_sendto(
-                args[0],
-                args[1] as *const u8,
-                args[2],
-                args[3],
-                args[4] as *const SockAddr,
-                args[5],
-            ),
-            SYS_RECVFROM => self.sys_recvfrom(
-                args[0],
-                args[1] as *mut u8,
-                args[2],
-                args[3],
-                args[4] as *mut SockAddr,
-                args[5] as *mut u32,
-            ),
-            //        SYS_SENDMSG => self.sys_sendmsg(),
-            SYS_RECVMSG => self.sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2]),
+            SYS_SOCKETPAIR => self.sys_socketpair(args[0], args[1], args[2], args[3] as *mut u32),
+            SYS_CONNECT => {
+                self.sys_connect(args[0], UserInPtr::from(args[1]), args[2])
+                    .await
+            }
+            SYS_ACCEPT => {
+                self.sys_accept4(
+                    args[0],
+                    UserOutPtr::from(args[1]),
+                    UserInOutPtr::from(args[2]),
+                    0,
+                )
+                .await
+            }
+            SYS_ACCEPT4 => {
+                self.sys_accept4(
+                    args[0],
+                    UserOutPtr::from(args[1]),
+                    UserInOutPtr::from(args[2]),
+                    args[3],
+                )
+                .await
+            }
+            SYS_SENDTO => {
+                self.sys_sendto(
+                    args[0],
+                    UserInPtr::from(args[1]),
+                    args[2],
+                    args[3],
+                    UserInPtr::from(args[4]),
+                    args[5],
+                )
+                .await
+            }
+            SYS_RECVFROM => {
+                self.sys_recvfrom(
+                    args[0],
+                    UserOutPtr::from(args[1]),
+                    args[2],
+                    args[3],
+                    UserOutPtr::from(args[4]),
+                    UserInOutPtr::from(args[5]),
+                )
+                .await
+            }
+            SYS_SENDMSG => {
+                self.sys_sendmsg(args[0], UserInPtr::from(args[1]), args[2])
+                    .await
+            }
+            SYS_RECVMSG => {
+                self.sys_recvmsg(args[0], UserInOutPtr::from(args[1]), args[2])
+                    .await
+            }
+            SYS_SENDMMSG => {
+                self.sys_sendmmsg(args[0], UserInOutPtr::from(args[1]), args[2], args[3])
+                    .await
+            }
+            SYS_RECVMMSG => {
+                self.sys_recvmmsg(
+                    args[0],
+                    UserInOutPtr::from(args[1]),
+                    args[2],
+                    args[3],
+                    UserInPtr::from(args[4]),
+                )
+                .await
+            }
             SYS_SHUTDOWN => self.sys_shutdown(args[0], args[1]),
             SYS_BIND => self.sys_bind(args[0], args[1] as *const SockAddr, args[2]),
             SYS_LISTEN => self.sys_listen(args[0], args[1]),
@@ -328,9 +404,9 @@ impl Syscall<'_> {
             SYS_EXIT => self.sys_exit(args[0] as usize),
             SYS_EXIT_GROUP => self.sys_exit_group(args[0]),
             SYS_WAIT4 => {
-                self.sys_wait4(args[0] as isize, UserInOutPtr::from(args[1]))
+                self.sys_wait4(args[0] as isize, UserInOutPtr::from(args[1]), args[2])
                     .await
-            } // TODO: wait4
+            }
             SYS_SET_TID_ADDRESS => self.sys_set_tid_address(args[0] as *mut u32),
             SYS_FUTEX => {
                 self.sys_futex(
@@ -385,21 +461,34 @@ impl Syscall<'_> {
             SYS_GETRUSAGE => self.sys_getrusage(args[0], args[1] as *mut RUsage),
             SYS_SYSINFO => self.sys_sysinfo(args[0] as *mut SysInfo),
             SYS_TIMES => self.sys_times(args[0] as *mut Tms),
-            SYS_GETUID => self.unimplemented("getuid", Ok(0)),
-            SYS_GETGID => self.unimplemented("getgid", Ok(0)),
-            SYS_SETUID => self.unimplemented("setuid", Ok(0)),
-            SYS_GETEUID => self.unimplemented("geteuid", Ok(0)),
-            SYS_GETEGID => self.unimplemented("getegid", Ok(0)),
+            SYS_GETUID => self.sys_getuid(),
+            SYS_GETGID => self.sys_getgid(),
+            SYS_SETUID => self.sys_setuid(args[0]),
+            SYS_GETEUID => self.sys_geteuid(),
+            SYS_GETEGID => self.sys_getegid(),
             SYS_GETPPID => self.sys_getppid(),
-            SYS_SETSID => self.unimplemented("setsid", Ok(0)),
+            SYS_SETSID => self.sys_setsid(),
+            SYS_GETSID => self.sys_getsid(args[0]),
             SYS_GETPGID => self.sys_getpgid(args[0]),
             SYS_SETPGID => self.sys_setpgid(args[0], args[1]),
-            SYS_GETGROUPS => self.unimplemented("getgroups", Ok(0)),
+            SYS_GETGROUPS => self.sys_getgroups(args[0], args[1] as *mut u32),
             SYS_RT_SIGTIMEDWAIT => self.unimplemented("rt_sigtimedwait", Ok(0)),
-            SYS_SETGROUPS => self.unimplemented("setgroups", Ok(0)),
-            SYS_SETRESUID => self.unimplemented("setresuid", Ok(0)),
-            SYS_SETRESGID => self.unimplemented("setresgid", Ok(0)),
-            SYS_SETGID => self.unimplemented("setgid", Ok(0)),
+            SYS_SETGROUPS => self.sys_setgroups(args[0], args[1] as *const u32),
+            SYS_SETRESUID => self.sys_setresuid(args[0], args[1], args[2]),
+            SYS_GETRESUID => self.sys_getresuid(
+                args[0] as *mut u32,
+                args[1] as *mut u32,
+                args[2] as *mut u32,
+            ),
+            SYS_SETREUID => self.sys_setreuid(args[0], args[1]),
+            SYS_SETRESGID => self.sys_setresgid(args[0], args[1], args[2]),
+            SYS_GETRESGID => self.sys_getresgid(
+                args[0] as *mut u32,
+                args[1] as *mut u32,
+                args[2] as *mut u32,
+            ),
+            SYS_SETREGID => self.sys_setregid(args[0], args[1]),
+            SYS_SETGID => self.sys_setgid(args[0]),
             SYS_SETPRIORITY => self.sys_set_priority(args[0]),
             SYS_PRCTL => self.unimplemented("prctl", Ok(0)),
             SYS_MEMBARRIER => self.unimplemented("membarrier", Ok(0)),
@@ -493,6 +582,9 @@ impl Syscall<'_> {
             SYS_FORK => self.sys_fork(),
             SYS_MMAP2 => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5] * 4096),
             SYS_FSTAT64 => self.sys_fstat(args[0], args[1] as *mut Stat),
+            SYS_CHMOD => self.sys_chmod(args[0] as *const u8, args[1]),
+            SYS_CHOWN => self.sys_chown(args[0] as *const u8, args[1], args[2]),
+            SYS_LCHOWN => self.sys_lchown(args[0] as *const u8, args[1], args[2]),
             SYS_LSTAT64 => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_STAT64 => self.sys_stat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_PIPE => {
@@ -525,8 +617,11 @@ impl Syscall<'_> {
             },
             SYS_EPOLL_CREATE => self.sys_epoll_create(args[0]),
             SYS_EPOLL_WAIT => {
-                self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3])
+                self.sys_epoll_wait(args[0], UserOutPtr::from(args[1]), args[2], args[3])
+                    .await
             }
+            SYS_EVENTFD => self.sys_eventfd2(args[0], 0),
+            SYS_SIGNALFD => self.sys_signalfd4(args[0], UserInPtr::from(args[1]), args[2], 0),
 
             _ => return None,
         };
@@ -563,14 +658,18 @@ impl Syscall<'_> {
             SYS_UNLINK => self.sys_unlink(args[0] as *const u8),
             SYS_SYMLINK => self.sys_symlink(args[0] as *const u8, args[1] as *const u8),
             SYS_READLINK => self.sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
-            SYS_CHMOD => self.unimplemented("chmod", Ok(0)),
-            SYS_CHOWN => self.unimplemented("chown", Ok(0)),
+            SYS_CHMOD => self.sys_chmod(args[0] as *const u8, args[1]),
+            SYS_CHOWN => self.sys_chown(args[0] as *const u8, args[1], args[2]),
+            SYS_LCHOWN => self.sys_lchown(args[0] as *const u8, args[1], args[2]),
             SYS_ARCH_PRCTL => self.sys_arch_prctl(args[0] as i32, args[1]),
             SYS_TIME => self.sys_time(args[0] as *mut u64),
             SYS_EPOLL_CREATE => self.sys_epoll_create(args[0]),
             SYS_EPOLL_WAIT => {
-                self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3])
+                self.sys_epoll_wait(args[0], UserOutPtr::from(args[1]), args[2], args[3])
+                    .await
             }
+            SYS_EVENTFD => self.sys_eventfd2(args[0], 0),
+            SYS_SIGNALFD => self.sys_signalfd4(args[0], UserInPtr::from(args[1]), args[2], 0),
             _ => return None,
         };
         Some(ret)
@@ -581,7 +680,7 @@ pub type SysResult = Result<usize, SysError>;
 
 #[allow(dead_code)]
 #[repr(isize)]
-#[derive(Debug, FromPrimitive)]
+#[derive(Debug, Clone, Copy, FromPrimitive)]
 pub enum SysError {
     EUNDEF = 0,
     EPERM = 1,
@@ -626,14 +725,24 @@ pub enum SysError {
     ELOOP = 40,
     EIDRM = 43,
     ENOTSOCK = 80,
+    EMSGSIZE = 90,
+    EPROTOTYPE = 91,
     ENOPROTOOPT = 92,
+    EPROTONOSUPPORT = 93,
+    EOPNOTSUPP = 95,
     EPFNOSUPPORT = 96,
     EAFNOSUPPORT = 97,
+    EADDRINUSE = 98,
+    EADDRNOTAVAIL = 99,
+    ENETUNREACH = 101,
     ENOBUFS = 105,
     EISCONN = 106,
     ENOTCONN = 107,
     ETIMEDOUT = 110,
     ECONNREFUSED = 111,
+    EHOSTUNREACH = 113,
+    EALREADY = 114,
+    EINPROGRESS = 115,
 }
 
 #[allow(non_snake_case)]
@@ -685,13 +794,23 @@ impl fmt::Display for SysError {
                 ENOTEMPTY => "Directory not empty",
                 ELOOP => "Too many symbolic links encountered",
                 ENOTSOCK => "Socket operation on non-socket",
+                EMSGSIZE => "Message too long",
+                EPROTOTYPE => "Protocol wrong type for socket",
                 ENOPROTOOPT => "Protocol not available",
+                EPROTONOSUPPORT => "Protocol not supported",
+                EOPNOTSUPP => "Operation not supported on transport endpoint",
                 EPFNOSUPPORT => "Protocol family not supported",
                 EAFNOSUPPORT => "Address family not supported by protocol",
+                EADDRINUSE => "Address already in use",
+                EADDRNOTAVAIL => "Cannot assign requested address",
+                ENETUNREACH => "Network is unreachable",
                 ENOBUFS => "No buffer space available",
                 EISCONN => "Transport endpoint is already connected",
                 ENOTCONN => "Transport endpoint is not connected",
                 ECONNREFUSED => "Connection refused",
+                EHOSTUNREACH => "No route to host",
+                EALREADY => "Operation already in progress",
+                EINPROGRESS => "Operation now in progress",
                 _ => "Unknown error",
             },
         )
@@ -699,8 +818,11 @@ impl fmt::Display for SysError {
 }
 
 impl From<VMError> for SysError {
-    fn from(_: VMError) -> Self {
-        SysError::EFAULT
+    fn from(err: VMError) -> Self {
+        match err {
+            VMError::InvalidPtr => SysError::EFAULT,
+            VMError::PermissionDenied => SysError::EACCES,
+        }
     }
 }
 
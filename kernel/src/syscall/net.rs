@@ -1,32 +1,51 @@
 //! Syscalls for networking
 
-use super::fs::IoVecs;
+use super::fs::{set_owner, split_path, IoVecs};
 use super::*;
-use crate::fs::FileLike;
+use crate::arch::timer::timer_now;
+use crate::fs::fcntl::{O_CLOEXEC, O_NONBLOCK};
+use crate::fs::{FileLike, SocketFile};
 use crate::memory::MemorySet;
 use crate::net::{
-    Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
-    RawSocketState, Socket, TcpSocketState, UdpSocketState,
+    block_on_socket, Ancillary, Endpoint, IpFamily, LinkLevelEndpoint, NetlinkEndpoint,
+    NetlinkSocketState, PacketSocketState, RawSocketState, Socket, TcpSocketState, UCred,
+    UdpSocketState, UnixAddr, UnixSocketState,
 };
+use crate::sync::Event;
 use alloc::boxed::Box;
 use core::cmp::min;
 use core::mem::size_of;
+use core::ptr;
+use core::time::Duration;
 use smoltcp::wire::*;
 
 impl Syscall<'_> {
     pub fn sys_socket(&mut self, domain: usize, socket_type: usize, protocol: usize) -> SysResult {
         let domain = AddressFamily::from(domain as u16);
+        let flags = socket_type & !(SOCK_TYPE_MASK as usize);
         let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
         info!(
-            "socket: domain: {:?}, socket_type: {:?}, protocol: {}",
-            domain, socket_type, protocol
+            "socket: domain: {:?}, socket_type: {:?}, protocol: {}, flags: {:#x}",
+            domain, socket_type, protocol, flags
         );
         let mut proc = self.process();
         let socket: Box<dyn Socket> = match domain {
-            AddressFamily::Internet | AddressFamily::Unix => match socket_type {
-                SocketType::Stream => Box::new(TcpSocketState::new()),
-                SocketType::Datagram => Box::new(UdpSocketState::new()),
-                SocketType::Raw => Box::new(RawSocketState::new(protocol as u8)),
+            AddressFamily::Internet | AddressFamily::Internet6 => {
+                let (family, version) = match domain {
+                    AddressFamily::Internet6 => (IpFamily::V6, IpVersion::Ipv6),
+                    _ => (IpFamily::V4, IpVersion::Ipv4),
+                };
+                match socket_type {
+                    SocketType::Stream => Box::new(TcpSocketState::new(family)),
+                    SocketType::Datagram => Box::new(UdpSocketState::new(family)),
+                    SocketType::Raw => Box::new(RawSocketState::new(version, protocol as u8)),
+                    _ => return Err(SysError::EINVAL),
+                }
+            }
+            AddressFamily::Unix => match socket_type {
+                SocketType::Stream | SocketType::Datagram | SocketType::SeqPacket => {
+                    Box::new(UnixSocketState::new(socket_type, UCred::new(&proc)))
+                }
                 _ => return Err(SysError::EINVAL),
             },
             AddressFamily::Packet => match socket_type {
@@ -34,15 +53,47 @@ impl Syscall<'_> {
                 _ => return Err(SysError::EINVAL),
             },
             AddressFamily::Netlink => match socket_type {
-                SocketType::Raw => Box::new(NetlinkSocketState::new()),
+                SocketType::Raw => Box::new(NetlinkSocketState::new(proc.pid.get() as u32)),
                 _ => return Err(SysError::EINVAL),
             },
             _ => return Err(SysError::EAFNOSUPPORT),
         };
-        let fd = proc.add_file(FileLike::Socket(socket));
+        let fd = proc.add_file(FileLike::Socket(socket_file(socket, flags)));
         Ok(fd)
     }
 
+    pub fn sys_socketpair(
+        &mut self,
+        domain: usize,
+        socket_type: usize,
+        protocol: usize,
+        fds: *mut u32,
+    ) -> SysResult {
+        let domain = AddressFamily::from(domain as u16);
+        let flags = socket_type & !(SOCK_TYPE_MASK as usize);
+        let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
+        info!(
+            "socketpair: domain: {:?}, socket_type: {:?}, protocol: {}, fds: {:?}, flags: {:#x}",
+            domain, socket_type, protocol, fds, flags
+        );
+        let mut proc = self.process();
+        let fds = unsafe { self.vm().check_write_array(fds, 2)? };
+        if domain != AddressFamily::Unix {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        match socket_type {
+            SocketType::Stream | SocketType::Datagram | SocketType::SeqPacket => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        if protocol != 0 {
+            return Err(SysError::EPROTONOSUPPORT);
+        }
+        let (a, b) = UnixSocketState::new_pair(socket_type, UCred::new(&proc));
+        fds[0] = proc.add_file(FileLike::Socket(socket_file(Box::new(a), flags))) as u32;
+        fds[1] = proc.add_file(FileLike::Socket(socket_file(Box::new(b), flags))) as u32;
+        Ok(0)
+    }
+
     pub fn sys_setsockopt(
         &mut self,
         fd: usize,
@@ -88,132 +139,331 @@ impl Syscall<'_> {
                     *optlen = 4;
                     Ok(0)
                 }
-                _ => Err(SysError::ENOPROTOOPT),
-            },
-            IPPROTO_TCP => match optname {
-                TCP_CONGESTION => Ok(0),
-                _ => Err(SysError::ENOPROTOOPT),
+                SO_PEERCRED => {
+                    let cred = self
+                        .process()
+                        .get_socket(fd)?
+                        .peer_cred()
+                        .ok_or(SysError::ENOTCONN)?;
+                    let optval = unsafe { self.vm().check_write_ptr(optval as *mut UCred)? };
+                    *optval = cred;
+                    *optlen = size_of::<UCred>() as u32;
+                    Ok(0)
+                }
+                _ => self.getsockopt_of_socket(fd, level, optname, optval, optlen),
             },
-            _ => Err(SysError::ENOPROTOOPT),
+            IPPROTO_TCP if optname == TCP_CONGESTION => Ok(0),
+            _ => self.getsockopt_of_socket(fd, level, optname, optval, optlen),
         }
     }
 
-    pub fn sys_connect(&mut self, fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
+    /// Copy out an option the socket keeps, truncated to `optlen`
+    fn getsockopt_of_socket(
+        &mut self,
+        fd: usize,
+        level: usize,
+        optname: usize,
+        optval: *mut u8,
+        optlen: &mut u32,
+    ) -> SysResult {
+        let value = self.process().get_socket(fd)?.getsockopt(level, optname)?;
+        let len = min(*optlen as usize, value.len());
+        let optval = unsafe { self.vm().check_write_array(optval, len)? };
+        optval.copy_from_slice(&value[..len]);
+        *optlen = len as u32;
+        Ok(0)
+    }
+
+    pub async fn sys_connect(
+        &mut self,
+        fd: usize,
+        addr: UserInPtr<SockAddr>,
+        addr_len: usize,
+    ) -> SysResult {
         info!(
             "sys_connect: fd: {}, addr: {:?}, addr_len: {}",
             fd, addr, addr_len
         );
 
+        let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr.ptr(), addr_len)?;
+        let endpoint = self.lookup_unix_addr(endpoint, false)?;
         let mut proc = self.process();
-        let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
-        let socket = proc.get_socket(fd)?;
-        socket.connect(endpoint)?;
-        Ok(0)
+        let socket = proc.get_socket_file(fd)?;
+        match socket.connect(endpoint) {
+            // wait for the connection, which goes on after a signal or timeout
+            Err(SysError::EINPROGRESS) if !socket.nonblock() => {
+                let socket = socket.clone();
+                drop(proc);
+                block_on_socket(&**socket, Event::WRITABLE, false, || {
+                    socket.connect_result()
+                })
+                .await
+            }
+            result => result,
+        }
     }
 
-    pub fn sys_sendto(
+    pub async fn sys_sendto(
         &mut self,
         fd: usize,
-        base: *const u8,
+        base: UserInPtr<u8>,
         len: usize,
-        _flags: usize,
-        addr: *const SockAddr,
+        flags: usize,
+        addr: UserInPtr<SockAddr>,
         addr_len: usize,
     ) -> SysResult {
         info!(
-            "sys_sendto: fd: {} base: {:?} len: {} addr: {:?} addr_len: {}",
-            fd, base, len, addr, addr_len
+            "sys_sendto: fd: {} base: {:?} len: {} flags: {} addr: {:?} addr_len: {}",
+            fd, base, len, flags, addr, addr_len
         );
 
-        let mut proc = self.process();
-
-        let slice = unsafe { self.vm().check_read_array(base, len)? };
+        let slice = unsafe { self.vm().check_read_array(base.ptr(), len)? };
         let endpoint = if addr.is_null() {
             None
         } else {
-            let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
+            let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr.ptr(), addr_len)?;
             info!("sys_sendto: sending to endpoint {:?}", endpoint);
-            Some(endpoint)
+            Some(self.lookup_unix_addr(endpoint, false)?)
         };
-        let socket = proc.get_socket(fd)?;
-        socket.write(&slice, endpoint)
+        let socket = self.process().get_socket_file(fd)?.clone();
+        let nonblock = socket.nonblock() || flags & MSG_DONTWAIT != 0;
+        block_on_socket(&**socket, Event::WRITABLE, nonblock, || {
+            socket.write(&slice, endpoint.clone())
+        })
+        .await
+    }
+
+    pub async fn sys_sendmsg(
+        &mut self,
+        fd: usize,
+        msg: UserInPtr<MsgHdr>,
+        flags: usize,
+    ) -> SysResult {
+        info!("sendmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
+        let (buf, endpoint, control) = {
+            let hdr = unsafe { self.vm().check_read_ptr(msg.ptr())? };
+            let iovs =
+                unsafe { IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &self.vm(), false)? };
+            let buf = iovs.read_all_to_vec();
+            let endpoint = if hdr.msg_name.is_null() {
+                None
+            } else {
+                let endpoint =
+                    sockaddr_to_endpoint(&self.vm(), hdr.msg_name, hdr.msg_namelen as usize)?;
+                Some(self.lookup_unix_addr(endpoint, false)?)
+            };
+            let control: &[u8] = if hdr.msg_controllen == 0 {
+                &[]
+            } else {
+                unsafe {
+                    self.vm()
+                        .check_read_array(hdr.msg_control as *const u8, hdr.msg_controllen)?
+                }
+            };
+            (buf, endpoint, control)
+        };
+
+        let mut proc = self.process();
+        let socket = proc.get_socket_file(fd)?.clone();
+        let ancillary = parse_control(&mut proc, control)?;
+        drop(proc);
+        let nonblock = socket.nonblock() || flags & MSG_DONTWAIT != 0;
+        block_on_socket(&**socket, Event::WRITABLE, nonblock, || {
+            // a try that fails drops its copy of the files
+            socket.write_msg(&buf, endpoint.clone(), ancillary.clone())
+        })
+        .await
     }
 
-    pub fn sys_recvfrom(
+    pub async fn sys_recvfrom(
         &mut self,
         fd: usize,
-        base: *mut u8,
+        base: UserOutPtr<u8>,
         len: usize,
         flags: usize,
-        addr: *mut SockAddr,
-        addr_len: *mut u32,
+        addr: UserOutPtr<SockAddr>,
+        addr_len: UserInOutPtr<u32>,
     ) -> SysResult {
         info!(
             "sys_recvfrom: fd: {} base: {:?} len: {} flags: {} addr: {:?} addr_len: {:?}",
             fd, base, len, flags, addr, addr_len
         );
 
-        let mut proc = self.process();
+        let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
+        let socket = self.process().get_socket_file(fd)?.clone();
+        let (msg_len, endpoint, _) = recv_by_flags(&socket, slice, flags).await?;
 
-        let mut slice = unsafe { self.vm().check_write_array(base, len)? };
-        let socket = proc.get_socket(fd)?;
-        let (result, endpoint) = socket.read(&mut slice);
-
-        if result.is_ok() && !addr.is_null() {
+        if !addr.is_null() {
             let sockaddr_in = SockAddr::from(endpoint);
             unsafe {
-                sockaddr_in.write_to(&mut self.vm(), addr, addr_len)?;
+                sockaddr_in.write_to(&mut self.vm(), addr.ptr(), addr_len.ptr())?;
             }
         }
-
-        result
+        if flags & MSG_TRUNC != 0 {
+            Ok(msg_len)
+        } else {
+            Ok(min(msg_len, len))
+        }
     }
 
-    pub fn sys_recvmsg(&mut self, fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
+    pub async fn sys_recvmsg(
+        &mut self,
+        fd: usize,
+        msg: UserInOutPtr<MsgHdr>,
+        flags: usize,
+    ) -> SysResult {
         info!("recvmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
-        let mut proc = self.process();
-        let hdr = unsafe { self.vm().check_write_ptr(msg)? };
-        let mut iovs =
-            unsafe { IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &self.vm(), true)? };
+        let (mut iovs, control) = {
+            let hdr = unsafe { self.vm().check_write_ptr(msg.ptr())? };
+            let iovs =
+                unsafe { IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &self.vm(), true)? };
+            let control: &mut [u8] = if hdr.msg_controllen == 0 {
+                &mut []
+            } else {
+                unsafe {
+                    self.vm()
+                        .check_write_array(hdr.msg_control as *mut u8, hdr.msg_controllen)?
+                }
+            };
+            (iovs, control)
+        };
 
         let mut buf = iovs.new_buf(true);
-        let socket = proc.get_socket(fd)?;
-        let (result, endpoint) = socket.read(&mut buf);
+        let socket = self.process().get_socket_file(fd)?.clone();
+        let (msg_len, endpoint, ancillary) = recv_by_flags(&socket, &mut buf, flags).await?;
 
-        if let Ok(len) = result {
-            // copy data to user
-            iovs.write_all_from_slice(&buf[..len]);
-            let sockaddr_in = SockAddr::from(endpoint);
-            unsafe {
-                sockaddr_in.write_to(
-                    &mut self.vm(),
-                    hdr.msg_name,
-                    &mut hdr.msg_namelen as *mut u32,
-                )?;
+        // copy data to user
+        let len = min(msg_len, buf.len());
+        iovs.write_all_from_slice(&buf[..len]);
+        let hdr = unsafe { self.vm().check_write_ptr(msg.ptr())? };
+        let sockaddr_in = SockAddr::from(endpoint);
+        unsafe {
+            sockaddr_in.write_to(
+                &mut self.vm(),
+                hdr.msg_name,
+                &mut hdr.msg_namelen as *mut u32,
+            )?;
+        }
+        let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
+        let (control_len, truncated) =
+            write_control(&mut self.process(), ancillary, control, cloexec);
+        hdr.msg_controllen = control_len;
+        hdr.msg_flags = 0;
+        if msg_len > len {
+            hdr.msg_flags |= MSG_TRUNC;
+        }
+        if truncated {
+            hdr.msg_flags |= MSG_CTRUNC;
+        }
+        if flags & MSG_TRUNC != 0 {
+            Ok(msg_len)
+        } else {
+            Ok(len)
+        }
+    }
+
+    pub async fn sys_sendmmsg(
+        &mut self,
+        fd: usize,
+        msgvec: UserInOutPtr<MMsgHdr>,
+        vlen: usize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "sendmmsg: fd: {}, msgvec: {:?}, vlen: {}, flags: {}",
+            fd, msgvec, vlen, flags
+        );
+        let mut count = 0;
+        for i in 0..min(vlen, UIO_MAXIOV) {
+            let mmsg = msgvec.add(i);
+            let len = match self
+                .sys_sendmsg(fd, UserInPtr::from(mmsg.ptr() as usize), flags)
+                .await
+            {
+                Ok(len) => len,
+                // errors after the first message are left for the next call
+                Err(err) if count == 0 => return Err(err),
+                Err(_) => break,
+            };
+            let mmsg = unsafe { self.vm().check_write_ptr(mmsg.ptr())? };
+            mmsg.msg_len = len as u32;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub async fn sys_recvmmsg(
+        &mut self,
+        fd: usize,
+        msgvec: UserInOutPtr<MMsgHdr>,
+        vlen: usize,
+        flags: usize,
+        timeout: UserInPtr<TimeSpec>,
+    ) -> SysResult {
+        info!(
+            "recvmmsg: fd: {}, msgvec: {:?}, vlen: {}, flags: {}, timeout: {:?}",
+            fd, msgvec, vlen, flags, timeout
+        );
+        // as on Linux, the timeout is only checked after each message
+        let deadline = match timeout.read_if_not_null()? {
+            Some(timeout) => Some(timer_now() + timeout.to_duration()),
+            None => None,
+        };
+        let mut flags = flags;
+        let mut count = 0;
+        for i in 0..min(vlen, UIO_MAXIOV) {
+            let mmsg = msgvec.add(i);
+            let len = match self
+                .sys_recvmsg(
+                    fd,
+                    UserInOutPtr::from(mmsg.ptr() as usize),
+                    flags & !MSG_WAITFORONE,
+                )
+                .await
+            {
+                Ok(len) => len,
+                // errors after the first message are left for the next call
+                Err(err) if count == 0 => return Err(err),
+                Err(_) => break,
+            };
+            let mmsg = unsafe { self.vm().check_write_ptr(mmsg.ptr())? };
+            mmsg.msg_len = len as u32;
+            count += 1;
+            if flags & MSG_WAITFORONE != 0 {
+                flags |= MSG_DONTWAIT;
+            }
+            if deadline.map_or(false, |deadline| timer_now() >= deadline) {
+                break;
             }
         }
-        result
+        Ok(count)
     }
 
     pub fn sys_bind(&mut self, fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
         info!("sys_bind: fd: {} addr: {:?} len: {}", fd, addr, addr_len);
-        let mut proc = self.process();
-
         let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
         info!("sys_bind: fd: {} bind to {:?}", fd, endpoint);
 
+        if let Endpoint::Unix(_) = endpoint {
+            // the socket file is not created for a bound socket
+            match self.process().get_socket(fd)?.endpoint() {
+                Some(Endpoint::Unix(UnixAddr::Unnamed)) => {}
+                Some(Endpoint::Unix(_)) => return Err(SysError::EINVAL),
+                _ => {}
+            }
+        }
+        let endpoint = self.lookup_unix_addr(endpoint, true)?;
+        let mut proc = self.process();
         let socket = proc.get_socket(fd)?;
         socket.bind(endpoint)
     }
 
     pub fn sys_listen(&mut self, fd: usize, backlog: usize) -> SysResult {
         info!("sys_listen: fd: {} backlog: {}", fd, backlog);
-        // smoltcp tcp sockets do not support backlog
-        // open multiple sockets for each connection
         let mut proc = self.process();
 
         let socket = proc.get_socket(fd)?;
-        socket.listen()
+        socket.listen(backlog)
     }
 
     pub fn sys_shutdown(&mut self, fd: usize, how: usize) -> SysResult {
@@ -221,27 +471,38 @@ impl Syscall<'_> {
         let mut proc = self.process();
 
         let socket = proc.get_socket(fd)?;
-        socket.shutdown()
+        socket.shutdown(how)
     }
 
-    pub fn sys_accept(&mut self, fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
+    pub async fn sys_accept4(
+        &mut self,
+        fd: usize,
+        addr: UserOutPtr<SockAddr>,
+        addr_len: UserInOutPtr<u32>,
+        flags: usize,
+    ) -> SysResult {
         info!(
-            "sys_accept: fd: {} addr: {:?} addr_len: {:?}",
-            fd, addr, addr_len
+            "sys_accept4: fd: {} addr: {:?} addr_len: {:?} flags: {:#x}",
+            fd, addr, addr_len, flags
         );
-        // smoltcp tcp sockets do not support backlog
-        // open multiple sockets for each connection
-        let mut proc = self.process();
-
-        let socket = proc.get_socket(fd)?;
-        let (new_socket, remote_endpoint) = socket.accept()?;
+        if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let socket = self.process().get_socket_file(fd)?.clone();
+        let (new_socket, remote_endpoint) =
+            block_on_socket(&**socket, Event::READABLE, socket.nonblock(), || {
+                self.process().get_socket(fd)?.accept()
+            })
+            .await?;
 
-        let new_fd = proc.add_file(FileLike::Socket(new_socket));
+        let new_fd = self
+            .process()
+            .add_file(FileLike::Socket(socket_file(new_socket, flags)));
 
         if !addr.is_null() {
             let sockaddr_in = SockAddr::from(remote_endpoint);
             unsafe {
-                sockaddr_in.write_to(&mut self.vm(), addr, addr_len)?;
+                sockaddr_in.write_to(&mut self.vm(), addr.ptr(), addr_len.ptr())?;
             }
         }
         Ok(new_fd)
@@ -300,15 +561,256 @@ impl Syscall<'_> {
         }
         Ok(0)
     }
+
+    /// Find the socket file of a Unix socket address, or create it to `bind`
+    fn lookup_unix_addr(&self, endpoint: Endpoint, bind: bool) -> Result<Endpoint, SysError> {
+        let path = match &endpoint {
+            Endpoint::Unix(UnixAddr::Path(path, None)) => path.clone(),
+            _ => return Ok(endpoint),
+        };
+        let proc = self.process();
+        let inode = if bind {
+            let (dir_path, file_name) = split_path(&path);
+            let dir_inode = proc.lookup_inode(dir_path)?;
+            if dir_inode.find(file_name).is_ok() {
+                return Err(SysError::EADDRINUSE);
+            }
+            proc.cred
+                .check_access(&dir_inode.metadata()?, Access::WRITE | Access::EXEC)?;
+            let inode = dir_inode.create(file_name, FileType::Socket, 0o777)?;
+            set_owner(&inode, &dir_inode, &proc.cred);
+            inode
+        } else {
+            let inode = proc.lookup_inode(&path)?;
+            let metadata = inode.metadata()?;
+            proc.cred.check_access(&metadata, Access::WRITE)?;
+            if metadata.type_ != FileType::Socket {
+                return Err(SysError::ECONNREFUSED);
+            }
+            inode
+        };
+        let metadata = inode.metadata()?;
+        let key = Some((metadata.dev, metadata.inode));
+        Ok(Endpoint::Unix(UnixAddr::Path(path, key)))
+    }
+}
+
+/// Receive into `buf` as `flags` ask
+///
+/// Return the length of the message, which may exceed `buf`, where it came
+/// from and its ancillary data.
+async fn recv_by_flags(
+    file: &SocketFile,
+    buf: &mut [u8],
+    flags: usize,
+) -> Result<(usize, Endpoint, Ancillary), SysError> {
+    let socket = &***file;
+    let peek = flags & MSG_PEEK != 0;
+    let nonblock = file.nonblock() || flags & MSG_DONTWAIT != 0;
+    let (mut len, endpoint, mut ancillary) =
+        block_on_socket(socket, Event::READABLE, nonblock, || {
+            let (result, endpoint, ancillary) = socket.read_msg(buf, peek);
+            result.map(|len| (len, endpoint, ancillary))
+        })
+        .await?;
+    if flags & MSG_WAITALL == 0 || peek || !socket.is_stream() {
+        return Ok((len, endpoint, ancillary));
+    }
+    // stop early at the end of stream, on errors and on passed files
+    while len > 0 && len < buf.len() && ancillary.rights.is_empty() {
+        let rest = &mut buf[len..];
+        let result = block_on_socket(socket, Event::READABLE, nonblock, || {
+            let (result, _, more) = socket.read_msg(rest, false);
+            result.map(|len| (len, more))
+        })
+        .await;
+        match result {
+            Ok((0, _)) | Err(_) => break,
+            Ok((more_len, more)) => {
+                len += more_len;
+                ancillary.rights = more.rights;
+            }
+        }
+    }
+    Ok((len, endpoint, ancillary))
+}
+
+/// Take the files and credentials of SCM_RIGHTS and SCM_CREDENTIALS in `control`
+fn parse_control(proc: &mut Process, control: &[u8]) -> Result<Ancillary, SysError> {
+    let mut ancillary = Ancillary::default();
+    let mut offset = 0;
+    while offset + size_of::<CMsgHdr>() <= control.len() {
+        let hdr: CMsgHdr = unsafe { ptr::read_unaligned(control[offset..].as_ptr() as *const _) };
+        if hdr.cmsg_len < size_of::<CMsgHdr>() || hdr.cmsg_len > control.len() - offset {
+            return Err(SysError::EINVAL);
+        }
+        let data = &control[offset + size_of::<CMsgHdr>()..offset + hdr.cmsg_len];
+        match (hdr.cmsg_level as usize, hdr.cmsg_type) {
+            (SOL_SOCKET, SCM_RIGHTS) => {
+                for fd in data.chunks_exact(size_of::<i32>()) {
+                    if ancillary.rights.len() == SCM_MAX_FD {
+                        return Err(SysError::EINVAL);
+                    }
+                    let fd = i32::from_ne_bytes([fd[0], fd[1], fd[2], fd[3]]) as usize;
+                    let file_like = proc.get_file_like(fd)?.dup(false);
+                    ancillary.rights.push(file_like);
+                }
+            }
+            (SOL_SOCKET, SCM_CREDENTIALS) => {
+                if data.len() < size_of::<UCred>() {
+                    return Err(SysError::EINVAL);
+                }
+                let cred: UCred = unsafe { ptr::read_unaligned(data.as_ptr() as *const _) };
+                // only root may pretend to be someone else
+                let own = UCred::new(proc);
+                let ids = &proc.cred;
+                let uid = cred.uid as usize;
+                let gid = cred.gid as usize;
+                if !ids.is_root()
+                    && (cred.pid != own.pid
+                        || (uid != ids.ruid && uid != ids.euid && uid != ids.suid)
+                        || (gid != ids.rgid && gid != ids.egid && gid != ids.sgid))
+                {
+                    return Err(SysError::EPERM);
+                }
+                ancillary.cred = Some(cred);
+            }
+            // smoltcp picks the interface and source address itself
+            (IPPROTO_IP, cmsg_type) if cmsg_type == IP_PKTINFO as i32 => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        offset += cmsg_align(hdr.cmsg_len);
+    }
+    Ok(ancillary)
+}
+
+/// Put the ancillary data into `control` as control messages
+///
+/// Return the length of them, and whether some did not fit, in which case the
+/// files left are closed.
+fn write_control(
+    proc: &mut Process,
+    ancillary: Ancillary,
+    control: &mut [u8],
+    cloexec: bool,
+) -> (usize, bool) {
+    let mut messages = Vec::new();
+    if let Some(cred) = ancillary.cred {
+        messages.push((SOL_SOCKET, SCM_CREDENTIALS, struct_bytes(&cred)));
+    }
+    if let Some(time) = ancillary.timestamp {
+        messages.push((SOL_SOCKET, SCM_TIMESTAMP, struct_bytes(&time)));
+    }
+    if let Some((index, addr)) = ancillary.pktinfo {
+        let info = InPktInfo {
+            ipi_ifindex: index as i32,
+            ipi_spec_dst: u32::from_ne_bytes(addr.0),
+            ipi_addr: u32::from_ne_bytes(addr.0),
+        };
+        messages.push((IPPROTO_IP, IP_PKTINFO as i32, struct_bytes(&info)));
+    }
+
+    let mut buf = Vec::new();
+    let mut truncated = false;
+    for (level, cmsg_type, data) in messages {
+        if buf.len() + size_of::<CMsgHdr>() + data.len() <= control.len() {
+            push_cmsg(&mut buf, level, cmsg_type, &data);
+        } else {
+            truncated = true;
+        }
+    }
+    if !ancillary.rights.is_empty() {
+        let room = control
+            .len()
+            .saturating_sub(buf.len() + size_of::<CMsgHdr>());
+        let count = min(ancillary.rights.len(), room / size_of::<i32>());
+        truncated |= count < ancillary.rights.len();
+        let mut data = Vec::new();
+        for mut file_like in ancillary.rights.into_iter().take(count) {
+            file_like.set_fd_cloexec(cloexec);
+            let fd = proc.add_file(file_like) as i32;
+            data.extend_from_slice(&fd.to_ne_bytes());
+        }
+        if count > 0 {
+            push_cmsg(&mut buf, SOL_SOCKET, SCM_RIGHTS, &data);
+        }
+    }
+    let len = min(buf.len(), control.len());
+    control[..len].copy_from_slice(&buf[..len]);
+    (len, truncated)
+}
+
+/// Append a control message, padded for the next one
+fn push_cmsg(buf: &mut Vec<u8>, level: usize, cmsg_type: i32, data: &[u8]) {
+    let hdr = CMsgHdr {
+        cmsg_len: size_of::<CMsgHdr>() + data.len(),
+        cmsg_level: level as i32,
+        cmsg_type,
+    };
+    let start = buf.len();
+    buf.extend_from_slice(unsafe {
+        slice::from_raw_parts(&hdr as *const CMsgHdr as *const u8, size_of::<CMsgHdr>())
+    });
+    buf.extend_from_slice(data);
+    buf.resize(start + cmsg_align(hdr.cmsg_len), 0);
+}
+
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// Copy the bytes of a control message or option value
+pub fn struct_bytes<T>(value: &T) -> Vec<u8> {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }.to_vec()
+}
+
+/// Read an int socket option
+pub fn sockopt_u32(data: &[u8]) -> Result<u32, SysError> {
+    if data.len() < size_of::<u32>() {
+        return Err(SysError::EINVAL);
+    }
+    Ok(u32::from_ne_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+/// Write an int socket option
+pub fn sockopt_value(value: u32) -> Vec<u8> {
+    value.to_ne_bytes().to_vec()
+}
+
+/// Read SO_RCVTIMEO or SO_SNDTIMEO, where zero is for no timeout
+pub fn sockopt_timeout(data: &[u8]) -> Result<Option<Duration>, SysError> {
+    if data.len() < size_of::<TimeVal>() {
+        return Err(SysError::EINVAL);
+    }
+    let timeout: TimeVal = unsafe { ptr::read_unaligned(data.as_ptr() as *const _) };
+    let timeout = timeout.to_duration();
+    if timeout == Duration::from_secs(0) {
+        Ok(None)
+    } else {
+        Ok(Some(timeout))
+    }
+}
+
+/// Open `socket` as a file, with SOCK_NONBLOCK and SOCK_CLOEXEC of `flags`
+fn socket_file(socket: Box<dyn Socket>, flags: usize) -> SocketFile {
+    SocketFile::new(
+        socket,
+        flags & SOCK_NONBLOCK != 0,
+        flags & SOCK_CLOEXEC != 0,
+    )
 }
 
 impl Process {
-    fn get_socket(&mut self, fd: usize) -> Result<&mut Box<dyn Socket>, SysError> {
+    fn get_socket_file(&mut self, fd: usize) -> Result<&mut SocketFile, SysError> {
         match self.get_file_like(fd)? {
             FileLike::Socket(socket) => Ok(socket),
             _ => Err(SysError::EBADF),
         }
     }
+
+    fn get_socket(&mut self, fd: usize) -> Result<&mut Box<dyn Socket>, SysError> {
+        Ok(&mut **self.get_socket_file(fd)?)
+    }
 }
 
 #[repr(C)]
@@ -319,6 +821,15 @@ pub struct SockAddrIn {
     pub sin_zero: [u8; 8],
 }
 
+#[repr(C)]
+pub struct SockAddrIn6 {
+    pub sin6_family: u16,
+    pub sin6_port: u16,
+    pub sin6_flowinfo: u32,
+    pub sin6_addr: [u8; 16],
+    pub sin6_scope_id: u32,
+}
+
 #[repr(C)]
 pub struct SockAddrUn {
     pub sun_family: u16,
@@ -348,6 +859,7 @@ pub struct SockAddrNl {
 pub union SockAddr {
     pub family: u16,
     pub addr_in: SockAddrIn,
+    pub addr_in6: SockAddrIn6,
     pub addr_un: SockAddrUn,
     pub addr_ll: SockAddrLl,
     pub addr_nl: SockAddrNl,
@@ -372,13 +884,21 @@ impl From<Endpoint> for SockAddr {
                         sin_zero: [0; 8],
                     },
                 },
-                IpAddress::Unspecified => SockAddr {
+                IpAddress::Ipv6(ipv6) => SockAddr {
+                    addr_in6: SockAddrIn6 {
+                        sin6_family: AddressFamily::Internet6.into(),
+                        sin6_port: u16::to_be(ip.port),
+                        sin6_flowinfo: 0,
+                        sin6_addr: ipv6.0,
+                        sin6_scope_id: 0,
+                    },
+                },
+                _ => SockAddr {
                     addr_ph: SockAddrPlaceholder {
                         family: AddressFamily::Unspecified.into(),
                         data: [0; 14],
                     },
                 },
-                _ => unimplemented!("only ipv4"),
             }
         } else if let Endpoint::LinkLevel(link_level) = endpoint {
             SockAddr {
@@ -401,6 +921,23 @@ impl From<Endpoint> for SockAddr {
                     nl_groups: netlink.multicast_groups_mask,
                 },
             }
+        } else if let Endpoint::Unix(unix) = endpoint {
+            let mut addr_un = SockAddrUn {
+                sun_family: AddressFamily::Unix.into(),
+                sun_path: [0; 108],
+            };
+            match unix {
+                UnixAddr::Unnamed => {}
+                UnixAddr::Path(path, _) => {
+                    let len = min(path.len(), addr_un.sun_path.len() - 1);
+                    addr_un.sun_path[..len].copy_from_slice(&path.as_bytes()[..len]);
+                }
+                UnixAddr::Abstract(name) => {
+                    let len = min(name.len(), addr_un.sun_path.len() - 1);
+                    addr_un.sun_path[1..=len].copy_from_slice(&name[..len]);
+                }
+            }
+            SockAddr { addr_un }
         } else {
             unimplemented!("only ip");
         }
@@ -418,6 +955,9 @@ fn sockaddr_to_endpoint(
         return Err(SysError::EINVAL);
     }
     let addr = unsafe { vm.check_read_ptr(addr)? };
+    if AddressFamily::from(unsafe { addr.family }) == AddressFamily::Unix {
+        return unix_sockaddr_to_endpoint(unsafe { &addr.addr_un }, len);
+    }
     if len < addr.len()? {
         return Err(SysError::EINVAL);
     }
@@ -430,7 +970,11 @@ fn sockaddr_to_endpoint(
                 ));
                 Ok(Endpoint::Ip((addr, port).into()))
             }
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            AddressFamily::Internet6 => {
+                let port = u16::from_be(addr.addr_in6.sin6_port);
+                let addr = IpAddress::Ipv6(Ipv6Address(addr.addr_in6.sin6_addr));
+                Ok(Endpoint::Ip((addr, port).into()))
+            }
             AddressFamily::Packet => Ok(Endpoint::LinkLevel(LinkLevelEndpoint::new(
                 addr.addr_ll.sll_ifindex as usize,
             ))),
@@ -443,13 +987,41 @@ fn sockaddr_to_endpoint(
     }
 }
 
+/// The first `len` bytes of `addr` name a socket file, or one in the abstract
+/// namespace after a null byte
+fn unix_sockaddr_to_endpoint(addr: &SockAddrUn, len: usize) -> Result<Endpoint, SysError> {
+    if len > size_of::<SockAddrUn>() {
+        return Err(SysError::EINVAL);
+    }
+    let path = &addr.sun_path[..len - size_of::<u16>()];
+    let addr = match path.first() {
+        None => UnixAddr::Unnamed,
+        Some(0) => UnixAddr::Abstract(path[1..].to_vec()),
+        Some(_) => {
+            let end = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+            let path = String::from_utf8(path[..end].to_vec()).map_err(|_| SysError::EINVAL)?;
+            UnixAddr::Path(path, None)
+        }
+    };
+    Ok(Endpoint::Unix(addr))
+}
+
 impl SockAddr {
     fn len(&self) -> Result<usize, SysError> {
         match AddressFamily::from(unsafe { self.family }) {
             AddressFamily::Internet => Ok(size_of::<SockAddrIn>()),
+            AddressFamily::Internet6 => Ok(size_of::<SockAddrIn6>()),
             AddressFamily::Packet => Ok(size_of::<SockAddrLl>()),
             AddressFamily::Netlink => Ok(size_of::<SockAddrNl>()),
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                // abstract names are taken not to end with null bytes
+                let path = unsafe { &self.addr_un.sun_path };
+                let len = match path[0] {
+                    0 => path.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1),
+                    _ => path.iter().position(|&c| c == 0).unwrap_or(path.len() - 1) + 1,
+                };
+                Ok(size_of::<u16>() + len)
+            }
             _ => Err(SysError::EINVAL),
         }
     }
@@ -477,6 +1049,22 @@ impl SockAddr {
     }
 }
 
+/// Linux struct cmsghdr, followed by its data
+#[repr(C)]
+struct CMsgHdr {
+    cmsg_len: usize,
+    cmsg_level: i32,
+    cmsg_type: i32,
+}
+
+/// Linux struct in_pktinfo
+#[repr(C)]
+struct InPktInfo {
+    ipi_ifindex: i32,
+    ipi_spec_dst: u32,
+    ipi_addr: u32,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct MsgHdr {
@@ -489,6 +1077,14 @@ pub struct MsgHdr {
     msg_flags: usize,
 }
 
+/// A message of sendmmsg and recvmmsg, with the length sent or received
+#[repr(C)]
+#[derive(Debug)]
+pub struct MMsgHdr {
+    msg_hdr: MsgHdr,
+    msg_len: u32,
+}
+
 enum_with_unknown! {
     /// Address families
     pub doc enum AddressFamily(u16) {
@@ -498,6 +1094,8 @@ enum_with_unknown! {
         Unix = 1,
         /// Internet IP Protocol
         Internet = 2,
+        /// IP version 6
+        Internet6 = 10,
         /// Netlink
         Netlink = 16,
         /// Packet family
@@ -506,6 +1104,8 @@ enum_with_unknown! {
 }
 
 const SOCK_TYPE_MASK: u8 = 0xf;
+const SOCK_NONBLOCK: usize = O_NONBLOCK;
+const SOCK_CLOEXEC: usize = O_CLOEXEC;
 
 enum_with_unknown! {
     /// Socket types
@@ -516,18 +1116,62 @@ enum_with_unknown! {
         Datagram = 2,
         /// Raw
         Raw = 3,
+        /// Sequenced packets
+        SeqPacket = 5,
     }
 }
 
 pub const IPPROTO_IP: usize = 0;
 pub const IPPROTO_ICMP: usize = 1;
 pub const IPPROTO_TCP: usize = 6;
+pub const IPPROTO_IPV6: usize = 41;
+pub const IPPROTO_ICMPV6: usize = 58;
 
 pub const SOL_SOCKET: usize = 1;
+pub const SO_REUSEADDR: usize = 2;
+pub const SO_ERROR: usize = 4;
+pub const SO_BROADCAST: usize = 6;
 pub const SO_SNDBUF: usize = 7;
 pub const SO_RCVBUF: usize = 8;
+pub const SO_KEEPALIVE: usize = 9;
 pub const SO_LINGER: usize = 13;
+pub const SO_REUSEPORT: usize = 15;
+pub const SO_PASSCRED: usize = 16;
+pub const SO_PEERCRED: usize = 17;
+pub const SO_RCVTIMEO: usize = 20;
+pub const SO_SNDTIMEO: usize = 21;
+pub const SO_TIMESTAMP: usize = 29;
+
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
 
+pub const TCP_NODELAY: usize = 1;
+pub const TCP_KEEPIDLE: usize = 4;
 pub const TCP_CONGESTION: usize = 13;
 
+pub const SCM_RIGHTS: i32 = 1;
+pub const SCM_CREDENTIALS: i32 = 2;
+pub const SCM_TIMESTAMP: i32 = SO_TIMESTAMP as i32;
+/// Max number of files passed in a message
+pub const SCM_MAX_FD: usize = 253;
+
+pub const MSG_PEEK: usize = 0x2;
+pub const MSG_CTRUNC: usize = 0x8;
+pub const MSG_TRUNC: usize = 0x20;
+pub const MSG_DONTWAIT: usize = 0x40;
+pub const MSG_WAITALL: usize = 0x100;
+pub const MSG_WAITFORONE: usize = 0x10000;
+pub const MSG_CMSG_CLOEXEC: usize = 0x40000000;
+
+/// Max number of messages in sendmmsg and recvmmsg
+pub const UIO_MAXIOV: usize = 1024;
+
+pub const IP_TTL: usize = 2;
 pub const IP_HDRINCL: usize = 3;
+pub const IP_PKTINFO: usize = 8;
+pub const IP_MULTICAST_IF: usize = 32;
+pub const IP_MULTICAST_TTL: usize = 33;
+pub const IP_MULTICAST_LOOP: usize = 34;
+
+pub const IPV6_V6ONLY: usize = 26;
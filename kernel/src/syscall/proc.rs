@@ -21,7 +21,7 @@ use core::{
 impl Syscall<'_> {
     /// Fork the current process. Return the child's PID.
     pub fn sys_fork(&mut self) -> SysResult {
-        let new_thread = self.thread.fork(self.context);
+        let new_thread = self.thread.fork(self.context).ok_or(SysError::ENOMEM)?;
         let pid = new_thread.proc.lock().pid.get();
         info!("fork: {} -> {}", self.process().pid, pid);
         spawn(new_thread);
@@ -82,8 +82,20 @@ impl Syscall<'_> {
 
     /// Wait for the process exit.
     /// Return the PID. Store exit code to `wstatus` if it's not null.
-    pub async fn sys_wait4(&mut self, pid: isize, wstatus: UserInOutPtr<i32>) -> SysResult {
-        info!("wait4: pid: {}, code: {:?}", pid, wstatus);
+    ///
+    /// Children stopped or continued by signals are reported
+    /// with `WUNTRACED` or `WCONTINUED` in `options`.
+    pub async fn sys_wait4(
+        &mut self,
+        pid: isize,
+        wstatus: UserInOutPtr<i32>,
+        options: usize,
+    ) -> SysResult {
+        let options = WaitOptions::from_bits_truncate(options);
+        info!(
+            "wait4: pid: {}, code: {:?}, options: {:?}",
+            pid, wstatus, options
+        );
         let wstatus = if !wstatus.is_null() {
             Some(wstatus)
         } else {
@@ -92,89 +104,83 @@ impl Syscall<'_> {
         #[derive(Debug)]
         enum WaitFor {
             AnyChild,
-            AnyChildInGroup,
+            AnyChildInGroup(Pgid),
             Pid(usize),
         }
         let target = match pid {
             -1 => WaitFor::AnyChild,
-            0 => WaitFor::AnyChildInGroup,
+            0 => WaitFor::AnyChildInGroup(self.process().pgid),
             p if p > 0 => WaitFor::Pid(p as usize),
-            _ => unimplemented!(),
+            p => WaitFor::AnyChildInGroup(-p as Pgid),
         };
         loop {
             info!("wait4 loop: pid: {}, code: {:?}", pid, wstatus);
             let mut proc = self.process();
 
             // check child state
-            let find = match target {
-                WaitFor::AnyChild | WaitFor::AnyChildInGroup => {
-                    let mut res = None;
-                    for (pid, child) in &proc.children {
-                        if let Some(c) = child.upgrade() {
-                            let p = c.lock();
-                            if p.exited() {
-                                res = Some((p.pid, p.exit_code));
-                                break;
-                            }
-                        } else {
-                            info!("wait: pid {} is missing", pid);
-                        }
+            let mut find = None;
+            let mut valid = false;
+            for (pid, child) in &proc.children {
+                let child = match child.upgrade() {
+                    Some(child) => child,
+                    None => {
+                        info!("wait: pid {} is missing", pid);
+                        continue;
                     }
-                    res
+                };
+                let mut p = child.lock();
+                let matched = match target {
+                    WaitFor::AnyChild => true,
+                    WaitFor::AnyChildInGroup(pgid) => p.pgid == pgid,
+                    WaitFor::Pid(pid) => p.pid.get() == pid,
+                };
+                if !matched {
+                    continue;
                 }
-                WaitFor::Pid(pid) => {
-                    let mut res = None;
-                    if let Some(c) = process(pid) {
-                        let p = c.lock();
-                        if p.exited() {
-                            res = Some((p.pid, p.exit_code));
-                        }
-                    }
-                    res
+                valid = true;
+                if p.exited() {
+                    find = Some((p.pid, p.exit_status, true));
+                    break;
                 }
-            };
+                let report = match p.status_change {
+                    Some(WaitStatus::Stopped(_)) => options.contains(WaitOptions::UNTRACED),
+                    Some(WaitStatus::Continued) => options.contains(WaitOptions::CONTINUED),
+                    _ => false,
+                };
+                if report {
+                    find = Some((p.pid, p.status_change.take().unwrap(), false));
+                    break;
+                }
+            }
             // if found, return
-            if let Some((pid, exit_code)) = find {
-                info!("wait: found pid {}", pid);
+            if let Some((pid, status, exited)) = find {
+                info!("wait: found pid {}: {:?}", pid, status);
 
                 // write before removing to handle EFAULT
                 if let Some(mut wstatus) = wstatus {
-                    wstatus.write(exit_code as i32)?;
+                    wstatus.write(status.encode())?;
                 }
 
-                // remove from process table
-                if true {
+                if exited {
+                    // remove from process table
                     let mut process_table = PROCESSES.write();
                     process_table.remove(&pid.get());
-                }
+                    drop(process_table);
 
-                // remove from children
-                proc.children.retain(|(p, _)| *p != pid);
+                    // remove from children
+                    proc.children.retain(|(p, _)| *p != pid);
+                }
 
                 return Ok(pid.get());
             }
             // if not, check pid
-            let invalid = {
-                let children = proc
-                    .children
-                    .iter()
-                    .filter_map(|(pid, weak)| {
-                        if weak.upgrade().is_none() {
-                            None
-                        } else {
-                            Some(pid)
-                        }
-                    })
-                    .collect::<Vec<_>>();
-                match target {
-                    WaitFor::AnyChild | WaitFor::AnyChildInGroup => children.len() == 0,
-                    WaitFor::Pid(pid) => children.iter().find(|p| p.get() == pid).is_none(),
-                }
-            };
-            if invalid {
+            if !valid {
                 info!("wait: no valid child proc");
                 return Err(SysError::ECHILD);
             }
+            if options.contains(WaitOptions::NOHANG) {
+                return Ok(0);
+            }
 
             info!("wait: thread {} -> {:?}, sleep", self.thread.tid, target);
 
@@ -219,12 +225,18 @@ impl Syscall<'_> {
 
         // Read program file
         let inode = proc.lookup_inode(&path)?;
+        let metadata = inode.metadata()?;
+        if metadata.type_ != FileType::File {
+            return Err(SysError::EACCES);
+        }
+        proc.cred.check_access(&metadata, Access::EXEC)?;
 
         // Make new Thread
         // Re-create vm
         let mut vm = self.vm();
-        let (entry_addr, ustack_top) =
-            Thread::new_user_vm(&inode, args, envs, &mut vm).map_err(|_| SysError::EINVAL)?;
+        let (entry_addr, ustack_top, brk_start) =
+            Thread::new_user_vm(&inode, args.clone(), envs, &mut vm)
+                .map_err(|_| SysError::EINVAL)?;
 
         // Kill other threads
         // TODO: stop and wait until they are finished
@@ -235,20 +247,17 @@ impl Syscall<'_> {
             .files
             .iter()
             .filter_map(|(fd, file_like)| {
-                if let FileLike::File(file) = file_like {
-                    if file.fd_cloexec {
-                        Some(*fd)
-                    } else {
-                        None
-                    }
+                if file_like.fd_cloexec() {
+                    Some(*fd)
                 } else {
                     None
                 }
             })
             .collect::<Vec<_>>();
-        for fd in close_fds {
-            proc.files.remove(&fd);
-        }
+        let closed: Vec<_> = close_fds
+            .iter()
+            .filter_map(|fd| proc.files.remove(fd))
+            .collect();
 
         // Activate new page table
         unsafe {
@@ -258,12 +267,22 @@ impl Syscall<'_> {
 
         // Modify exec path
         proc.exec_path = path.clone();
+        proc.args = args;
+
+        // the old heap has gone with the old vm
+        proc.brk_start = brk_start;
+        proc.brk = brk_start;
+
+        // set-user-ID and set-group-ID programs
+        proc.cred.exec(&metadata);
 
         // reset disposition (man signal(7))
         for d in proc.dispositions.iter_mut() {
             *d = SignalAction::default();
         }
         drop(proc);
+        // closing a pty master signals its session, which may include us
+        drop(closed);
 
         // Modify the TrapFrame
         self.context.set_ip(entry_addr);
@@ -318,6 +337,30 @@ impl Syscall<'_> {
         }
     }
 
+    /// Create a new session led by the calling process
+    pub fn sys_setsid(&mut self) -> SysResult {
+        let mut proc = self.process();
+        let pid = proc.pid.get() as Pgid;
+        info!("setsid: pid {}", pid);
+        // a process group leader can not leave its group
+        if proc.pgid == pid {
+            return Err(SysError::EPERM);
+        }
+        proc.sid = pid;
+        proc.pgid = pid;
+        Ok(pid as usize)
+    }
+
+    pub fn sys_getsid(&mut self, pid: usize) -> SysResult {
+        info!("getsid: pid {}", pid);
+        if pid == 0 {
+            return Ok(self.process().sid as usize);
+        }
+        let proc = process(pid).ok_or(ESRCH)?;
+        let sid = proc.lock().sid;
+        Ok(sid as usize)
+    }
+
     /// Get the current thread id
     pub fn sys_gettid(&mut self) -> SysResult {
         info!("gettid");
@@ -335,6 +378,130 @@ impl Syscall<'_> {
         }
     }
 
+    pub fn sys_getuid(&mut self) -> SysResult {
+        Ok(self.process().cred.ruid)
+    }
+
+    pub fn sys_geteuid(&mut self) -> SysResult {
+        Ok(self.process().cred.euid)
+    }
+
+    pub fn sys_getgid(&mut self) -> SysResult {
+        Ok(self.process().cred.rgid)
+    }
+
+    pub fn sys_getegid(&mut self) -> SysResult {
+        Ok(self.process().cred.egid)
+    }
+
+    pub fn sys_getresuid(&mut self, ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> SysResult {
+        let cred = self.process().cred.clone();
+        let vm = self.vm();
+        unsafe {
+            *vm.check_write_ptr(ruid)? = cred.ruid as u32;
+            *vm.check_write_ptr(euid)? = cred.euid as u32;
+            *vm.check_write_ptr(suid)? = cred.suid as u32;
+        }
+        Ok(0)
+    }
+
+    pub fn sys_getresgid(&mut self, rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> SysResult {
+        let cred = self.process().cred.clone();
+        let vm = self.vm();
+        unsafe {
+            *vm.check_write_ptr(rgid)? = cred.rgid as u32;
+            *vm.check_write_ptr(egid)? = cred.egid as u32;
+            *vm.check_write_ptr(sgid)? = cred.sgid as u32;
+        }
+        Ok(0)
+    }
+
+    pub fn sys_setuid(&mut self, uid: usize) -> SysResult {
+        info!("setuid: {}", uid);
+        self.process().cred.setuid(uid)?;
+        Ok(0)
+    }
+
+    pub fn sys_setgid(&mut self, gid: usize) -> SysResult {
+        info!("setgid: {}", gid);
+        self.process().cred.setgid(gid)?;
+        Ok(0)
+    }
+
+    pub fn sys_setreuid(&mut self, ruid: usize, euid: usize) -> SysResult {
+        info!("setreuid: ruid: {}, euid: {}", ruid as u32, euid as u32);
+        self.process()
+            .cred
+            .setreuid(ruid as u32 as usize, euid as u32 as usize)?;
+        Ok(0)
+    }
+
+    pub fn sys_setregid(&mut self, rgid: usize, egid: usize) -> SysResult {
+        info!("setregid: rgid: {}, egid: {}", rgid as u32, egid as u32);
+        self.process()
+            .cred
+            .setregid(rgid as u32 as usize, egid as u32 as usize)?;
+        Ok(0)
+    }
+
+    pub fn sys_setresuid(&mut self, ruid: usize, euid: usize, suid: usize) -> SysResult {
+        info!(
+            "setresuid: ruid: {}, euid: {}, suid: {}",
+            ruid as u32, euid as u32, suid as u32
+        );
+        self.process().cred.setresuid(
+            ruid as u32 as usize,
+            euid as u32 as usize,
+            suid as u32 as usize,
+        )?;
+        Ok(0)
+    }
+
+    pub fn sys_setresgid(&mut self, rgid: usize, egid: usize, sgid: usize) -> SysResult {
+        info!(
+            "setresgid: rgid: {}, egid: {}, sgid: {}",
+            rgid as u32, egid as u32, sgid as u32
+        );
+        self.process().cred.setresgid(
+            rgid as u32 as usize,
+            egid as u32 as usize,
+            sgid as u32 as usize,
+        )?;
+        Ok(0)
+    }
+
+    pub fn sys_getgroups(&mut self, size: usize, list: *mut u32) -> SysResult {
+        let groups = self.process().cred.groups.clone();
+        if size == 0 {
+            return Ok(groups.len());
+        }
+        if size < groups.len() {
+            return Err(SysError::EINVAL);
+        }
+        let list = unsafe { self.vm().check_write_array(list, groups.len())? };
+        for (dst, &gid) in list.iter_mut().zip(groups.iter()) {
+            *dst = gid as u32;
+        }
+        Ok(groups.len())
+    }
+
+    pub fn sys_setgroups(&mut self, size: usize, list: *const u32) -> SysResult {
+        info!("setgroups: size: {}", size);
+        if size > NGROUPS_MAX {
+            return Err(SysError::EINVAL);
+        }
+        let groups: Vec<Gid> = unsafe { self.vm().check_read_array(list, size)? }
+            .iter()
+            .map(|&gid| gid as Gid)
+            .collect();
+        let mut proc = self.process();
+        if !proc.cred.is_root() {
+            return Err(SysError::EPERM);
+        }
+        proc.cred.groups = groups;
+        Ok(0)
+    }
+
     /// Exit the current thread
     pub fn sys_exit(&mut self, exit_code: usize) -> SysResult {
         let tid = self.thread.tid;
@@ -344,9 +511,11 @@ impl Syscall<'_> {
         proc.threads.retain(|&id| id != tid);
 
         // for last thread, exit the process
-        if proc.threads.len() == 0 {
-            proc.exit(exit_code);
-        }
+        let closed = if proc.threads.len() == 0 {
+            proc.exit(exit_code)
+        } else {
+            Vec::new()
+        };
 
         // perform futex wake 1
         // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
@@ -363,6 +532,7 @@ impl Syscall<'_> {
         }
 
         drop(proc);
+        drop(closed);
         self.exit = true;
         Ok(0)
     }
@@ -372,8 +542,9 @@ impl Syscall<'_> {
         let mut proc = self.process();
         info!("exit_group: {}, code: {}", proc.pid, exit_code);
 
-        proc.exit(exit_code);
+        let closed = proc.exit(exit_code);
         drop(proc);
+        drop(closed);
         // TODO: quit other threads
         self.exit = true;
         Ok(0)
@@ -451,6 +622,14 @@ impl Future for SleepFuture {
     }
 }
 
+bitflags! {
+    pub struct WaitOptions: usize {
+        const NOHANG =          1;
+        const UNTRACED =        2;
+        const CONTINUED =       8;
+    }
+}
+
 bitflags! {
     pub struct CloneFlags: usize {
         const CSIGNAL =         0x000000ff;
@@ -1,7 +1,9 @@
 use super::{UserInPtr, UserOutPtr};
+use crate::fs::signalfd::{SignalFd, SignalFdFlags};
+use crate::fs::FileLike;
 use crate::process::*;
 use crate::signal::*;
-use crate::syscall::SysError::{EINVAL, ENOMEM, EPERM, ESRCH};
+use crate::syscall::SysError::{EBADF, EINVAL, ENOMEM, EPERM, ESRCH};
 use crate::syscall::{SysResult, Syscall};
 use num::FromPrimitive;
 
@@ -105,6 +107,39 @@ impl Syscall<'_> {
         return Ok(0);
     }
 
+    /// Create a signalfd, or change the mask of signalfd `fd`
+    pub fn sys_signalfd4(
+        &mut self,
+        fd: usize,
+        mask: UserInPtr<Sigset>,
+        sigsetsize: usize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "signalfd4: fd: {}, mask: {:?}, sigsetsize: {}, flags: {:#x}",
+            fd as isize, mask, sigsetsize, flags
+        );
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let flags = SignalFdFlags::from_bits(flags).ok_or(EINVAL)?;
+        let mask = mask.read()?;
+        let mut proc = self.process();
+        if fd as isize == -1 {
+            let signalfd = SignalFd::new(mask, flags);
+            Ok(proc.add_file(FileLike::SignalFd(signalfd)))
+        } else {
+            match proc.files.get(&fd) {
+                Some(FileLike::SignalFd(signalfd)) => {
+                    signalfd.set_mask(mask);
+                    Ok(fd)
+                }
+                Some(_) => Err(EINVAL),
+                None => Err(EBADF),
+            }
+        }
+    }
+
     /// sending signal sig to process pid
     pub fn sys_kill(&mut self, pid: isize, signum: usize) -> SysResult {
         if let Some(signal) = <Signal as FromPrimitive>::from_usize(signum) {
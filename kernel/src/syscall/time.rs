@@ -2,6 +2,8 @@
 
 use super::*;
 use crate::consts::USEC_PER_TICK;
+use crate::fs::timerfd::*;
+use crate::fs::FileLike;
 use core::time::Duration;
 use lazy_static::lazy_static;
 use rcore_fs::vfs::Timespec;
@@ -41,6 +43,61 @@ impl Syscall<'_> {
         Ok(sec as usize)
     }
 
+    pub fn sys_timerfd_create(&mut self, clock: usize, flags: usize) -> SysResult {
+        info!("timerfd_create: clock: {}, flags: {:#x}", clock, flags);
+        match clock {
+            CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        let flags = TimerFdFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        let timerfd = TimerFd::new(clock, flags);
+        let fd = self.process().add_file(FileLike::TimerFd(timerfd));
+        Ok(fd)
+    }
+
+    pub fn sys_timerfd_settime(
+        &mut self,
+        fd: usize,
+        flags: usize,
+        new: UserInPtr<ITimerSpec>,
+        mut old: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!(
+            "timerfd_settime: fd: {}, flags: {:#x}, new: {:?}, old: {:?}",
+            fd, flags, new, old
+        );
+        let flags = TimerSetFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        let new = new.read()?;
+        if new.value.nsec >= 1_000_000_000 || new.interval.nsec >= 1_000_000_000 {
+            return Err(SysError::EINVAL);
+        }
+        let timerfd = self.timerfd(fd)?;
+        let old_value = timerfd.set(new, flags);
+        if !old.is_null() {
+            old.write(old_value)?;
+        }
+        Ok(0)
+    }
+
+    pub fn sys_timerfd_gettime(
+        &mut self,
+        fd: usize,
+        mut curr: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!("timerfd_gettime: fd: {}, curr: {:?}", fd, curr);
+        let timerfd = self.timerfd(fd)?;
+        curr.write(timerfd.get())?;
+        Ok(0)
+    }
+
+    fn timerfd(&self, fd: usize) -> Result<TimerFd, SysError> {
+        match self.process().files.get(&fd) {
+            Some(FileLike::TimerFd(timerfd)) => Ok(timerfd.clone()),
+            Some(_) => Err(SysError::EINVAL),
+            None => Err(SysError::EBADF),
+        }
+    }
+
     pub fn sys_getrusage(&mut self, who: usize, rusage: *mut RUsage) -> SysResult {
         info!("getrusage: who: {}, rusage: {:?}", who, rusage);
         let rusage = unsafe { self.vm().check_write_ptr(rusage)? };
@@ -119,6 +176,17 @@ impl TimeVal {
         (self.sec as u64) * MSEC_PER_SEC + (self.usec as u64) / USEC_PER_MSEC
     }
 
+    pub fn to_duration(&self) -> Duration {
+        Duration::new(self.sec as u64, (self.usec as u64 * NSEC_PER_USEC) as u32)
+    }
+
+    pub fn from_duration(duration: Duration) -> Self {
+        TimeVal {
+            sec: duration.as_secs() as usize,
+            usec: duration.subsec_micros() as usize,
+        }
+    }
+
     pub fn get_epoch() -> Self {
         let usec = get_epoch_usec();
         TimeVal {
@@ -44,10 +44,6 @@ pub fn timer() {
 }
 
 pub fn serial(c: u8) {
-    if c == b'\r' {
-        // in linux, we use '\n' instead
-        crate::fs::TTY.push(b'\n');
-    } else {
-        crate::fs::TTY.push(c);
-    }
+    // '\r' is translated by the line discipline
+    crate::fs::TTY.push(c);
 }